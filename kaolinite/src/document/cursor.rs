@@ -20,11 +20,19 @@ impl Document {
 
     /// Select with the cursor up
     pub fn select_up(&mut self) -> Status {
+        // Move between rows rather than lines when wrapping
+        if self.wrap.is_some() {
+            return if self.wrapped_up() {
+                Status::None
+            } else {
+                Status::StartOfFile
+            };
+        }
         // Return if already at start of document
         if self.loc().y == 0 {
             return Status::StartOfFile;
         }
-        self.cursor.loc.y = self.cursor.loc.y.saturating_sub(1);
+        self.cursor.loc.y = self.prev_visible(self.cursor.loc.y);
         self.cursor.loc.x = self.old_cursor;
         // Snap to end of line
         self.fix_dangling_cursor();
@@ -45,11 +53,20 @@ impl Document {
 
     /// Select with the cursor down
     pub fn select_down(&mut self) -> Status {
+        // Move between rows rather than lines when wrapping
+        if self.wrap.is_some() {
+            return if self.wrapped_down() {
+                Status::None
+            } else {
+                Status::EndOfFile
+            };
+        }
         // Return if already on end of document
-        if self.len_lines() < self.loc().y + 1 {
+        let next = self.next_visible(self.loc().y);
+        if self.len_lines() < next {
             return Status::EndOfFile;
         }
-        self.cursor.loc.y += 1;
+        self.cursor.loc.y = next;
         self.cursor.loc.x = self.old_cursor;
         // Snap to end of line
         self.fix_dangling_cursor();
@@ -197,6 +214,7 @@ impl Document {
         self.cursor.loc.y = new_cursor_y;
         self.offset.y = self.offset.y.saturating_sub(self.size.h);
         // Clean up
+        self.fix_page();
         self.cancel_selection();
     }
 
@@ -223,9 +241,24 @@ impl Document {
         }
         // Clean up
         self.load_to(self.offset.y + self.size.h);
+        self.fix_page();
         self.cancel_selection();
     }
 
+    /// Keep the cursor in view (and out of folds) after moving a page up or down
+    fn fix_page(&mut self) {
+        self.fix_hidden_cursor();
+        if self.wrap.is_some() || !self.folds.is_empty() {
+            if let Some(fold) = self.hidden_by(self.offset.y) {
+                self.offset.y = fold.start;
+            }
+            if self.wrap.is_some() {
+                self.offset.x = 0;
+            }
+            self.bring_cursor_in_viewport();
+        }
+    }
+
     /// Function to go to a specific position
     pub fn move_to(&mut self, loc: &Loc) {
         self.select_to(loc);
@@ -271,6 +304,10 @@ impl Document {
 
     /// Function to select to a specific y position
     pub fn select_to_y(&mut self, y: usize) {
+        // Open any fold hiding the line
+        if self.hidden_by(y).is_some() {
+            self.unfold(y);
+        }
         // Bounds checking
         if self.loc().y != y && y <= self.len_lines() {
             self.cursor.loc.y = y;
@@ -290,13 +327,21 @@ impl Document {
 
     /// Move the view down
     pub fn scroll_down(&mut self) {
-        self.offset.y += 1;
+        if self.wrap.is_some() {
+            self.wrapped_scroll_down();
+            return;
+        }
+        self.offset.y = self.next_visible(self.offset.y);
         self.load_to(self.offset.y + self.size.h);
     }
 
     /// Move the view up
     pub fn scroll_up(&mut self) {
-        self.offset.y = self.offset.y.saturating_sub(1);
+        if self.wrap.is_some() {
+            self.wrapped_scroll_up();
+            return;
+        }
+        self.offset.y = self.prev_visible(self.offset.y);
         self.load_to(self.offset.y + self.size.h);
     }
 
@@ -321,6 +366,9 @@ impl Document {
     /// If the cursor is within the viewport, this will return where it is relatively
     #[must_use]
     pub fn cursor_loc_in_screen(&self) -> Option<Loc> {
+        if self.wrap.is_some() || !self.folds.is_empty() {
+            return self.cursor_row_in_screen();
+        }
         if self.cursor.loc.x < self.offset.x {
             return None;
         }
@@ -403,14 +451,45 @@ impl Document {
     /// Will return the text contained within the current selection
     #[must_use]
     pub fn selection_text(&self) -> String {
+        if self.lazy.is_some() {
+            // There is no rope to take the text from, so gather it line by line
+            let (left, right) = self.selection_loc_bound();
+            let mut text = String::new();
+            for y in left.y..=right.y {
+                let line = self.line(y).unwrap_or_default();
+                let start = if y == left.y { left.x } else { 0 };
+                let end = if y == right.y { right.x } else { usize::MAX };
+                text.extend(line.chars().take(end).skip(start));
+                if y != right.y {
+                    text.push('\n');
+                }
+            }
+            return text;
+        }
         self.file.slice(self.selection_range()).to_string()
     }
 
     /// Delete the currently selected text
     pub fn remove_selection(&mut self) {
-        self.file.remove(self.selection_range());
-        self.reload_lines();
-        let mut goto = self.selection_loc_bound().0;
+        let (left, mut right) = self.selection_loc_bound();
+        let mut goto = left;
+        self.load_to(right.y + 1);
+        // Keep within the lines of the document
+        if right.y >= self.len_lines() && right.y > left.y {
+            right.y = self.len_lines().saturating_sub(1);
+            right.x = self.line(right.y).unwrap_or_default().chars().count();
+        }
+        // Remove the selected text line by line
+        if left.y == right.y {
+            let _ = self.delete(left.x..=right.x, left.y);
+        } else {
+            let _ = self.delete(left.x.., left.y);
+            for _ in left.y + 1..right.y {
+                let _ = self.delete_line(left.y + 1);
+            }
+            let _ = self.delete(..=right.x, left.y + 1);
+            let _ = self.splice_up(left.y);
+        }
         goto.x = self.display_idx(&goto);
         self.cursor.loc = goto;
         self.char_ptr = self.character_idx(&self.cursor.loc);
@@ -428,6 +507,8 @@ impl Document {
         if let Some(idx) = self.has_cursor(loc) {
             self.secondary_cursors.remove(idx);
         } else if self.out_of_range(loc.x, loc.y).is_ok() {
+            // Cursors can't be hidden away in a fold
+            self.unfold(loc.y);
             self.secondary_cursors.push(loc);
         }
     }
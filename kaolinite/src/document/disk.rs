@@ -1,13 +1,18 @@
+use crate::document::encoding::{decode_file, Encoding};
+use crate::document::history::fnv_hash;
 use crate::document::Cursor;
 use crate::event::{Error, EventMgmt, Result};
 use crate::map::{form_map, CharMap};
 use crate::utils::get_absolute_path;
 use crate::{Document, Loc, Size};
-use ropey::Rope;
+use ropey::{Rope, RopeSlice};
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Read};
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 /// A document info struct to store information about the file it represents
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct DocumentInfo {
     /// Whether or not the document can be edited
@@ -16,6 +21,105 @@ pub struct DocumentInfo {
     pub eol: bool,
     /// Contains the number of lines buffered into the document
     pub loaded_to: usize,
+    /// The line ending used throughout the file
+    pub line_ending: LineEnding,
+    /// The character encoding of the file
+    pub encoding: Encoding,
+    /// Whether the file starts with a byte order mark
+    pub bom: bool,
+    /// Whether some of the file couldn't be decoded (and was replaced)
+    pub lossy: bool,
+}
+
+/// The characters used to mark the end of a line
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    /// Unix style (`\n`)
+    #[default]
+    Lf,
+    /// Windows style (`\r\n`)
+    CrLf,
+    /// Classic Mac OS style (`\r`)
+    Cr,
+}
+
+impl LineEnding {
+    /// Work out which line ending is used the most in a rope
+    #[must_use]
+    pub fn detect(rope: &Rope) -> Self {
+        let (mut lf, mut crlf, mut cr) = (0usize, 0usize, 0usize);
+        let mut last_cr = false;
+        for chunk in rope.chunks() {
+            for byte in chunk.bytes() {
+                match byte {
+                    b'\n' if last_cr => {
+                        crlf += 1;
+                        cr -= 1;
+                    }
+                    b'\n' => lf += 1,
+                    b'\r' => cr += 1,
+                    _ => (),
+                }
+                last_cr = byte == b'\r';
+            }
+        }
+        if crlf > lf && crlf >= cr {
+            Self::CrLf
+        } else if cr > lf && cr > crlf {
+            Self::Cr
+        } else {
+            Self::Lf
+        }
+    }
+
+    /// The characters that make up this line ending
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+}
+
+impl std::fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Lf => "LF",
+                Self::CrLf => "CRLF",
+                Self::Cr => "CR",
+            }
+        )
+    }
+}
+
+impl std::str::FromStr for LineEnding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "lf" | "unix" => Ok(Self::Lf),
+            "crlf" | "windows" | "dos" => Ok(Self::CrLf),
+            "cr" | "mac" => Ok(Self::Cr),
+            _ => Err(Error::UnknownLineEnding),
+        }
+    }
+}
+
+/// Determines what happens to the previous version of a file when it is overwritten
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+pub enum Backup {
+    /// Don't keep the previous version
+    #[default]
+    None,
+    /// Keep the previous version next to the file, with a `~` suffix (e.g. `main.rs~`)
+    Tilde,
+    /// Keep timestamped copies of previous versions within a directory
+    Directory(PathBuf),
 }
 
 impl Document {
@@ -37,10 +141,21 @@ impl Document {
             tab_width: 4,
             old_cursor: 0,
             in_redo: false,
+            backup: Backup::None,
+            lazy: None,
+            wrap: None,
+            folds: vec![],
+            edits: None,
+            marks: vec![],
+            version: 0,
             info: DocumentInfo {
                 loaded_to: 1,
                 eol: false,
                 read_only: false,
+                line_ending: LineEnding::default(),
+                encoding: Encoding::default(),
+                bom: false,
+                lossy: false,
             },
             secondary_cursors: vec![],
         }
@@ -56,7 +171,8 @@ impl Document {
         // Try to find the absolute path and load it into the reader
         let file_name = file_name.into();
         let full_path = std::fs::canonicalize(&file_name)?;
-        let file = load_rope_from_reader(BufReader::new(File::open(&full_path)?));
+        let (text, encoding, bom, lossy) = decode_file(&std::fs::read(&full_path)?);
+        let file = Rope::from_str(&text);
         // Find the string representation of the absolute path
         let file_name = get_absolute_path(&file_name);
         Ok(Self {
@@ -67,6 +183,10 @@ impl Document {
                     .to_string()
                     .is_empty(),
                 read_only: false,
+                line_ending: LineEnding::detect(&file),
+                encoding,
+                bom,
+                lossy,
             },
             file,
             lines: vec![],
@@ -81,6 +201,13 @@ impl Document {
             tab_width: 4,
             old_cursor: 0,
             in_redo: false,
+            backup: Backup::None,
+            lazy: None,
+            wrap: None,
+            folds: vec![],
+            edits: None,
+            marks: vec![],
+            version: 0,
             secondary_cursors: vec![],
         })
     }
@@ -90,12 +217,9 @@ impl Document {
     /// Returns an error if the file fails to write, due to permissions
     /// or character set issues.
     pub fn save(&mut self) -> Result<()> {
-        if self.info.read_only {
-            Err(Error::ReadOnlyFile)
-        } else if let Some(file_name) = &self.file_name {
-            self.file
-                .write_to(BufWriter::new(File::create(file_name)?))?;
-            self.event_mgmt.disk_write(&self.take_snapshot());
+        if let Some(file_name) = &self.file_name {
+            self.write_to_disk(Path::new(file_name))?;
+            self.event_mgmt.disk_write(self.cursor);
             Ok(())
         } else {
             Err(Error::NoFileName)
@@ -107,19 +231,66 @@ impl Document {
     /// Returns an error if the file fails to write, due to permissions
     /// or character set issues.
     pub fn save_as(&self, file_name: &str) -> Result<()> {
+        self.write_to_disk(Path::new(file_name))
+    }
+
+    /// Safely write the contents of this document to a path (see `write_atomically`),
+    /// refusing to if it can't be written without losing anything
+    fn write_to_disk(&self, path: &Path) -> Result<()> {
+        let encoding = self.info.encoding;
         if self.info.read_only {
-            Err(Error::ReadOnlyFile)
-        } else {
-            self.file
-                .write_to(BufWriter::new(File::create(file_name)?))?;
+            return Err(Error::ReadOnlyFile);
+        } else if self.info.lossy {
+            return Err(Error::LossyFile);
+        } else if !self.file.chunks().all(|chunk| encoding.can_encode(chunk)) {
+            return Err(Error::CannotEncode);
+        }
+        write_atomically(path, &self.backup, |writer| {
+            if self.info.bom {
+                writer.write_all(encoding.bom())?;
+            }
+            let mut buffer = vec![];
+            for chunk in with_line_ending(&self.file, self.info.line_ending) {
+                if encoding == Encoding::Utf8 {
+                    writer.write_all(chunk.as_bytes())?;
+                } else {
+                    buffer.clear();
+                    encoding.encode(chunk, &mut buffer);
+                    writer.write_all(&buffer)?;
+                }
+            }
             Ok(())
+        })
+    }
+
+    /// Convert every line in this document over to a different line ending.
+    /// The document will need saving for this to reach the disk.
+    /// Events can't describe this change, so the undo history starts again from here.
+    pub fn convert_line_ending(&mut self, ending: LineEnding) {
+        let converted: String = with_line_ending(&self.file, ending).collect();
+        if self.file != converted.as_str() {
+            self.file = Rope::from_str(&converted);
+            self.event_mgmt = EventMgmt::default();
+            self.event_mgmt.force_not_with_disk = true;
+            self.edits = None;
         }
+        self.info.line_ending = ending;
     }
 
     /// Load lines in this document up to a specified index.
     /// This must be called before starting to edit the document as
     /// this is the function that actually load and processes the text.
     pub fn load_to(&mut self, mut to: usize) {
+        // Lines hidden by folds don't take up any space
+        for fold in &self.folds {
+            if fold.start < to {
+                to += fold.len();
+            }
+        }
+        if self.lazy.is_some() {
+            self.load_lazy(to);
+            return;
+        }
         // Make sure to doesn't go over the number of lines in the buffer
         let len_lines = self.file.len_lines();
         if to >= len_lines {
@@ -144,6 +315,127 @@ impl Document {
     }
 }
 
+/// Find a temporary path to write to, alongside the target (so renaming is atomic)
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+/// Safely write to a path.
+/// The contents are written to a temporary file in the same directory, which is then
+/// synced and renamed over the target, so a crash or full disk never leaves a
+/// half written file behind. Permissions and ownership of the original are kept.
+pub(crate) fn write_atomically<F>(path: &Path, backup: &Backup, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+{
+    // Write through symbolic links, rather than replacing them
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = std::fs::metadata(&path).ok();
+    if original.is_some() {
+        backup_file(&path, backup)?;
+    }
+    // Write out to a temporary file
+    let temp = temp_path(&path);
+    let result = write_and_sync(&temp, original.as_ref(), write);
+    // Swap the temporary file in for the target
+    let result = result.and_then(|()| std::fs::rename(&temp, &path));
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&temp);
+        return Err(err.into());
+    }
+    // Make sure the rename itself persists
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+/// Keep a copy of the file at a path, according to the backup setting
+fn backup_file(path: &Path, backup: &Backup) -> Result<()> {
+    let Some(name) = path.file_name() else {
+        return Ok(());
+    };
+    let name = name.to_string_lossy();
+    let backup = match backup {
+        Backup::None => return Ok(()),
+        Backup::Tilde => path.with_file_name(format!("{name}~")),
+        Backup::Directory(dir) => {
+            std::fs::create_dir_all(dir)?;
+            // Files with the same name in different folders get their own backups
+            let full = get_absolute_path(&path.to_string_lossy()).unwrap_or_default();
+            let id = fnv_hash(full.as_bytes());
+            let time = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let stem = format!("{name}.{id:016x}.{time}");
+            // Saves made within the same millisecond are numbered
+            let mut backup = dir.join(format!("{stem}~"));
+            let mut count = 1;
+            while backup.exists() {
+                backup = dir.join(format!("{stem}.{count}~"));
+                count += 1;
+            }
+            backup
+        }
+    };
+    std::fs::copy(path, backup)?;
+    Ok(())
+}
+
+/// Write out to a new file and flush it all the way to the disk
+fn write_and_sync<F>(
+    path: &Path,
+    original: Option<&std::fs::Metadata>,
+    write: F,
+) -> std::io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+{
+    let file = File::create(path)?;
+    if let Some(original) = original {
+        file.set_permissions(original.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Only privileged users can give files away, so don't fail if this isn't possible
+            let _ = std::os::unix::fs::fchown(&file, Some(original.uid()), Some(original.gid()));
+        }
+    }
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+/// Go through the text in a rope, swapping out each line ending for a certain one
+fn with_line_ending(rope: &Rope, ending: LineEnding) -> impl Iterator<Item = &str> {
+    rope.lines().flat_map(move |line| {
+        let length = line.len_chars();
+        let content = length - line_ending_len(line);
+        line.slice(..content)
+            .chunks()
+            .chain((content < length).then_some(ending.as_str()))
+    })
+}
+
+/// Find the number of characters making up the line ending at the end of a line
+#[must_use]
+pub fn line_ending_len(line: RopeSlice) -> usize {
+    let length = line.len_chars();
+    let last = |n: usize| length.checked_sub(n).map(|i| line.char(i));
+    match (last(2), last(1)) {
+        (Some('\r'), Some('\n')) => 2,
+        (_, Some('\n' | '\r')) => 1,
+        _ => 0,
+    }
+}
+
 pub fn load_rope_from_reader<T: Read + BufRead>(mut reader: T) -> Rope {
     let mut buffer = [0u8; 2048]; // Buffer to read chunks
     let mut valid_string = String::new();
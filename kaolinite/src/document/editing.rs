@@ -1,3 +1,4 @@
+use crate::document::disk::line_ending_len;
 use crate::event::{Error, Event, Result};
 use crate::map::form_map;
 use crate::utils::{get_range, tab_boundaries_backward};
@@ -10,6 +11,11 @@ impl Document {
     /// Returns an error if location is out of range.
     pub fn insert(&mut self, loc: &Loc, st: &str) -> Result<()> {
         self.out_of_range(loc.x, loc.y)?;
+        if !st.is_empty() {
+            self.event_mgmt.record(Event::Insert(*loc, st.to_string()));
+            self.note_edit(Event::Insert(*loc, st.to_string()));
+        }
+        self.update_folds(loc.y, 0);
         // Move cursor to location
         self.move_to(loc);
         // Update rope
@@ -74,10 +80,16 @@ impl Document {
         // Extract range information
         let (mut start, mut end) = get_range(&x, line_start, line_end);
         self.valid_range(start, end, y)?;
+        self.update_folds(y, 0);
         self.move_to(&Loc::at(start, y));
         start += line_start;
         end += line_start;
         let removed = self.file.slice(start..end).to_string();
+        if !removed.is_empty() {
+            let loc = Loc::at(start - line_start, y);
+            self.event_mgmt.record(Event::Delete(loc, removed.clone()));
+            self.note_edit(Event::Delete(loc, removed.clone()));
+        }
         // Update unicode and tab map
         self.dbl_map.shift_deletion(
             &Loc::at(line_start, y),
@@ -107,6 +119,9 @@ impl Document {
         if !(self.lines.is_empty() || self.len_lines() == 0 && loc == 0) {
             self.out_of_range(0, loc.saturating_sub(1))?;
         }
+        self.event_mgmt
+            .record(Event::InsertLine(loc, contents.clone()));
+        self.note_edit(Event::InsertLine(loc, contents.clone()));
         // Update unicode and tab map
         self.dbl_map.shift_down(loc);
         self.tab_map.shift_down(loc);
@@ -115,11 +130,14 @@ impl Document {
         self.dbl_map.insert(loc, dbl_map);
         self.tab_map.insert(loc, tab_map);
         // Update cache
-        self.lines.insert(loc, contents.to_string());
+        self.lines
+            .insert(loc, contents.trim_end_matches(['\n', '\r']).to_string());
         // Update rope
         let char_idx = self.file.line_to_char(loc);
-        self.file.insert(char_idx, &(contents + "\n"));
+        self.file
+            .insert(char_idx, &(contents + self.info.line_ending.as_str()));
         self.info.loaded_to += 1;
+        self.update_folds(loc, 1);
         // Goto line
         self.move_to_y(loc);
         self.old_cursor = self.loc().x;
@@ -131,6 +149,15 @@ impl Document {
     /// Returns an error if location is out of range.
     pub fn delete_line(&mut self, loc: usize) -> Result<()> {
         self.out_of_range(0, loc)?;
+        // Register the exact contents being removed (so it can be restored)
+        let idx_start = self.file.line_to_char(loc);
+        let idx_end = self.file.line_to_char(loc + 1);
+        let line = self.file.slice(idx_start..idx_end);
+        let removed = line
+            .slice(..line.len_chars() - line_ending_len(line))
+            .to_string();
+        self.note_edit(Event::DeleteLine(loc, removed.clone()));
+        self.event_mgmt.record(Event::DeleteLine(loc, removed));
         // Update tab & unicode map
         self.dbl_map.delete(loc);
         self.tab_map.delete(loc);
@@ -140,10 +167,9 @@ impl Document {
         // Update cache
         self.lines.remove(loc);
         // Update rope
-        let idx_start = self.file.line_to_char(loc);
-        let idx_end = self.file.line_to_char(loc + 1);
         self.file.remove(idx_start..idx_end);
         self.info.loaded_to = self.info.loaded_to.saturating_sub(1);
+        self.update_folds(loc, -1);
         // Goto line
         self.move_to_y(loc);
         self.old_cursor = self.loc().x;
@@ -159,8 +185,10 @@ impl Document {
         // Gather context
         let line = self.line(loc.y).ok_or(Error::OutOfRange)?;
         let rhs: String = line.chars().skip(loc.x).collect();
+        let marks = self.shifted_marks(&Event::SplitDown(*loc));
         self.delete(loc.x.., loc.y)?;
         self.insert_line(loc.y + 1, rhs)?;
+        self.marks = marks;
         self.move_to(&Loc::at(0, loc.y + 1));
         self.old_cursor = self.loc().x;
         Ok(())
@@ -175,10 +203,50 @@ impl Document {
         // Gather context
         let length = self.line(y).ok_or(Error::OutOfRange)?.chars().count();
         let below = self.line(y + 1).ok_or(Error::OutOfRange)?;
+        let marks = self.shifted_marks(&Event::SpliceUp(Loc::at(length, y)));
         self.delete_line(y + 1)?;
         self.insert(&Loc::at(length, y), &below)?;
+        self.marks = marks;
         self.move_to(&Loc::at(length, y));
         self.old_cursor = self.loc().x;
         Ok(())
     }
+
+    /// Replace the text between two locations (by character index) with other text.
+    /// Both the text being replaced and the new text can span several lines.
+    /// # Errors
+    /// Returns an error if location is out of range.
+    pub fn replace_range(&mut self, start: Loc, mut end: Loc, text: &str) -> Result<()> {
+        self.load_to(end.y + 1);
+        // Keep within the lines of the document
+        if end.y >= self.len_lines() && end.y > start.y {
+            end.y = self.len_lines().saturating_sub(1);
+            end.x = self.line(end.y).unwrap_or_default().chars().count();
+        }
+        // Remove the old text
+        if start.y == end.y {
+            if end.x > start.x {
+                self.delete(start.x..=end.x, start.y)?;
+            }
+        } else {
+            self.delete(start.x.., start.y)?;
+            for _ in start.y + 1..end.y {
+                self.delete_line(start.y + 1)?;
+            }
+            self.delete(..=end.x, start.y + 1)?;
+            self.splice_up(start.y)?;
+        }
+        // Put in the new text, line by line
+        let mut loc = start;
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.split_down(&loc)?;
+                loc = Loc::at(0, loc.y + 1);
+            }
+            let part = part.strip_suffix('\r').unwrap_or(part);
+            self.insert(&loc, part)?;
+            loc.x += part.chars().count();
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,212 @@
+use crate::event::{Error, Result};
+
+/// Characters represented by the bytes 0x80 to 0x9F in Windows-1252
+/// (the rest of the bytes line up with their unicode code points)
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// The character encoding used to store a file on the disk
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+impl Encoding {
+    /// Work out which encoding some bytes are in.
+    /// Also returns whether the bytes start with a byte order mark.
+    #[must_use]
+    pub fn detect(bytes: &[u8]) -> (Self, bool) {
+        // Byte order marks give the encoding away
+        for encoding in [Self::Utf8, Self::Utf16Le, Self::Utf16Be] {
+            if bytes.starts_with(encoding.bom()) {
+                return (encoding, true);
+            }
+        }
+        // Mostly ASCII text in UTF-16 will have every other byte empty
+        let sample = &bytes[..bytes.len().min(4096)];
+        let pairs = sample.len() / 2;
+        let zeros_at = |offset: usize| {
+            sample
+                .chunks_exact(2)
+                .filter(|pair| pair[offset] == 0)
+                .count()
+        };
+        let (even, odd) = (zeros_at(0), zeros_at(1));
+        if pairs > 0 && odd * 10 > pairs * 4 && even * 10 < pairs {
+            return (Self::Utf16Le, false);
+        }
+        if pairs > 0 && even * 10 > pairs * 4 && odd * 10 < pairs {
+            return (Self::Utf16Be, false);
+        }
+        // Fall back to a single byte encoding if this isn't valid UTF-8
+        if std::str::from_utf8(bytes).is_ok() {
+            (Self::Utf8, false)
+        } else if bytes.iter().any(|b| (0x80..0xA0).contains(b)) {
+            (Self::Windows1252, false)
+        } else {
+            (Self::Latin1, false)
+        }
+    }
+
+    /// The byte order mark for this encoding
+    #[must_use]
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8 => &[0xEF, 0xBB, 0xBF],
+            Self::Utf16Le => &[0xFF, 0xFE],
+            Self::Utf16Be => &[0xFE, 0xFF],
+            Self::Latin1 | Self::Windows1252 => &[],
+        }
+    }
+
+    /// Convert bytes (without a byte order mark) in this encoding into text.
+    /// Also returns whether any bytes were invalid and had to be replaced.
+    #[must_use]
+    pub fn decode(self, bytes: &[u8]) -> (String, bool) {
+        match self {
+            Self::Utf8 => {
+                let text = String::from_utf8_lossy(bytes);
+                let lossy = matches!(text, std::borrow::Cow::Owned(_));
+                (text.to_string(), lossy)
+            }
+            Self::Utf16Le | Self::Utf16Be => {
+                let units = bytes.chunks_exact(2).map(|pair| {
+                    let pair = [pair[0], pair[1]];
+                    if self == Self::Utf16Le {
+                        u16::from_le_bytes(pair)
+                    } else {
+                        u16::from_be_bytes(pair)
+                    }
+                });
+                let mut lossy = !bytes.chunks_exact(2).remainder().is_empty();
+                let text = char::decode_utf16(units)
+                    .map(|c| {
+                        c.unwrap_or_else(|_| {
+                            lossy = true;
+                            char::REPLACEMENT_CHARACTER
+                        })
+                    })
+                    .collect();
+                (text, lossy)
+            }
+            Self::Latin1 => (bytes.iter().map(|b| char::from(*b)).collect(), false),
+            Self::Windows1252 => {
+                let text = bytes
+                    .iter()
+                    .map(|b| match b {
+                        0x80..0xA0 => WINDOWS_1252[usize::from(b - 0x80)],
+                        _ => char::from(*b),
+                    })
+                    .collect();
+                (text, false)
+            }
+        }
+    }
+
+    /// Find out if some text can be represented in this encoding
+    #[must_use]
+    pub fn can_encode(self, text: &str) -> bool {
+        text.chars().all(|c| self.encode_char(c).is_some())
+    }
+
+    /// Convert text into bytes in this encoding.
+    /// Characters that can't be represented are replaced with a question mark.
+    pub fn encode(self, text: &str, out: &mut Vec<u8>) {
+        match self {
+            Self::Utf8 => out.extend_from_slice(text.as_bytes()),
+            Self::Utf16Le => text
+                .encode_utf16()
+                .for_each(|u| out.extend_from_slice(&u.to_le_bytes())),
+            Self::Utf16Be => text
+                .encode_utf16()
+                .for_each(|u| out.extend_from_slice(&u.to_be_bytes())),
+            Self::Latin1 | Self::Windows1252 => {
+                out.extend(text.chars().map(|c| self.encode_char(c).unwrap_or(b'?')));
+            }
+        }
+    }
+
+    /// Convert a character into a single byte (for single byte encodings)
+    fn encode_char(self, c: char) -> Option<u8> {
+        match self {
+            Self::Utf8 | Self::Utf16Le | Self::Utf16Be => Some(0),
+            Self::Latin1 => u8::try_from(u32::from(c)).ok(),
+            Self::Windows1252 => {
+                if let Some(idx) = WINDOWS_1252.iter().position(|w| *w == c) {
+                    u8::try_from(idx + 0x80).ok()
+                } else {
+                    u8::try_from(u32::from(c))
+                        .ok()
+                        .filter(|b| !(0x80..0xA0).contains(b))
+                }
+            }
+        }
+    }
+}
+
+/// Determine if the contents of a file look like binary data rather than text
+#[must_use]
+pub fn is_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(8192)];
+    // Empty bytes are normal in UTF-16, but rarely found in any other kind of text
+    let (encoding, _) = Encoding::detect(sample);
+    if matches!(encoding, Encoding::Utf16Le | Encoding::Utf16Be) {
+        return false;
+    }
+    let control = sample
+        .iter()
+        .filter(|b| b.is_ascii_control() && !b"\t\n\r\x0c\x1b".contains(b))
+        .count();
+    sample.contains(&0) || control * 10 > sample.len()
+}
+
+/// Work out the encoding of the contents of a file and decode it.
+/// Returns the text, encoding, whether there was a byte order mark,
+/// and whether any bytes were invalid and had to be replaced.
+#[must_use]
+pub fn decode_file(bytes: &[u8]) -> (String, Encoding, bool, bool) {
+    let (encoding, bom) = Encoding::detect(bytes);
+    let start = if bom { encoding.bom().len() } else { 0 };
+    let (text, lossy) = encoding.decode(&bytes[start..]);
+    (text, encoding, bom, lossy)
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Utf8 => "UTF-8",
+                Self::Utf16Le => "UTF-16LE",
+                Self::Utf16Be => "UTF-16BE",
+                Self::Latin1 => "ISO-8859-1",
+                Self::Windows1252 => "Windows-1252",
+            }
+        )
+    }
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "utf-16le" | "utf16le" | "utf-16" | "utf16" => Ok(Self::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Self::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Self::Latin1),
+            "windows-1252" | "cp1252" => Ok(Self::Windows1252),
+            _ => Err(Error::UnknownEncoding),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+/// folding.rs - collapsing regions of a document down to a single line
+use crate::utils::width;
+use crate::{Document, Loc};
+
+/// A folded region of a document.
+/// The first line stays visible (as a summary of the region) while the rest are hidden.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fold {
+    /// The line that stays visible
+    pub start: usize,
+    /// The last line that is hidden
+    pub end: usize,
+}
+
+impl Fold {
+    /// Determine if a line is hidden by this fold
+    #[must_use]
+    pub fn hides(&self, y: usize) -> bool {
+        self.start < y && y <= self.end
+    }
+
+    /// The number of lines hidden by this fold
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Determine if this fold hides no lines
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+}
+
+/// Work out how far a line is indented (None for blank lines)
+#[must_use]
+pub fn indent_level(line: &str, tab_width: usize) -> Option<usize> {
+    let content = line.trim_start();
+    if content.is_empty() {
+        None
+    } else {
+        Some(width(&line[..line.len() - content.len()], tab_width))
+    }
+}
+
+/// Find the bracket that is left open at the end of a line (if any)
+fn open_bracket(line: &str) -> Option<char> {
+    let mut stack = vec![];
+    for c in line.chars() {
+        match c {
+            '{' | '[' | '(' => stack.push(c),
+            '}' | ']' | ')' => {
+                stack.pop();
+            }
+            _ => (),
+        }
+    }
+    stack.pop()
+}
+
+impl Document {
+    /// Work out the region that can be folded at a line, returning the last line it would hide.
+    /// Regions are found from indentation, or from matching brackets when `brackets` is set and
+    /// the line leaves a bracket open.
+    #[must_use]
+    pub fn fold_region(&self, y: usize, brackets: bool) -> Option<usize> {
+        let line = self.line(y)?;
+        let end = match open_bracket(&line).filter(|_| brackets) {
+            Some(open) => self.bracket_region(y, open),
+            None => self.indent_region(y, &line),
+        };
+        end.filter(|end| *end > y)
+    }
+
+    /// Find the last line indented further than a line
+    fn indent_region(&self, y: usize, line: &str) -> Option<usize> {
+        let base = indent_level(line, self.tab_width)?;
+        let mut end = None;
+        let mut at = y + 1;
+        while let Some(line) = self.line(at) {
+            match indent_level(&line, self.tab_width) {
+                // Blank lines are only included if the region carries on after them
+                None => (),
+                Some(level) if level > base => end = Some(at),
+                Some(_) => break,
+            }
+            at += 1;
+        }
+        end
+    }
+
+    /// Find the line before the one that closes a bracket left open on a line
+    fn bracket_region(&self, y: usize, open: char) -> Option<usize> {
+        let close = match open {
+            '{' => '}',
+            '[' => ']',
+            _ => ')',
+        };
+        let mut depth = 1;
+        let mut at = y + 1;
+        while let Some(line) = self.line(at) {
+            for c in line.chars() {
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(at - 1);
+                    }
+                }
+            }
+            at += 1;
+        }
+        None
+    }
+
+    /// Get the fold that a line is the summary of (if folded)
+    #[must_use]
+    pub fn fold_at(&self, y: usize) -> Option<Fold> {
+        self.folds.iter().find(|fold| fold.start == y).copied()
+    }
+
+    /// Get the fold that hides a line (if hidden)
+    #[must_use]
+    pub fn hidden_by(&self, y: usize) -> Option<Fold> {
+        self.folds.iter().find(|fold| fold.hides(y)).copied()
+    }
+
+    /// Find the next line that isn't hidden by a fold
+    #[must_use]
+    pub fn next_visible(&self, y: usize) -> usize {
+        self.fold_at(y).map_or(y, |fold| fold.end) + 1
+    }
+
+    /// Find the previous line that isn't hidden by a fold
+    #[must_use]
+    pub fn prev_visible(&self, y: usize) -> usize {
+        let y = y.saturating_sub(1);
+        self.hidden_by(y).map_or(y, |fold| fold.start)
+    }
+
+    /// Fold the region at a line, or the closest region surrounding it.
+    /// Returns true if anything was folded.
+    pub fn fold(&mut self, y: usize, brackets: bool) -> bool {
+        let region = self
+            .fold_region(y, brackets)
+            .filter(|_| self.fold_at(y).is_none())
+            .map(|end| Fold { start: y, end })
+            .or_else(|| {
+                (0..y).rev().find_map(|start| {
+                    let end = self.fold_region(start, brackets)?;
+                    (end >= y && self.hidden_by(start).is_none()).then_some(Fold { start, end })
+                })
+            });
+        let Some(fold) = region else {
+            return false;
+        };
+        // Folds within this one are absorbed into it
+        self.folds
+            .retain(|f| f.start < fold.start || f.start > fold.end);
+        let idx = self.folds.partition_point(|f| f.start < fold.start);
+        self.folds.insert(idx, fold);
+        // Move the cursor out of the hidden lines
+        if fold.hides(self.loc().y) {
+            self.move_to_y(fold.start);
+        }
+        self.bring_cursor_in_viewport();
+        true
+    }
+
+    /// Open the fold that a line is the summary of, or is hidden by.
+    /// Returns true if anything was unfolded.
+    pub fn unfold(&mut self, y: usize) -> bool {
+        let before = self.folds.len();
+        self.folds.retain(|fold| fold.start != y && !fold.hides(y));
+        before != self.folds.len()
+    }
+
+    /// Fold or unfold the region the cursor is in
+    pub fn toggle_fold(&mut self, brackets: bool) {
+        let y = self.loc().y;
+        if !self.unfold(y) {
+            self.fold(y, brackets);
+        }
+    }
+
+    /// Fold every outermost region in the document
+    pub fn fold_all(&mut self, brackets: bool) {
+        self.load_to(self.len_lines());
+        self.folds.clear();
+        let mut y = 0;
+        while y < self.len_lines() {
+            if let Some(end) = self.fold_region(y, brackets) {
+                self.folds.push(Fold { start: y, end });
+                y = end;
+            }
+            y += 1;
+        }
+        if let Some(fold) = self.hidden_by(self.loc().y) {
+            self.move_to_y(fold.start);
+        }
+        self.bring_cursor_in_viewport();
+    }
+
+    /// Open every fold in the document
+    pub fn unfold_all(&mut self) {
+        self.folds.clear();
+        self.bring_cursor_in_viewport();
+    }
+
+    /// Keep folds in place when a line is edited (`delta` is the number of lines added).
+    /// Folds hiding the edited line are opened, as are folds whose summary line is removed.
+    pub(crate) fn update_folds(&mut self, y: usize, delta: isize) {
+        if self.folds.is_empty() {
+            return;
+        }
+        self.folds
+            .retain(|fold| !(fold.hides(y) || (delta < 0 && fold.start == y)));
+        for fold in &mut self.folds {
+            if fold.start > y || (delta > 0 && fold.start == y) {
+                fold.start = fold.start.saturating_add_signed(delta);
+                fold.end = fold.end.saturating_add_signed(delta);
+            }
+        }
+    }
+
+    /// Move the cursor out of any hidden lines (onto the summary line of the fold)
+    pub(crate) fn fix_hidden_cursor(&mut self) {
+        if let Some(fold) = self.hidden_by(self.loc().y) {
+            self.cursor.loc = Loc::at(0, fold.start);
+            self.char_ptr = 0;
+        }
+    }
+}
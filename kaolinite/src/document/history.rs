@@ -0,0 +1,286 @@
+use crate::document::disk::line_ending_len;
+use crate::document::Cursor;
+use crate::event::{Event, EventMgmt, Result, UndoNode};
+use crate::{Document, Loc};
+use ropey::Rope;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+impl Document {
+    /// Work out where the undo history of this document is kept within a store directory.
+    /// Returns None if the document doesn't have a file name.
+    #[must_use]
+    pub fn history_path(&self, store: &Path) -> Option<PathBuf> {
+        let file_name = self.file_name.as_ref()?;
+        let id = fnv_hash(file_name.as_bytes());
+        Some(store.join(format!("{id:016x}.undo")))
+    }
+
+    /// Write the undo / redo history of this document into a store directory,
+    /// so that it can be restored when the file is opened again.
+    /// # Errors
+    /// Returns an error if the store directory or history file couldn't be written to.
+    pub fn save_history(&self, store: &Path) -> Result<()> {
+        let Some(path) = self.history_path(store) else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(store)?;
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&self.event_mgmt.serialize(&self.disk_content()))?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Restore the undo / redo history of this document from a store directory.
+    /// History is only restored if the file is unchanged since the history was written.
+    /// Returns true if the history was restored.
+    pub fn load_history(&mut self, store: &Path) -> bool {
+        let (Some(path), None) = (self.history_path(store), &self.lazy) else {
+            return false;
+        };
+        let Ok(data) = std::fs::read(path) else {
+            return false;
+        };
+        if let Some(event_mgmt) = EventMgmt::deserialize(&data, &self.file) {
+            self.event_mgmt = event_mgmt;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Find what the contents of the file on the disk should be
+    fn disk_content(&self) -> Rope {
+        let mgmt = &self.event_mgmt;
+        // Undo any changes that haven't been committed yet
+        let mut events: Vec<Event> = mgmt
+            .pending
+            .iter()
+            .rev()
+            .map(|ev| ev.clone().reverse())
+            .collect();
+        // Move from the current state to the state on the disk
+        if let (Some(ptr), false) = (mgmt.ptr, mgmt.history.is_empty()) {
+            events.extend(mgmt.path(ptr, mgmt.on_disk.unwrap_or(0)));
+        }
+        let mut rope = self.file.clone();
+        let ending = self.info.line_ending.as_str();
+        for ev in &events {
+            apply_to_rope(&mut rope, ev, ending);
+        }
+        rope
+    }
+}
+
+impl EventMgmt {
+    /// Convert the undo tree into bytes, alongside the contents of the file on the disk.
+    #[must_use]
+    pub fn serialize(&self, on_disk: &Rope) -> Vec<u8> {
+        let opt = |o: Option<usize>| o.map_or("-".to_string(), |n| n.to_string());
+        let mut result = format!(
+            "{:016x}\n{}\n{}\n{}\n",
+            content_hash(on_disk),
+            opt(self.ptr),
+            opt(self.on_disk),
+            self.history.len(),
+        )
+        .into_bytes();
+        for node in &self.history {
+            let Cursor { loc, selection_end } = node.cursor;
+            let time = node
+                .time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let header = format!(
+                "{} {} {} {} {} {} {} {}\n",
+                loc.x,
+                loc.y,
+                selection_end.x,
+                selection_end.y,
+                opt(node.parent),
+                opt(node.next),
+                time,
+                node.patch.len()
+            );
+            result.extend_from_slice(header.as_bytes());
+            for ev in &node.patch {
+                let (kind, loc, text) = match ev {
+                    Event::Insert(loc, st) => ("insert", *loc, st.as_str()),
+                    Event::Delete(loc, st) => ("delete", *loc, st.as_str()),
+                    Event::InsertLine(y, st) => ("insert_line", Loc::at(0, *y), st.as_str()),
+                    Event::DeleteLine(y, st) => ("delete_line", Loc::at(0, *y), st.as_str()),
+                    Event::SplitDown(loc) => ("split_down", *loc, ""),
+                    Event::SpliceUp(loc) => ("splice_up", *loc, ""),
+                };
+                let header = format!("{kind} {} {} {}\n", loc.x, loc.y, text.len());
+                result.extend_from_slice(header.as_bytes());
+                result.extend_from_slice(text.as_bytes());
+            }
+        }
+        result
+    }
+
+    /// Reconstruct an undo tree from bytes, given the current contents of the file.
+    /// Returns None if the data is malformed or the file has changed since.
+    #[must_use]
+    pub fn deserialize(data: &[u8], file: &Rope) -> Option<Self> {
+        let mut reader = HistoryReader { data, at: 0 };
+        let hash = u64::from_str_radix(&reader.line()?, 16).ok()?;
+        if hash != content_hash(file) {
+            return None;
+        }
+        let opt = |s: String| s.parse::<usize>().ok();
+        let ptr = opt(reader.line()?);
+        let on_disk = opt(reader.line()?);
+        let len = reader.line()?.parse::<usize>().ok()?;
+        let mut history = vec![];
+        for _ in 0..len {
+            let header = reader.line()?;
+            let parts: Vec<Option<usize>> = header.split(' ').map(|n| n.parse().ok()).collect();
+            let [Some(lx), Some(ly), Some(sx), Some(sy), parent, next, Some(time), Some(events)] =
+                parts[..]
+            else {
+                return None;
+            };
+            let mut patch = vec![];
+            for _ in 0..events {
+                patch.push(reader.event()?);
+            }
+            history.push(UndoNode {
+                patch,
+                cursor: Cursor {
+                    loc: Loc::at(lx, ly),
+                    selection_end: Loc::at(sx, sy),
+                },
+                parent,
+                next,
+                time: SystemTime::UNIX_EPOCH + Duration::from_millis(time as u64),
+            });
+        }
+        // Validate pointers
+        let links = history.iter().flat_map(|n| [n.parent, n.next]);
+        if [ptr, on_disk]
+            .into_iter()
+            .chain(links)
+            .flatten()
+            .any(|p| p >= history.len())
+        {
+            return None;
+        }
+        // Parents must come before their children (and only the root has no parent)
+        // and redo states must be children, otherwise walking the tree won't end
+        let is_tree = history.iter().enumerate().all(|(idx, node)| {
+            let parent_ok = match node.parent {
+                Some(parent) => parent < idx,
+                None => idx == 0,
+            };
+            let next_ok = node
+                .next
+                .is_none_or(|next| history[next].parent == Some(idx));
+            parent_ok && next_ok
+        });
+        if !is_tree {
+            return None;
+        }
+        // The document will be in the state on the disk, point there
+        let ptr = on_disk.or((!history.is_empty()).then_some(0));
+        Some(Self {
+            history,
+            ptr,
+            on_disk,
+            ..Self::default()
+        })
+    }
+}
+
+/// Helper for reading the history format
+struct HistoryReader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> HistoryReader<'a> {
+    /// Read up until the next new line
+    fn line(&mut self) -> Option<String> {
+        let rest = self.data.get(self.at..)?;
+        let end = rest.iter().position(|b| *b == b'\n')?;
+        self.at += end + 1;
+        String::from_utf8(rest[..end].to_vec()).ok()
+    }
+
+    /// Read an event
+    fn event(&mut self) -> Option<Event> {
+        let header = self.line()?;
+        let mut parts = header.split(' ');
+        let kind = parts.next()?;
+        let nums: Vec<usize> = parts.filter_map(|n| n.parse().ok()).collect();
+        let [x, y, bytes] = nums[..] else {
+            return None;
+        };
+        let text = String::from_utf8(self.take(bytes)?.to_vec()).ok()?;
+        let loc = Loc::at(x, y);
+        Some(match kind {
+            "insert" => Event::Insert(loc, text),
+            "delete" => Event::Delete(loc, text),
+            "insert_line" => Event::InsertLine(y, text),
+            "delete_line" => Event::DeleteLine(y, text),
+            "split_down" => Event::SplitDown(loc),
+            "splice_up" => Event::SpliceUp(loc),
+            _ => return None,
+        })
+    }
+
+    /// Read a certain number of bytes
+    fn take(&mut self, bytes: usize) -> Option<&'a [u8]> {
+        let result = self.data.get(self.at..self.at + bytes)?;
+        self.at += bytes;
+        Some(result)
+    }
+}
+
+/// Work out a hash of the contents of a rope (stable between sessions)
+#[must_use]
+pub fn content_hash(rope: &Rope) -> u64 {
+    rope.chunks().fold(FNV_OFFSET, |hash, chunk| {
+        fnv_continue(hash, chunk.as_bytes())
+    })
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a hash of some bytes (stable between sessions)
+#[must_use]
+pub fn fnv_hash(bytes: &[u8]) -> u64 {
+    fnv_continue(FNV_OFFSET, bytes)
+}
+
+/// Continue an FNV-1a hash with more bytes
+fn fnv_continue(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ u64::from(*b)).wrapping_mul(FNV_PRIME))
+}
+
+/// Apply an event directly to a rope (using a certain line ending for new lines)
+fn apply_to_rope(rope: &mut Rope, ev: &Event, ending: &str) -> Option<()> {
+    let start = rope.try_line_to_char(ev.loc().y).ok()? + ev.loc().x;
+    match ev {
+        Event::Insert(_, st) => rope.try_insert(start, st).ok(),
+        Event::Delete(_, st) => rope.try_remove(start..start + st.chars().count()).ok(),
+        Event::InsertLine(_, st) => rope.try_insert(start, &format!("{st}{ending}")).ok(),
+        Event::DeleteLine(y, _) => {
+            let end = rope.try_line_to_char(y + 1).ok()?;
+            rope.try_remove(start..end).ok()
+        }
+        Event::SplitDown(_) => rope.try_insert(start, ending).ok(),
+        Event::SpliceUp(loc) => {
+            let end = rope.try_line_to_char(loc.y + 1).ok()?;
+            let line_ending = line_ending_len(rope.line(loc.y));
+            rope.try_remove(end.saturating_sub(line_ending)..end).ok()
+        }
+    }
+}
@@ -0,0 +1,279 @@
+/// lazy.rs - read only access to huge files without reading them into memory
+use crate::event::Result;
+use crate::map::{form_map, CharMap};
+use crate::utils::get_absolute_path;
+use crate::{Document, Size};
+use ropey::Rope;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// The number of lines between each checkpoint in the line index
+const INDEX_STRIDE: usize = 512;
+/// The size of each block read when building the line index
+const SCAN_BLOCK: usize = 1 << 20;
+/// The number of lines to read either side of the viewport
+const WINDOW_MARGIN: usize = 256;
+
+/// An index of where lines start within a file, built up in the background
+#[derive(Default, Debug)]
+struct LineIndex {
+    /// The byte offset of every `INDEX_STRIDE`th line
+    checkpoints: Vec<u64>,
+    /// The number of line breaks found so far
+    breaks: usize,
+    /// Whether the file has a final line without a line break
+    partial: bool,
+    /// Whether the whole file has been scanned
+    done: bool,
+}
+
+/// A file that is read on demand, rather than held in memory.
+/// Only the lines surrounding the viewport are kept in memory, while the line index is built
+/// in a background thread, meaning even multi-gigabyte files open immediately.
+/// Lazy files are read only, and are always decoded as UTF-8.
+#[derive(Clone, Debug)]
+pub struct LazyFile {
+    /// The path of the file being read
+    path: PathBuf,
+    /// The handle used to read the file
+    file: Arc<File>,
+    /// The line index (shared with the thread building it)
+    index: Arc<Mutex<LineIndex>>,
+    /// The line number of the first line in the window
+    start: usize,
+    /// The lines currently held in memory
+    window: Vec<String>,
+    /// The block of lines (starting at a checkpoint) last read from outside the window,
+    /// so that going through lines one by one doesn't read from the checkpoint every time
+    block: RefCell<(usize, Vec<String>)>,
+}
+
+impl PartialEq for LazyFile {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.index, &other.index)
+    }
+}
+
+impl Eq for LazyFile {}
+
+impl LazyFile {
+    /// Start reading a file lazily, building the line index in the background.
+    /// # Errors
+    /// Returns an error if the file couldn't be opened.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = Arc::new(File::open(path)?);
+        let index = Arc::new(Mutex::new(LineIndex {
+            checkpoints: vec![0],
+            ..LineIndex::default()
+        }));
+        let scanner = File::open(path)?;
+        let shared = Arc::clone(&index);
+        std::thread::spawn(move || build_index(scanner, &shared));
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            index,
+            start: 0,
+            window: vec![],
+            block: RefCell::default(),
+        })
+    }
+
+    /// The path of the file being read
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of lines found so far (the final count once indexing is complete)
+    #[must_use]
+    pub fn len_lines(&self) -> usize {
+        let index = self.index();
+        index.breaks + usize::from(index.partial)
+    }
+
+    /// Determine if the whole file has been indexed
+    #[must_use]
+    pub fn indexed(&self) -> bool {
+        self.index().done
+    }
+
+    /// Get access to the line index
+    fn index(&self) -> MutexGuard<'_, LineIndex> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Get a line, from the window if possible, otherwise from the disk
+    /// (along with the rest of the lines up to the next checkpoint)
+    #[must_use]
+    pub fn line(&self, y: usize) -> Option<String> {
+        if let Some(line) = y.checked_sub(self.start).and_then(|i| self.window.get(i)) {
+            return Some(line.clone());
+        }
+        let start = y - y % INDEX_STRIDE;
+        let mut block = self.block.borrow_mut();
+        if block.0 != start || block.1.len() <= y - start {
+            *block = (start, self.read_lines(start, INDEX_STRIDE));
+        }
+        block.1.get(y - start).cloned()
+    }
+
+    /// Determine if a range of lines is held in memory
+    #[must_use]
+    pub fn in_window(&self, start: usize, end: usize) -> bool {
+        let available = self.len_lines().min(end);
+        start >= self.start && available <= self.start + self.window.len()
+    }
+
+    /// Read in the lines surrounding a range of lines, replacing the current window.
+    /// Returns the line number of the first line read in.
+    /// Lines already in the window are kept, so only the lines that are new are read.
+    pub fn load_window(&mut self, start: usize, end: usize) -> usize {
+        let first = start.saturating_sub(WINDOW_MARGIN);
+        let count = end.saturating_sub(first) + WINDOW_MARGIN;
+        let old_end = self.start + self.window.len();
+        if (self.start..old_end).contains(&first) {
+            // Moving down, read in the lines after the window
+            self.window.drain(..first - self.start);
+            self.window.truncate(count);
+            let more = self.read_lines(old_end, count - self.window.len());
+            self.window.extend(more);
+        } else if first < self.start && first + count > self.start {
+            // Moving up, read in the lines before the window
+            let mut lines = self.read_lines(first, self.start - first);
+            if lines.len() == self.start - first {
+                self.window.truncate(count - lines.len());
+                lines.append(&mut self.window);
+                self.window = lines;
+            } else {
+                self.window = self.read_lines(first, count);
+            }
+        } else {
+            self.window = self.read_lines(first, count);
+        }
+        self.start = first;
+        first
+    }
+
+    /// The lines currently held in memory
+    #[must_use]
+    pub fn window(&self) -> &[String] {
+        &self.window
+    }
+
+    /// Read a number of lines from the disk, starting at a certain line.
+    /// Only lines that have been indexed can be read.
+    fn read_lines(&self, start: usize, count: usize) -> Vec<String> {
+        let (offset, available) = {
+            let index = self.index();
+            let Some(offset) = index.checkpoints.get(start / INDEX_STRIDE) else {
+                return vec![];
+            };
+            (*offset, index.breaks + usize::from(index.partial))
+        };
+        let count = count.min(available.saturating_sub(start));
+        let mut reader = BufReader::new(&*self.file);
+        if count == 0 || reader.seek(SeekFrom::Start(offset)).is_err() {
+            return vec![];
+        }
+        let mut buffer = vec![];
+        // Skip over the lines between the checkpoint and the start
+        for _ in 0..start % INDEX_STRIDE {
+            buffer.clear();
+            if !matches!(reader.read_until(b'\n', &mut buffer), Ok(n) if n > 0) {
+                return vec![];
+            }
+        }
+        let mut result = Vec::with_capacity(count);
+        for _ in 0..count {
+            buffer.clear();
+            if !matches!(reader.read_until(b'\n', &mut buffer), Ok(n) if n > 0) {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer);
+            result.push(
+                line.trim_end_matches('\n')
+                    .trim_end_matches('\r')
+                    .to_string(),
+            );
+        }
+        result
+    }
+}
+
+/// Scan through a file, recording where lines start into the index
+fn build_index(mut file: File, index: &Arc<Mutex<LineIndex>>) {
+    let mut buffer = vec![0; SCAN_BLOCK];
+    let mut offset = 0u64;
+    let mut breaks = 0usize;
+    let mut last = None;
+    loop {
+        // Stop early if the file was closed
+        if Arc::strong_count(index) == 1 {
+            return;
+        }
+        let read = match file.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        let mut checkpoints = vec![];
+        for (i, byte) in buffer[..read].iter().enumerate() {
+            if *byte == b'\n' {
+                breaks += 1;
+                if breaks.is_multiple_of(INDEX_STRIDE) {
+                    checkpoints.push(offset + i as u64 + 1);
+                }
+            }
+        }
+        last = buffer.get(read - 1).copied();
+        offset += read as u64;
+        let mut index = index.lock().unwrap_or_else(PoisonError::into_inner);
+        index.checkpoints.extend(checkpoints);
+        index.breaks = breaks;
+    }
+    let mut index = index.lock().unwrap_or_else(PoisonError::into_inner);
+    index.partial = last.is_some_and(|b| b != b'\n');
+    index.done = true;
+}
+
+impl Document {
+    /// Open a document lazily, for files that are too large to read into memory.
+    /// Only the lines around the viewport are read from the disk, and the document is read only.
+    /// # Errors
+    /// Returns an error when file doesn't exist, or has incorrect permissions.
+    pub fn open_lazy<S: Into<String>>(size: Size, file_name: S) -> Result<Self> {
+        let file_name = file_name.into();
+        let lazy = LazyFile::open(&std::fs::canonicalize(&file_name)?)?;
+        let mut doc = Self::new(size);
+        doc.file = Rope::new();
+        doc.lines.clear();
+        doc.info.loaded_to = 0;
+        doc.info.read_only = true;
+        doc.file_name = get_absolute_path(&file_name);
+        doc.lazy = Some(lazy);
+        Ok(doc)
+    }
+
+    /// Make sure the lines up to a certain point (and the viewport) are held in memory
+    pub(crate) fn load_lazy(&mut self, to: usize) {
+        let Some(lazy) = &mut self.lazy else {
+            return;
+        };
+        let start = to.saturating_sub(self.size.h);
+        if lazy.in_window(start, to) {
+            return;
+        }
+        let first = lazy.load_window(start, to);
+        // Only keep track of characters within the window
+        self.dbl_map = CharMap::default();
+        self.tab_map = CharMap::default();
+        for (i, line) in lazy.window().iter().enumerate() {
+            let (dbl_map, tab_map) = form_map(line, self.tab_width);
+            self.dbl_map.insert(first + i, dbl_map);
+            self.tab_map.insert(first + i, tab_map);
+        }
+    }
+}
@@ -6,6 +6,9 @@ impl Document {
     /// Get the line at a specified index
     #[must_use]
     pub fn line(&self, line: usize) -> Option<String> {
+        if let Some(lazy) = &self.lazy {
+            return lazy.line(line);
+        }
         Some(self.lines.get(line)?.to_string())
     }
 
@@ -19,6 +22,9 @@ impl Document {
     /// Returns the number of lines in the document
     #[must_use]
     pub fn len_lines(&self) -> usize {
+        if let Some(lazy) = &self.lazy {
+            return lazy.len_lines();
+        }
         self.file.len_lines().saturating_sub(1) + usize::from(self.info.eol)
     }
 
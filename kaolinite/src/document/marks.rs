@@ -0,0 +1,86 @@
+/// marks.rs - locations in a document that move along with the text around them
+use crate::event::Event;
+use crate::{Document, Loc};
+
+/// A location in a document that stays with the text around it as edits are made
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Mark {
+    /// Where the mark is (by character index)
+    pub loc: Loc,
+    /// Whether text inserted right at the mark goes before it (pushing the mark along)
+    pub push: bool,
+}
+
+impl Mark {
+    /// Move the mark to account for an edit
+    pub fn shift(&mut self, ev: &Event) {
+        let loc = &mut self.loc;
+        match ev {
+            Event::Insert(at, text) => {
+                if loc.y == at.y && (loc.x > at.x || (loc.x == at.x && self.push)) {
+                    loc.x += text.chars().count();
+                }
+            }
+            Event::Delete(at, text) => {
+                let end = at.x + text.chars().count();
+                if loc.y == at.y && loc.x >= end {
+                    loc.x -= end - at.x;
+                } else if loc.y == at.y && loc.x > at.x {
+                    loc.x = at.x;
+                }
+            }
+            Event::InsertLine(y, _) => {
+                if loc.y >= *y {
+                    loc.y += 1;
+                }
+            }
+            Event::DeleteLine(y, _) => {
+                if loc.y == *y {
+                    loc.x = 0;
+                } else if loc.y > *y {
+                    loc.y -= 1;
+                }
+            }
+            Event::SplitDown(at) => {
+                if loc.y == at.y && (loc.x > at.x || (loc.x == at.x && self.push)) {
+                    *loc = Loc::at(loc.x - at.x, at.y + 1);
+                } else if loc.y > at.y {
+                    loc.y += 1;
+                }
+            }
+            Event::SpliceUp(at) => {
+                if loc.y == at.y + 1 {
+                    *loc = Loc::at(loc.x + at.x, at.y);
+                } else if loc.y > at.y + 1 {
+                    loc.y -= 1;
+                }
+            }
+        }
+    }
+}
+
+impl Document {
+    /// Add a mark to the document, returning its index in `marks`
+    pub fn add_mark(&mut self, loc: Loc, push: bool) -> usize {
+        self.marks.push(Mark { loc, push });
+        self.marks.len() - 1
+    }
+
+    /// Move every mark to account for an edit
+    pub(crate) fn update_marks(&mut self, ev: &Event) {
+        for mark in &mut self.marks {
+            mark.shift(ev);
+        }
+    }
+
+    /// Work out where the marks will be after an edit.
+    /// Splitting and splicing lines are made of several smaller edits, which would bunch the
+    /// marks together, so the marks are worked out beforehand and put back afterwards.
+    pub(crate) fn shifted_marks(&self, ev: &Event) -> Vec<Mark> {
+        let mut marks = self.marks.clone();
+        for mark in &mut marks {
+            mark.shift(ev);
+        }
+        marks
+    }
+}
@@ -5,15 +5,28 @@ use crate::searching::{Match, Searcher};
 use crate::utils::{modeline, width, Loc, Size};
 use ropey::Rope;
 use std::path::Path;
+use std::time::Duration;
 
 pub mod cursor;
 pub mod disk;
 pub mod editing;
+pub mod encoding;
+pub mod folding;
+pub mod history;
+pub mod lazy;
 pub mod lines;
+pub mod marks;
+pub mod swap;
 pub mod words;
+pub mod wrap;
 
 pub use cursor::Cursor;
-pub use disk::DocumentInfo;
+pub use disk::{Backup, DocumentInfo, LineEnding};
+pub use encoding::Encoding;
+pub use folding::Fold;
+pub use lazy::LazyFile;
+pub use marks::Mark;
+pub use wrap::Wrap;
 
 /// A document struct manages a file.
 /// It has tools to read, write and traverse a document.
@@ -52,6 +65,20 @@ pub struct Document {
     pub tab_width: usize,
     /// Secondary cursor (for multi-cursors)
     pub secondary_cursors: Vec<Loc>,
+    /// What to do with the previous version of the file when saving
+    pub backup: Backup,
+    /// The file being read on demand (for files too large to read into memory)
+    pub lazy: Option<LazyFile>,
+    /// How long lines are soft wrapped (if at all)
+    pub wrap: Option<Wrap>,
+    /// Regions of the document that are folded (in order, without overlapping)
+    pub folds: Vec<Fold>,
+    /// Edits made since they were last taken (None if they aren't being tracked)
+    pub edits: Option<Vec<Event>>,
+    /// Locations that move along with the text around them (see `add_mark`)
+    pub marks: Vec<Mark>,
+    /// Goes up with every edit, so anything worked out from the contents can tell it's stale
+    pub version: usize,
 }
 
 impl Document {
@@ -95,8 +122,8 @@ impl Document {
     /// # Errors
     /// Will return an error if any of the events failed to be reversed.
     pub fn undo(&mut self) -> Result<()> {
-        if let Some(s) = self.event_mgmt.undo(self.take_snapshot()) {
-            self.apply_snapshot(s);
+        if let Some(change) = self.event_mgmt.undo(self.cursor) {
+            self.apply_change(change)?;
         }
         Ok(())
     }
@@ -105,8 +132,38 @@ impl Document {
     /// # Errors
     /// Will return an error if any of the events failed to be re-executed.
     pub fn redo(&mut self) -> Result<()> {
-        if let Some(s) = self.event_mgmt.redo(&self.take_snapshot()) {
-            self.apply_snapshot(s);
+        if let Some(change) = self.event_mgmt.redo(self.cursor) {
+            self.apply_change(change)?;
+        }
+        Ok(())
+    }
+
+    /// Move to the tip of a branch in the undo tree.
+    /// # Errors
+    /// Will return an error if any of the events failed to be re-executed.
+    pub fn switch_branch(&mut self, idx: usize) -> Result<()> {
+        if let Some(change) = self.event_mgmt.jump(self.cursor, idx) {
+            self.apply_change(change)?;
+        }
+        Ok(())
+    }
+
+    /// Go back to the state this document was in a certain amount of time ago.
+    /// # Errors
+    /// Will return an error if any of the events failed to be reversed.
+    pub fn earlier(&mut self, duration: Duration) -> Result<()> {
+        if let Some(change) = self.event_mgmt.earlier(self.cursor, duration) {
+            self.apply_change(change)?;
+        }
+        Ok(())
+    }
+
+    /// Go forward to the state this document was in a certain amount of time later.
+    /// # Errors
+    /// Will return an error if any of the events failed to be re-executed.
+    pub fn later(&mut self, duration: Duration) -> Result<()> {
+        if let Some(change) = self.event_mgmt.later(self.cursor, duration) {
+            self.apply_change(change)?;
         }
         Ok(())
     }
@@ -126,6 +183,28 @@ impl Document {
         }
     }
 
+    /// Start keeping track of the edits made to this document (see `take_edits`)
+    pub fn track_edits(&mut self) {
+        self.edits = Some(vec![]);
+    }
+
+    /// Take the edits made since they were last taken, for keeping other programs (such as
+    /// language servers) up to date with this document.
+    /// Returns None if edits aren't being tracked, or the document was changed in a way that
+    /// can't be described by edits (in which case the whole document should be sent again).
+    pub fn take_edits(&mut self) -> Option<Vec<Event>> {
+        self.edits.as_mut().map(std::mem::take)
+    }
+
+    /// Make a note of an edit (if edits are being tracked), and keep marks in place
+    fn note_edit(&mut self, ev: Event) {
+        self.version = self.version.wrapping_add(1);
+        self.update_marks(&ev);
+        if let Some(edits) = &mut self.edits {
+            edits.push(ev);
+        }
+    }
+
     /// Takes a loc and converts it into a char index for ropey
     #[must_use]
     pub fn loc_to_file_pos(&self, loc: &Loc) -> usize {
@@ -210,11 +289,20 @@ impl Document {
 
     /// Brings the cursor into the viewport so it can be seen
     pub fn bring_cursor_in_viewport(&mut self) {
-        if self.offset.y > self.cursor.loc.y {
-            self.offset.y = self.cursor.loc.y;
+        if self.wrap.is_some() {
+            self.bring_cursor_row_in_viewport();
+            self.load_to(self.offset.y + self.size.h);
+            return;
         }
-        if self.offset.y + self.size.h <= self.cursor.loc.y {
-            self.offset.y = self.cursor.loc.y.saturating_sub(self.size.h) + 1;
+        if self.folds.is_empty() {
+            if self.offset.y > self.cursor.loc.y {
+                self.offset.y = self.cursor.loc.y;
+            }
+            if self.offset.y + self.size.h <= self.cursor.loc.y {
+                self.offset.y = self.cursor.loc.y.saturating_sub(self.size.h) + 1;
+            }
+        } else {
+            self.bring_cursor_row_in_viewport();
         }
         if self.offset.x > self.cursor.loc.x {
             self.offset.x = self.cursor.loc.x;
@@ -368,12 +456,14 @@ impl Document {
 
     /// Commit a change to the undo management system
     pub fn commit(&mut self) {
-        let s = self.take_snapshot();
-        self.event_mgmt.commit(s);
+        self.event_mgmt.commit(self.cursor);
     }
 
     /// Completely reload the file
     pub fn reload_lines(&mut self) {
+        if self.lazy.is_some() {
+            return;
+        }
         let to = std::mem::take(&mut self.info.loaded_to);
         self.lines.clear();
         self.load_to(to);
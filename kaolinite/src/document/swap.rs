@@ -0,0 +1,108 @@
+use crate::document::history::fnv_hash;
+use crate::event::Result;
+use crate::{Document, Loc};
+use ropey::Rope;
+use std::path::{Path, PathBuf};
+
+impl Document {
+    /// Work out where the swap file of this document is kept within a store directory.
+    /// Returns None if the document doesn't have a file name.
+    #[must_use]
+    pub fn swap_path(&self, store: &Path) -> Option<PathBuf> {
+        let file_name = self.file_name.as_ref()?;
+        let id = fnv_hash(file_name.as_bytes());
+        Some(store.join(format!("{id:016x}.swp")))
+    }
+
+    /// Write the current (unsaved) contents of this document into a swap file,
+    /// so that they can be recovered if the editor doesn't exit cleanly.
+    /// # Errors
+    /// Returns an error if the store directory or swap file couldn't be written to.
+    pub fn write_swap(&self, store: &Path) -> Result<()> {
+        let Some(path) = self.swap_path(store) else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(store)?;
+        let mut contents = format!("{}\n", std::process::id());
+        contents.push_str(&self.file.to_string());
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Read the swap file of this document from a store directory.
+    /// Returns the process id of the editor that wrote it, alongside the contents.
+    #[must_use]
+    pub fn read_swap(&self, store: &Path) -> Option<(u32, String)> {
+        let data = std::fs::read_to_string(self.swap_path(store)?).ok()?;
+        let (pid, contents) = data.split_once('\n')?;
+        Some((pid.parse().ok()?, contents.to_string()))
+    }
+
+    /// Remove the swap file of this document (if there is one)
+    pub fn remove_swap(&self, store: &Path) {
+        if let Some(path) = self.swap_path(store) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Replace the entire contents of this document.
+    /// Only the lines that differ are touched, and the replacement is registered
+    /// as a single change, so it can be undone in one go.
+    /// # Errors
+    /// Returns an error if the document failed to be modified.
+    pub fn set_contents(&mut self, text: &str) -> Result<()> {
+        self.load_to(self.file.len_lines());
+        if self.file == text {
+            return Ok(());
+        }
+        // Lines are compared along with their line endings
+        // (lines that are added take on the line ending of the document)
+        let old: Vec<String> = self.file.lines().map(|line| line.to_string()).collect();
+        let new: Vec<String> = Rope::from_str(text)
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        self.commit();
+        // Leave the lines at the start and end that are the same alone (keeping at least one)
+        let shortest = old.len().min(new.len()).saturating_sub(1);
+        let same = |(old, new): &(&String, &String)| old == new;
+        let prefix = old.iter().zip(&new).take(shortest).take_while(same).count();
+        let suffix = old.iter().rev().zip(new.iter().rev());
+        let suffix = suffix.take(shortest - prefix).take_while(same).count();
+        let old_len = old.len() - prefix - suffix;
+        let new_len = new.len() - prefix - suffix;
+        let common = old_len.min(new_len);
+        // Lines are removed and added before the ones that are replaced, so that there is
+        // always a line after them (removing from the end to avoid shifting the rest around)
+        for y in (prefix..prefix + old_len - common).rev() {
+            self.delete_line(y)?;
+        }
+        let start = prefix + new_len - common;
+        for (y, line) in new.iter().enumerate().take(start).skip(prefix) {
+            self.insert_line(y, without_ending(line).to_string())?;
+        }
+        let replaced = old.iter().skip(prefix + old_len - common);
+        for (y, (old, new)) in (start..).zip(replaced.zip(new.iter().skip(start)).take(common)) {
+            let content = without_ending(new).to_string();
+            if old == new {
+                continue;
+            }
+            if old[without_ending(old).len()..] == new[content.len()..] {
+                // Same line ending, so just the text in the line needs replacing
+                self.delete(.., y)?;
+                self.insert(&Loc::at(0, y), &content)?;
+            } else {
+                self.delete_line(y)?;
+                self.insert_line(y, content)?;
+            }
+        }
+        self.move_to(&Loc::at(0, 0));
+        self.commit();
+        Ok(())
+    }
+}
+
+/// Take the line ending off the end of a line
+fn without_ending(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
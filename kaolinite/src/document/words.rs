@@ -12,15 +12,14 @@ pub enum WordState {
 }
 
 impl Document {
-    /// Find the word boundaries
+    /// Find the word boundaries (as byte indices into the line)
     #[must_use]
     pub fn word_boundaries(&self, line: &str) -> Vec<(usize, usize)> {
         let re = r"(\s{2,}|[A-Za-z0-9_]+|\.)";
         let mut searcher = Searcher::new(re);
         let starts: Vec<Match> = searcher.lfinds_raw(line);
         let mut ends: Vec<Match> = starts.clone();
-        ends.iter_mut()
-            .for_each(|m| m.loc.x += m.text.chars().count());
+        ends.iter_mut().for_each(|m| m.loc.x += m.text.len());
         let starts: Vec<usize> = starts.iter().map(|m| m.loc.x).collect();
         let ends: Vec<usize> = ends.iter().map(|m| m.loc.x).collect();
         starts.into_iter().zip(ends).collect()
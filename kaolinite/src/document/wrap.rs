@@ -0,0 +1,295 @@
+/// wrap.rs - soft wrapping of long lines onto several rows of the viewport
+use crate::utils::{width, width_char};
+use crate::{Document, Loc};
+use std::ops::Range;
+
+/// Describes how the lines of a document should be soft wrapped
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Wrap {
+    /// The maximum width of a row (in display columns)
+    pub width: usize,
+    /// Whether to break rows at word boundaries where possible
+    pub words: bool,
+}
+
+/// Work out where each row of a wrapped line starts (as display indices).
+/// The first row always starts at 0.
+#[must_use]
+pub fn wrap_line(line: &str, tab_width: usize, wrap: Wrap) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut row_start = 0;
+    let mut disp = 0;
+    // The display index just after the last whitespace in this row
+    let mut last_break = None;
+    for c in line.chars() {
+        let c_width = width_char(&c, tab_width);
+        if disp + c_width > row_start + wrap.width && disp > row_start {
+            row_start = match last_break {
+                Some(at) if wrap.words && at > row_start => at,
+                _ => disp,
+            };
+            starts.push(row_start);
+            last_break = None;
+        }
+        disp += c_width;
+        if c.is_whitespace() {
+            last_break = Some(disp);
+        }
+    }
+    starts
+}
+
+impl Document {
+    /// Turn soft wrapping on (or off with `None`), keeping the cursor in view
+    pub fn set_wrap(&mut self, wrap: Option<Wrap>) {
+        if self.wrap != wrap {
+            self.wrap = wrap;
+            self.offset.x = 0;
+            self.bring_cursor_in_viewport();
+        }
+    }
+
+    /// Get the display indices that each row of a line starts at
+    /// (folded lines only ever take up a single row)
+    #[must_use]
+    pub fn wrap_rows(&self, y: usize) -> Vec<usize> {
+        if self.fold_at(y).is_some() {
+            return vec![0];
+        }
+        match (self.wrap, self.line(y)) {
+            (Some(wrap), Some(line)) => wrap_line(&line, self.tab_width, wrap),
+            _ => vec![0],
+        }
+    }
+
+    /// Find which row of its line a location (by display index) is on.
+    /// Returns the index of the row and the display index that row starts at.
+    #[must_use]
+    pub fn wrap_row_of(&self, loc: &Loc) -> (usize, usize) {
+        let rows = self.wrap_rows(loc.y);
+        let idx = rows.iter().rposition(|start| *start <= loc.x).unwrap_or(0);
+        (idx, rows[idx])
+    }
+
+    /// Get the rows at the top of the viewport, as a line number and a range of display indices
+    #[must_use]
+    pub fn visual_rows(&self, count: usize) -> Vec<(usize, Range<usize>)> {
+        let mut result = vec![];
+        let (_, top) = self.wrap_row_of(&self.offset);
+        let mut y = self.offset.y;
+        while result.len() < count {
+            let Some(line) = self.line(y) else {
+                break;
+            };
+            let end = width(&line, self.tab_width);
+            let rows = self.wrap_rows(y);
+            for (i, start) in rows.iter().enumerate() {
+                if y == self.offset.y && *start < top {
+                    continue;
+                }
+                let row_end = rows.get(i + 1).copied().unwrap_or(end);
+                result.push((y, *start..row_end));
+                if result.len() == count {
+                    break;
+                }
+            }
+            y = self.next_visible(y);
+        }
+        result
+    }
+
+    /// Get the row shown at a certain point in the viewport
+    #[must_use]
+    pub fn visual_row(&self, y: usize) -> Option<(usize, Range<usize>)> {
+        let mut rows = self.visual_rows(y + 1);
+        (rows.len() == y + 1).then(|| rows.pop()).flatten()
+    }
+
+    /// Work out the location (by display index) that a point in the viewport refers to
+    #[must_use]
+    pub fn disp_loc_from_screen(&self, screen: Loc) -> Loc {
+        if self.wrap.is_none() && self.folds.is_empty() {
+            return Loc {
+                x: screen.x + self.offset.x,
+                y: screen.y + self.offset.y,
+            };
+        }
+        if let Some((y, range)) = self.visual_row(screen.y) {
+            if self.wrap.is_none() {
+                return Loc::at(screen.x + self.offset.x, y);
+            }
+            // Only the last row of a line can be clicked past its end
+            let last = self.wrap_rows(y).last() == Some(&range.start);
+            let end = if last {
+                range.end
+            } else {
+                range.end.saturating_sub(1)
+            };
+            Loc {
+                x: (range.start + screen.x).min(end),
+                y,
+            }
+        } else {
+            // Below the end of the document
+            let y = self.len_lines().saturating_sub(1);
+            let x = width(&self.line(y).unwrap_or_default(), self.tab_width);
+            Loc::at(x, y)
+        }
+    }
+
+    /// Count the rows between two row starts (including the first, excluding the last),
+    /// giving up once the count reaches a limit
+    fn rows_between(&self, from: Loc, to: Loc, limit: usize) -> usize {
+        let mut count = 0;
+        let mut y = from.y;
+        while y <= to.y && count < limit {
+            count += self
+                .wrap_rows(y)
+                .iter()
+                .filter(|start| (y != from.y || **start >= from.x) && (y != to.y || **start < to.x))
+                .count();
+            y = self.next_visible(y);
+        }
+        count
+    }
+
+    /// Brings the cursor into the viewport, row by row (for when lines are wrapped or folded)
+    pub(crate) fn bring_cursor_row_in_viewport(&mut self) {
+        let (_, cursor_row) = self.wrap_row_of(&self.cursor.loc);
+        let cursor_row = Loc::at(cursor_row, self.cursor.loc.y);
+        let (_, top) = self.wrap_row_of(&self.offset);
+        let top = Loc::at(top, self.offset.y);
+        // Walk back from the cursor to find the first row to show, without passing the top
+        let mut first = cursor_row;
+        let mut space = self.size.h.saturating_sub(1);
+        let mut y = cursor_row.y;
+        'rows: loop {
+            for start in self.wrap_rows(y).into_iter().rev() {
+                if y == cursor_row.y && start >= cursor_row.x {
+                    continue;
+                }
+                if space == 0 || (y, start) < (top.y, top.x) {
+                    break 'rows;
+                }
+                first = Loc::at(start, y);
+                space -= 1;
+            }
+            if y <= top.y {
+                break;
+            }
+            y = self.prev_visible(y);
+        }
+        self.offset.y = first.y;
+        if self.wrap.is_some() {
+            self.offset.x = first.x;
+        }
+    }
+
+    /// Where the cursor is in the viewport, row by row (for when lines are wrapped or folded)
+    pub(crate) fn cursor_row_in_screen(&self) -> Option<Loc> {
+        let (_, cursor_row) = self.wrap_row_of(&self.cursor.loc);
+        let cursor_row = Loc::at(cursor_row, self.cursor.loc.y);
+        let (_, top) = self.wrap_row_of(&self.offset);
+        let top = Loc::at(top, self.offset.y);
+        if (cursor_row.y, cursor_row.x) < (top.y, top.x) {
+            return None;
+        }
+        let y = self.rows_between(top, cursor_row, self.size.h);
+        let x = if self.wrap.is_some() {
+            self.cursor.loc.x - cursor_row.x
+        } else {
+            self.cursor.loc.x.checked_sub(self.offset.x)?
+        };
+        (y < self.size.h && x <= self.size.w).then_some(Loc::at(x, y))
+    }
+
+    /// Work out the column within its row that the cursor should snap back to
+    fn wrap_column(&self) -> usize {
+        let (_, start) = self.wrap_row_of(&Loc::at(self.old_cursor, self.loc().y));
+        self.old_cursor.saturating_sub(start)
+    }
+
+    /// Move the cursor up a row of a wrapped line (or onto the last row of the previous line).
+    /// Returns false if the cursor is already on the first row of the document.
+    pub(crate) fn wrapped_up(&mut self) -> bool {
+        let y = self.loc().y;
+        let column = self.wrap_column();
+        let rows = self.wrap_rows(y);
+        let (idx, _) = self.wrap_row_of(&self.loc());
+        if idx > 0 {
+            let end = rows[idx].saturating_sub(1);
+            self.cursor.loc.x = (rows[idx - 1] + column).min(end);
+        } else if y == 0 {
+            return false;
+        } else {
+            let y = self.prev_visible(y);
+            self.cursor.loc.y = y;
+            let start = self.wrap_rows(y).last().copied().unwrap_or(0);
+            self.cursor.loc.x = start + column;
+        }
+        self.wrap_moved(column);
+        true
+    }
+
+    /// Move the cursor down a row of a wrapped line (or onto the first row of the next line).
+    /// Returns false if the cursor is already on the last row of the document.
+    pub(crate) fn wrapped_down(&mut self) -> bool {
+        let y = self.loc().y;
+        let column = self.wrap_column();
+        let rows = self.wrap_rows(y);
+        let (idx, _) = self.wrap_row_of(&self.loc());
+        if let Some(start) = rows.get(idx + 1) {
+            let end = rows
+                .get(idx + 2)
+                .map_or(usize::MAX, |end| end.saturating_sub(1));
+            self.cursor.loc.x = (start + column).min(end);
+        } else if self.next_visible(y) >= self.len_lines() {
+            return false;
+        } else {
+            let y = self.next_visible(y);
+            self.cursor.loc.y = y;
+            let end = self.wrap_rows(y).get(1).map_or(usize::MAX, |end| end - 1);
+            self.cursor.loc.x = column.min(end);
+        }
+        self.wrap_moved(column);
+        true
+    }
+
+    /// Move the view down a row
+    pub(crate) fn wrapped_scroll_down(&mut self) {
+        let rows = self.wrap_rows(self.offset.y);
+        let (idx, _) = self.wrap_row_of(&self.offset);
+        if let Some(start) = rows.get(idx + 1) {
+            self.offset.x = *start;
+        } else if self.next_visible(self.offset.y) < self.len_lines() {
+            self.offset = Loc::at(0, self.next_visible(self.offset.y));
+        }
+        self.load_to(self.offset.y + self.size.h);
+    }
+
+    /// Move the view up a row
+    pub(crate) fn wrapped_scroll_up(&mut self) {
+        let rows = self.wrap_rows(self.offset.y);
+        let (idx, _) = self.wrap_row_of(&self.offset);
+        if idx > 0 {
+            self.offset.x = rows[idx - 1];
+        } else if self.offset.y > 0 {
+            let y = self.prev_visible(self.offset.y);
+            let start = self.wrap_rows(y).last().copied().unwrap_or(0);
+            self.offset = Loc::at(start, y);
+        }
+    }
+
+    /// Tidy up after moving the cursor between rows
+    fn wrap_moved(&mut self, column: usize) {
+        // Snap to end of line
+        self.fix_dangling_cursor();
+        // Move back if in the middle of a longer character
+        self.fix_split();
+        // Update the character pointer
+        self.update_char_ptr();
+        self.bring_cursor_in_viewport();
+        // Remember the column within the row (rather than within the line)
+        self.old_cursor = column;
+    }
+}
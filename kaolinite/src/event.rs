@@ -1,14 +1,7 @@
 /// event.rs - manages editing events and provides tools for error handling
 use crate::{document::Cursor, utils::Loc, Document};
 use error_set::error_set;
-use ropey::Rope;
-
-/// A snapshot stores the state of a document at a certain time
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Snapshot {
-    pub content: Rope,
-    pub cursor: Cursor,
-}
+use std::time::{Duration, SystemTime};
 
 /// Represents an editing event.
 /// All possible editing events can be made up of a combination these events.
@@ -85,115 +78,267 @@ error_set! {
         Rope(ropey::Error),
         NoFileName,
         OutOfRange,
-        ReadOnlyFile
+        ReadOnlyFile,
+        UnknownLineEnding,
+        UnknownEncoding,
+        LossyFile,
+        CannotEncode,
+        #[display("Language server error: {}", message)]
+        LanguageServer {
+            message: String
+        }
     };
 }
 
+/// A change from one state of the document to another
+pub type Change = (Vec<Event>, Cursor);
+
+/// A point in the undo tree, representing a state the document was in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndoNode {
+    /// The events that lead from the parent state to this state
+    pub patch: Vec<Event>,
+    /// Where the cursor was in this state
+    pub cursor: Cursor,
+    /// The state that this one was made from (None if this is the root)
+    pub parent: Option<usize>,
+    /// The most recently visited state made from this one (where redo will go)
+    pub next: Option<usize>,
+    /// When this state was registered
+    pub time: SystemTime,
+}
+
 /// For managing events for purposes of undo and redo
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct EventMgmt {
-    /// Contains all the snapshots in the current timeline
-    pub history: Vec<Snapshot>,
+    /// Contains all the states in the undo tree, in the order they were registered
+    pub history: Vec<UndoNode>,
     /// Stores where the document currently is
     pub ptr: Option<usize>,
     /// Store where the file on the disk is currently at
     pub on_disk: Option<usize>,
+    /// Events that have occured since the last commit
+    pub pending: Vec<Event>,
     /// Store the last event to occur (so that we can see if there is a change)
     pub last_event: Option<Event>,
     /// Flag to force the file not to be with disk (i.e. file only exists in memory)
     pub force_not_with_disk: bool,
+    /// Flag for when events are being replayed (and shouldn't be recorded)
+    pub replaying: bool,
 }
 
 impl Document {
-    #[must_use]
-    pub fn take_snapshot(&self) -> Snapshot {
-        Snapshot {
-            content: self.file.clone(),
-            cursor: self.cursor,
-        }
+    /// Move the document to another state by applying a change from the event manager
+    /// # Errors
+    /// Will return an error if any of the events failed to be applied.
+    pub fn apply_change(&mut self, change: Change) -> Result<()> {
+        let (events, cursor) = change;
+        self.event_mgmt.replaying = true;
+        let result = events.into_iter().try_for_each(|ev| self.replay(ev));
+        self.event_mgmt.replaying = false;
+        result?;
+        self.cursor = cursor;
+        self.char_ptr = self.character_idx(&cursor.loc);
+        self.old_cursor = self.loc().x;
+        self.bring_cursor_in_viewport();
+        Ok(())
     }
 
-    pub fn apply_snapshot(&mut self, snapshot: Snapshot) {
-        self.file = snapshot.content;
-        self.cursor = snapshot.cursor;
-        self.char_ptr = self.character_idx(&snapshot.cursor.loc);
-        self.reload_lines();
-        self.bring_cursor_in_viewport();
+    /// Apply an event exactly as it was recorded
+    fn replay(&mut self, ev: Event) -> Result<()> {
+        // Make sure the lines this event touches are loaded
+        self.load_to(ev.loc().y + 2);
+        match ev {
+            Event::Insert(loc, st) => self.insert(&loc, &st),
+            Event::Delete(loc, st) => self.delete(loc.x..=loc.x + st.chars().count(), loc.y),
+            Event::InsertLine(loc, st) => self.insert_line(loc, st),
+            Event::DeleteLine(loc, _) => self.delete_line(loc),
+            Event::SplitDown(loc) => self.split_down(&loc),
+            Event::SpliceUp(loc) => self.splice_up(loc.y),
+        }
     }
 }
 
 impl EventMgmt {
-    /// In the event of some changes, redo should be cleared
-    pub fn clear_redo(&mut self) {
-        if let Some(ptr) = self.ptr {
-            self.history.drain(ptr + 1..);
+    /// To be called by the editing functions whenever the document changes
+    pub fn record(&mut self, ev: Event) {
+        if !self.replaying {
+            self.pending.push(ev);
         }
     }
 
-    /// To be called when a snapshot needs to be registered
-    pub fn commit(&mut self, snapshot: Snapshot) {
-        // Only commit when previous snapshot differs
-        let ptr = self.ptr.unwrap_or(0);
-        if self.history.get(ptr).map(|s| &s.content) != Some(&snapshot.content) {
-            self.clear_redo();
-            self.history.push(snapshot);
-            self.ptr = Some(self.history.len().saturating_sub(1));
+    /// To be called when the current state needs to be registered
+    pub fn commit(&mut self, cursor: Cursor) {
+        if self.history.is_empty() {
+            // The root is the state before any recorded changes were made
+            let root_cursor = if self.pending.is_empty() {
+                cursor
+            } else {
+                Cursor::default()
+            };
+            self.history.push(UndoNode {
+                patch: vec![],
+                cursor: root_cursor,
+                parent: None,
+                next: None,
+                time: SystemTime::now(),
+            });
+            self.ptr = Some(0);
+        }
+        // Only commit when there have been changes
+        if !self.pending.is_empty() {
+            // Branch off from the current state, leaving any redo states intact
+            let idx = self.history.len();
+            self.history.push(UndoNode {
+                patch: std::mem::take(&mut self.pending),
+                cursor,
+                parent: self.ptr,
+                next: None,
+                time: SystemTime::now(),
+            });
+            if let Some(parent) = self.ptr {
+                self.history[parent].next = Some(idx);
+            }
+            self.ptr = Some(idx);
         }
     }
 
     /// To be called when writing to disk
-    pub fn disk_write(&mut self, snapshot: &Snapshot) {
+    pub fn disk_write(&mut self, cursor: Cursor) {
         self.force_not_with_disk = false;
-        self.commit(snapshot.clone());
+        self.commit(cursor);
         self.on_disk = self.ptr;
     }
 
     /// A way to query whether we're currently up to date with the disk
     #[must_use]
-    pub fn with_disk(&self, snapshot: &Snapshot) -> bool {
+    pub fn with_disk(&self) -> bool {
         if self.force_not_with_disk {
             false
-        } else if let Some(disk) = self.on_disk {
-            self.history.get(disk).map(|s| &s.content) == Some(&snapshot.content)
         } else if self.history.is_empty() {
             true
         } else {
-            self.history.first().map(|s| &s.content) == Some(&snapshot.content)
+            self.pending.is_empty() && self.ptr == self.on_disk.or(Some(0))
         }
     }
 
-    /// Get previous snapshot to restore to
-    pub fn undo(&mut self, snapshot: Snapshot) -> Option<Snapshot> {
-        // Push cursor back by 1
-        self.commit(snapshot);
-        if let Some(ptr) = self.ptr {
-            if ptr != 0 {
-                let new_ptr = ptr.saturating_sub(1);
-                self.ptr = Some(new_ptr);
-                self.history.get(new_ptr).cloned()
-            } else {
-                None
+    /// Get the change that moves back to the previous state
+    pub fn undo(&mut self, cursor: Cursor) -> Option<Change> {
+        self.commit(cursor);
+        let ptr = self.ptr?;
+        let parent = self.history.get(ptr)?.parent?;
+        // Make sure redo brings us back to this branch
+        self.history[parent].next = Some(ptr);
+        self.ptr = Some(parent);
+        Some((self.path(ptr, parent), self.history[parent].cursor))
+    }
+
+    /// Get the change that moves forward to the state that used to be in place
+    pub fn redo(&mut self, cursor: Cursor) -> Option<Change> {
+        // If the user has edited since the undo, start a new branch (which has nothing to redo)
+        self.commit(cursor);
+        let ptr = self.ptr?;
+        let next = self.history.get(ptr)?.next?;
+        self.ptr = Some(next);
+        Some((self.path(ptr, next), self.history[next].cursor))
+    }
+
+    /// Work out the events needed to move between two states in the undo tree
+    #[must_use]
+    pub fn path(&self, from: usize, to: usize) -> Vec<Event> {
+        let ancestors = |mut idx: usize| {
+            let mut result = vec![idx];
+            while let Some(parent) = self.history[idx].parent {
+                result.push(parent);
+                idx = parent;
             }
-        } else {
-            None
+            result
+        };
+        let (up, mut down) = (ancestors(from), ancestors(to));
+        // Find where the two states meet in the tree
+        let Some(meet) = up.iter().position(|i| down.contains(i)) else {
+            return vec![];
+        };
+        down.truncate(down.iter().position(|i| *i == up[meet]).unwrap_or(0));
+        // Reverse the patches going up, then apply the patches going down
+        let mut result = vec![];
+        for idx in &up[..meet] {
+            let patch = &self.history[*idx].patch;
+            result.extend(patch.iter().rev().map(|ev| ev.clone().reverse()));
+        }
+        for idx in down.iter().rev() {
+            result.extend(self.history[*idx].patch.iter().cloned());
         }
+        result
     }
 
-    /// Get snapshot that used to be in place
-    pub fn redo(&mut self, snapshot: &Snapshot) -> Option<Snapshot> {
-        if let Some(ptr) = self.ptr {
-            // If the user has edited since the undo, wipe the redo stack
-            if self.history.get(ptr).map(|s| &s.content) != Some(&snapshot.content) {
-                self.clear_redo();
+    /// Get the tips of every branch in the undo tree, in the order they were created
+    #[must_use]
+    pub fn branches(&self) -> Vec<usize> {
+        let mut leaves = vec![true; self.history.len()];
+        for node in &self.history {
+            if let Some(parent) = node.parent {
+                leaves[parent] = false;
             }
-            // Perform the redo
-            let new_ptr = if ptr + 1 < self.history.len() {
-                ptr + 1
-            } else {
-                return None;
-            };
-            self.ptr = Some(new_ptr);
-            self.history.get(new_ptr).cloned()
+        }
+        (0..self.history.len()).filter(|i| leaves[*i]).collect()
+    }
+
+    /// Find which branch the current state is on
+    #[must_use]
+    pub fn current_branch(&self) -> Option<usize> {
+        // Follow redo states to the end of this branch
+        let mut idx = self.ptr?;
+        while let Some(next) = self.history.get(idx)?.next {
+            idx = next;
+        }
+        Some(idx)
+    }
+
+    /// Get the change that moves to any state in the undo tree
+    pub fn jump(&mut self, cursor: Cursor, idx: usize) -> Option<Change> {
+        self.commit(cursor);
+        let ptr = self.ptr?;
+        if idx >= self.history.len() || ptr == idx {
+            return None;
+        }
+        // Make sure redo states lead along the path to this state
+        let mut child = idx;
+        while let Some(parent) = self.history[child].parent {
+            self.history[parent].next = Some(child);
+            child = parent;
+        }
+        self.ptr = Some(idx);
+        Some((self.path(ptr, idx), self.history[idx].cursor))
+    }
+
+    /// Get the change that moves back to the state a certain amount of time ago
+    pub fn earlier(&mut self, cursor: Cursor, duration: Duration) -> Option<Change> {
+        self.commit(cursor);
+        let now = self.history.get(self.ptr?)?.time;
+        let target = now.checked_sub(duration).unwrap_or(SystemTime::UNIX_EPOCH);
+        // Find the most recent state registered before the target time
+        let idx = self
+            .history
+            .iter()
+            .rposition(|n| n.time <= target)
+            .unwrap_or(0);
+        self.jump(cursor, idx)
+    }
+
+    /// Get the change that moves forward to the state a certain amount of time later
+    pub fn later(&mut self, cursor: Cursor, duration: Duration) -> Option<Change> {
+        self.commit(cursor);
+        let ptr = self.ptr?;
+        // A target too far ahead to represent is later than every state
+        let target = self.history.get(ptr)?.time.checked_add(duration);
+        // Find the most recent state registered before the target time
+        let idx = self
+            .history
+            .iter()
+            .rposition(|n| target.is_none_or(|target| n.time <= target))?;
+        if idx > ptr {
+            self.jump(cursor, idx)
         } else {
             None
         }
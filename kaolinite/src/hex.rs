@@ -0,0 +1,358 @@
+/// hex.rs - has `HexDocument`, for viewing and editing files byte by byte
+use crate::document::disk::write_atomically;
+use crate::document::Backup;
+use crate::event::{Error, Result};
+use crate::utils::get_absolute_path;
+use crate::{Loc, Size};
+use std::io::Write;
+use std::path::Path;
+
+/// The number of bytes shown on each row
+pub const ROW_WIDTH: usize = 16;
+
+/// An edit made to a hex document, kept for the purposes of undo and redo
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HexEdit {
+    /// A byte was changed (index, old value, new value)
+    Set(usize, u8, u8),
+    /// A byte was inserted (index, value)
+    Insert(usize, u8),
+    /// A byte was removed (index, value)
+    Delete(usize, u8),
+}
+
+impl HexEdit {
+    /// Find the opposite of this edit (to undo it)
+    #[must_use]
+    pub fn reverse(self) -> Self {
+        match self {
+            Self::Set(idx, old, new) => Self::Set(idx, new, old),
+            Self::Insert(idx, byte) => Self::Delete(idx, byte),
+            Self::Delete(idx, byte) => Self::Insert(idx, byte),
+        }
+    }
+
+    /// The index of the byte this edit affects
+    #[must_use]
+    pub fn idx(self) -> usize {
+        match self {
+            Self::Set(idx, ..) | Self::Insert(idx, _) | Self::Delete(idx, _) => idx,
+        }
+    }
+}
+
+/// A hex document manages a file as raw bytes, rather than as lines of text.
+/// It is used for binary files, which would otherwise be mangled when decoded as text,
+/// and saves the exact bytes back to the disk.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HexDocument {
+    /// The file name of the document opened
+    pub file_name: Option<String>,
+    /// The contents of the file
+    pub bytes: Vec<u8>,
+    /// The index of the byte the cursor is on (the length of the file when at the very end)
+    pub cursor: usize,
+    /// Whether the cursor is on the second digit of the byte
+    pub low_nibble: bool,
+    /// Whether typing replaces bytes (true) or inserts new ones (false)
+    pub overwrite: bool,
+    /// The first row shown in the viewport
+    pub offset: usize,
+    /// Contains the size of this document for purposes of offset
+    pub size: Size,
+    /// Whether or not the document can be edited
+    pub read_only: bool,
+    /// What to do with the previous version of the file when saving
+    pub backup: Backup,
+    /// Edits that can be undone
+    undo: Vec<HexEdit>,
+    /// Edits that can be redone
+    redo: Vec<HexEdit>,
+    /// The number of edits in the undo stack when the document was last saved
+    saved: Option<usize>,
+}
+
+impl HexDocument {
+    /// Creates a new, empty hex document with no file name.
+    #[must_use]
+    pub fn new(size: Size) -> Self {
+        Self {
+            file_name: None,
+            bytes: vec![],
+            cursor: 0,
+            low_nibble: false,
+            overwrite: true,
+            offset: 0,
+            size,
+            read_only: false,
+            backup: Backup::None,
+            undo: vec![],
+            redo: vec![],
+            saved: Some(0),
+        }
+    }
+
+    /// Open a hex document from a file name.
+    /// # Errors
+    /// Returns an error when file doesn't exist, or has incorrect permissions.
+    pub fn open<S: Into<String>>(size: Size, file_name: S) -> Result<Self> {
+        let file_name = file_name.into();
+        let bytes = std::fs::read(&file_name)?;
+        Ok(Self {
+            file_name: get_absolute_path(&file_name),
+            bytes,
+            ..Self::new(size)
+        })
+    }
+
+    /// Save back to the file the document was opened from.
+    /// # Errors
+    /// Returns an error if the file fails to write, due to permissions.
+    pub fn save(&mut self) -> Result<()> {
+        if self.read_only {
+            Err(Error::ReadOnlyFile)
+        } else if let Some(file_name) = &self.file_name {
+            write_atomically(Path::new(file_name), &self.backup, |writer| {
+                writer.write_all(&self.bytes)
+            })?;
+            self.saved = Some(self.undo.len());
+            Ok(())
+        } else {
+            Err(Error::NoFileName)
+        }
+    }
+
+    /// Save to a specified file.
+    /// # Errors
+    /// Returns an error if the file fails to write, due to permissions.
+    pub fn save_as(&self, file_name: &str) -> Result<()> {
+        if self.read_only {
+            Err(Error::ReadOnlyFile)
+        } else {
+            write_atomically(Path::new(file_name), &self.backup, |writer| {
+                writer.write_all(&self.bytes)
+            })
+        }
+    }
+
+    /// Determine if there are changes that haven't been saved
+    #[must_use]
+    pub fn modified(&self) -> bool {
+        self.saved != Some(self.undo.len())
+    }
+
+    /// Returns the number of rows in the document (including the row for appending bytes)
+    #[must_use]
+    pub fn len_rows(&self) -> usize {
+        self.bytes.len() / ROW_WIDTH + 1
+    }
+
+    /// Get the bytes on a specified row
+    #[must_use]
+    pub fn row(&self, row: usize) -> Option<&[u8]> {
+        let start = row * ROW_WIDTH;
+        let end = (start + ROW_WIDTH).min(self.bytes.len());
+        (row < self.len_rows()).then(|| &self.bytes[start..end])
+    }
+
+    /// The row (y) and column (x) of the byte the cursor is on
+    #[must_use]
+    pub fn loc(&self) -> Loc {
+        Loc::at(self.cursor % ROW_WIDTH, self.cursor / ROW_WIDTH)
+    }
+
+    /// Move the cursor to a byte, scrolling it into view
+    pub fn move_to(&mut self, idx: usize) {
+        self.cursor = idx.min(self.bytes.len());
+        self.low_nibble = false;
+        let row = self.cursor / ROW_WIDTH;
+        if row < self.offset {
+            self.offset = row;
+        } else if row >= self.offset + self.size.h {
+            self.offset = row.saturating_sub(self.size.h) + 1;
+        }
+    }
+
+    /// Move the cursor back a byte
+    pub fn move_left(&mut self) {
+        self.move_to(self.cursor.saturating_sub(1));
+    }
+
+    /// Move the cursor forward a byte
+    pub fn move_right(&mut self) {
+        self.move_to(self.cursor + 1);
+    }
+
+    /// Move the cursor up a row
+    pub fn move_up(&mut self) {
+        self.move_to(self.cursor.saturating_sub(ROW_WIDTH));
+    }
+
+    /// Move the cursor down a row
+    pub fn move_down(&mut self) {
+        if self.cursor / ROW_WIDTH + 1 < self.len_rows() {
+            self.move_to(self.cursor + ROW_WIDTH);
+        }
+    }
+
+    /// Move the cursor to the start of the row
+    pub fn move_home(&mut self) {
+        self.move_to(self.cursor - self.cursor % ROW_WIDTH);
+    }
+
+    /// Move the cursor to the end of the row
+    pub fn move_end(&mut self) {
+        self.move_to(self.cursor - self.cursor % ROW_WIDTH + ROW_WIDTH - 1);
+    }
+
+    /// Move the cursor up a page
+    pub fn move_page_up(&mut self) {
+        self.move_to(self.cursor.saturating_sub(self.size.h * ROW_WIDTH));
+    }
+
+    /// Move the cursor down a page
+    pub fn move_page_down(&mut self) {
+        self.move_to(self.cursor + self.size.h * ROW_WIDTH);
+    }
+
+    /// Type a hex digit (0-15) at the cursor.
+    /// In overwrite mode this changes the byte under the cursor, otherwise a new byte is
+    /// inserted when starting to type a byte.
+    /// # Errors
+    /// Returns an error if the document is read only.
+    pub fn type_digit(&mut self, digit: u8) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyFile);
+        }
+        let digit = digit & 0xF;
+        let idx = self.cursor;
+        let edit = match (self.bytes.get(idx), self.low_nibble) {
+            (Some(old), true) => HexEdit::Set(idx, *old, (old & 0xF0) | digit),
+            (Some(old), false) if self.overwrite => {
+                HexEdit::Set(idx, *old, (digit << 4) | (old & 0xF))
+            }
+            _ => HexEdit::Insert(idx, digit << 4),
+        };
+        self.edit(edit);
+        if self.low_nibble {
+            self.move_to(idx + 1);
+        } else {
+            self.low_nibble = true;
+        }
+        Ok(())
+    }
+
+    /// Remove the byte before the cursor
+    /// # Errors
+    /// Returns an error if the document is read only.
+    pub fn backspace(&mut self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyFile);
+        }
+        if self.low_nibble {
+            // Give up on the byte being typed
+            self.low_nibble = false;
+        } else if let Some(idx) = self.cursor.checked_sub(1) {
+            self.edit(HexEdit::Delete(idx, self.bytes[idx]));
+            self.move_to(idx);
+        }
+        Ok(())
+    }
+
+    /// Remove the byte under the cursor
+    /// # Errors
+    /// Returns an error if the document is read only.
+    pub fn delete(&mut self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyFile);
+        }
+        if let Some(byte) = self.bytes.get(self.cursor) {
+            self.edit(HexEdit::Delete(self.cursor, *byte));
+            self.move_to(self.cursor);
+        }
+        Ok(())
+    }
+
+    /// Perform an edit, registering it in the undo / redo
+    fn edit(&mut self, edit: HexEdit) {
+        // The saved state can no longer be reached by undoing or redoing
+        if self.saved.is_some_and(|saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+        self.apply(edit);
+        self.undo.push(edit);
+        self.redo.clear();
+    }
+
+    /// Apply an edit to the bytes
+    fn apply(&mut self, edit: HexEdit) {
+        match edit {
+            HexEdit::Set(idx, _, new) => self.bytes[idx] = new,
+            HexEdit::Insert(idx, byte) => self.bytes.insert(idx, byte),
+            HexEdit::Delete(idx, _) => {
+                self.bytes.remove(idx);
+            }
+        }
+    }
+
+    /// Undo the last edit
+    /// # Errors
+    /// Returns an error if the document is read only.
+    pub fn undo(&mut self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyFile);
+        }
+        if let Some(edit) = self.undo.pop() {
+            self.apply(edit.reverse());
+            self.redo.push(edit);
+            self.move_to(edit.idx());
+        }
+        Ok(())
+    }
+
+    /// Redo the last edit that was undone
+    /// # Errors
+    /// Returns an error if the document is read only.
+    pub fn redo(&mut self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyFile);
+        }
+        if let Some(edit) = self.redo.pop() {
+            self.apply(edit);
+            self.undo.push(edit);
+            self.move_to(edit.idx());
+        }
+        Ok(())
+    }
+
+    /// Find the next occurrence of a sequence of bytes after the cursor (wrapping around to
+    /// the start of the file) and move the cursor to it.
+    /// Returns the index of the match.
+    pub fn find(&mut self, pattern: &[u8]) -> Option<usize> {
+        if pattern.is_empty() || pattern.len() > self.bytes.len() {
+            return None;
+        }
+        let starts = self.bytes.len() - pattern.len() + 1;
+        let found = (1..=starts)
+            .map(|i| (self.cursor + i) % starts)
+            .find(|i| self.bytes[*i..].starts_with(pattern))?;
+        self.move_to(found);
+        Some(found)
+    }
+
+    /// Read a sequence of bytes written in hex (e.g. `de ad be ef` or `0xCAFE`)
+    #[must_use]
+    pub fn parse_pattern(text: &str) -> Option<Vec<u8>> {
+        let digits: String = text
+            .split_whitespace()
+            .map(|part| part.trim_start_matches("0x"))
+            .collect();
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+}
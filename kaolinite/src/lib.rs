@@ -5,7 +5,7 @@
 //!
 //! It'll handle things like
 //! - Opening and saving files
-//! - Handle documents that are too long to be fitted on the whole terminal
+//! - Handle documents that are too long to be fitted on the whole terminal (or soft wrap them)
 //! - Rendering line numbers
 //! - Insertion and deletion from the document
 //! - File type detection
@@ -13,7 +13,11 @@
 //! - Moving around the document, by word, page, character or other means
 //! - Searching & Replacing
 //! - Handles tabs, different line endings and double width characters perfectly
-//! - File buffering for larger files
+//! - File buffering for larger files, and lazy read only loading for huge files
+//! - Viewing and editing binary files byte by byte
+//! - Talking to language servers, to find out about the meaning of code
+//! - Expanding snippets with tab stops, placeholders and variables
+//! - Emulating a terminal, for running programs inside the editor
 //!
 //! It removes a lot of complexity from your text editor and allows the creation of an advanced
 //! text editor in very few lines of idiomatic code.
@@ -28,9 +32,14 @@
 #![allow(clippy::module_name_repetitions)]
 pub mod document;
 pub mod event;
+pub mod hex;
+pub mod lsp;
 pub mod map;
 pub mod searching;
+pub mod snippet;
+pub mod terminal;
 pub mod utils;
 
 pub use document::Document;
+pub use hex::HexDocument;
 pub use utils::{Loc, Size};
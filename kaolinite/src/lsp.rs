@@ -0,0 +1,920 @@
+/// lsp.rs - a client for language servers, which know about the meaning of code
+use crate::event::{Error, Event, Result};
+use crate::{Document, Loc};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How positions within a line are counted by a language server
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PositionEncoding {
+    /// In bytes
+    Utf8,
+    /// In UTF-16 code units (what servers use unless told otherwise)
+    #[default]
+    Utf16,
+    /// In characters (the same as locations within documents)
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Find the encoding from its name in the protocol
+    fn from_name(name: &str) -> Self {
+        match name {
+            "utf-8" => Self::Utf8,
+            "utf-32" => Self::Utf32,
+            _ => Self::Utf16,
+        }
+    }
+
+    /// The number of units a character takes up
+    fn units(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// Work out which character of a line a position (counted in these units) is at
+    #[must_use]
+    pub fn char_index(self, line: &str, character: usize) -> usize {
+        let mut units = 0;
+        line.chars()
+            .take_while(|c| {
+                units += self.units(*c);
+                units <= character
+            })
+            .count()
+    }
+}
+
+/// A position within a document, as a language server counts it
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+impl Position {
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            line: usize::try_from(value["line"].as_u64()?).ok()?,
+            character: usize::try_from(value["character"].as_u64()?).ok()?,
+        })
+    }
+
+    fn to_json(self) -> Value {
+        json!({ "line": self.line, "character": self.character })
+    }
+}
+
+/// A range within a document, as a language server counts it
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            start: Position::from_json(&value["start"])?,
+            end: Position::from_json(&value["end"])?,
+        })
+    }
+}
+
+/// How serious a diagnostic (or message) is
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    /// The name of this severity (e.g. "warning")
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Information => "information",
+            Self::Hint => "hint",
+        }
+    }
+
+    /// Find a severity from its name (e.g. "warning")
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            "information" | "info" => Some(Self::Information),
+            "hint" => Some(Self::Hint),
+            _ => None,
+        }
+    }
+
+    fn from_json(value: &Value) -> Self {
+        match value.as_u64() {
+            Some(2) => Self::Warning,
+            Some(3) => Self::Information,
+            Some(4) => Self::Hint,
+            _ => Self::Error,
+        }
+    }
+}
+
+/// A problem with a document, reported by a language server
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub message: String,
+    /// What reported the problem (e.g. the compiler or a linter)
+    pub source: Option<String>,
+}
+
+impl Diagnostic {
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            range: Range::from_json(&value["range"])?,
+            severity: Severity::from_json(&value["severity"]),
+            message: value["message"].as_str()?.to_string(),
+            source: value["source"].as_str().map(str::to_string),
+        })
+    }
+}
+
+/// A range within a file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Location {
+    pub uri: String,
+    pub range: Range,
+}
+
+impl Location {
+    fn from_json(value: &Value) -> Option<Self> {
+        // Location links point to the name of what is being linked to
+        if let Some(uri) = value["targetUri"].as_str() {
+            return Some(Self {
+                uri: uri.to_string(),
+                range: Range::from_json(&value["targetSelectionRange"])?,
+            });
+        }
+        Some(Self {
+            uri: value["uri"].as_str()?.to_string(),
+            range: Range::from_json(&value["range"])?,
+        })
+    }
+}
+
+/// A change to a document, suggested by a language server
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TextEdit {
+    pub range: Range,
+    pub text: String,
+}
+
+impl TextEdit {
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            range: Range::from_json(&value["range"])?,
+            text: value["newText"].as_str()?.to_string(),
+        })
+    }
+}
+
+/// A client that talks to a language server using JSON-RPC.
+/// Messages from the server are read on a separate thread, and are dealt with whenever
+/// the client is polled, or is waiting for a response.
+pub struct Client {
+    /// Where messages to the server are written
+    writer: Box<dyn Write + Send>,
+    /// Messages that have been read from the server
+    incoming: Receiver<Value>,
+    /// The server process (if it was started by the client)
+    process: Option<Child>,
+    /// The uri of the directory the server is working in
+    root: String,
+    /// The id to give to the next request
+    next_id: i64,
+    /// The id of the initialize request (until the server responds to it)
+    initializing: Option<i64>,
+    /// Messages waiting to be sent once the server has been initialised
+    queue: Vec<Value>,
+    /// Responses that haven't been collected yet
+    responses: HashMap<i64, std::result::Result<Value, String>>,
+    /// Requests that were given up on (their responses are thrown away)
+    abandoned: HashSet<i64>,
+    /// What the server is capable of
+    pub capabilities: Value,
+    /// How the server counts positions within a line
+    pub encoding: PositionEncoding,
+    /// The documents open on the server (by uri), with their version numbers
+    pub documents: HashMap<String, i64>,
+    /// The latest diagnostics for each document (by uri)
+    pub diagnostics: HashMap<String, Vec<Diagnostic>>,
+    /// Messages the server would like to show to the user
+    pub messages: Vec<(Severity, String)>,
+    /// How long to wait for a response before giving up
+    pub timeout: Duration,
+    /// Whether the server is still running
+    pub running: bool,
+}
+
+impl Client {
+    /// Start a language server and talk to it over its standard input and output.
+    /// # Errors
+    /// Returns an error if the server couldn't be started.
+    pub fn spawn(command: &str, args: &[String], root: &str) -> Result<Self> {
+        let mut process = Command::new(command)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let (Some(stdin), Some(stdout)) = (process.stdin.take(), process.stdout.take()) else {
+            let _ = process.kill();
+            return Err(server_error("Couldn't talk to the language server"));
+        };
+        let mut client = Self::new(stdout, stdin, root)?;
+        client.process = Some(process);
+        Ok(client)
+    }
+
+    /// Talk to a language server through any reader and writer, and start initialising it.
+    /// # Errors
+    /// Returns an error if the server couldn't be written to.
+    pub fn new<R, W>(reader: R, writer: W, root: &str) -> Result<Self>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let (sender, incoming) = channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            while let Some(message) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut client = Self {
+            writer: Box::new(writer),
+            incoming,
+            process: None,
+            root: path_to_uri(root),
+            next_id: 0,
+            initializing: None,
+            queue: vec![],
+            responses: HashMap::new(),
+            abandoned: HashSet::new(),
+            capabilities: Value::Null,
+            encoding: PositionEncoding::default(),
+            documents: HashMap::new(),
+            diagnostics: HashMap::new(),
+            messages: vec![],
+            timeout: Duration::from_secs(5),
+            running: true,
+        };
+        let name = Path::new(root)
+            .file_name()
+            .map_or(root.to_string(), |name| name.to_string_lossy().to_string());
+        let id = client.request(
+            "initialize",
+            &json!({
+                "processId": std::process::id(),
+                "rootUri": client.root,
+                "workspaceFolders": [{ "uri": client.root, "name": name }],
+                "capabilities": {
+                    "general": { "positionEncodings": ["utf-32", "utf-16"] },
+                    "textDocument": {
+                        "synchronization": { "didSave": true },
+                        "hover": { "contentFormat": ["plaintext", "markdown"] },
+                        "definition": { "linkSupport": true },
+                        "references": {},
+                        "rename": {},
+                        "formatting": {},
+                        "publishDiagnostics": {},
+                    },
+                    "workspace": {
+                        "workspaceEdit": { "documentChanges": true },
+                        "configuration": true,
+                        "workspaceFolders": true,
+                    },
+                },
+            }),
+        )?;
+        client.initializing = Some(id);
+        Ok(client)
+    }
+
+    /// Send a request to the server, returning its id (to wait for the response with).
+    /// # Errors
+    /// Returns an error if the server isn't running or couldn't be written to.
+    pub fn request(&mut self, method: &str, params: &Value) -> Result<i64> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+        Ok(id)
+    }
+
+    /// Send a notification to the server.
+    /// # Errors
+    /// Returns an error if the server isn't running or couldn't be written to.
+    pub fn notify(&mut self, method: &str, params: &Value) -> Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    /// Send a request and wait for the response.
+    /// # Errors
+    /// Returns an error if the server responds with an error, stops or takes too long.
+    pub fn call(&mut self, method: &str, params: &Value) -> Result<Value> {
+        let id = self.request(method, params)?;
+        self.wait(id)
+    }
+
+    /// Wait for the response to a request (dealing with anything else that arrives meanwhile).
+    /// # Errors
+    /// Returns an error if the server responds with an error, stops or takes too long.
+    pub fn wait(&mut self, id: i64) -> Result<Value> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(result) = self.responses.remove(&id) {
+                return result.map_err(|message| Error::LanguageServer { message });
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.incoming.recv_timeout(remaining) {
+                Ok(message) => {
+                    self.handle(&message)?;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.abandoned.insert(id);
+                    return Err(server_error("The language server took too long to respond"));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.running = false;
+                    return Err(server_error("The language server has stopped"));
+                }
+            }
+        }
+    }
+
+    /// Deal with any messages that have arrived from the server, without waiting.
+    /// Returns true if anything the user may want to see has changed (e.g. diagnostics).
+    /// # Errors
+    /// Returns an error if the server couldn't be initialised or written to.
+    pub fn poll(&mut self) -> Result<bool> {
+        let mut changed = false;
+        loop {
+            match self.incoming.try_recv() {
+                Ok(message) => changed |= self.handle(&message)?,
+                Err(TryRecvError::Empty) => return Ok(changed),
+                Err(TryRecvError::Disconnected) => {
+                    self.running = false;
+                    return Ok(changed);
+                }
+            }
+        }
+    }
+
+    /// Ask the server to shut down, stopping it if it doesn't respond in time
+    pub fn shutdown(&mut self) {
+        if self.running && self.initializing.is_none() {
+            self.timeout = Duration::from_secs(1);
+            let _ = self.call("shutdown", &Value::Null);
+            let _ = self.notify("exit", &Value::Null);
+        }
+        self.running = false;
+        if let Some(mut process) = self.process.take() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+
+    /// Bring the server up to date with a document, sending the whole document if it hasn't
+    /// been seen before (or edits to it weren't being tracked), or just the edits otherwise.
+    /// # Errors
+    /// Returns an error if the server isn't running or couldn't be written to.
+    pub fn sync_document(&mut self, doc: &mut Document, language_id: &str) -> Result<()> {
+        let Some(file_name) = &doc.file_name else {
+            return Ok(());
+        };
+        let uri = path_to_uri(file_name);
+        let edits = doc
+            .take_edits()
+            .filter(|_| self.documents.contains_key(&uri));
+        let changes = match edits {
+            // Nothing has changed
+            Some(edits) if edits.is_empty() => return Ok(()),
+            // Send only the edits (when the server can work with them)
+            Some(edits) if self.incremental() => {
+                let line_ending = doc.info.line_ending.as_str();
+                edits
+                    .iter()
+                    .map(|ev| content_change(ev, line_ending))
+                    .collect()
+            }
+            // Send the whole document
+            _ => {
+                doc.load_to(doc.len_lines() + 1);
+                doc.track_edits();
+                vec![json!({ "text": doc.file.to_string() })]
+            }
+        };
+        if let Some(version) = self.documents.get_mut(&uri) {
+            *version += 1;
+            let version = *version;
+            self.notify(
+                "textDocument/didChange",
+                &json!({
+                    "textDocument": { "uri": uri, "version": version },
+                    "contentChanges": changes,
+                }),
+            )
+        } else {
+            self.documents.insert(uri.clone(), 1);
+            self.notify(
+                "textDocument/didOpen",
+                &json!({
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": language_id,
+                        "version": 1,
+                        "text": changes[0]["text"],
+                    },
+                }),
+            )
+        }
+    }
+
+    /// Let the server know a document has been saved.
+    /// # Errors
+    /// Returns an error if the server isn't running or couldn't be written to.
+    pub fn save_document(&mut self, doc: &Document) -> Result<()> {
+        match &doc.file_name {
+            Some(file_name) => self.notify(
+                "textDocument/didSave",
+                &json!({ "textDocument": { "uri": path_to_uri(file_name) } }),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Let the server know a document (by uri) has been closed.
+    /// # Errors
+    /// Returns an error if the server isn't running or couldn't be written to.
+    pub fn close_document(&mut self, uri: &str) -> Result<()> {
+        self.documents.remove(uri);
+        self.diagnostics.remove(uri);
+        self.notify(
+            "textDocument/didClose",
+            &json!({ "textDocument": { "uri": uri } }),
+        )
+    }
+
+    /// Get the diagnostics for a file
+    #[must_use]
+    pub fn diagnostics_for(&self, file_name: &str) -> &[Diagnostic] {
+        self.diagnostics
+            .get(&path_to_uri(file_name))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Work out how the server counts the position of a location (by character index)
+    #[must_use]
+    pub fn position(&self, doc: &Document, loc: Loc) -> Position {
+        let line = doc.line(loc.y).unwrap_or_default();
+        let character = line
+            .chars()
+            .take(loc.x)
+            .map(|c| self.encoding.units(c))
+            .sum();
+        Position {
+            line: loc.y,
+            character,
+        }
+    }
+
+    /// Work out the location (by character index) of a position from the server
+    #[must_use]
+    pub fn loc(&self, doc: &Document, pos: Position) -> Loc {
+        let line = doc.line(pos.line).unwrap_or_default();
+        Loc::at(self.encoding.char_index(&line, pos.character), pos.line)
+    }
+
+    /// Get information about the code at a location (e.g. its type and documentation)
+    /// # Errors
+    /// Returns an error if the server responds with an error, stops or takes too long.
+    pub fn hover(&mut self, doc: &Document, loc: Loc) -> Result<Option<String>> {
+        let result = self.call("textDocument/hover", &self.at(doc, loc))?;
+        Ok(hover_text(&result["contents"]))
+    }
+
+    /// Find where the code at a location is defined
+    /// # Errors
+    /// Returns an error if the server responds with an error, stops or takes too long.
+    pub fn definition(&mut self, doc: &Document, loc: Loc) -> Result<Vec<Location>> {
+        let result = self.call("textDocument/definition", &self.at(doc, loc))?;
+        Ok(locations(&result))
+    }
+
+    /// Find everywhere the code at a location is referred to
+    /// # Errors
+    /// Returns an error if the server responds with an error, stops or takes too long.
+    pub fn references(&mut self, doc: &Document, loc: Loc) -> Result<Vec<Location>> {
+        let mut params = self.at(doc, loc);
+        params["context"] = json!({ "includeDeclaration": true });
+        let result = self.call("textDocument/references", &params)?;
+        Ok(locations(&result))
+    }
+
+    /// Work out the edits needed to rename the symbol at a location, by document uri
+    /// # Errors
+    /// Returns an error if the server responds with an error, stops or takes too long.
+    pub fn rename(
+        &mut self,
+        doc: &Document,
+        loc: Loc,
+        new_name: &str,
+    ) -> Result<HashMap<String, Vec<TextEdit>>> {
+        let mut params = self.at(doc, loc);
+        params["newName"] = json!(new_name);
+        let result = self.call("textDocument/rename", &params)?;
+        Ok(workspace_edit(&result))
+    }
+
+    /// Work out the edits needed to format a document
+    /// # Errors
+    /// Returns an error if the server responds with an error, stops or takes too long.
+    pub fn format(
+        &mut self,
+        doc: &Document,
+        tab_size: usize,
+        insert_spaces: bool,
+    ) -> Result<Vec<TextEdit>> {
+        let params = json!({
+            "textDocument": { "uri": path_to_uri(doc.file_name.as_deref().unwrap_or_default()) },
+            "options": { "tabSize": tab_size, "insertSpaces": insert_spaces },
+        });
+        let result = self.call("textDocument/formatting", &params)?;
+        Ok(text_edits(&result))
+    }
+
+    /// Make edits suggested by the server to a document.
+    /// # Errors
+    /// Returns an error if an edit is out of range.
+    pub fn apply_edits(&self, doc: &mut Document, edits: &[TextEdit]) -> Result<()> {
+        doc.load_to(doc.len_lines() + 1);
+        // Every edit refers to the document as it was before any of them were made
+        let mut edits: Vec<(Loc, Loc, &str)> = edits
+            .iter()
+            .map(|edit| {
+                let start = self.loc(doc, edit.range.start);
+                let end = self.loc(doc, edit.range.end);
+                (start, end, edit.text.as_str())
+            })
+            .collect();
+        // So work backwards through the document, keeping earlier locations correct
+        edits.sort_by_key(|(start, _, _)| (start.y, start.x));
+        for (start, end, text) in edits.into_iter().rev() {
+            doc.replace_range(start, end, text)?;
+        }
+        Ok(())
+    }
+
+    /// Parameters referring to a location in a document
+    fn at(&self, doc: &Document, loc: Loc) -> Value {
+        let file_name = doc.file_name.as_deref().unwrap_or_default();
+        json!({
+            "textDocument": { "uri": path_to_uri(file_name) },
+            "position": self.position(doc, loc).to_json(),
+        })
+    }
+
+    /// Determine if the server can be sent just the edits made to documents
+    fn incremental(&self) -> bool {
+        let sync = &self.capabilities["textDocumentSync"];
+        let kind = sync.as_u64().or_else(|| sync["change"].as_u64());
+        // Edits are described by character, which needs the server to count in characters
+        self.initializing.is_none() && kind == Some(2) && self.encoding == PositionEncoding::Utf32
+    }
+
+    /// Send a message to the server (once it has been initialised)
+    fn send(&mut self, message: Value) -> Result<()> {
+        if !self.running {
+            return Err(server_error("The language server isn't running"));
+        }
+        if self.initializing.is_some() {
+            self.queue.push(message);
+            Ok(())
+        } else {
+            self.write(&message)
+        }
+    }
+
+    /// Write a message to the server straight away
+    fn write(&mut self, message: &Value) -> Result<()> {
+        if let Err(err) = write_message(&mut self.writer, message) {
+            self.running = false;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Deal with a message from the server, returning true if the user may want to know
+    fn handle(&mut self, message: &Value) -> Result<bool> {
+        let method = message["method"].as_str();
+        match (message.get("id"), method) {
+            // The server is asking something of the client
+            (Some(id), Some(method)) => {
+                self.answer(id, method, &message["params"])?;
+                Ok(false)
+            }
+            // The server is telling the client something
+            (None, Some(method)) => Ok(self.notification(method, &message["params"])),
+            // The server is responding to a request
+            (Some(id), None) => {
+                let Some(id) = id.as_i64() else {
+                    return Ok(false);
+                };
+                let result = match message.get("error") {
+                    Some(error) => Err(error["message"]
+                        .as_str()
+                        .unwrap_or("Unknown error")
+                        .to_string()),
+                    None => Ok(message.get("result").cloned().unwrap_or_default()),
+                };
+                if self.initializing == Some(id) {
+                    self.initialized(result)?;
+                } else if !self.abandoned.remove(&id) {
+                    self.responses.insert(id, result);
+                }
+                Ok(false)
+            }
+            (None, None) => Ok(false),
+        }
+    }
+
+    /// Finish initialising the server, now that it has responded
+    fn initialized(&mut self, result: std::result::Result<Value, String>) -> Result<()> {
+        self.initializing = None;
+        let result = result.map_err(|message| {
+            self.running = false;
+            Error::LanguageServer { message }
+        })?;
+        self.capabilities = result["capabilities"].clone();
+        self.encoding = self.capabilities["positionEncoding"]
+            .as_str()
+            .map_or(PositionEncoding::Utf16, PositionEncoding::from_name);
+        self.write(&json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }))?;
+        for message in std::mem::take(&mut self.queue) {
+            self.write(&message)?;
+        }
+        Ok(())
+    }
+
+    /// Answer a request from the server
+    fn answer(&mut self, id: &Value, method: &str, params: &Value) -> Result<()> {
+        let result = match method {
+            // There are no settings to give, so the server should use its defaults
+            "workspace/configuration" => {
+                let items = params["items"].as_array().map_or(0, Vec::len);
+                Value::Array(vec![Value::Null; items])
+            }
+            "workspace/workspaceFolders" => json!([{ "uri": self.root, "name": "root" }]),
+            "client/registerCapability"
+            | "client/unregisterCapability"
+            | "window/workDoneProgress/create"
+            | "window/showMessageRequest" => Value::Null,
+            _ => {
+                let error =
+                    json!({ "code": -32601, "message": format!("{method} isn't supported") });
+                return self.write(&json!({ "jsonrpc": "2.0", "id": id, "error": error }));
+            }
+        };
+        self.write(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    /// Take note of a notification from the server, returning true if the user may want to know
+    fn notification(&mut self, method: &str, params: &Value) -> bool {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let Some(uri) = params["uri"].as_str() else {
+                    return false;
+                };
+                let diagnostics = params["diagnostics"]
+                    .as_array()
+                    .map(|list| list.iter().filter_map(Diagnostic::from_json).collect())
+                    .unwrap_or_default();
+                self.diagnostics.insert(uri.to_string(), diagnostics);
+                true
+            }
+            "window/showMessage" => {
+                let severity = Severity::from_json(&params["type"]);
+                let message = params["message"].as_str().unwrap_or_default().to_string();
+                self.messages.push((severity, message));
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+/// Read a message (with its header) from a language server or client.
+/// Returns None once there is nothing left to read.
+pub fn read_message<R: BufRead>(reader: &mut R) -> Option<Value> {
+    loop {
+        // Read the header, which ends with an empty line
+        let mut length = None;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().ok();
+            }
+        }
+        // Read the content (skipping anything that doesn't make sense)
+        let Some(length) = length else {
+            continue;
+        };
+        let mut content = vec![0; length];
+        reader.read_exact(&mut content).ok()?;
+        if let Ok(message) = serde_json::from_slice(&content) {
+            return Some(message);
+        }
+    }
+}
+
+/// Write a message (with its header) to a language server or client.
+/// # Errors
+/// Returns an error if the message couldn't be written.
+pub fn write_message<W: Write + ?Sized>(writer: &mut W, message: &Value) -> std::io::Result<()> {
+    let content = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{content}", content.len())?;
+    writer.flush()
+}
+
+/// Turn a path into a `file://` uri
+#[must_use]
+pub fn path_to_uri(path: &str) -> String {
+    let path = std::path::absolute(path)
+        .map_or(path.to_string(), |path| path.to_string_lossy().to_string());
+    let path = path.replace('\\', "/");
+    let mut uri = String::from("file://");
+    // Windows paths start with a drive letter rather than a slash
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/:".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            write!(uri, "%{byte:02X}").unwrap_or_default();
+        }
+    }
+    uri
+}
+
+/// Turn a `file://` uri back into a path
+#[must_use]
+pub fn uri_to_path(uri: &str) -> Option<String> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = encoded
+            .get(i + 1..i + 3)
+            .filter(|_| encoded[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        if let Some(byte) = escaped {
+            bytes.push(byte);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // Windows paths start with a drive letter rather than a slash
+    if path.get(2..3) == Some(":") {
+        Some(path[1..].to_string())
+    } else {
+        Some(path)
+    }
+}
+
+/// Describe an edit as a change to the text (counting positions in characters)
+fn content_change(ev: &Event, line_ending: &str) -> Value {
+    let (start, end, text) = match ev {
+        Event::Insert(loc, text) => (*loc, *loc, text.clone()),
+        Event::Delete(loc, text) => {
+            let end = Loc::at(loc.x + text.chars().count(), loc.y);
+            (*loc, end, String::new())
+        }
+        Event::InsertLine(y, text) => (Loc::at(0, *y), Loc::at(0, *y), text.clone() + line_ending),
+        Event::DeleteLine(y, _) => (Loc::at(0, *y), Loc::at(0, y + 1), String::new()),
+        Event::SplitDown(loc) => (*loc, *loc, line_ending.to_string()),
+        Event::SpliceUp(loc) => (*loc, Loc::at(0, loc.y + 1), String::new()),
+    };
+    let start = Position {
+        line: start.y,
+        character: start.x,
+    };
+    let end = Position {
+        line: end.y,
+        character: end.x,
+    };
+    json!({
+        "range": { "start": start.to_json(), "end": end.to_json() },
+        "text": text,
+    })
+}
+
+/// Get the text to show from the contents of a hover response
+fn hover_text(contents: &Value) -> Option<String> {
+    let text = match contents {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(hover_text)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        Value::Object(_) => contents["value"].as_str()?.to_string(),
+        _ => return None,
+    };
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Get the locations from a response (which can be a single location or a list)
+fn locations(value: &Value) -> Vec<Location> {
+    match value {
+        Value::Array(list) => list.iter().filter_map(Location::from_json).collect(),
+        Value::Object(_) => Location::from_json(value).into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// Get a list of text edits from a response
+fn text_edits(value: &Value) -> Vec<TextEdit> {
+    value.as_array().map_or(vec![], |list| {
+        list.iter().filter_map(TextEdit::from_json).collect()
+    })
+}
+
+/// Get the text edits to make to each document (by uri) from a workspace edit
+fn workspace_edit(value: &Value) -> HashMap<String, Vec<TextEdit>> {
+    let mut result: HashMap<String, Vec<TextEdit>> = HashMap::new();
+    if let Some(changes) = value["changes"].as_object() {
+        for (uri, edits) in changes {
+            result
+                .entry(uri.clone())
+                .or_default()
+                .extend(text_edits(edits));
+        }
+    }
+    // Creating, renaming and deleting files isn't supported, only changing their contents
+    for change in value["documentChanges"].as_array().into_iter().flatten() {
+        if let Some(uri) = change["textDocument"]["uri"].as_str() {
+            result
+                .entry(uri.to_string())
+                .or_default()
+                .extend(text_edits(&change["edits"]));
+        }
+    }
+    result
+}
+
+/// Create an error to do with the language server
+fn server_error(message: &str) -> Error {
+    Error::LanguageServer {
+        message: message.to_string(),
+    }
+}
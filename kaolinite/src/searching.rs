@@ -115,3 +115,181 @@ impl Searcher {
         st.char_indices().nth(x).map_or(st.len(), |(byte, _)| byte)
     }
 }
+
+/// Stores how a search term should be matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Whether capital letters must match exactly
+    pub case_sensitive: bool,
+    /// Whether matches must be whole words
+    pub whole_word: bool,
+    /// Whether the search term is a regex (rather than literal text)
+    pub regex: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            case_sensitive: true,
+            whole_word: false,
+            regex: true,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Turn a search term into the regex that searches for it with these options
+    #[must_use]
+    pub fn pattern(&self, term: &str) -> String {
+        let mut pattern = if self.regex {
+            term.to_string()
+        } else {
+            regex::escape(term)
+        };
+        if self.whole_word {
+            pattern = format!(r"\b(?:{pattern})\b");
+        }
+        if !self.case_sensitive {
+            pattern = format!("(?i){pattern}");
+        }
+        pattern
+    }
+}
+
+/// How well some text fuzzily matches a query
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FuzzyMatch {
+    /// Higher scores are better matches
+    pub score: isize,
+    /// The character indices of the text that matched the query
+    pub indices: Vec<usize>,
+}
+
+/// Score for each character that matches
+const FUZZY_MATCH: isize = 1;
+/// Bonus for matching a character right after the previous match
+const FUZZY_CONSECUTIVE: isize = 5;
+/// Bonus for matching the first character of a word
+const FUZZY_WORD_START: isize = 8;
+/// Penalty for skipping characters between matches
+const FUZZY_GAP: isize = 1;
+/// Bonus for a path match that lies within the file name (rather than its folders)
+const FUZZY_FILE_NAME: isize = 10;
+
+/// Fuzzily match some text against a query.
+/// The characters of the query must appear in the text in order, but not necessarily next to
+/// each other. Case is ignored unless the query contains capital letters.
+/// Matches score higher when the characters are together, start words, or are near the start.
+#[must_use]
+pub fn fuzzy_match(query: &str, text: &str) -> Option<FuzzyMatch> {
+    let smart_case = query.chars().any(char::is_uppercase);
+    let fold = |c: char| {
+        if smart_case {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let original: Vec<char> = text.chars().collect();
+    let chars: Vec<char> = original.iter().map(|c| fold(*c)).collect();
+    if query.len() > chars.len() {
+        return None;
+    }
+    let Some(last_query) = query.len().checked_sub(1) else {
+        return Some(FuzzyMatch {
+            score: 0,
+            indices: vec![],
+        });
+    };
+    // Find where words start (after punctuation or at a change to capitals)
+    let bonus: Vec<isize> = original
+        .iter()
+        .enumerate()
+        .map(|(j, c)| {
+            let prev = j.checked_sub(1).map(|k| original[k]);
+            let starts_word = match prev {
+                None => true,
+                Some(prev) => !prev.is_alphanumeric() || (prev.is_lowercase() && c.is_uppercase()),
+            };
+            if starts_word {
+                FUZZY_WORD_START
+            } else {
+                0
+            }
+        })
+        .collect();
+    // best[i][j] is the best score for matching the query up to i, with i matched at j
+    // (from[i][j] remembers where i - 1 was matched to get that score)
+    let mut best: Vec<Vec<Option<isize>>> = vec![vec![None; chars.len()]; query.len()];
+    let mut from: Vec<Vec<usize>> = vec![vec![0; chars.len()]; query.len()];
+    for (i, q) in query.iter().enumerate() {
+        // The best score for the previous query character at least 2 characters back
+        let mut apart: Option<(isize, usize)> = None;
+        for (j, c) in chars.iter().enumerate() {
+            if i > 0 && j >= 2 {
+                if let Some(score) = best[i - 1][j - 2] {
+                    if apart.is_none_or(|(s, _)| score > s) {
+                        apart = Some((score, j - 2));
+                    }
+                }
+            }
+            if c != q {
+                continue;
+            }
+            let score = if i == 0 {
+                // Matches further into the text are worth a bit less
+                let leading = match j {
+                    0 => 0,
+                    1 => 1,
+                    2 => 2,
+                    _ => 3,
+                };
+                Some(FUZZY_MATCH + bonus[j] - leading)
+            } else {
+                let together = j
+                    .checked_sub(1)
+                    .and_then(|k| Some((best[i - 1][k]? + FUZZY_CONSECUTIVE, k)));
+                let apart = apart.map(|(s, k)| (s - FUZZY_GAP, k));
+                let prev = match (together, apart) {
+                    (Some(t), Some(a)) => Some(if a.0 > t.0 { a } else { t }),
+                    (t, a) => t.or(a),
+                };
+                prev.map(|(s, k)| {
+                    from[i][j] = k;
+                    s + FUZZY_MATCH + bonus[j]
+                })
+            };
+            best[i][j] = score;
+        }
+    }
+    // Pick the best place to finish, then work backwards to find which characters matched
+    let (mut at, score) = best[last_query]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, s)| s.map(|s| (j, s)))
+        .max_by_key(|(j, s)| (*s, std::cmp::Reverse(*j)))?;
+    let mut indices = vec![at; query.len()];
+    for i in (1..query.len()).rev() {
+        at = from[i][at];
+        indices[i - 1] = at;
+    }
+    Some(FuzzyMatch { score, indices })
+}
+
+/// Fuzzily match a path (with `/` separators) against a query,
+/// preferring matches that lie within the file name over ones spread across folders
+#[must_use]
+pub fn fuzzy_match_path(query: &str, path: &str) -> Option<FuzzyMatch> {
+    let mut best = fuzzy_match(query, path)?;
+    let folders = path.rfind('/').map_or(0, |idx| idx + 1);
+    if let Some(mut found) = fuzzy_match(query, &path[folders..]) {
+        let offset = path[..folders].chars().count();
+        found.score += FUZZY_FILE_NAME;
+        if found.score > best.score {
+            found.indices.iter_mut().for_each(|idx| *idx += offset);
+            best = found;
+        }
+    }
+    Some(best)
+}
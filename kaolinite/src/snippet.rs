@@ -0,0 +1,346 @@
+/// snippet.rs - expanding snippets of text with tab stops, placeholders and variables
+///
+/// Snippets are written in the same format as language servers and other editors use:
+/// - `$1` or `${1}` is a tab stop, `$0` is where the cursor ends up
+/// - `${1:text}` is a tab stop with placeholder text (which can contain other tab stops)
+/// - `${1|one,two,three|}` is a tab stop with a choice of text
+/// - `$NAME` or `${NAME:default}` is a variable
+/// - Tab stops with the same number are mirrors of each other
+/// - `\$`, `\}` and `\\` insert the character itself
+use crate::Loc;
+use std::collections::HashMap;
+
+/// A place in an expanded snippet to move the cursor to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabStop {
+    /// The number of the tab stop (0 is where the cursor ends up)
+    pub number: usize,
+    /// Where the tab stop is (there are several when it is mirrored), as the start and end of
+    /// its placeholder text, by character index from the start of the snippet
+    pub ranges: Vec<(Loc, Loc)>,
+    /// Choices for what to put at the tab stop (empty if it doesn't have any)
+    pub choices: Vec<String>,
+}
+
+/// A snippet, expanded with its variables filled in
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Snippet {
+    /// The text of the snippet
+    pub text: String,
+    /// The tab stops, in the order they are visited (ending with the 0th)
+    pub stops: Vec<TabStop>,
+}
+
+/// A part of a snippet
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Stop {
+        number: usize,
+        placeholder: Vec<Part>,
+        choices: Vec<String>,
+    },
+    Variable {
+        name: String,
+        default: Option<Vec<Part>>,
+    },
+}
+
+/// Reads snippets into parts
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    /// Look at the next character without moving past it
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    /// Move past the next character if it is the one expected
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        self.at += usize::from(found);
+        found
+    }
+
+    /// Read parts until the end of the snippet (or the end of a placeholder, when nested)
+    fn parts(&mut self, nested: bool) -> Vec<Part> {
+        let mut result = vec![];
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if nested && c == '}' {
+                break;
+            }
+            self.at += 1;
+            if c == '\\' && self.peek().is_some_and(|c| "$}\\,|".contains(c)) {
+                text.push(self.chars[self.at]);
+                self.at += 1;
+            } else if c == '$' {
+                let start = self.at;
+                if let Some(part) = self.dollar() {
+                    result.push(Part::Text(std::mem::take(&mut text)));
+                    result.push(part);
+                } else {
+                    // Not a tab stop or variable after all
+                    self.at = start;
+                    text.push(c);
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        result.push(Part::Text(text));
+        result
+    }
+
+    /// Read a tab stop or variable (just after the dollar sign)
+    fn dollar(&mut self) -> Option<Part> {
+        if let Some(number) = self.number() {
+            return Some(Part::Stop {
+                number,
+                placeholder: vec![],
+                choices: vec![],
+            });
+        }
+        if let Some(name) = self.name() {
+            return Some(Part::Variable {
+                name,
+                default: None,
+            });
+        }
+        if !self.eat('{') {
+            return None;
+        }
+        if let Some(number) = self.number() {
+            let mut placeholder = vec![];
+            let mut choices = vec![];
+            if self.eat(':') {
+                placeholder = self.parts(true);
+            } else if self.eat('|') {
+                choices = self.choices()?;
+            }
+            self.eat('}').then_some(Part::Stop {
+                number,
+                placeholder,
+                choices,
+            })
+        } else {
+            let name = self.name()?;
+            let default = if self.eat(':') {
+                Some(self.parts(true))
+            } else {
+                None
+            };
+            self.eat('}').then_some(Part::Variable { name, default })
+        }
+    }
+
+    /// Read the number of a tab stop
+    fn number(&mut self) -> Option<usize> {
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.at += 1;
+        }
+        let digits: String = self.chars[start..self.at].iter().collect();
+        digits.parse().ok()
+    }
+
+    /// Read the name of a variable
+    fn name(&mut self) -> Option<String> {
+        let start = self.at;
+        if !self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.at += 1;
+        }
+        Some(self.chars[start..self.at].iter().collect())
+    }
+
+    /// Read a list of choices (up to and including the closing bar)
+    fn choices(&mut self) -> Option<Vec<String>> {
+        let mut result = vec![String::new()];
+        loop {
+            let c = self.peek()?;
+            self.at += 1;
+            match c {
+                '\\' if self.peek().is_some_and(|c| "$}\\,|".contains(c)) => {
+                    result.last_mut()?.push(self.chars[self.at]);
+                    self.at += 1;
+                }
+                ',' => result.push(String::new()),
+                '|' => return Some(result),
+                c => result.last_mut()?.push(c),
+            }
+        }
+    }
+}
+
+/// Writes parts out into a snippet, keeping track of where the tab stops end up
+struct Writer<'a> {
+    snippet: Snippet,
+    loc: Loc,
+    variable: &'a dyn Fn(&str) -> Option<String>,
+    /// The text each tab stop starts with (for filling in mirrors)
+    defaults: HashMap<usize, String>,
+}
+
+impl Writer<'_> {
+    /// Write some text
+    fn write(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.loc = Loc::at(0, self.loc.y + 1);
+            } else {
+                self.loc.x += 1;
+            }
+        }
+        self.snippet.text.push_str(text);
+    }
+
+    /// Work out the text that parts would write (without keeping track of tab stops)
+    fn plain(&self, parts: &[Part]) -> String {
+        parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Stop {
+                    placeholder,
+                    choices,
+                    ..
+                } => match choices.first() {
+                    Some(choice) => choice.clone(),
+                    None => self.plain(placeholder),
+                },
+                Part::Variable { name, default } => self.resolve(name, default.as_deref()),
+            })
+            .collect()
+    }
+
+    /// Work out the value of a variable
+    fn resolve(&self, name: &str, default: Option<&[Part]>) -> String {
+        match ((self.variable)(name), default) {
+            (Some(value), _) if !value.is_empty() => value,
+            (_, Some(default)) => self.plain(default),
+            (Some(value), None) => value,
+            // Variables that aren't known are left as their name, so they can be filled in
+            (None, None) => name.to_string(),
+        }
+    }
+
+    /// Find the text each tab stop starts with (the first placeholder or choice given for it)
+    fn find_defaults(&mut self, parts: &[Part]) {
+        for part in parts {
+            match part {
+                Part::Stop {
+                    number,
+                    placeholder,
+                    choices,
+                } => {
+                    let has_text = !(placeholder.is_empty() && choices.is_empty());
+                    if has_text && !self.defaults.contains_key(number) {
+                        let text = match choices.first() {
+                            Some(choice) => choice.clone(),
+                            None => self.plain(placeholder),
+                        };
+                        self.defaults.insert(*number, text);
+                    }
+                    self.find_defaults(placeholder);
+                }
+                Part::Variable {
+                    default: Some(default),
+                    ..
+                } => self.find_defaults(default),
+                _ => (),
+            }
+        }
+    }
+
+    /// Write parts out
+    fn parts(&mut self, parts: &[Part]) {
+        for part in parts {
+            match part {
+                Part::Text(text) => self.write(text),
+                Part::Stop {
+                    number,
+                    placeholder,
+                    choices,
+                } => {
+                    let start = self.loc;
+                    if placeholder.is_empty() {
+                        let text = self.defaults.get(number).cloned().unwrap_or_default();
+                        self.write(&text);
+                    } else {
+                        self.parts(placeholder);
+                    }
+                    self.add_range(*number, (start, self.loc), choices);
+                }
+                Part::Variable { name, default } => match ((self.variable)(name), default) {
+                    (Some(value), _) if !value.is_empty() => self.write(&value),
+                    (_, Some(default)) => self.parts(default),
+                    _ => self.write(&self.resolve(name, None)),
+                },
+            }
+        }
+    }
+
+    /// Make a note of where a tab stop is
+    fn add_range(&mut self, number: usize, range: (Loc, Loc), choices: &[String]) {
+        let stops = &mut self.snippet.stops;
+        if let Some(stop) = stops.iter_mut().find(|stop| stop.number == number) {
+            stop.ranges.push(range);
+            if stop.choices.is_empty() {
+                stop.choices = choices.to_vec();
+            }
+        } else {
+            stops.push(TabStop {
+                number,
+                ranges: vec![range],
+                choices: choices.to_vec(),
+            });
+        }
+    }
+}
+
+impl Snippet {
+    /// Expand a snippet, using a function to find the values of variables
+    /// (which returns None for variables it doesn't know about)
+    #[must_use]
+    pub fn expand(body: &str, variable: impl Fn(&str) -> Option<String>) -> Self {
+        let mut parser = Parser {
+            chars: body.chars().collect(),
+            at: 0,
+        };
+        let parts = parser.parts(false);
+        let mut writer = Writer {
+            snippet: Snippet::default(),
+            loc: Loc::default(),
+            variable: &variable,
+            defaults: HashMap::new(),
+        };
+        writer.find_defaults(&parts);
+        writer.parts(&parts);
+        let end = writer.loc;
+        let mut snippet = writer.snippet;
+        // Visit the tab stops in order, finishing at the 0th (or the end of the snippet)
+        snippet
+            .stops
+            .sort_by_key(|stop| (stop.number == 0, stop.number));
+        if snippet.stops.last().is_none_or(|stop| stop.number != 0) {
+            snippet.stops.push(TabStop {
+                number: 0,
+                ranges: vec![(end, end)],
+                choices: vec![],
+            });
+        }
+        snippet
+    }
+}
@@ -0,0 +1,750 @@
+/// terminal.rs - emulating a terminal, turning the output of programs into a grid of characters
+use crate::utils::{Loc, Size};
+use std::collections::VecDeque;
+use unicode_width::UnicodeWidthChar;
+
+/// The most lines that are kept once they scroll off the top of the screen
+pub const SCROLLBACK_LIMIT: usize = 10_000;
+
+/// How far apart tab stops are
+const TAB_STOP: usize = 8;
+
+/// The colour of a cell in the terminal
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Colour {
+    /// Whatever colour the editor uses for text and backgrounds
+    #[default]
+    Default,
+    /// One of the 256 xterm colours
+    Indexed(u8),
+    /// A true colour
+    Rgb(u8, u8, u8),
+}
+
+/// How a cell in the terminal looks
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Colour,
+    pub bg: Colour,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+/// A single character in the terminal
+/// (the cell after a double width character holds '\0')
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            ch: ' ',
+            style: Style::default(),
+        }
+    }
+}
+
+/// Where the parser is in an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Plain text
+    Ground,
+    /// Just after an escape character
+    Escape,
+    /// Choosing a character set (the next character is ignored)
+    Charset,
+    /// Inside a control sequence (ESC [)
+    Csi,
+    /// Inside an operating system command (ESC ])
+    Osc,
+    /// Just after an escape character in an operating system command
+    OscEscape,
+    /// Inside a string that isn't used (device control strings and friends)
+    Ignore,
+    /// Just after an escape character in a string that isn't used
+    IgnoreEscape,
+}
+
+/// An emulated VT100 / xterm screen, which programs running in a terminal write to.
+/// Feed it what the program outputs and it keeps track of what should be displayed.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone)]
+pub struct Screen {
+    /// The size of the screen in cells
+    pub size: Size,
+    /// The lines currently on the screen
+    lines: Vec<Vec<Cell>>,
+    /// Lines that have scrolled off the top of the screen (oldest first)
+    pub scrollback: VecDeque<Vec<Cell>>,
+    /// The most lines to keep in the scrollback
+    pub scrollback_limit: usize,
+    /// How many lines back into the scrollback is being viewed
+    pub view_offset: usize,
+    /// The normal screen, kept while the alternate screen is in use
+    primary: Option<Vec<Vec<Cell>>>,
+    /// Where the cursor is on the screen
+    pub cursor: Loc,
+    /// Whether programs want the cursor to be shown
+    pub cursor_visible: bool,
+    /// The style given to characters as they are written
+    pub style: Style,
+    /// Cursor position and style saved by the program
+    saved_cursor: Option<(Loc, Style)>,
+    /// The lines that scroll (inclusive of both ends)
+    scroll_region: (usize, usize),
+    /// Whether pasted text should be surrounded by markers
+    pub bracketed_paste: bool,
+    /// Whether arrow keys should be sent in application mode
+    pub application_cursor: bool,
+    /// Whether writing past the end of a line wraps onto the next
+    autowrap: bool,
+    /// Whether the last character written filled the line (so the next one wraps)
+    pending_wrap: bool,
+    /// The window title, as set by the program
+    pub title: String,
+    /// Replies to queries from the program, waiting to be sent back to it
+    pub responses: Vec<u8>,
+    /// Where the parser is in an escape sequence
+    state: State,
+    /// Parameters of the escape sequence being parsed
+    params: String,
+    /// The start of a character whose remaining bytes haven't arrived yet
+    partial: Vec<u8>,
+}
+
+impl Screen {
+    /// Create a new, empty screen of a certain size
+    #[must_use]
+    pub fn new(w: usize, h: usize) -> Self {
+        let (w, h) = (w.max(1), h.max(1));
+        Self {
+            size: Size { w, h },
+            lines: vec![vec![Cell::default(); w]; h],
+            scrollback: VecDeque::new(),
+            scrollback_limit: SCROLLBACK_LIMIT,
+            view_offset: 0,
+            primary: None,
+            cursor: Loc::at(0, 0),
+            cursor_visible: true,
+            style: Style::default(),
+            saved_cursor: None,
+            scroll_region: (0, h - 1),
+            bracketed_paste: false,
+            application_cursor: false,
+            autowrap: true,
+            pending_wrap: false,
+            title: String::new(),
+            responses: vec![],
+            state: State::Ground,
+            params: String::new(),
+            partial: vec![],
+        }
+    }
+
+    /// Whether the alternate screen (used by full screen programs) is showing
+    #[must_use]
+    pub fn alternate(&self) -> bool {
+        self.primary.is_some()
+    }
+
+    /// Get a line of the screen as it is being viewed (taking scrollback into account)
+    #[must_use]
+    pub fn row(&self, y: usize) -> Option<&[Cell]> {
+        let at = self.scrollback.len().saturating_sub(self.view_offset) + y;
+        if at < self.scrollback.len() {
+            self.scrollback.get(at).map(Vec::as_slice)
+        } else {
+            self.lines
+                .get(at - self.scrollback.len())
+                .map(Vec::as_slice)
+        }
+    }
+
+    /// Where the cursor should be shown on the screen as it is being viewed (if at all)
+    #[must_use]
+    pub fn cursor_in_view(&self) -> Option<Loc> {
+        let y = self.cursor.y + self.view_offset;
+        (self.cursor_visible && y < self.size.h).then_some(Loc::at(self.cursor.x, y))
+    }
+
+    /// Move the view up (back through the scrollback) or down
+    pub fn scroll_view(&mut self, up: bool, amount: usize) {
+        self.view_offset = if up {
+            (self.view_offset + amount).min(self.scrollback.len())
+        } else {
+            self.view_offset.saturating_sub(amount)
+        };
+    }
+
+    /// Get all the text in the scrollback and on the screen
+    /// (without trailing spaces and blank lines at the end)
+    #[must_use]
+    pub fn text(&self) -> String {
+        let mut lines: Vec<String> = self
+            .scrollback
+            .iter()
+            .chain(&self.lines)
+            .map(|line| line_text(line))
+            .collect();
+        while lines.last().is_some_and(String::is_empty) {
+            lines.pop();
+        }
+        lines.join("\n")
+    }
+
+    /// Take the replies to queries that need to be sent back to the program
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Change the size of the screen, keeping as much of what is shown as possible
+    pub fn resize(&mut self, w: usize, h: usize) {
+        let (w, h) = (w.max(1), h.max(1));
+        // Keep the cursor on screen by moving lines above it into the scrollback
+        let excess = (self.cursor.y + 1).saturating_sub(h);
+        for line in self.lines.drain(..excess).collect::<Vec<_>>() {
+            if !self.alternate() {
+                self.push_scrollback(line);
+            }
+        }
+        self.cursor.y -= excess;
+        for lines in std::iter::once(&mut self.lines).chain(&mut self.primary) {
+            lines.resize(h, vec![Cell::default(); w]);
+            for line in lines.iter_mut() {
+                line.resize(w, Cell::default());
+                // Don't leave half of a double width character behind
+                if line[w - 1].ch.width() == Some(2) {
+                    line[w - 1].ch = ' ';
+                }
+            }
+        }
+        self.size = Size { w, h };
+        self.scroll_region = (0, h - 1);
+        self.cursor.x = self.cursor.x.min(w - 1);
+        self.cursor.y = self.cursor.y.min(h - 1);
+        self.pending_wrap = false;
+    }
+
+    /// Process some output from the program
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let mut data = std::mem::take(&mut self.partial);
+        data.extend_from_slice(bytes);
+        let mut rest = &data[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    text.chars().for_each(|c| self.process(c));
+                    break;
+                }
+                Err(err) => {
+                    let (valid, after) = rest.split_at(err.valid_up_to());
+                    let valid = std::str::from_utf8(valid).unwrap_or_default();
+                    valid.chars().for_each(|c| self.process(c));
+                    if let Some(len) = err.error_len() {
+                        self.process(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    } else {
+                        // Wait for the rest of this character to arrive
+                        self.partial = after.to_vec();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Process a single character of output
+    fn process(&mut self, c: char) {
+        match self.state {
+            State::Ground => match c {
+                '\x1b' => self.state = State::Escape,
+                c if c < ' ' || c == '\x7f' => self.control(c),
+                c => self.print(c),
+            },
+            State::Escape => self.escape(c),
+            State::Charset => self.state = State::Ground,
+            State::Csi => match c {
+                '\x1b' => self.state = State::Escape,
+                c if c < ' ' => self.control(c),
+                ' '..='?' => self.params.push(c),
+                '@'..='~' => {
+                    self.state = State::Ground;
+                    self.csi(c);
+                }
+                _ => self.state = State::Ground,
+            },
+            State::Osc => match c {
+                '\x07' => self.osc(),
+                '\x1b' => self.state = State::OscEscape,
+                c => self.params.push(c),
+            },
+            State::OscEscape => {
+                self.osc();
+                // Anything other than a string terminator starts a new escape sequence
+                if c != '\\' {
+                    self.escape(c);
+                }
+            }
+            State::Ignore => match c {
+                '\x07' => self.state = State::Ground,
+                '\x1b' => self.state = State::IgnoreEscape,
+                _ => (),
+            },
+            State::IgnoreEscape => {
+                self.state = if c == '\\' {
+                    State::Ground
+                } else {
+                    State::Ignore
+                };
+            }
+        }
+    }
+
+    /// Handle a control character
+    fn control(&mut self, c: char) {
+        match c {
+            '\x08' => {
+                self.pending_wrap = false;
+                self.cursor.x = self.cursor.x.saturating_sub(1);
+            }
+            '\t' => {
+                let next = (self.cursor.x / TAB_STOP + 1) * TAB_STOP;
+                self.cursor.x = next.min(self.size.w - 1);
+            }
+            '\n' | '\x0b' | '\x0c' => {
+                self.pending_wrap = false;
+                self.index();
+            }
+            '\r' => {
+                self.pending_wrap = false;
+                self.cursor.x = 0;
+            }
+            _ => (),
+        }
+    }
+
+    /// Handle the character after an escape character
+    fn escape(&mut self, c: char) {
+        self.state = State::Ground;
+        match c {
+            '[' => {
+                self.params.clear();
+                self.state = State::Csi;
+            }
+            ']' => {
+                self.params.clear();
+                self.state = State::Osc;
+            }
+            'P' | 'X' | '^' | '_' => self.state = State::Ignore,
+            '(' | ')' | '*' | '+' | '#' | '%' => self.state = State::Charset,
+            '\x1b' => self.state = State::Escape,
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            'D' => self.index(),
+            'E' => {
+                self.cursor.x = 0;
+                self.index();
+            }
+            'M' => self.reverse_index(),
+            'c' => self.reset(),
+            _ => (),
+        }
+        if matches!(c, 'D' | 'E' | 'M' | '8') {
+            self.pending_wrap = false;
+        }
+    }
+
+    /// Handle an operating system command (only window titles are of interest)
+    fn osc(&mut self) {
+        self.state = State::Ground;
+        if let Some((kind, text)) = self.params.split_once(';') {
+            if kind == "0" || kind == "2" {
+                self.title = text.to_string();
+            }
+        }
+    }
+
+    /// Write a character where the cursor is
+    fn print(&mut self, c: char) {
+        let width = c.width().unwrap_or(0);
+        // Combining characters aren't tracked
+        if width == 0 {
+            return;
+        }
+        if self.pending_wrap && self.autowrap {
+            self.cursor.x = 0;
+            self.index();
+        }
+        self.pending_wrap = false;
+        // Double width characters that don't fit go onto the next line
+        if width == 2 && self.cursor.x + 1 >= self.size.w {
+            if !self.autowrap || self.size.w < 2 {
+                return;
+            }
+            let blank = self.blank();
+            self.lines[self.cursor.y][self.cursor.x] = blank;
+            self.cursor.x = 0;
+            self.index();
+        }
+        let Loc { x, y } = self.cursor;
+        self.clear_wide(x, y);
+        if width == 2 {
+            self.clear_wide(x + 1, y);
+        }
+        let style = self.style;
+        self.lines[y][x] = Cell { ch: c, style };
+        if width == 2 {
+            self.lines[y][x + 1] = Cell { ch: '\0', style };
+        }
+        // Move along, waiting to wrap if the end of the line has been reached
+        if x + width >= self.size.w {
+            self.cursor.x = self.size.w - 1;
+            self.pending_wrap = true;
+        } else {
+            self.cursor.x = x + width;
+        }
+    }
+
+    /// Remove the other half of a double width character that is about to be overwritten
+    fn clear_wide(&mut self, x: usize, y: usize) {
+        let blank = self.blank();
+        let line = &mut self.lines[y];
+        if line[x].ch == '\0' && x > 0 {
+            line[x - 1] = blank;
+        } else if line[x].ch.width() == Some(2) && x + 1 < line.len() {
+            line[x + 1] = blank;
+        }
+    }
+
+    /// A blank cell (erasing keeps the current background colour)
+    fn blank(&self) -> Cell {
+        Cell {
+            ch: ' ',
+            style: Style {
+                bg: self.style.bg,
+                ..Style::default()
+            },
+        }
+    }
+
+    /// A blank line
+    fn blank_line(&self) -> Vec<Cell> {
+        vec![self.blank(); self.size.w]
+    }
+
+    /// Add a line to the scrollback, forgetting the oldest lines if there are too many
+    fn push_scrollback(&mut self, line: Vec<Cell>) {
+        self.scrollback.push_back(line);
+        while self.scrollback.len() > self.scrollback_limit {
+            self.scrollback.pop_front();
+        }
+    }
+
+    /// Move the cursor down, scrolling if it is at the bottom of the scrolling region
+    fn index(&mut self) {
+        if self.cursor.y == self.scroll_region.1 {
+            self.scroll_up(1);
+        } else if self.cursor.y + 1 < self.size.h {
+            self.cursor.y += 1;
+        }
+    }
+
+    /// Move the cursor up, scrolling if it is at the top of the scrolling region
+    fn reverse_index(&mut self) {
+        if self.cursor.y == self.scroll_region.0 {
+            self.scroll_down(1);
+        } else {
+            self.cursor.y = self.cursor.y.saturating_sub(1);
+        }
+    }
+
+    /// Scroll the scrolling region up, adding a blank line to the bottom
+    fn scroll_up(&mut self, n: usize) {
+        let (top, bottom) = self.scroll_region;
+        for _ in 0..n.min(bottom + 1 - top) {
+            let line = self.lines.remove(top);
+            // Only lines leaving the top of the normal screen are worth keeping
+            if top == 0 && !self.alternate() {
+                self.push_scrollback(line);
+            }
+            self.lines.insert(bottom, self.blank_line());
+        }
+    }
+
+    /// Scroll the scrolling region down, adding a blank line to the top
+    fn scroll_down(&mut self, n: usize) {
+        let (top, bottom) = self.scroll_region;
+        for _ in 0..n.min(bottom + 1 - top) {
+            self.lines.remove(bottom);
+            self.lines.insert(top, self.blank_line());
+        }
+    }
+
+    /// Remember where the cursor is
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some((self.cursor, self.style));
+    }
+
+    /// Go back to where the cursor was remembered to be
+    fn restore_cursor(&mut self) {
+        let (cursor, style) = self.saved_cursor.unwrap_or_default();
+        self.cursor = Loc::at(cursor.x.min(self.size.w - 1), cursor.y.min(self.size.h - 1));
+        self.style = style;
+    }
+
+    /// Go back to how the screen was when it was created (keeping the scrollback)
+    fn reset(&mut self) {
+        let scrollback = std::mem::take(&mut self.scrollback);
+        let limit = self.scrollback_limit;
+        *self = Self::new(self.size.w, self.size.h);
+        self.scrollback = scrollback;
+        self.scrollback_limit = limit;
+    }
+
+    /// Switch to or from the alternate screen
+    fn set_alternate(&mut self, on: bool) {
+        if on && self.primary.is_none() {
+            let blank = vec![vec![Cell::default(); self.size.w]; self.size.h];
+            self.primary = Some(std::mem::replace(&mut self.lines, blank));
+        } else if !on {
+            if let Some(primary) = self.primary.take() {
+                self.lines = primary;
+            }
+        }
+        self.scroll_region = (0, self.size.h - 1);
+    }
+
+    /// Erase part of a line (the start and end are clamped to the line)
+    fn erase(&mut self, y: usize, start: usize, end: usize) {
+        let blank = self.blank();
+        let end = end.min(self.size.w);
+        for x in start.min(end)..end {
+            self.lines[y][x] = blank;
+        }
+    }
+
+    /// Handle a control sequence
+    #[allow(clippy::too_many_lines)]
+    fn csi(&mut self, action: char) {
+        let private = self.params.chars().next().filter(|c| "<=>?".contains(*c));
+        let intermediate = self.params.contains(|c: char| (' '..='/').contains(&c));
+        let args: Vec<usize> = self
+            .params
+            .trim_start_matches(['<', '=', '>', '?'])
+            .split([';', ':'])
+            .map(|arg| arg.parse().unwrap_or(0))
+            .collect();
+        // The nth argument, where missing or zero arguments are 1
+        let n = |i: usize| args.get(i).copied().filter(|a| *a != 0).unwrap_or(1);
+        let (w, h) = (self.size.w, self.size.h);
+        let Loc { x, y } = self.cursor;
+        let (top, bottom) = self.scroll_region;
+        if intermediate {
+            // Soft reset is the only sequence of this kind worth handling
+            if action == 'p' && self.params == "!" {
+                self.style = Style::default();
+                self.scroll_region = (0, h - 1);
+                self.cursor_visible = true;
+                self.autowrap = true;
+                self.application_cursor = false;
+            }
+            return;
+        }
+        if !matches!(action, 'm' | 'n' | 'c' | 'h' | 'l' | 't') {
+            self.pending_wrap = false;
+        }
+        match (private, action) {
+            (None, 'A') => {
+                let limit = if y >= top { top } else { 0 };
+                self.cursor.y = y.saturating_sub(n(0)).max(limit);
+            }
+            (None, 'B' | 'e') => {
+                let limit = if y <= bottom { bottom } else { h - 1 };
+                self.cursor.y = y.saturating_add(n(0)).min(limit);
+            }
+            (None, 'C' | 'a') => self.cursor.x = x.saturating_add(n(0)).min(w - 1),
+            (None, 'D') => self.cursor.x = x.saturating_sub(n(0)),
+            (None, 'E') => self.cursor = Loc::at(0, y.saturating_add(n(0)).min(h - 1)),
+            (None, 'F') => self.cursor = Loc::at(0, y.saturating_sub(n(0))),
+            (None, 'G' | '`') => self.cursor.x = (n(0) - 1).min(w - 1),
+            (None, 'd') => self.cursor.y = (n(0) - 1).min(h - 1),
+            (None, 'H' | 'f') => {
+                self.cursor = Loc::at((n(1) - 1).min(w - 1), (n(0) - 1).min(h - 1));
+            }
+            (None | Some('?'), 'J') => match args[0] {
+                0 => {
+                    self.erase(y, x, w);
+                    for line in y + 1..h {
+                        self.erase(line, 0, w);
+                    }
+                }
+                1 => {
+                    for line in 0..y {
+                        self.erase(line, 0, w);
+                    }
+                    self.erase(y, 0, x + 1);
+                }
+                kind => {
+                    for line in 0..h {
+                        self.erase(line, 0, w);
+                    }
+                    if kind == 3 {
+                        self.scrollback.clear();
+                        self.view_offset = 0;
+                    }
+                }
+            },
+            (None | Some('?'), 'K') => match args[0] {
+                0 => self.erase(y, x, w),
+                1 => self.erase(y, 0, x + 1),
+                _ => self.erase(y, 0, w),
+            },
+            (None, 'L') if (top..=bottom).contains(&y) => {
+                for _ in 0..n(0).min(bottom + 1 - y) {
+                    self.lines.remove(bottom);
+                    self.lines.insert(y, self.blank_line());
+                }
+                self.cursor.x = 0;
+            }
+            (None, 'M') if (top..=bottom).contains(&y) => {
+                for _ in 0..n(0).min(bottom + 1 - y) {
+                    self.lines.remove(y);
+                    self.lines.insert(bottom, self.blank_line());
+                }
+                self.cursor.x = 0;
+            }
+            (None, 'P') => {
+                let blank = self.blank();
+                let line = &mut self.lines[y];
+                for _ in 0..n(0).min(w - x) {
+                    line.remove(x);
+                    line.push(blank);
+                }
+            }
+            (None, '@') => {
+                let blank = self.blank();
+                let line = &mut self.lines[y];
+                for _ in 0..n(0).min(w - x) {
+                    line.insert(x, blank);
+                    line.pop();
+                }
+            }
+            (None, 'X') => self.erase(y, x, x.saturating_add(n(0))),
+            (None, 'S') => self.scroll_up(n(0)),
+            (None, 'T') => self.scroll_down(n(0)),
+            (None, 'r') => {
+                let new_top = n(0) - 1;
+                let new_bottom = args.get(1).copied().filter(|a| *a != 0).unwrap_or(h) - 1;
+                if new_top < new_bottom && new_bottom < h {
+                    self.scroll_region = (new_top, new_bottom);
+                    self.cursor = Loc::at(0, 0);
+                }
+            }
+            (None, 's') => self.save_cursor(),
+            (None, 'u') => self.restore_cursor(),
+            (None, 'm') => self.sgr(&args),
+            (None, 'n') if args[0] == 5 => self.responses.extend_from_slice(b"\x1b[0n"),
+            (None, 'n') if args[0] == 6 => {
+                let report = format!("\x1b[{};{}R", y + 1, x + 1);
+                self.responses.extend_from_slice(report.as_bytes());
+            }
+            (None, 'c') => self.responses.extend_from_slice(b"\x1b[?1;2c"),
+            (Some('?'), 'h' | 'l') => {
+                let on = action == 'h';
+                for mode in &args {
+                    match mode {
+                        1 => self.application_cursor = on,
+                        7 => self.autowrap = on,
+                        25 => self.cursor_visible = on,
+                        47 | 1047 => self.set_alternate(on),
+                        1049 => {
+                            if on {
+                                self.save_cursor();
+                                self.set_alternate(true);
+                            } else {
+                                self.set_alternate(false);
+                                self.restore_cursor();
+                            }
+                        }
+                        2004 => self.bracketed_paste = on,
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Handle a change of style (select graphic rendition)
+    fn sgr(&mut self, args: &[usize]) {
+        let mut i = 0;
+        while i < args.len() {
+            match args[i] {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                2 => self.style.dim = true,
+                3 => self.style.italic = true,
+                4 => self.style.underline = true,
+                7 => self.style.reverse = true,
+                22 => {
+                    self.style.bold = false;
+                    self.style.dim = false;
+                }
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                27 => self.style.reverse = false,
+                n @ 30..=37 => self.style.fg = Colour::Indexed(byte(n - 30)),
+                n @ 40..=47 => self.style.bg = Colour::Indexed(byte(n - 40)),
+                n @ 90..=97 => self.style.fg = Colour::Indexed(byte(n - 90 + 8)),
+                n @ 100..=107 => self.style.bg = Colour::Indexed(byte(n - 100 + 8)),
+                39 => self.style.fg = Colour::Default,
+                49 => self.style.bg = Colour::Default,
+                kind @ (38 | 48) => {
+                    let (colour, used) = extended_colour(&args[i + 1..]);
+                    if let Some(colour) = colour {
+                        if kind == 38 {
+                            self.style.fg = colour;
+                        } else {
+                            self.style.bg = colour;
+                        }
+                    }
+                    i += used;
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Read a 256 colour (5;n) or true colour (2;r;g;b), returning how many arguments were used
+fn extended_colour(args: &[usize]) -> (Option<Colour>, usize) {
+    match args {
+        [5, n, ..] => (Some(Colour::Indexed(byte(*n))), 2),
+        [2, r, g, b, ..] => (Some(Colour::Rgb(byte(*r), byte(*g), byte(*b))), 4),
+        _ => (None, args.len()),
+    }
+}
+
+/// Squash an argument into a byte
+fn byte(n: usize) -> u8 {
+    u8::try_from(n).unwrap_or(u8::MAX)
+}
+
+/// Get the text of a line of cells (without trailing spaces)
+#[must_use]
+pub fn line_text(line: &[Cell]) -> String {
+    let text: String = line
+        .iter()
+        .map(|cell| cell.ch)
+        .filter(|c| *c != '\0')
+        .collect();
+    text.trim_end().to_string()
+}
@@ -1,6 +1,6 @@
 use kaolinite::regex;
 #[cfg(test)]
-use kaolinite::{document::*, event::*, map::*, searching::*, utils::*};
+use kaolinite::{document::*, event::*, map::*, searching::*, terminal::line_text, utils::*};
 use std::io::Write;
 use std::ops::{Range, RangeBounds};
 use sugars::hmap;
@@ -88,7 +88,7 @@ fn widths() {
 #[test]
 fn tab_boundaries() {
     // Forward
-    assert_eq!(tab_boundaries_forward("hello", 4), vec![]);
+    assert_eq!(tab_boundaries_forward("hello", 4), Vec::<usize>::new());
     assert_eq!(tab_boundaries_forward("   hello", 3), vec![0]);
     assert_eq!(tab_boundaries_forward("    hello", 2), vec![0, 2]);
     assert_eq!(tab_boundaries_forward("     hello     hello2", 5), vec![0]);
@@ -98,7 +98,7 @@ fn tab_boundaries() {
     );
     assert_eq!(tab_boundaries_forward(" 你 ", 1), vec![0]);
     // Backward
-    assert_eq!(tab_boundaries_backward("hello", 4), vec![]);
+    assert_eq!(tab_boundaries_backward("hello", 4), Vec::<usize>::new());
     assert_eq!(tab_boundaries_backward("   hello", 3), vec![3]);
     assert_eq!(tab_boundaries_backward("    hello", 2), vec![2, 4]);
     assert_eq!(tab_boundaries_backward("     hello     hello2", 5), vec![5]);
@@ -151,6 +151,69 @@ fn searching() {
             text: st!("你好")
         })
     );
+    // Search options
+    let mut options = SearchOptions::default();
+    assert_eq!(options.pattern("a.c"), st!("a.c"));
+    options.regex = false;
+    assert_eq!(options.pattern("a.c"), st!(r"a\.c"));
+    options.whole_word = true;
+    options.case_sensitive = false;
+    let mut word_finder = Searcher::new(&options.pattern("cat"));
+    assert_eq!(word_finder.lfinds("concatenate Cat cat.").len(), 2);
+    assert_eq!(word_finder.lfind("concatenate CAT").unwrap().loc.x, 12);
+}
+
+#[test]
+fn fuzzy_matching() {
+    // Characters must appear in order
+    assert_eq!(fuzzy_match("abc", "cba"), None);
+    assert_eq!(fuzzy_match("abcd", "abc"), None);
+    assert_eq!(fuzzy_match("", "abc").unwrap().indices, Vec::<usize>::new());
+    assert_eq!(fuzzy_match("ace", "abcde").unwrap().indices, vec![0, 2, 4]);
+    // Case is ignored unless the query has capitals
+    assert_eq!(fuzzy_match("fb", "FooBar").unwrap().indices, vec![0, 3]);
+    assert_eq!(fuzzy_match("FB", "foobar"), None);
+    assert!(fuzzy_match("Ü", "über").is_none());
+    assert_eq!(fuzzy_match("ü", "Über").unwrap().indices, vec![0]);
+    // Word starts and consecutive characters are preferred
+    assert_eq!(
+        fuzzy_match("wb", "word_boundaries").unwrap().indices,
+        vec![0, 5]
+    );
+    assert_eq!(
+        fuzzy_match("bound", "bxoxuxnxd_bound").unwrap().indices,
+        vec![10, 11, 12, 13, 14]
+    );
+    let score = |q, t| fuzzy_match(q, t).unwrap().score;
+    assert!(score("doc", "document") > score("doc", "undocumented"));
+    assert!(score("dc", "dir_contents") > score("dc", "decide"));
+    assert!(score("main", "main.rs") > score("main", "src/domain.rs"));
+    assert!(score("rs", "src/main.rs") > score("rs", "crates"));
+}
+
+#[test]
+fn fuzzy_path_matching() {
+    // Matches in the file name are preferred, with indices into the whole path
+    assert_eq!(
+        fuzzy_match_path("main", "src/main.rs").unwrap().indices,
+        vec![4, 5, 6, 7]
+    );
+    assert_eq!(
+        fuzzy_match_path("ed", "src/editor/ed.rs").unwrap().indices,
+        vec![11, 12]
+    );
+    assert_eq!(fuzzy_match_path("ü", "dïr/über").unwrap().indices, vec![4]);
+    // Matches that have to span folders still count
+    assert_eq!(
+        fuzzy_match_path("sm", "src/main.rs").unwrap().indices,
+        vec![0, 4]
+    );
+    assert_eq!(fuzzy_match_path("xyz", "src/main.rs"), None);
+    // A match in the file name beats the same match spread across folders
+    let score = |q, t| fuzzy_match_path(q, t).unwrap().score;
+    assert!(score("finder", "src/editor/finder.rs") > score("finder", "finder/mod.rs"));
+    assert!(score("mod", "src/editor/mod.rs") > score("mod", "module/editor.rs"));
+    assert!(score("main", "src/main.rs") > fuzzy_match("main", "src/main.rs").unwrap().score);
 }
 
 #[test]
@@ -328,6 +391,763 @@ fn document_disks() {
     assert_eq!(result, st!("\n"));
 }
 
+#[test]
+fn document_backups() {
+    std::fs::write("tests/data/backup.txt", "original\n").unwrap();
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/backup.txt").unwrap();
+    doc.load_to(100);
+    doc.insert(&Loc::at(0, 0), "not ").unwrap();
+    // No backups by default, and no temporary files are left behind
+    doc.save().unwrap();
+    assert!(!std::path::Path::new("tests/data/backup.txt~").exists());
+    assert!(!std::fs::read_dir("tests/data").unwrap().any(|e| e
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with(".backup.txt")));
+    // Tilde backups keep the previous version
+    doc.backup = Backup::Tilde;
+    doc.insert(&Loc::at(0, 0), "very ").unwrap();
+    doc.save().unwrap();
+    let backup = std::fs::read_to_string("tests/data/backup.txt~").unwrap();
+    assert_eq!(backup, st!("not original\n"));
+    // Directory backups are timestamped, and saves in quick succession don't overwrite each other
+    let dir = std::path::Path::new("tests/data/backups");
+    doc.backup = Backup::Directory(dir.to_path_buf());
+    doc.save().unwrap();
+    doc.save().unwrap();
+    // Neither do files with the same name in different folders
+    std::fs::create_dir_all("tests/data/elsewhere").unwrap();
+    std::fs::write("tests/data/elsewhere/backup.txt", "other\n").unwrap();
+    let mut other = Document::open(Size::is(100, 10), "tests/data/elsewhere/backup.txt").unwrap();
+    other.load_to(100);
+    other.backup = Backup::Directory(dir.to_path_buf());
+    other.save().unwrap();
+    let backups: Vec<_> = std::fs::read_dir(dir).unwrap().collect();
+    assert_eq!(backups.len(), 3);
+    let result = std::fs::read_to_string("tests/data/backup.txt").unwrap();
+    // Clean up
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_dir_all("tests/data/elsewhere").unwrap();
+    std::fs::remove_file("tests/data/backup.txt~").unwrap();
+    std::fs::remove_file("tests/data/backup.txt").unwrap();
+    assert_eq!(result, st!("very not original\n"));
+}
+
+#[test]
+fn document_line_endings() {
+    // Detection
+    assert_eq!(
+        LineEnding::detect(&ropey::Rope::from_str("a\nb\n")),
+        LineEnding::Lf
+    );
+    assert_eq!(
+        LineEnding::detect(&ropey::Rope::from_str("a\r\nb\n\r\n")),
+        LineEnding::CrLf
+    );
+    assert_eq!(
+        LineEnding::detect(&ropey::Rope::from_str("a\rb\r")),
+        LineEnding::Cr
+    );
+    assert_eq!("crlf".parse::<LineEnding>().unwrap(), LineEnding::CrLf);
+    assert!("nonsense".parse::<LineEnding>().is_err());
+    // New lines follow the line ending of the file
+    std::fs::write("tests/data/crlf.txt", "hello\r\nworld\r\n").unwrap();
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/crlf.txt").unwrap();
+    doc.load_to(100);
+    assert_eq!(doc.info.line_ending, LineEnding::CrLf);
+    assert_eq!(doc.line(0), Some(st!("hello")));
+    doc.commit();
+    doc.split_down(&Loc::at(2, 0)).unwrap();
+    doc.commit();
+    assert_eq!(doc.file.to_string(), st!("he\r\nllo\r\nworld\r\n"));
+    doc.delete_line(2).unwrap();
+    doc.commit();
+    assert!(doc.undo().is_ok());
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.file.to_string(), st!("hello\r\nworld\r\n"));
+    // Converting between line endings
+    doc.convert_line_ending(LineEnding::Lf);
+    assert_eq!(doc.file.to_string(), st!("hello\nworld\n"));
+    assert!(!doc.event_mgmt.with_disk());
+    // ...which starts the undo history again
+    assert!(doc.event_mgmt.history.is_empty());
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.file.to_string(), st!("hello\nworld\n"));
+    doc.save().unwrap();
+    let result = std::fs::read_to_string("tests/data/crlf.txt").unwrap();
+    std::fs::remove_file("tests/data/crlf.txt").unwrap();
+    assert_eq!(result, st!("hello\nworld\n"));
+}
+
+#[test]
+fn document_encodings() {
+    // Detection and round trips
+    let bytes = [0xFF, 0xFE, b'h', 0, b'i', 0, b'\n', 0];
+    assert_eq!(
+        encoding::decode_file(&bytes),
+        (st!("hi\n"), Encoding::Utf16Le, true, false)
+    );
+    assert_eq!(Encoding::detect(b"h\0i\0").0, Encoding::Utf16Le);
+    assert_eq!(Encoding::detect(b"\0h\0i").0, Encoding::Utf16Be);
+    assert_eq!(Encoding::detect(b"caf\xE9").0, Encoding::Latin1);
+    assert_eq!(Encoding::detect(b"\x93quoted\x94").0, Encoding::Windows1252);
+    assert_eq!(Encoding::detect("café".as_bytes()), (Encoding::Utf8, false));
+    let (text, lossy) = Encoding::Windows1252.decode(b"\x93\x80\x94");
+    assert_eq!((text.as_str(), lossy), ("“€”", false));
+    let mut out = vec![];
+    Encoding::Windows1252.encode("“€”", &mut out);
+    assert_eq!(out, b"\x93\x80\x94");
+    assert!(Encoding::Latin1.can_encode("café"));
+    assert!(!Encoding::Latin1.can_encode("你好"));
+    assert_eq!("cp1252".parse::<Encoding>().unwrap(), Encoding::Windows1252);
+    assert!("nonsense".parse::<Encoding>().is_err());
+    // Files are saved back in the encoding they were opened in
+    std::fs::write("tests/data/utf16.txt", bytes).unwrap();
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/utf16.txt").unwrap();
+    doc.load_to(100);
+    assert_eq!(doc.line(0), Some(st!("hi")));
+    doc.insert(&Loc::at(2, 0), "!").unwrap();
+    doc.save().unwrap();
+    let result = std::fs::read("tests/data/utf16.txt").unwrap();
+    std::fs::remove_file("tests/data/utf16.txt").unwrap();
+    assert_eq!(result, [0xFF, 0xFE, b'h', 0, b'i', 0, b'!', 0, b'\n', 0]);
+    std::fs::write("tests/data/latin1.txt", b"caf\xE9\n").unwrap();
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/latin1.txt").unwrap();
+    doc.load_to(100);
+    assert_eq!(doc.line(0), Some(st!("café")));
+    doc.save().unwrap();
+    assert_eq!(
+        std::fs::read("tests/data/latin1.txt").unwrap(),
+        b"caf\xE9\n"
+    );
+    // Characters that can't be represented are refused
+    doc.insert(&Loc::at(0, 0), "你").unwrap();
+    assert!(doc.save().is_err());
+    assert!(doc.save_as("tests/data/latin1_copy.txt").is_err());
+    assert_eq!(
+        std::fs::read("tests/data/latin1.txt").unwrap(),
+        b"caf\xE9\n"
+    );
+    std::fs::remove_file("tests/data/latin1.txt").unwrap();
+    // Files that couldn't be decoded aren't saved without permission
+    std::fs::write(
+        "tests/data/lossy.txt",
+        [0xFF, 0xFE, b'h', 0, 0x00, 0xD8, b'i'],
+    )
+    .unwrap();
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/lossy.txt").unwrap();
+    assert!(doc.info.lossy);
+    assert!(doc.save().is_err());
+    assert!(doc.save_as("tests/data/lossy_copy.txt").is_err());
+    assert!(!std::path::Path::new("tests/data/lossy_copy.txt").exists());
+    doc.info.lossy = false;
+    assert!(doc.save().is_ok());
+    std::fs::remove_file("tests/data/lossy.txt").unwrap();
+}
+
+#[test]
+fn document_lazy_loading() {
+    let mut contents = String::new();
+    for i in 0..2000 {
+        contents.push_str(&format!("line {i}\r\n"));
+    }
+    contents.push_str("你好\tend");
+    std::fs::write("tests/data/lazy.txt", &contents).unwrap();
+    let mut doc = Document::open_lazy(Size::is(100, 10), "tests/data/lazy.txt").unwrap();
+    // Wait for the line index to be built
+    while !doc.lazy.as_ref().unwrap().indexed() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(doc.len_lines(), 2001);
+    assert!(doc.lines.is_empty());
+    assert_eq!(doc.line(0), Some(st!("line 0")));
+    assert_eq!(doc.line(1500), Some(st!("line 1500")));
+    assert_eq!(doc.line(2000), Some(st!("你好\tend")));
+    assert_eq!(doc.line(2001), None);
+    // Moving around reads in the lines around the viewport
+    doc.move_to(&Loc::at(0, 1999));
+    assert_eq!(doc.offset.y, 1990);
+    assert!(doc.lazy.as_ref().unwrap().in_window(1990, 2000));
+    assert!(!doc.lazy.as_ref().unwrap().in_window(0, 10));
+    doc.move_to(&Loc::at(3, 2000));
+    assert_eq!(doc.loc(), Loc::at(8, 2000));
+    doc.move_to(&Loc::at(2, 1999));
+    doc.select_to(&Loc::at(2, 2000));
+    assert_eq!(doc.selection_text(), st!("ne 1999\n你好"));
+    // The window keeps up when moving a little at a time, in either direction
+    for y in (1000..1400).chain((600..1400).rev()) {
+        doc.move_to(&Loc::at(0, y));
+        assert_eq!(
+            doc.line(doc.offset.y),
+            Some(format!("line {}", doc.offset.y))
+        );
+        assert_eq!(doc.line(y), Some(format!("line {y}")));
+    }
+    let lazy = doc.lazy.as_ref().unwrap();
+    assert!(lazy.in_window(600, 610));
+    let first: usize = lazy.window()[0][5..].parse().unwrap();
+    assert!(lazy
+        .window()
+        .iter()
+        .enumerate()
+        .all(|(i, l)| *l == format!("line {}", first + i)));
+    // Lines outside of the window can be gone through one after another
+    assert!((0..2000).all(|y| lazy.line(y) == Some(format!("line {y}"))));
+    // Lazy documents can't be edited or saved
+    doc.exe(Event::Insert(Loc::at(0, 0), st!("hello"))).unwrap();
+    assert_eq!(doc.line(0), Some(st!("line 0")));
+    assert!(doc.save().is_err());
+    std::fs::remove_file("tests/data/lazy.txt").unwrap();
+}
+
+#[test]
+fn document_hex() {
+    use kaolinite::document::encoding::is_binary;
+    use kaolinite::HexDocument;
+    let original: Vec<u8> = vec![0x7f, b'E', b'L', b'F', 0x00, 0xde, 0xad, 0xbe, 0xef, 0x0a];
+    std::fs::write("tests/data/hex.bin", &original).unwrap();
+    // Binary detection
+    assert!(is_binary(&original));
+    assert!(!is_binary(b"hello\tworld\r\n"));
+    assert!(!is_binary(&[0xff, 0xfe, b'h', 0x00, b'i', 0x00]));
+    assert!(is_binary(&[0x01, 0x02, 0x03, b'a']));
+    let mut hex = HexDocument::open(Size::is(100, 10), "tests/data/hex.bin").unwrap();
+    assert_eq!(hex.bytes, original);
+    assert_eq!(hex.len_rows(), 1);
+    assert!(!hex.modified());
+    // Overwriting
+    hex.type_digit(0x1).unwrap();
+    assert!(hex.low_nibble);
+    hex.type_digit(0x2).unwrap();
+    assert_eq!(hex.bytes[0], 0x12);
+    assert_eq!(hex.cursor, 1);
+    assert!(hex.modified());
+    // Inserting
+    hex.overwrite = false;
+    hex.type_digit(0xa).unwrap();
+    hex.type_digit(0xb).unwrap();
+    assert_eq!(hex.bytes[..3], [0x12, 0xab, b'E']);
+    assert_eq!(hex.bytes.len(), 11);
+    // Removing
+    hex.backspace().unwrap();
+    assert_eq!(hex.bytes[..2], [0x12, b'E']);
+    hex.delete().unwrap();
+    assert_eq!(hex.bytes[..2], [0x12, b'L']);
+    // Undo and redo
+    assert!(hex.undo().is_ok());
+    assert!(hex.undo().is_ok());
+    assert_eq!(hex.bytes[..3], [0x12, 0xab, b'E']);
+    assert!(hex.redo().is_ok());
+    assert_eq!(hex.bytes[..2], [0x12, b'E']);
+    // (each digit typed is its own edit)
+    for _ in 0..5 {
+        assert!(hex.undo().is_ok());
+    }
+    assert_eq!(hex.bytes, original);
+    assert!(!hex.modified());
+    // Searching
+    assert_eq!(HexDocument::parse_pattern("de ad"), Some(vec![0xde, 0xad]));
+    assert_eq!(HexDocument::parse_pattern("0xBEEF"), Some(vec![0xbe, 0xef]));
+    assert_eq!(HexDocument::parse_pattern("abc"), None);
+    assert_eq!(HexDocument::parse_pattern("zz"), None);
+    assert_eq!(hex.find(&[0xbe, 0xef]), Some(7));
+    assert_eq!(hex.cursor, 7);
+    assert_eq!(hex.find(&[0x7f]), Some(0));
+    assert_eq!(hex.find(&[0x99]), None);
+    // Saving writes the exact bytes back
+    hex.move_to(usize::MAX);
+    assert_eq!(hex.cursor, 10);
+    hex.type_digit(0xf).unwrap();
+    hex.type_digit(0xf).unwrap();
+    hex.save().unwrap();
+    assert!(!hex.modified());
+    let mut expected = original.clone();
+    expected.push(0xff);
+    assert_eq!(std::fs::read("tests/data/hex.bin").unwrap(), expected);
+    hex.read_only = true;
+    assert!(hex.type_digit(0x1).is_err());
+    assert!(hex.undo().is_err());
+    assert_eq!(hex.bytes, expected);
+    std::fs::remove_file("tests/data/hex.bin").unwrap();
+}
+
+#[test]
+fn document_soft_wrap() {
+    use kaolinite::document::wrap::wrap_line;
+    // Wrapping lines
+    let chars = Wrap {
+        width: 5,
+        words: false,
+    };
+    let words = Wrap {
+        width: 5,
+        words: true,
+    };
+    assert_eq!(wrap_line("", 4, chars), vec![0]);
+    assert_eq!(wrap_line("hello", 4, chars), vec![0]);
+    assert_eq!(wrap_line("hello world", 4, chars), vec![0, 5, 10]);
+    assert_eq!(wrap_line("hi there you", 4, chars), vec![0, 5, 10]);
+    assert_eq!(wrap_line("hi there you", 4, words), vec![0, 3, 8]);
+    assert_eq!(wrap_line("abcdefghijk", 4, words), vec![0, 5, 10]);
+    assert_eq!(wrap_line("你好你好", 4, chars), vec![0, 4]);
+    assert_eq!(wrap_line("\tab", 4, chars), vec![0, 5]);
+    // Moving around wrapped lines
+    let mut doc = Document::new(Size::is(5, 3));
+    doc.exe(Event::Insert(Loc::at(0, 0), st!("hi there you")))
+        .unwrap();
+    doc.exe(Event::InsertLine(1, st!("ab"))).unwrap();
+    doc.exe(Event::InsertLine(2, st!("cdefghijklmno"))).unwrap();
+    doc.move_to(&Loc::at(0, 0));
+    doc.set_wrap(Some(words));
+    assert_eq!(doc.wrap_rows(0), vec![0, 3, 8]);
+    assert_eq!(doc.wrap_row_of(&Loc::at(4, 0)), (1, 3));
+    assert_eq!(
+        doc.visual_rows(10),
+        vec![
+            (0, 0..3),
+            (0, 3..8),
+            (0, 8..12),
+            (1, 0..2),
+            (2, 0..5),
+            (2, 5..10),
+            (2, 10..13),
+            (3, 0..0)
+        ]
+    );
+    doc.move_to(&Loc::at(1, 0));
+    doc.old_cursor = 1;
+    doc.move_down();
+    assert_eq!(doc.loc(), Loc::at(4, 0));
+    assert_eq!(doc.cursor_loc_in_screen(), Some(Loc::at(1, 1)));
+    doc.move_down();
+    doc.move_down();
+    assert_eq!(doc.loc(), Loc::at(1, 1));
+    doc.move_down();
+    doc.move_down();
+    assert_eq!(doc.loc(), Loc::at(6, 2));
+    // The viewport scrolls by rows
+    assert_eq!(doc.offset, Loc::at(0, 1));
+    assert_eq!(doc.cursor_loc_in_screen(), Some(Loc::at(1, 2)));
+    assert_eq!(doc.visual_row(0), Some((1, 0..2)));
+    assert_eq!(doc.disp_loc_from_screen(Loc::at(4, 0)), Loc::at(2, 1));
+    assert_eq!(doc.disp_loc_from_screen(Loc::at(4, 1)), Loc::at(4, 2));
+    assert_eq!(doc.disp_loc_from_screen(Loc::at(4, 2)), Loc::at(9, 2));
+    doc.move_up();
+    doc.move_up();
+    doc.move_up();
+    assert_eq!(doc.loc(), Loc::at(9, 0));
+    assert_eq!(doc.offset, Loc::at(8, 0));
+    doc.scroll_up();
+    assert_eq!(doc.offset, Loc::at(3, 0));
+    doc.scroll_down();
+    doc.scroll_down();
+    doc.scroll_down();
+    assert_eq!(doc.offset, Loc::at(0, 2));
+    // Turning off wrapping
+    doc.set_wrap(None);
+    assert_eq!(doc.wrap_rows(0), vec![0]);
+    doc.move_to(&Loc::at(1, 0));
+    doc.old_cursor = 1;
+    doc.move_down();
+    assert_eq!(doc.loc(), Loc::at(1, 1));
+}
+
+#[test]
+fn document_folding() {
+    use kaolinite::document::folding::indent_level;
+    use kaolinite::document::Fold;
+    // Indentation
+    assert_eq!(indent_level("    a", 4), Some(4));
+    assert_eq!(indent_level("\tb", 4), Some(4));
+    assert_eq!(indent_level("c", 4), Some(0));
+    assert_eq!(indent_level("  ", 4), None);
+    // Working out regions
+    let mut doc = Document::new(Size::is(20, 3));
+    let lines = [
+        "fn main() {",
+        "    if x {",
+        "        a",
+        "    }",
+        "}",
+        "top",
+        "    indented",
+        "",
+        "    more",
+        "end",
+    ];
+    doc.exe(Event::Insert(Loc::at(0, 0), st!(lines[0])))
+        .unwrap();
+    for (y, line) in lines.iter().enumerate().skip(1) {
+        doc.exe(Event::InsertLine(y, st!(line))).unwrap();
+    }
+    assert_eq!(doc.fold_region(0, true), Some(3));
+    assert_eq!(doc.fold_region(0, false), Some(3));
+    assert_eq!(doc.fold_region(1, true), Some(2));
+    assert_eq!(doc.fold_region(2, true), None);
+    assert_eq!(doc.fold_region(5, false), Some(8));
+    assert_eq!(doc.fold_region(9, false), None);
+    // Folding and unfolding
+    doc.move_to(&Loc::at(0, 2));
+    assert!(doc.fold(2, true));
+    assert_eq!(doc.folds, vec![Fold { start: 1, end: 2 }]);
+    assert_eq!(doc.loc().y, 1);
+    assert!(doc.fold(0, true));
+    assert_eq!(doc.folds, vec![Fold { start: 0, end: 3 }]);
+    assert!(doc.unfold(0));
+    assert!(!doc.unfold(0));
+    assert!(doc.folds.is_empty());
+    doc.move_to(&Loc::at(0, 1));
+    doc.toggle_fold(true);
+    assert_eq!(doc.folds, vec![Fold { start: 1, end: 2 }]);
+    doc.toggle_fold(true);
+    assert!(doc.folds.is_empty());
+    // Moving around folds
+    doc.move_to(&Loc::at(0, 0));
+    doc.fold(0, true);
+    doc.move_down();
+    assert_eq!(doc.loc().y, 4);
+    assert_eq!(doc.cursor_loc_in_screen(), Some(Loc::at(0, 1)));
+    assert_eq!(doc.visual_row(1), Some((4, 0..1)));
+    doc.move_up();
+    assert_eq!(doc.loc().y, 0);
+    // Folds follow edits
+    doc.exe(Event::InsertLine(0, st!("// hi"))).unwrap();
+    assert_eq!(doc.folds, vec![Fold { start: 1, end: 4 }]);
+    doc.exe(Event::Insert(Loc::at(8, 3), st!("x"))).unwrap();
+    assert!(doc.folds.is_empty());
+    // Moving into a fold opens it
+    doc.fold(1, true);
+    assert_eq!(doc.folds, vec![Fold { start: 1, end: 4 }]);
+    doc.move_to(&Loc::at(0, 3));
+    assert!(doc.folds.is_empty());
+    assert_eq!(doc.loc().y, 3);
+    // Folding everything
+    doc.fold_all(false);
+    assert_eq!(
+        doc.folds,
+        vec![Fold { start: 1, end: 4 }, Fold { start: 6, end: 9 }]
+    );
+    assert_eq!(doc.loc().y, 1);
+    doc.unfold_all();
+    assert!(doc.folds.is_empty());
+}
+
+#[test]
+fn snippets() {
+    use kaolinite::snippet::Snippet;
+    let vars = |name: &str| match name {
+        "FILENAME" => Some(st!("main.rs")),
+        "SELECTION" => Some(st!("")),
+        _ => None,
+    };
+    // Tab stops, placeholders and the final stop
+    let snippet = Snippet::expand("fn ${1:name}($2) {\n\t$0\n}", vars);
+    assert_eq!(snippet.text, "fn name() {\n\t\n}");
+    let stops: Vec<_> = snippet
+        .stops
+        .iter()
+        .map(|s| (s.number, s.ranges.clone()))
+        .collect();
+    assert_eq!(
+        stops,
+        vec![
+            (1, vec![(Loc::at(3, 0), Loc::at(7, 0))]),
+            (2, vec![(Loc::at(8, 0), Loc::at(8, 0))]),
+            (0, vec![(Loc::at(1, 1), Loc::at(1, 1))]),
+        ]
+    );
+    // Mirrors take the placeholder of the first tab stop to have one
+    let snippet = Snippet::expand("$1 = ${1:x} + ${2:y}", vars);
+    assert_eq!(snippet.text, "x = x + y");
+    assert_eq!(
+        snippet.stops[0].ranges,
+        vec![
+            (Loc::at(0, 0), Loc::at(1, 0)),
+            (Loc::at(4, 0), Loc::at(5, 0))
+        ]
+    );
+    assert_eq!(
+        snippet.stops[2].ranges,
+        vec![(Loc::at(9, 0), Loc::at(9, 0))]
+    );
+    // Choices, nesting and variables
+    let snippet = Snippet::expand("${1|pub,pub(crate)|} ${2:a ${3:b}} // $FILENAME", vars);
+    assert_eq!(snippet.text, "pub a b // main.rs");
+    assert_eq!(
+        snippet.stops[0].choices,
+        vec![st!("pub"), st!("pub(crate)")]
+    );
+    assert_eq!(
+        snippet.stops[1].ranges,
+        vec![(Loc::at(4, 0), Loc::at(7, 0))]
+    );
+    assert_eq!(
+        snippet.stops[2].ranges,
+        vec![(Loc::at(6, 0), Loc::at(7, 0))]
+    );
+    let snippet = Snippet::expand("${SELECTION:none} $UNKNOWN ${UNKNOWN:default}", vars);
+    assert_eq!(snippet.text, "none UNKNOWN default");
+    // Escapes and things that aren't tab stops
+    let snippet = Snippet::expand(r"\$1 costs $ 5 {\}} ${", vars);
+    assert_eq!(snippet.text, "$1 costs $ 5 {}} ${");
+    assert_eq!(snippet.stops.len(), 1);
+}
+
+#[test]
+fn terminal_emulation() {
+    use kaolinite::terminal::{Colour, Screen};
+    // Text, wrapping and scrolling into the scrollback
+    let mut screen = Screen::new(5, 2);
+    screen.feed(b"hello world\r\nbye");
+    assert_eq!(screen.text(), "hello\n worl\nd\nbye");
+    assert_eq!(screen.scrollback.len(), 2);
+    assert_eq!(screen.cursor, Loc::at(3, 1));
+    screen.scroll_view(true, 10);
+    assert_eq!(screen.view_offset, 2);
+    assert_eq!(screen.row(0).unwrap()[0].ch, 'h');
+    assert_eq!(screen.cursor_in_view(), None);
+    screen.scroll_view(false, 10);
+    assert_eq!(screen.cursor_in_view(), Some(Loc::at(3, 1)));
+    // Cursor addressing and erasing
+    let mut screen = Screen::new(10, 3);
+    screen.feed(b"abcdef\x1b[2;3Hxy\x1b[1;3H\x1b[K");
+    assert_eq!(screen.text(), "ab\n  xy");
+    screen.feed(b"\x1b[2J\x1b[H\x1b[3Cz\x1b[1D\x1b[2@");
+    assert_eq!(screen.text(), "     z");
+    // Colours and attributes
+    screen.feed(b"\x1b[H\x1b[1;31;48;5;200mA\x1b[38;2;1;2;3mB\x1b[0mC");
+    let row = screen.row(0).unwrap();
+    assert_eq!(row[0].style.fg, Colour::Indexed(1));
+    assert_eq!(row[0].style.bg, Colour::Indexed(200));
+    assert!(row[0].style.bold);
+    assert_eq!(row[1].style.fg, Colour::Rgb(1, 2, 3));
+    assert_eq!(row[2].style, Default::default());
+    // Alternate screen, modes and queries
+    screen.feed(b"\x1b[?1049h\x1b[?2004h\x1b[?1h\x1b[Hfull");
+    assert!(screen.alternate() && screen.bracketed_paste && screen.application_cursor);
+    assert_eq!(screen.row(0).unwrap()[0].ch, 'f');
+    screen.feed(b"\x1b[?1049l\x1b[6n");
+    assert!(!screen.alternate());
+    assert_eq!(screen.row(0).unwrap()[0].ch, 'A');
+    assert_eq!(screen.take_responses(), b"\x1b[1;4R");
+    // Titles, split characters, double width characters and resizing
+    screen.feed(b"\x1b]0;title\x07\x1b[3;1H\xe4\xbd");
+    screen.feed(b"\xa0!");
+    assert_eq!(screen.title, "title");
+    assert_eq!(line_text(screen.row(2).unwrap()), "你!");
+    screen.resize(4, 2);
+    assert_eq!(screen.cursor, Loc::at(3, 1));
+    assert_eq!(screen.text(), "ABC  z\n\n你!");
+    // Huge counts are clamped rather than overflowing
+    let max = usize::MAX;
+    let mut screen = Screen::new(4, 3);
+    screen.feed(format!("ab\x1b[2;2H\x1b[{max}B\x1b[{max}C").as_bytes());
+    assert_eq!(screen.cursor, Loc::at(3, 2));
+    screen.feed(format!("\x1b[{max}E\x1b[H\x1b[{max}X").as_bytes());
+    assert_eq!(screen.cursor, Loc::at(0, 0));
+    assert_eq!(screen.text(), "");
+}
+
+#[test]
+fn document_marks() {
+    let mut doc = Document::new(Size::is(20, 3));
+    doc.exe(Event::Insert(Loc::at(0, 0), st!("let x = 1;")))
+        .unwrap();
+    let start = doc.add_mark(Loc::at(4, 0), false);
+    let end = doc.add_mark(Loc::at(5, 0), true);
+    let after = doc.add_mark(Loc::at(8, 0), false);
+    // Insertions push along marks after them (and marks right at them that ask for it)
+    doc.exe(Event::Insert(Loc::at(5, 0), st!("yz"))).unwrap();
+    doc.exe(Event::Insert(Loc::at(4, 0), st!("w"))).unwrap();
+    assert_eq!(doc.line(0).unwrap(), "let wxyz = 1;");
+    assert_eq!(doc.marks[start].loc, Loc::at(4, 0));
+    assert_eq!(doc.marks[end].loc, Loc::at(8, 0));
+    assert_eq!(doc.marks[after].loc, Loc::at(11, 0));
+    // Deletions pull marks back, bunching up marks inside them
+    doc.exe(Event::Delete(Loc::at(5, 0), st!("xyz"))).unwrap();
+    assert_eq!(doc.marks[end].loc, Loc::at(5, 0));
+    doc.exe(Event::Delete(Loc::at(3, 0), st!(" w"))).unwrap();
+    assert_eq!(doc.marks[start].loc, Loc::at(3, 0));
+    assert_eq!(doc.marks[end].loc, Loc::at(3, 0));
+    assert_eq!(doc.marks[after].loc, Loc::at(6, 0));
+    // Splitting and splicing lines carries marks between lines
+    doc.exe(Event::SplitDown(Loc::at(4, 0))).unwrap();
+    assert_eq!(doc.line(1).unwrap(), "= 1;");
+    assert_eq!(doc.marks[start].loc, Loc::at(3, 0));
+    assert_eq!(doc.marks[after].loc, Loc::at(2, 1));
+    doc.exe(Event::InsertLine(0, st!("fn main() {"))).unwrap();
+    assert_eq!(doc.marks[after].loc, Loc::at(2, 2));
+    doc.exe(Event::SpliceUp(Loc::at(4, 1))).unwrap();
+    assert_eq!(doc.marks[after].loc, Loc::at(6, 1));
+    doc.exe(Event::DeleteLine(0, st!("fn main() {"))).unwrap();
+    assert_eq!(doc.marks[start].loc, Loc::at(3, 0));
+    assert_eq!(doc.marks[after].loc, Loc::at(6, 0));
+}
+
+#[test]
+fn document_version() {
+    let mut doc = Document::new(Size::is(20, 3));
+    assert_eq!(doc.version, 0);
+    doc.exe(Event::Insert(Loc::at(0, 0), st!("hello"))).unwrap();
+    let edited = doc.version;
+    assert!(edited > 0);
+    // Moving around doesn't change the document
+    doc.move_to(&Loc::at(2, 0));
+    doc.select_to(&Loc::at(4, 0));
+    assert_eq!(doc.version, edited);
+    // Every kind of change does, including undoing and redoing
+    doc.exe(Event::InsertLine(1, st!("world"))).unwrap();
+    let inserted = doc.version;
+    assert!(inserted > edited);
+    doc.commit();
+    doc.undo().unwrap();
+    assert!(doc.version > inserted);
+    let undone = doc.version;
+    doc.redo().unwrap();
+    assert!(doc.version > undone);
+}
+
+#[test]
+fn language_server() {
+    use kaolinite::lsp::*;
+    use serde_json::{json, Value};
+    use std::io::BufReader;
+    // Uris
+    assert_eq!(path_to_uri("/tmp/a b.rs"), st!("file:///tmp/a%20b.rs"));
+    assert_eq!(
+        uri_to_path("file:///tmp/a%20b.rs"),
+        Some(st!("/tmp/a b.rs"))
+    );
+    assert_eq!(uri_to_path("file:///C:/a.rs"), Some(st!("C:/a.rs")));
+    assert_eq!(uri_to_path("http://a.rs"), None);
+    // Positions and severities
+    assert_eq!(PositionEncoding::Utf16.char_index("a😀b", 3), 2);
+    assert_eq!(PositionEncoding::Utf8.char_index("a😀b", 5), 2);
+    assert_eq!(PositionEncoding::Utf32.char_index("a😀b", 9), 3);
+    assert_eq!(Severity::from_name("hint"), Some(Severity::Hint));
+    assert_eq!(Severity::from_name("fatal"), None);
+    assert_eq!(Severity::Warning.name(), "warning");
+    // A mock server that reports what it is sent
+    let (server_in, client_out) = std::io::pipe().unwrap();
+    let (client_in, mut server_out) = std::io::pipe().unwrap();
+    let (report, received) = std::sync::mpsc::channel::<Value>();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(server_in);
+        while let Some(message) = read_message(&mut reader) {
+            let reply = match message["method"].as_str() {
+                Some("initialize") => json!({ "id": message["id"], "result": {
+                    "capabilities": { "positionEncoding": "utf-32", "textDocumentSync": 2 },
+                }}),
+                Some("textDocument/didOpen") => json!({
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {
+                        "uri": message["params"]["textDocument"]["uri"],
+                        "diagnostics": [{
+                            "range": {
+                                "start": { "line": 0, "character": 3 },
+                                "end": { "line": 0, "character": 7 },
+                            },
+                            "severity": 2,
+                            "message": "unused function",
+                        }],
+                    },
+                }),
+                Some("textDocument/hover") => json!({ "id": message["id"], "result": {
+                    "contents": { "kind": "markdown", "value": "fn main()" },
+                }}),
+                _ => Value::Null,
+            };
+            if !reply.is_null() {
+                write_message(&mut server_out, &reply).unwrap();
+            }
+            report.send(message).unwrap();
+        }
+    });
+    let next = || {
+        received
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap()
+    };
+    let mut client = Client::new(client_in, client_out, "/tmp").unwrap();
+    let mut doc = Document::new(Size::is(100, 10));
+    doc.file_name = Some(st!("/tmp/main.rs"));
+    doc.exe(Event::Insert(Loc::at(0, 0), st!("fn main() {}")))
+        .unwrap();
+    // Opening documents (which waits until the server is initialised)
+    client.sync_document(&mut doc, "rust").unwrap();
+    assert_eq!(
+        client.hover(&doc, Loc::at(3, 0)).unwrap(),
+        Some(st!("fn main()"))
+    );
+    assert_eq!(next()["method"], "initialize");
+    assert_eq!(next()["method"], "initialized");
+    let open = next();
+    assert_eq!(open["params"]["textDocument"]["text"], "fn main() {}\n");
+    assert_eq!(open["params"]["textDocument"]["uri"], "file:///tmp/main.rs");
+    assert_eq!(next()["params"]["position"]["character"], 3);
+    assert_eq!(client.encoding, PositionEncoding::Utf32);
+    // Diagnostics
+    let diagnostics = client.diagnostics_for("/tmp/main.rs");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].message, "unused function");
+    assert_eq!(diagnostics[0].range.end.character, 7);
+    // Edits are sent as they are made
+    doc.exe(Event::Insert(Loc::at(3, 0), st!("x"))).unwrap();
+    doc.exe(Event::SplitDown(Loc::at(11, 0))).unwrap();
+    client.sync_document(&mut doc, "rust").unwrap();
+    let change = next();
+    assert_eq!(change["params"]["textDocument"]["version"], 2);
+    let changes = &change["params"]["contentChanges"];
+    assert_eq!(changes[0]["text"], "x");
+    assert_eq!(changes[0]["range"]["start"]["character"], 3);
+    // Splitting a line removes the end of it and inserts it as a new line
+    assert_eq!(changes[1]["text"], "");
+    assert_eq!(changes[1]["range"]["start"]["character"], 11);
+    assert_eq!(changes[1]["range"]["end"]["character"], 13);
+    assert_eq!(changes[2]["text"], "{}\n");
+    assert_eq!(changes[2]["range"]["start"]["line"], 1);
+    // Nothing is sent when nothing has changed
+    client.sync_document(&mut doc, "rust").unwrap();
+    client.save_document(&doc).unwrap();
+    assert_eq!(next()["method"], "textDocument/didSave");
+    // Making edits from the server
+    let edit = |(sl, sc), (el, ec), text: &str| TextEdit {
+        range: Range {
+            start: Position {
+                line: sl,
+                character: sc,
+            },
+            end: Position {
+                line: el,
+                character: ec,
+            },
+        },
+        text: st!(text),
+    };
+    client
+        .apply_edits(
+            &mut doc,
+            &[
+                edit((1, 0), (1, 0), "    "),
+                edit((0, 0), (0, 8), "pub fn go"),
+            ],
+        )
+        .unwrap();
+    assert_eq!(doc.line(0), Some(st!("pub fn go() ")));
+    assert_eq!(doc.line(1), Some(st!("    {}")));
+    client
+        .apply_edits(&mut doc, &[edit((0, 11), (1, 6), " {}\n// end")])
+        .unwrap();
+    assert_eq!(doc.line(0), Some(st!("pub fn go() {}")));
+    assert_eq!(doc.line(1), Some(st!("// end")));
+    assert_eq!(doc.len_lines(), 2);
+    client.close_document("file:///tmp/main.rs").unwrap();
+    assert!(client.diagnostics_for("/tmp/main.rs").is_empty());
+    assert_eq!(next()["method"], "textDocument/didClose");
+}
+
 #[test]
 fn document_insertion() {
     let mut doc = Document::open(Size::is(100, 10), "tests/data/unicode.txt").unwrap();
@@ -406,38 +1226,222 @@ fn document_deletion() {
 fn document_undo_redo() {
     let mut doc = Document::open(Size::is(100, 10), "tests/data/unicode.txt").unwrap();
     doc.load_to(100);
-    assert!(doc.event_mgmt.undo(doc.take_snapshot()).is_none());
-    assert!(doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(doc.event_mgmt.undo(doc.cursor).is_none());
+    assert!(doc.event_mgmt.with_disk());
     doc.event_mgmt.force_not_with_disk = true;
-    assert!(!doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(!doc.event_mgmt.with_disk());
     doc.event_mgmt.force_not_with_disk = false;
-    assert!(doc.event_mgmt.with_disk(&doc.take_snapshot()));
-    assert!(doc.event_mgmt.undo(doc.take_snapshot()).is_none());
+    assert!(doc.event_mgmt.with_disk());
+    assert!(doc.event_mgmt.undo(doc.cursor).is_none());
     assert!(doc.redo().is_ok());
-    assert!(doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(doc.event_mgmt.with_disk());
     doc.exe(Event::InsertLine(0, st!("hello你bye好hello")));
     doc.exe(Event::Delete(Loc { x: 0, y: 2 }, st!("\t")));
     doc.exe(Event::Insert(Loc { x: 3, y: 2 }, st!("a")));
-    assert!(!doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(!doc.event_mgmt.with_disk());
     doc.commit();
-    assert!(!doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(!doc.event_mgmt.with_disk());
     assert!(doc.undo().is_ok());
-    assert!(doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(doc.event_mgmt.with_disk());
     assert_eq!(doc.line(0), Some(st!("    你好")));
     assert_eq!(doc.line(1), Some(st!("\thello")));
     assert_eq!(doc.line(2), Some(st!("    hello")));
     assert!(doc.redo().is_ok());
-    assert!(!doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(!doc.event_mgmt.with_disk());
     assert_eq!(doc.line(0), Some(st!("hello你bye好hello")));
     assert_eq!(doc.line(2), Some(st!("helalo")));
-    assert!(!doc.event_mgmt.with_disk(&doc.take_snapshot()));
-    doc.event_mgmt.disk_write(&doc.take_snapshot());
-    assert!(doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(!doc.event_mgmt.with_disk());
+    doc.event_mgmt.disk_write(doc.cursor);
+    assert!(doc.event_mgmt.with_disk());
     let mut doc = Document::open(Size::is(100, 10), "tests/data/unicode.txt").unwrap();
     doc.load_to(100);
-    assert!(doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(doc.event_mgmt.with_disk());
     doc.exe(Event::InsertLine(0, st!("hello你bye好hello")));
-    assert!(doc.event_mgmt.with_disk(&doc.take_snapshot()));
+    assert!(doc.event_mgmt.with_disk());
+}
+
+#[test]
+fn document_undo_tree() {
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/unicode.txt").unwrap();
+    doc.load_to(100);
+    doc.commit();
+    doc.exe(Event::Insert(Loc { x: 0, y: 1 }, st!("a")));
+    doc.commit();
+    // Undoing and then editing should keep the old branch around
+    assert!(doc.undo().is_ok());
+    doc.exe(Event::Insert(Loc { x: 0, y: 1 }, st!("b")));
+    doc.commit();
+    assert_eq!(doc.event_mgmt.branches(), vec![1, 2]);
+    assert_eq!(doc.event_mgmt.current_branch(), Some(2));
+    assert!(doc.redo().is_ok());
+    assert_eq!(doc.line(1), Some(st!("b\thello")));
+    // Jump between branches
+    assert!(doc.switch_branch(1).is_ok());
+    assert_eq!(doc.line(1), Some(st!("a\thello")));
+    assert_eq!(doc.event_mgmt.current_branch(), Some(1));
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.line(1), Some(st!("\thello")));
+    assert!(doc.redo().is_ok());
+    assert_eq!(doc.line(1), Some(st!("a\thello")));
+    assert!(doc.switch_branch(2).is_ok());
+    assert_eq!(doc.line(1), Some(st!("b\thello")));
+    assert_eq!(doc.event_mgmt.history.len(), 3);
+    // Travel through time
+    assert!(doc.earlier(std::time::Duration::from_secs(60)).is_ok());
+    assert_eq!(doc.line(1), Some(st!("\thello")));
+    assert_eq!(doc.event_mgmt.ptr, Some(0));
+    assert!(doc.later(std::time::Duration::from_secs(60)).is_ok());
+    assert_eq!(doc.line(1), Some(st!("b\thello")));
+    assert_eq!(doc.event_mgmt.ptr, Some(2));
+    assert!(doc.later(std::time::Duration::from_secs(60)).is_ok());
+    assert_eq!(doc.event_mgmt.ptr, Some(2));
+    // Periods too long to represent go to the ends of history
+    assert!(doc.earlier(std::time::Duration::MAX).is_ok());
+    assert_eq!(doc.event_mgmt.ptr, Some(0));
+    assert!(doc.later(std::time::Duration::MAX).is_ok());
+    assert_eq!(doc.event_mgmt.ptr, Some(2));
+}
+
+#[test]
+fn document_undo_deltas() {
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/unicode.txt").unwrap();
+    doc.load_to(100);
+    let (file, lines) = (doc.file.to_string(), doc.lines.clone());
+    doc.commit();
+    doc.exe(Event::Insert(Loc { x: 0, y: 1 }, st!("ab")));
+    doc.exe(Event::SplitDown(Loc { x: 1, y: 1 }));
+    doc.commit();
+    // States only store the events that lead to them
+    assert_eq!(
+        doc.event_mgmt.history[1].patch,
+        vec![
+            Event::Insert(Loc { x: 0, y: 1 }, st!("ab")),
+            Event::Delete(Loc { x: 1, y: 1 }, st!("b\thello")),
+            Event::InsertLine(2, st!("b\thello")),
+        ]
+    );
+    let (edited_file, edited_lines) = (doc.file.to_string(), doc.lines.clone());
+    // Removing a selection over multiple lines can be undone
+    doc.move_to(&Loc { x: 2, y: 0 });
+    doc.select_to(&Loc { x: 0, y: 3 });
+    doc.remove_selection();
+    doc.commit();
+    assert_eq!(doc.line(0), Some(st!("      hello")));
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.file.to_string(), edited_file);
+    assert_eq!(doc.lines, edited_lines);
+    assert!(doc.redo().is_ok());
+    assert_eq!(doc.line(0), Some(st!("      hello")));
+    // Everything can be undone back to the original state
+    assert!(doc.undo().is_ok());
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.file.to_string(), file);
+    assert_eq!(doc.lines, lines);
+    assert!(doc.event_mgmt.with_disk());
+    // Nothing is registered if there are no changes
+    doc.commit();
+    assert_eq!(doc.event_mgmt.history.len(), 3);
+}
+
+#[test]
+fn document_history_persistence() {
+    let store = std::path::Path::new("tests/data/history_store");
+    std::fs::write("tests/data/history.txt", "hello\nworld\n").unwrap();
+    // Make some edits and store the history
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/history.txt").unwrap();
+    doc.load_to(100);
+    doc.commit();
+    doc.exe(Event::Insert(Loc { x: 5, y: 0 }, st!(" there")));
+    doc.commit();
+    doc.exe(Event::DeleteLine(1, st!("world")));
+    doc.commit();
+    assert!(doc.undo().is_ok());
+    doc.save_history(store).unwrap();
+    // Restore the history (the file on the disk is unchanged)
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/history.txt").unwrap();
+    doc.load_to(100);
+    assert!(doc.load_history(store));
+    assert_eq!(doc.event_mgmt.history.len(), 3);
+    assert_eq!(doc.event_mgmt.ptr, Some(0));
+    assert_eq!(doc.event_mgmt.history[2].parent, Some(1));
+    assert!(doc.event_mgmt.with_disk());
+    assert!(doc.redo().is_ok());
+    assert_eq!(doc.line(0), Some(st!("hello there")));
+    assert!(doc.redo().is_ok());
+    assert_eq!(doc.line(1), Some(st!("")));
+    assert!(doc.undo().is_ok());
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.line(0), Some(st!("hello")));
+    // Changes to the file on the disk should invalidate the history
+    std::fs::write("tests/data/history.txt", "goodbye\n").unwrap();
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/history.txt").unwrap();
+    assert!(!doc.load_history(store));
+    assert!(doc.event_mgmt.history.is_empty());
+    // Malformed history should be rejected
+    assert!(EventMgmt::deserialize(b"not a history", &doc.file).is_none());
+    // Histories with cycles in them should be rejected
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/history.txt").unwrap();
+    doc.load_to(100);
+    doc.exe(Event::Insert(Loc { x: 0, y: 0 }, st!("well ")));
+    doc.commit();
+    let data = String::from_utf8(doc.event_mgmt.serialize(&doc.file)).unwrap();
+    let mut lines: Vec<String> = data.split('\n').map(str::to_string).collect();
+    let mut root: Vec<&str> = lines[4].split(' ').collect();
+    root[4] = "1";
+    lines[4] = root.join(" ");
+    let cyclic = lines.join("\n");
+    assert!(EventMgmt::deserialize(cyclic.as_bytes(), &doc.file).is_none());
+    assert!(EventMgmt::deserialize(data.as_bytes(), &doc.file).is_some());
+    // Clean up
+    std::fs::remove_dir_all(store).unwrap();
+    std::fs::remove_file("tests/data/history.txt").unwrap();
+}
+
+#[test]
+fn document_swap_files() {
+    let store = std::path::Path::new("tests/data/swap_store");
+    std::fs::write("tests/data/swap.txt", "hello\nworld\n").unwrap();
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/swap.txt").unwrap();
+    doc.load_to(100);
+    assert!(doc.read_swap(store).is_none());
+    doc.insert(&Loc::at(5, 0), " there").unwrap();
+    doc.write_swap(store).unwrap();
+    // Swap file contents can be recovered into a fresh copy of the document
+    let mut doc = Document::open(Size::is(100, 10), "tests/data/swap.txt").unwrap();
+    doc.load_to(100);
+    let (pid, contents) = doc.read_swap(store).unwrap();
+    assert_eq!(pid, std::process::id());
+    assert_eq!(contents, st!("hello there\nworld\n"));
+    doc.set_contents(&contents).unwrap();
+    assert_eq!(doc.file.to_string(), contents);
+    assert_eq!(doc.line(1), Some(st!("world")));
+    assert!(!doc.event_mgmt.with_disk());
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.file.to_string(), st!("hello\nworld\n"));
+    // Replacing contents only touches lines that differ, and can be undone in one go
+    doc.set_contents("a\nhello\nb\nc\n").unwrap();
+    assert_eq!(doc.file.to_string(), st!("a\nhello\nb\nc\n"));
+    doc.set_contents("").unwrap();
+    assert_eq!(doc.file.to_string(), st!(""));
+    assert_eq!(doc.lines, vec![st!("")]);
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.file.to_string(), st!("a\nhello\nb\nc\n"));
+    assert!(doc.undo().is_ok());
+    assert_eq!(doc.file.to_string(), st!("hello\nworld\n"));
+    // Line endings are kept
+    doc.info.line_ending = LineEnding::CrLf;
+    doc.set_contents("hello\r\nthere\r\nworld\n").unwrap();
+    assert_eq!(doc.file.to_string(), st!("hello\r\nthere\r\nworld\n"));
+    assert_eq!(
+        doc.lines,
+        vec![st!("hello"), st!("there"), st!("world"), st!("")]
+    );
+    // Swap files can be removed
+    doc.remove_swap(store);
+    assert!(doc.read_swap(store).is_none());
+    // Clean up
+    std::fs::remove_dir_all(store).unwrap();
+    std::fs::remove_file("tests/data/swap.txt").unwrap();
 }
 
 #[test]
@@ -649,6 +1653,15 @@ fn document_moving() {
     assert_eq!(doc.next_word_close(Loc { x: 20, y: 11 }), 20);
     assert_eq!(doc.next_word_close(Loc { x: 24, y: 11 }), 24);
     assert_eq!(doc.next_word_close(Loc { x: 22, y: 11 }), 24);
+    // Word boundaries are byte indices, even around wide and non-breaking whitespace
+    let line = "a\u{3000}\u{3000}b";
+    assert_eq!(doc.word_boundaries(line), vec![(0, 1), (1, 7), (7, 8)]);
+    let line = "x\u{a0}\u{a0}y  z";
+    let words = doc.word_boundaries(line);
+    assert_eq!(words, vec![(0, 1), (1, 5), (5, 6), (6, 8), (8, 9)]);
+    assert!(words
+        .iter()
+        .all(|(start, end)| line.get(*start..*end).is_some()));
     doc.move_to(&Loc {
         x: 0,
         y: 10000000000,
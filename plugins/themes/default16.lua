@@ -48,6 +48,9 @@ colors.error_fg = red
 colors.selection_bg = darkgrey
 colors.selection_fg = cyan
 
+colors.search_match_bg = yellow
+colors.search_match_fg = black
+
 colors.file_tree_bg = black
 colors.file_tree_fg = white
 colors.file_tree_selection_bg = darkgrey
@@ -44,6 +44,9 @@ colors.error_fg = red
 colors.selection_bg = grey1
 colors.selection_fg = lightblue
 
+colors.search_match_bg = grey2
+colors.search_match_fg = yellow
+
 colors.file_tree_bg = black
 colors.file_tree_fg = white
 colors.file_tree_selection_bg = purple
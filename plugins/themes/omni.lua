@@ -44,6 +44,9 @@ colors.error_fg = red
 colors.selection_bg = selection
 colors.selection_fg = foreground
 
+colors.search_match_bg = comment
+colors.search_match_fg = yellow
+
 colors.file_tree_bg = background
 colors.file_tree_fg = foreground
 colors.file_tree_selection_bg = pink
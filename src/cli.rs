@@ -17,7 +17,7 @@ OPTIONS:
   --version, -v                : Show the version number
   --config [path], -c [path]   : Specify the configuration file
   --readonly, -r               : Prevent opened files from writing
-  --filetype [name], -f [name] : Set the file type of files opened
+  --filetype [name], -f [name] : Set the file type of files opened (hex for a hex view)
   --stdin                      : Reads file from the stdin
   --config-assist              : Activate the configuration assistant
 
@@ -37,6 +37,9 @@ pub struct Colors {
     pub selection_fg: Color,
     pub selection_bg: Color,
 
+    pub search_match_fg: Color,
+    pub search_match_bg: Color,
+
     pub file_tree_fg: Color,
     pub file_tree_bg: Color,
     pub file_tree_selection_fg: Color,
@@ -86,6 +89,9 @@ impl Default for Colors {
             selection_fg: Color::Rgb(255, 255, 255),
             selection_bg: Color::Rgb(59, 59, 130),
 
+            search_match_fg: Color::Rgb(255, 255, 255),
+            search_match_bg: Color::Rgb(96, 84, 48),
+
             file_tree_bg: Color::Rgb(41, 41, 61),
             file_tree_fg: Color::Rgb(255, 255, 255),
             file_tree_selection_bg: Color::Rgb(59, 59, 130),
@@ -145,6 +151,12 @@ impl LuaUserData for Colors {
         fields.add_field_method_get("selection_bg", |env, this| {
             Ok(this.selection_bg.to_lua(env))
         });
+        fields.add_field_method_get("search_match_fg", |env, this| {
+            Ok(this.search_match_fg.to_lua(env))
+        });
+        fields.add_field_method_get("search_match_bg", |env, this| {
+            Ok(this.search_match_bg.to_lua(env))
+        });
         fields.add_field_method_set("editor_bg", |_, this, value| {
             this.editor_bg = Color::from_lua(value);
             Ok(())
@@ -229,6 +241,14 @@ impl LuaUserData for Colors {
             this.selection_bg = Color::from_lua(value);
             Ok(())
         });
+        fields.add_field_method_set("search_match_fg", |_, this, value| {
+            this.search_match_fg = Color::from_lua(value);
+            Ok(())
+        });
+        fields.add_field_method_set("search_match_bg", |_, this, value| {
+            this.search_match_bg = Color::from_lua(value);
+            Ok(())
+        });
         fields.add_field_method_set("file_tree_bg", |_, this, value| {
             this.file_tree_bg = Color::from_lua(value);
             Ok(())
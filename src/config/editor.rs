@@ -2,18 +2,28 @@
 use crate::cli::VERSION;
 #[cfg(not(target_os = "windows"))]
 use crate::config::runner::RunCommand;
-use crate::editor::{Editor, FileContainer, FileLayout};
+use crate::config::Indentation;
+use crate::editor::{
+    parse_duration, Diagnostic, Editor, FileContainer, FileLayout, QuickfixEntry, QuickfixList,
+};
+use crate::error::OxError;
 #[cfg(not(target_os = "windows"))]
-use crate::pty::Pty;
+use crate::pty::{Program, Pty};
 use crate::ui::Feedback;
 use crate::{config, fatal_error, PLUGIN_BOOTSTRAP, PLUGIN_MANAGER, PLUGIN_NETWORKING, PLUGIN_RUN};
+use kaolinite::document::{Encoding, LineEnding};
+use kaolinite::lsp::Severity;
 use kaolinite::utils::{get_absolute_path, get_cwd, get_file_ext, get_file_name};
 use kaolinite::Loc;
 use mlua::prelude::*;
 #[cfg(not(target_os = "windows"))]
 use std::collections::HashMap;
+use std::path::Path;
+#[cfg(not(target_os = "windows"))]
+use std::sync::{Arc, Mutex};
 
 impl LuaUserData for Editor {
+    #[allow(clippy::too_many_lines)]
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
         fields.add_field_method_get("cursor", |_, editor| {
             if let Some(doc) = editor.try_doc() {
@@ -67,6 +77,41 @@ impl LuaUserData for Editor {
                 .clone()
                 .map_or("Unknown".to_string(), |ft| ft.name))
         });
+        fields.add_field_method_get("line_ending", |_, editor| {
+            Ok(editor.try_doc().map(|doc| doc.info.line_ending.to_string()))
+        });
+        fields.add_field_method_get("encoding", |_, editor| {
+            Ok(editor.try_doc().map(|doc| doc.info.encoding.to_string()))
+        });
+        fields.add_field_method_get("soft_wrap", |_, editor| {
+            Ok(editor.try_doc().is_some_and(|doc| doc.wrap.is_some()))
+        });
+        fields.add_field_method_get("folds", |lua, editor| {
+            let folds = lua.create_table()?;
+            if let Some(doc) = editor.try_doc() {
+                for fold in &doc.folds {
+                    let range = lua.create_table()?;
+                    range.set("first", fold.start + 1)?;
+                    range.set("last", fold.end + 1)?;
+                    folds.push(range)?;
+                }
+            }
+            Ok(folds)
+        });
+        fields.add_field_method_get("completing", |_, editor| Ok(editor.completion.is_some()));
+        fields.add_field_method_get("search_history", |_, editor| {
+            Ok(editor.query_history.search.clone())
+        });
+        fields.add_field_method_get("replace_history", |_, editor| {
+            Ok(editor.query_history.replace.clone())
+        });
+        fields.add_field_method_get("quickfix", |_, editor| Ok(editor.quickfix.entries.clone()));
+        fields.add_field_method_get("in_snippet", |_, editor| {
+            Ok(editor
+                .files
+                .get(editor.ptr.clone())
+                .is_some_and(|file| file.snippet.is_some()))
+        });
         fields.add_field_method_get("file_name", |_, editor| {
             if let Some(doc) = editor.try_doc() {
                 Ok(Some(get_file_name(
@@ -550,6 +595,13 @@ impl LuaUserData for Editor {
             }
             Ok(())
         });
+        methods.add_method_mut("find_file", |_, editor, ()| {
+            if let Err(err) = editor.find_file() {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            Ok(())
+        });
         methods.add_method_mut("save", |_, editor, ()| {
             if let Err(err) = editor.save() {
                 editor.feedback = Feedback::Error(err.to_string());
@@ -588,6 +640,58 @@ impl LuaUserData for Editor {
             editor.update_highlighter();
             Ok(())
         });
+        methods.add_method("undo_branches", |_, editor, ()| {
+            let mut result = vec![];
+            if let Some(doc) = editor.try_doc() {
+                let history = &doc.event_mgmt.history;
+                let current = doc.event_mgmt.current_branch();
+                for (i, idx) in doc.event_mgmt.branches().into_iter().enumerate() {
+                    // Count the number of states that lead up to this branch tip
+                    let mut changes = 0;
+                    let mut node = history[idx].parent;
+                    while let Some(parent) = node {
+                        changes += 1;
+                        node = history[parent].parent;
+                    }
+                    result.push(LuaUndoBranch {
+                        id: i + 1,
+                        changes,
+                        age: history[idx].time.elapsed().unwrap_or_default().as_secs(),
+                        current: current == Some(idx),
+                    });
+                }
+            }
+            Ok(result)
+        });
+        methods.add_method_mut("switch_undo_branch", |_, editor, branch: usize| {
+            if let Err(err) = editor.switch_undo_branch(branch.saturating_sub(1)) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            Ok(())
+        });
+        methods.add_method_mut("undo_earlier", |_, editor, period: String| {
+            if let Some(duration) = parse_duration(&period) {
+                if let Err(err) = editor.undo_earlier(duration) {
+                    editor.feedback = Feedback::Error(err.to_string());
+                }
+                editor.update_highlighter();
+            } else {
+                editor.feedback = Feedback::Error(format!("{period} is not a valid time period"));
+            }
+            Ok(())
+        });
+        methods.add_method_mut("undo_later", |_, editor, period: String| {
+            if let Some(duration) = parse_duration(&period) {
+                if let Err(err) = editor.undo_later(duration) {
+                    editor.feedback = Feedback::Error(err.to_string());
+                }
+                editor.update_highlighter();
+            } else {
+                editor.feedback = Feedback::Error(format!("{period} is not a valid time period"));
+            }
+            Ok(())
+        });
         methods.add_method_mut("commit", |_, editor, ()| {
             if let Some(doc) = editor.try_doc_mut() {
                 doc.commit();
@@ -721,6 +825,30 @@ impl LuaUserData for Editor {
             let _ = editor.render(lua);
             Ok(())
         });
+        methods.add_method_mut("search_project", |lua, editor, pattern: Option<String>| {
+            if let Err(err) = editor.search_project(pattern) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            editor.needs_rerender = true;
+            let _ = editor.render(lua);
+            Ok(())
+        });
+        methods.add_method_mut("replace_project", |lua, editor, ()| {
+            if let Err(err) = editor.replace_project(lua) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            editor.needs_rerender = true;
+            let _ = editor.render(lua);
+            Ok(())
+        });
+        methods.add_method_mut("open_match", |_, editor, ()| {
+            if let Err(err) = editor.jump_to_hit() {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            Ok(())
+        });
         methods.add_method_mut("move_next_match", |_, editor, query: String| {
             editor.next_match(&query);
             if let Some(doc) = editor.try_doc_mut() {
@@ -739,8 +867,234 @@ impl LuaUserData for Editor {
         });
         // Document state modification
         methods.add_method_mut("set_read_only", |_, editor, status: bool| {
+            if let Some(fc) = editor.files.get_mut(editor.ptr.clone()) {
+                if let Some(hex) = &mut fc.hex {
+                    // The document behind a hex view is never edited
+                    hex.read_only = status;
+                } else {
+                    // Lazily loaded documents can never be edited
+                    fc.doc.info.read_only = status || fc.doc.lazy.is_some();
+                }
+            }
+            Ok(())
+        });
+        methods.add_method_mut("set_soft_wrap", |_, editor, status: bool| {
+            if let Some(fc) = editor.files.get_mut(editor.ptr.clone()) {
+                fc.soft_wrap = Some(status);
+            }
+            Ok(())
+        });
+        methods.add_method_mut("fold", |_, editor, ()| {
+            let brackets = editor.fold_brackets();
+            if let Some(doc) = editor.try_doc_mut() {
+                if !doc.fold(doc.loc().y, brackets) {
+                    editor.feedback =
+                        Feedback::Warning("There is nothing to fold here".to_string());
+                }
+            }
+            Ok(())
+        });
+        methods.add_method_mut("unfold", |_, editor, ()| {
+            if let Some(doc) = editor.try_doc_mut() {
+                doc.unfold(doc.loc().y);
+            }
+            Ok(())
+        });
+        methods.add_method_mut("toggle_fold", |_, editor, ()| {
+            let brackets = editor.fold_brackets();
+            if let Some(doc) = editor.try_doc_mut() {
+                doc.toggle_fold(brackets);
+            }
+            Ok(())
+        });
+        methods.add_method_mut("fold_all", |_, editor, ()| {
+            let brackets = editor.fold_brackets();
             if let Some(doc) = editor.try_doc_mut() {
-                doc.info.read_only = status;
+                doc.fold_all(brackets);
+            }
+            Ok(())
+        });
+        methods.add_method_mut("unfold_all", |_, editor, ()| {
+            if let Some(doc) = editor.try_doc_mut() {
+                doc.unfold_all();
+            }
+            Ok(())
+        });
+        // Diagnostics
+        methods.add_method_mut("diagnostics", |lua, editor, ()| {
+            editor.poll_language_servers();
+            let result = lua.create_table()?;
+            for diagnostic in editor.current_diagnostics() {
+                let entry = lua.create_table()?;
+                entry.set("x", diagnostic.start.x)?;
+                entry.set("y", diagnostic.start.y + 1)?;
+                entry.set("end_x", diagnostic.end.x)?;
+                entry.set("end_y", diagnostic.end.y + 1)?;
+                entry.set("severity", diagnostic.severity.name())?;
+                entry.set("message", diagnostic.message)?;
+                entry.set("source", diagnostic.source)?;
+                result.push(entry)?;
+            }
+            Ok(result)
+        });
+        methods.add_method_mut(
+            "set_diagnostics",
+            |_, editor, (file, list): (String, Vec<LuaTable>)| {
+                let mut diagnostics = vec![];
+                for entry in list {
+                    let x: usize = entry.get::<Option<usize>>("x")?.unwrap_or(0);
+                    let y = entry.get::<usize>("y")?.saturating_sub(1);
+                    let end_x = entry.get::<Option<usize>>("end_x")?.unwrap_or(x + 1);
+                    let end_y = entry
+                        .get::<Option<usize>>("end_y")?
+                        .map_or(y, |end_y| end_y.saturating_sub(1));
+                    let severity = entry.get::<Option<String>>("severity")?;
+                    let severity = match severity.as_deref().map(Severity::from_name) {
+                        None => Severity::Error,
+                        Some(Some(severity)) => severity,
+                        Some(None) => {
+                            let msg =
+                                "Unknown severity, expected error, warning, information or hint";
+                            return Err(LuaError::runtime(msg));
+                        }
+                    };
+                    diagnostics.push(Diagnostic {
+                        start: Loc::at(x, y),
+                        end: Loc::at(end_x, end_y),
+                        severity,
+                        message: entry.get("message")?,
+                        source: entry.get("source")?,
+                    });
+                }
+                diagnostics.sort_by_key(|d| (d.start.y, d.start.x));
+                editor.set_diagnostics(&file, diagnostics);
+                editor.needs_rerender = true;
+                Ok(())
+            },
+        );
+        methods.add_method_mut("next_diagnostic", |_, editor, ()| {
+            if !editor.next_diagnostic() {
+                editor.feedback =
+                    Feedback::Warning("There are no diagnostics in this file".to_string());
+            }
+            editor.update_highlighter();
+            Ok(())
+        });
+        methods.add_method_mut("previous_diagnostic", |_, editor, ()| {
+            if !editor.prev_diagnostic() {
+                editor.feedback =
+                    Feedback::Warning("There are no diagnostics in this file".to_string());
+            }
+            editor.update_highlighter();
+            Ok(())
+        });
+        // Completion
+        methods.add_method_mut("complete", |_, editor, ()| {
+            // The popup is opened once the key press has been dealt with
+            editor.completion_requested = true;
+            Ok(())
+        });
+        methods.add_method_mut("close_completion", |_, editor, ()| {
+            editor.completion = None;
+            Ok(())
+        });
+        // Snippets
+        methods.add_method_mut("expand_snippet", |_, editor, body: String| {
+            if let Err(err) = editor.expand_snippet(&body, None) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            Ok(())
+        });
+        methods.add_method_mut("insert_snippet", |lua, editor, name: String| {
+            if let Err(err) = editor.insert_snippet(lua, &name) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            Ok(())
+        });
+        methods.add_method_mut("end_snippet", |_, editor, ()| {
+            editor.end_snippet();
+            Ok(())
+        });
+        // Language servers
+        methods.add_method_mut("hover", |_, editor, ()| match editor.hover() {
+            Ok(text) => Ok(text),
+            Err(err) => {
+                editor.feedback = Feedback::Error(err.to_string());
+                Ok(None)
+            }
+        });
+        methods.add_method_mut("definition", |_, editor, ()| {
+            match editor.goto_definition() {
+                Ok(true) => {
+                    editor.update_highlighter();
+                    Ok(true)
+                }
+                Ok(false) => {
+                    editor.feedback = Feedback::Warning("No definition was found".to_string());
+                    Ok(false)
+                }
+                Err(err) => {
+                    editor.feedback = Feedback::Error(err.to_string());
+                    Ok(false)
+                }
+            }
+        });
+        methods.add_method_mut("references", |lua, editor, ()| match editor.references() {
+            Ok(references) => {
+                let result = lua.create_table()?;
+                for (file, loc) in references {
+                    let entry = lua.create_table()?;
+                    entry.set("file", file)?;
+                    entry.set("x", loc.x)?;
+                    entry.set("y", loc.y + 1)?;
+                    result.push(entry)?;
+                }
+                Ok(Some(result))
+            }
+            Err(err) => {
+                editor.feedback = Feedback::Error(err.to_string());
+                Ok(None)
+            }
+        });
+        methods.add_method_mut("rename", |_, editor, new_name: String| {
+            if let Err(err) = editor.rename(&new_name) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            Ok(())
+        });
+        methods.add_method_mut("format", |_, editor, ()| {
+            let tab_width = config!(editor.config, document).tab_width;
+            let spaces = config!(editor.config, document).indentation == Indentation::Spaces;
+            if let Err(err) = editor.format(tab_width, spaces) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            Ok(())
+        });
+        methods.add_method_mut("convert_line_ending", |_, editor, ending: String| {
+            match ending.parse::<LineEnding>() {
+                Ok(ending) => {
+                    if let Some(doc) = editor.try_doc_mut() {
+                        doc.convert_line_ending(ending);
+                    }
+                }
+                Err(err) => editor.feedback = Feedback::Error(OxError::from(err).to_string()),
+            }
+            Ok(())
+        });
+        methods.add_method_mut("set_encoding", |_, editor, name: String| {
+            match name.parse::<Encoding>() {
+                Ok(encoding) => {
+                    if let Some(doc) = editor.try_doc_mut() {
+                        if doc.info.encoding != encoding {
+                            doc.info.encoding = encoding;
+                            doc.info.bom = false;
+                            doc.event_mgmt.force_not_with_disk = true;
+                        }
+                    }
+                }
+                Err(err) => editor.feedback = Feedback::Error(OxError::from(err).to_string()),
             }
             Ok(())
         });
@@ -789,13 +1143,8 @@ impl LuaUserData for Editor {
         });
         // Terminal
         #[cfg(not(target_os = "windows"))]
-        methods.add_method_mut("open_terminal_up", |_, editor, cmd: Option<String>| {
-            if let Ok(term) = Pty::new(config!(editor.config, terminal).shell) {
-                if let Some(cmd) = cmd {
-                    term.lock()
-                        .unwrap()
-                        .silent_run_command(&format!("{cmd}\n"))?;
-                }
+        methods.add_method_mut("open_terminal_up", |lua, editor, arg: LuaValue| {
+            if let Some(term) = start_terminal(lua, editor, arg)? {
                 editor.ptr = editor
                     .files
                     .open_up(editor.ptr.clone(), FileLayout::Terminal(term));
@@ -806,13 +1155,8 @@ impl LuaUserData for Editor {
             }
         });
         #[cfg(not(target_os = "windows"))]
-        methods.add_method_mut("open_terminal_down", |_, editor, cmd: Option<String>| {
-            if let Ok(term) = Pty::new(config!(editor.config, terminal).shell) {
-                if let Some(cmd) = cmd {
-                    term.lock()
-                        .unwrap()
-                        .silent_run_command(&format!("{cmd}\n"))?;
-                }
+        methods.add_method_mut("open_terminal_down", |lua, editor, arg: LuaValue| {
+            if let Some(term) = start_terminal(lua, editor, arg)? {
                 editor.ptr = editor
                     .files
                     .open_down(editor.ptr.clone(), FileLayout::Terminal(term));
@@ -823,13 +1167,8 @@ impl LuaUserData for Editor {
             }
         });
         #[cfg(not(target_os = "windows"))]
-        methods.add_method_mut("open_terminal_left", |_, editor, cmd: Option<String>| {
-            if let Ok(term) = Pty::new(config!(editor.config, terminal).shell) {
-                if let Some(cmd) = cmd {
-                    term.lock()
-                        .unwrap()
-                        .silent_run_command(&format!("{cmd}\n"))?;
-                }
+        methods.add_method_mut("open_terminal_left", |lua, editor, arg: LuaValue| {
+            if let Some(term) = start_terminal(lua, editor, arg)? {
                 editor.ptr = editor
                     .files
                     .open_left(editor.ptr.clone(), FileLayout::Terminal(term));
@@ -840,13 +1179,8 @@ impl LuaUserData for Editor {
             }
         });
         #[cfg(not(target_os = "windows"))]
-        methods.add_method_mut("open_terminal_right", |_, editor, cmd: Option<String>| {
-            if let Ok(term) = Pty::new(config!(editor.config, terminal).shell) {
-                if let Some(cmd) = cmd {
-                    term.lock()
-                        .unwrap()
-                        .silent_run_command(&format!("{cmd}\n"))?;
-                }
+        methods.add_method_mut("open_terminal_right", |lua, editor, arg: LuaValue| {
+            if let Some(term) = start_terminal(lua, editor, arg)? {
                 editor.ptr = editor
                     .files
                     .open_right(editor.ptr.clone(), FileLayout::Terminal(term));
@@ -876,7 +1210,8 @@ impl LuaUserData for Editor {
                     if let Some(cmds) = runcmds.get(&kind) {
                         let RunCommand { compile, run } = cmds;
                         // ...open a terminal...
-                        if let Ok(term) = Pty::new(config!(editor.config, terminal).shell) {
+                        let shell = config!(editor.config, terminal).shell.clone();
+                        if let Ok(term) = Pty::new(&shell) {
                             editor.ptr = editor
                                 .files
                                 .open_right(editor.ptr.clone(), FileLayout::Terminal(term));
@@ -888,13 +1223,11 @@ impl LuaUserData for Editor {
                             {
                                 if let Some(compile_cmd) = compile {
                                     let compile_cmd = compile_cmd.replace("{file_path}", &path);
-                                    term.lock()
-                                        .unwrap()
-                                        .run_command(&format!("{compile_cmd}\n"))?;
+                                    term.lock().unwrap().run_command(&compile_cmd)?;
                                 }
                                 if let Some(run_cmd) = run {
                                     let run_cmd = run_cmd.replace("{file_path}", &path);
-                                    term.lock().unwrap().run_command(&format!("{run_cmd}\n"))?;
+                                    term.lock().unwrap().run_command(&run_cmd)?;
                                 }
                             }
                         }
@@ -903,6 +1236,78 @@ impl LuaUserData for Editor {
             }
             Ok(())
         });
+        methods.add_method_mut("run_task", |lua, editor, name: String| {
+            if let Err(err) = editor.run_task(lua, &name) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            editor.needs_rerender = true;
+            let _ = editor.render(lua);
+            Ok(())
+        });
+        methods.add_method_mut("rerun_task", |lua, editor, ()| {
+            if let Err(err) = editor.rerun_task(lua) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            editor.needs_rerender = true;
+            let _ = editor.render(lua);
+            Ok(())
+        });
+        methods.add_method_mut("list_tasks", |lua, editor, ()| {
+            if let Err(err) = editor.list_tasks(lua) {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            editor.update_highlighter();
+            Ok(())
+        });
+        methods.add_method_mut("open_problem", |_, editor, ()| {
+            if let Err(err) = editor.jump_to_problem() {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            Ok(())
+        });
+        // Quickfix list
+        methods.add_method_mut(
+            "set_quickfix",
+            |lua, editor, (places, title): (LuaValue, Option<String>)| {
+                let title = title.unwrap_or_default();
+                // Places can be given as a table, or found in the output of a command
+                let list = if let LuaValue::String(output) = places {
+                    let root = get_cwd().unwrap_or_default();
+                    QuickfixList::from_output(title, &output.to_str()?, Path::new(&root))
+                } else {
+                    QuickfixList::new(title, Vec::<QuickfixEntry>::from_lua(places, lua)?)
+                };
+                let places = list.entries.len();
+                editor.set_quickfix(list);
+                Ok(places)
+            },
+        );
+        methods.add_method_mut("open_quickfix", |_, editor, ()| {
+            editor.open_quickfix();
+            Ok(())
+        });
+        methods.add_method_mut("close_quickfix", |_, editor, ()| {
+            editor.close_quickfix();
+            Ok(())
+        });
+        methods.add_method_mut("toggle_quickfix", |_, editor, ()| {
+            editor.toggle_quickfix();
+            Ok(())
+        });
+        methods.add_method_mut("quickfix_next", |_, editor, ()| {
+            if let Err(err) = editor.quickfix_next() {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            Ok(())
+        });
+        methods.add_method_mut("quickfix_prev", |_, editor, ()| {
+            if let Err(err) = editor.quickfix_prev() {
+                editor.feedback = Feedback::Error(err.to_string());
+            }
+            Ok(())
+        });
         // Miscellaneous
         methods.add_method_mut("open_command_line", |_, editor, ()| {
             match editor.prompt("Command") {
@@ -950,3 +1355,44 @@ impl IntoLua for LuaLoc {
         Ok(LuaValue::Table(table))
     }
 }
+
+/// For representing a branch of the undo tree within lua
+pub struct LuaUndoBranch {
+    id: usize,
+    changes: usize,
+    age: u64,
+    current: bool,
+}
+
+impl IntoLua for LuaUndoBranch {
+    /// Convert this rust struct so the plug-in and configuration system can use it
+    fn into_lua(self, lua: &Lua) -> std::result::Result<LuaValue, LuaError> {
+        let table = lua.create_table()?;
+        table.set("id", self.id)?;
+        table.set("changes", self.changes)?;
+        table.set("age", self.age)?;
+        table.set("current", self.current)?;
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Start a terminal for the `open_terminal` functions, which take either a command to type into
+/// the configured shell, or a table describing a program to run instead
+#[cfg(not(target_os = "windows"))]
+fn start_terminal(lua: &Lua, editor: &Editor, arg: LuaValue) -> LuaResult<Option<Arc<Mutex<Pty>>>> {
+    let (program, cmd) = match arg {
+        LuaValue::Table(_) => (Program::from_lua(arg, lua)?, None),
+        LuaValue::String(cmd) => {
+            let shell = config!(editor.config, terminal).shell.clone();
+            (shell, Some(cmd.to_string_lossy()))
+        }
+        _ => (config!(editor.config, terminal).shell.clone(), None),
+    };
+    let Ok(term) = Pty::new(&program) else {
+        return Ok(None);
+    };
+    if let Some(cmd) = cmd {
+        term.lock().unwrap().run_command(&cmd)?;
+    }
+    Ok(Some(term))
+}
@@ -2,9 +2,9 @@
 use crate::cli::VERSION;
 use crate::editor::{Editor, FileContainer};
 #[cfg(not(target_os = "windows"))]
-use crate::pty::Shell;
+use crate::pty::Program;
 use crate::Feedback;
-use kaolinite::searching::Searcher;
+use kaolinite::searching::{SearchOptions, Searcher};
 use kaolinite::utils::{get_absolute_path, get_file_ext, get_file_name};
 use mlua::prelude::*;
 use std::result::Result as RResult;
@@ -19,7 +19,7 @@ pub struct Terminal {
     pub mouse_enabled: bool,
     pub scroll_amount: usize,
     #[cfg(not(target_os = "windows"))]
-    pub shell: Shell,
+    pub shell: Program,
     #[cfg(target_os = "windows")]
     #[allow(dead_code)]
     pub shell: (),
@@ -31,7 +31,7 @@ impl Default for Terminal {
             mouse_enabled: true,
             scroll_amount: 1,
             #[cfg(not(target_os = "windows"))]
-            shell: Shell::Bash,
+            shell: Program::default(),
             #[cfg(target_os = "windows")]
             shell: (),
         }
@@ -51,7 +51,7 @@ impl LuaUserData for Terminal {
             Ok(())
         });
         #[cfg(not(target_os = "windows"))]
-        fields.add_field_method_get("shell", |_, this| Ok(this.shell));
+        fields.add_field_method_get("shell", |_, this| Ok(this.shell.clone()));
         #[cfg(not(target_os = "windows"))]
         fields.add_field_method_set("shell", |_, this, value| {
             this.shell = value;
@@ -102,6 +102,176 @@ impl LuaUserData for LineNumbers {
     }
 }
 
+/// For storing configuration information related to the completion popup
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Completion {
+    pub enabled: bool,
+    pub min_length: usize,
+    pub height: usize,
+    pub words: bool,
+    pub paths: bool,
+    pub documentation: bool,
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_length: 2,
+            height: 8,
+            words: true,
+            paths: true,
+            documentation: true,
+        }
+    }
+}
+
+impl LuaUserData for Completion {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("enabled", |_, this| Ok(this.enabled));
+        fields.add_field_method_set("enabled", |_, this, value| {
+            this.enabled = value;
+            Ok(())
+        });
+        fields.add_field_method_get("min_length", |_, this| Ok(this.min_length));
+        fields.add_field_method_set("min_length", |_, this, value| {
+            this.min_length = value;
+            Ok(())
+        });
+        fields.add_field_method_get("height", |_, this| Ok(this.height));
+        fields.add_field_method_set("height", |_, this, value| {
+            this.height = value;
+            Ok(())
+        });
+        fields.add_field_method_get("words", |_, this| Ok(this.words));
+        fields.add_field_method_set("words", |_, this, value| {
+            this.words = value;
+            Ok(())
+        });
+        fields.add_field_method_get("paths", |_, this| Ok(this.paths));
+        fields.add_field_method_set("paths", |_, this, value| {
+            this.paths = value;
+            Ok(())
+        });
+        fields.add_field_method_get("documentation", |_, this| Ok(this.documentation));
+        fields.add_field_method_set("documentation", |_, this, value| {
+            this.documentation = value;
+            Ok(())
+        });
+    }
+}
+
+/// For storing configuration information related to the fuzzy file finder
+#[derive(Debug)]
+pub struct Finder {
+    pub hidden: bool,
+    pub ignored: bool,
+    pub preview: bool,
+}
+
+impl Default for Finder {
+    fn default() -> Self {
+        Self {
+            hidden: false,
+            ignored: false,
+            preview: true,
+        }
+    }
+}
+
+impl LuaUserData for Finder {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("hidden", |_, this| Ok(this.hidden));
+        fields.add_field_method_set("hidden", |_, this, value| {
+            this.hidden = value;
+            Ok(())
+        });
+        fields.add_field_method_get("ignored", |_, this| Ok(this.ignored));
+        fields.add_field_method_set("ignored", |_, this, value| {
+            this.ignored = value;
+            Ok(())
+        });
+        fields.add_field_method_get("preview", |_, this| Ok(this.preview));
+        fields.add_field_method_set("preview", |_, this, value| {
+            this.preview = value;
+            Ok(())
+        });
+    }
+}
+
+/// For storing configuration information related to searching
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Search {
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+    pub highlight: bool,
+    pub save_history: bool,
+    pub history_length: usize,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        let options = SearchOptions::default();
+        Self {
+            case_sensitive: options.case_sensitive,
+            whole_word: options.whole_word,
+            regex: options.regex,
+            highlight: true,
+            save_history: false,
+            history_length: 50,
+        }
+    }
+}
+
+impl Search {
+    /// Find out how search terms should be matched
+    pub fn options(&self) -> SearchOptions {
+        SearchOptions {
+            case_sensitive: self.case_sensitive,
+            whole_word: self.whole_word,
+            regex: self.regex,
+        }
+    }
+}
+
+impl LuaUserData for Search {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("case_sensitive", |_, this| Ok(this.case_sensitive));
+        fields.add_field_method_set("case_sensitive", |_, this, value| {
+            this.case_sensitive = value;
+            Ok(())
+        });
+        fields.add_field_method_get("whole_word", |_, this| Ok(this.whole_word));
+        fields.add_field_method_set("whole_word", |_, this, value| {
+            this.whole_word = value;
+            Ok(())
+        });
+        fields.add_field_method_get("regex", |_, this| Ok(this.regex));
+        fields.add_field_method_set("regex", |_, this, value| {
+            this.regex = value;
+            Ok(())
+        });
+        fields.add_field_method_get("highlight", |_, this| Ok(this.highlight));
+        fields.add_field_method_set("highlight", |_, this, value| {
+            this.highlight = value;
+            Ok(())
+        });
+        fields.add_field_method_get("save_history", |_, this| Ok(this.save_history));
+        fields.add_field_method_set("save_history", |_, this, value| {
+            this.save_history = value;
+            Ok(())
+        });
+        fields.add_field_method_get("history_length", |_, this| Ok(this.history_length));
+        fields.add_field_method_set("history_length", |_, this, value| {
+            this.history_length = value;
+            Ok(())
+        });
+    }
+}
+
 /// For storing configuration information related to the greeting message
 #[derive(Debug)]
 pub struct GreetingMessage {
@@ -279,7 +449,7 @@ impl TabLine {
         let absolute_path = get_absolute_path(&path).unwrap_or_else(|| "[No Name]".to_string());
         let file_name = get_file_name(&path).unwrap_or_else(|| "[No Name]".to_string());
         let icon = fc.file_type.clone().map_or("󰈙 ".to_string(), |t| t.icon);
-        let modified = if fc.doc.event_mgmt.with_disk(&fc.doc.take_snapshot()) {
+        let modified = if fc.doc.event_mgmt.with_disk() {
             ""
         } else {
             "[+]"
@@ -376,14 +546,37 @@ impl StatusLine {
             .clone()
             .map_or("Unknown".to_string(), |ft| ft.name);
         let icon = fc.file_type.clone().map_or("󰈙 ".to_string(), |ft| ft.icon);
-        let modified = if doc.event_mgmt.with_disk(&doc.take_snapshot()) {
+        let modified = if doc.event_mgmt.with_disk() {
             ""
         } else {
             "[+]"
         };
         let cursor_y = (doc.loc().y + 1).to_string();
         let cursor_x = doc.char_ptr.to_string();
-        let line_count = doc.len_lines().to_string();
+        let line_count = if doc.lazy.as_ref().is_some_and(|lazy| !lazy.indexed()) {
+            // Still counting the lines of a lazily loaded document
+            format!("{}+", doc.len_lines())
+        } else {
+            doc.len_lines().to_string()
+        };
+        let mut line_ending = doc.info.line_ending.to_string();
+        let mut encoding = if doc.info.bom {
+            format!("{} BOM", doc.info.encoding)
+        } else {
+            doc.info.encoding.to_string()
+        };
+        // Hex documents are made up of rows of bytes
+        let (mut cursor_y, mut cursor_x, mut line_count, mut file_type) =
+            (cursor_y, cursor_x, line_count, file_type);
+        if let Some(hex) = &fc.hex {
+            cursor_y = (hex.loc().y + 1).to_string();
+            cursor_x = hex.loc().x.to_string();
+            line_count = hex.len_rows().to_string();
+            file_type = "Hex".to_string();
+            encoding = "Binary".to_string();
+            // Line endings don't apply, so show how typing behaves instead
+            line_ending = if hex.overwrite { "OVR" } else { "INS" }.to_string();
+        }
 
         for part in &self.parts {
             let mut part = part.clone();
@@ -399,6 +592,8 @@ impl StatusLine {
             part = part.replace("{cursor_y}", &cursor_y).to_string();
             part = part.replace("{cursor_x}", &cursor_x).to_string();
             part = part.replace("{line_count}", &line_count).to_string();
+            part = part.replace("{line_ending}", &line_ending);
+            part = part.replace("{encoding}", &encoding);
             // Find functions to call and substitute in
             let mut searcher = Searcher::new(r"\{[A-Za-z_][A-Za-z0-9_]*\}");
             while let Some(m) = searcher.lfind(&part) {
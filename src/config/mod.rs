@@ -1,6 +1,7 @@
 /// For general configuration
 use crate::editor::{FileType, FileTypes};
 use crate::error::{OxError, Result};
+use kaolinite::document::Backup;
 use mlua::prelude::*;
 use std::fmt::{Display, Error, Formatter};
 use std::sync::{Arc, Mutex};
@@ -19,8 +20,12 @@ pub use assistant::Assistant;
 pub use colors::{Color, Colors};
 pub use filetree::FileTree;
 pub use highlighting::SyntaxHighlighting;
-pub use interface::{GreetingMessage, HelpMessage, LineNumbers, StatusLine, TabLine, Terminal};
+pub use interface::{
+    Completion, Finder, GreetingMessage, HelpMessage, LineNumbers, Search, StatusLine, TabLine,
+    Terminal,
+};
 pub use keys::{get_listeners, key_to_string, run_key, run_key_before};
+pub use runner::{default_matchers, ProblemMatcher, TaskDefinition};
 pub use tasks::TaskManager;
 
 /// Issue a warning to the user
@@ -91,6 +96,17 @@ macro_rules! config {
     ($cfg:expr, terminal) => {
         $cfg.terminal.borrow::<$crate::config::Terminal>().unwrap()
     };
+    ($cfg:expr, completion) => {
+        $cfg.completion
+            .borrow::<$crate::config::Completion>()
+            .unwrap()
+    };
+    ($cfg:expr, finder) => {
+        $cfg.finder.borrow::<$crate::config::Finder>().unwrap()
+    };
+    ($cfg:expr, search) => {
+        $cfg.search.borrow::<$crate::config::Search>().unwrap()
+    };
 }
 
 /// The struct that holds all the configuration information
@@ -105,6 +121,9 @@ pub struct Config {
     pub help_message: LuaAnyUserData,
     pub file_tree: LuaAnyUserData,
     pub terminal: LuaAnyUserData,
+    pub completion: LuaAnyUserData,
+    pub finder: LuaAnyUserData,
+    pub search: LuaAnyUserData,
     pub document: LuaAnyUserData,
     pub task_manager: Arc<Mutex<TaskManager>>,
 }
@@ -122,6 +141,9 @@ impl Config {
         let tab_line = lua.create_userdata(TabLine::default())?;
         let file_tree = lua.create_userdata(FileTree::default())?;
         let terminal = lua.create_userdata(Terminal::default())?;
+        let completion = lua.create_userdata(Completion::default())?;
+        let finder = lua.create_userdata(Finder::default())?;
+        let search = lua.create_userdata(Search::default())?;
         let document = lua.create_userdata(Document::default())?;
 
         // Set up the task manager
@@ -143,6 +165,9 @@ impl Config {
         lua.globals().set("file_tree", file_tree.clone())?;
         lua.globals().set("colors", colors.clone())?;
         lua.globals().set("terminal", terminal.clone())?;
+        lua.globals().set("completion", completion.clone())?;
+        lua.globals().set("finder", finder.clone())?;
+        lua.globals().set("search", search.clone())?;
         lua.globals().set("document", document.clone())?;
 
         // Define task list
@@ -189,6 +214,9 @@ impl Config {
             help_message,
             file_tree,
             terminal,
+            completion,
+            finder,
+            search,
             document,
             task_manager,
         })
@@ -304,12 +332,67 @@ impl From<String> for Indentation {
     }
 }
 
+/// A setting that can be switched on for all documents, or just those of certain file types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PerFileType {
+    Off,
+    On,
+    /// Only applies to documents of these file types (by name)
+    FileTypes(Vec<String>),
+}
+
+impl PerFileType {
+    /// Determine if this setting applies to documents of a certain file type
+    pub fn applies_to(&self, file_type: &str) -> bool {
+        match self {
+            Self::Off => false,
+            Self::On => true,
+            Self::FileTypes(types) => types.iter().any(|t| t.eq_ignore_ascii_case(file_type)),
+        }
+    }
+}
+
+impl IntoLua for PerFileType {
+    fn into_lua(self, lua: &Lua) -> std::result::Result<LuaValue, LuaError> {
+        Ok(match self {
+            Self::Off => LuaValue::Boolean(false),
+            Self::On => LuaValue::Boolean(true),
+            Self::FileTypes(types) => LuaValue::Table(lua.create_sequence_from(types)?),
+        })
+    }
+}
+
+impl FromLua for PerFileType {
+    fn from_lua(value: LuaValue, _: &Lua) -> std::result::Result<Self, LuaError> {
+        Ok(match value {
+            LuaValue::Boolean(true) => Self::On,
+            LuaValue::Table(types) => Self::FileTypes(
+                types
+                    .sequence_values()
+                    .filter_map(std::result::Result::ok)
+                    .collect(),
+            ),
+            _ => Self::Off,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Document {
     pub tab_width: usize,
     pub indentation: Indentation,
     pub undo_period: usize,
     pub wrap_cursor: bool,
+    pub backup: Backup,
+    pub swap_period: usize,
+    pub lazy_threshold: u64,
+    pub soft_wrap: PerFileType,
+    pub wrap_column: usize,
+    pub wrap_words: bool,
+    pub wrap_indicator: String,
+    pub fold_brackets: PerFileType,
+    pub fold_indicator: String,
+    pub diagnostic_sign: String,
     pub file_types: FileTypes,
 }
 
@@ -320,6 +403,16 @@ impl Default for Document {
             indentation: Indentation::Tabs,
             undo_period: 10,
             wrap_cursor: true,
+            backup: Backup::None,
+            swap_period: 5,
+            lazy_threshold: 256,
+            soft_wrap: PerFileType::Off,
+            wrap_column: 0,
+            wrap_words: true,
+            wrap_indicator: "↪".to_string(),
+            fold_brackets: PerFileType::On,
+            fold_indicator: "▸".to_string(),
+            diagnostic_sign: "●".to_string(),
             file_types: FileTypes::default(),
         }
     }
@@ -349,6 +442,78 @@ impl LuaUserData for Document {
             this.wrap_cursor = value;
             Ok(())
         });
+        fields.add_field_method_get("backup", |_, document| {
+            Ok(match &document.backup {
+                Backup::None => "none".to_string(),
+                Backup::Tilde => "tilde".to_string(),
+                Backup::Directory(dir) => dir.to_string_lossy().to_string(),
+            })
+        });
+        fields.add_field_method_set("backup", |_, this, value: LuaValue| {
+            this.backup = match value {
+                LuaValue::Boolean(true) => Backup::Tilde,
+                LuaValue::String(s) => match s.to_str()?.as_ref() {
+                    "none" => Backup::None,
+                    "tilde" => Backup::Tilde,
+                    dir => Backup::Directory(shellexpand::tilde(dir).to_string().into()),
+                },
+                _ => Backup::None,
+            };
+            Ok(())
+        });
+        fields.add_field_method_get("swap_period", |_, document| Ok(document.swap_period));
+        fields.add_field_method_set("swap_period", |_, this, value| {
+            this.swap_period = value;
+            Ok(())
+        });
+        fields.add_field_method_get("lazy_threshold", |_, document| Ok(document.lazy_threshold));
+        fields.add_field_method_set("lazy_threshold", |_, this, value| {
+            this.lazy_threshold = value;
+            Ok(())
+        });
+        fields.add_field_method_get("soft_wrap", |_, document| Ok(document.soft_wrap.clone()));
+        fields.add_field_method_set("soft_wrap", |_, this, value| {
+            this.soft_wrap = value;
+            Ok(())
+        });
+        fields.add_field_method_get("wrap_column", |_, document| Ok(document.wrap_column));
+        fields.add_field_method_set("wrap_column", |_, this, value| {
+            this.wrap_column = value;
+            Ok(())
+        });
+        fields.add_field_method_get("wrap_words", |_, document| Ok(document.wrap_words));
+        fields.add_field_method_set("wrap_words", |_, this, value| {
+            this.wrap_words = value;
+            Ok(())
+        });
+        fields.add_field_method_get("wrap_indicator", |_, document| {
+            Ok(document.wrap_indicator.clone())
+        });
+        fields.add_field_method_set("wrap_indicator", |_, this, value| {
+            this.wrap_indicator = value;
+            Ok(())
+        });
+        fields.add_field_method_get("fold_brackets", |_, document| {
+            Ok(document.fold_brackets.clone())
+        });
+        fields.add_field_method_set("fold_brackets", |_, this, value| {
+            this.fold_brackets = value;
+            Ok(())
+        });
+        fields.add_field_method_get("fold_indicator", |_, document| {
+            Ok(document.fold_indicator.clone())
+        });
+        fields.add_field_method_set("fold_indicator", |_, this, value| {
+            this.fold_indicator = value;
+            Ok(())
+        });
+        fields.add_field_method_get("diagnostic_sign", |_, document| {
+            Ok(document.diagnostic_sign.clone())
+        });
+        fields.add_field_method_set("diagnostic_sign", |_, this, value| {
+            this.diagnostic_sign = value;
+            Ok(())
+        });
     }
 }
 
@@ -378,6 +543,7 @@ impl FromLua for FileTypes {
                     .filter_map(|val| if let Ok((_, v)) = val { Some(v) } else { None })
                     .collect::<Vec<String>>();
                 let color = info.get::<String>("color")?;
+                let language_server = info.get::<Option<String>>("language_server")?;
                 result.push(FileType {
                     name,
                     icon,
@@ -385,6 +551,7 @@ impl FromLua for FileTypes {
                     extensions,
                     modelines,
                     color,
+                    language_server,
                 });
             }
         }
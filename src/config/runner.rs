@@ -1,6 +1,7 @@
 //! Configuration for defining how programs should be compiled and run
 
 use mlua::prelude::*;
+use regex::Regex;
 
 /// Main struct to determine how a language should be compiled / run
 #[derive(Debug, Default)]
@@ -22,3 +23,145 @@ impl FromLua for RunCommand {
         }
     }
 }
+
+/// Patterns that find problems in the output of most compilers and linters when none are given
+/// (such as `src/main.rs:3:5: error: ...`, `  --> src/main.rs:3:5` or `main.py:10: message`)
+const DEFAULT_MATCHERS: [&str; 2] = [
+    r"^\s*(?:--> )?(?P<file>[^\s:][^:]*):(?P<line>\d+):(?P<column>\d+)(?::\s*(?P<message>.*))?$",
+    r"^\s*(?:--> )?(?P<file>[^\s:][^:]*):(?P<line>\d+):\s*(?P<message>.*)$",
+];
+
+/// A pattern that picks out problems from the output of a task.
+/// It names the parts of the line that hold the `file`, `line` and (optionally)
+/// the `column` and `message`
+#[derive(Debug, Clone)]
+pub struct ProblemMatcher {
+    pub regex: Regex,
+}
+
+impl ProblemMatcher {
+    /// Look for a problem in a line of output,
+    /// giving back the file, the line and column (both starting from 1) and the message
+    pub fn find(&self, text: &str) -> Option<(String, usize, usize, String)> {
+        let caps = self.regex.captures(text)?;
+        let file = caps.name("file")?.as_str().trim().to_string();
+        let line = caps.name("line")?.as_str().parse().ok()?;
+        let column = caps
+            .name("column")
+            .and_then(|column| column.as_str().parse().ok())
+            .unwrap_or(1);
+        let message = caps
+            .name("message")
+            .map_or(text, |message| message.as_str())
+            .trim()
+            .to_string();
+        Some((file, line, column, message))
+    }
+}
+
+impl FromLua for ProblemMatcher {
+    fn from_lua(val: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let pattern = String::from_lua(val, lua)?;
+        let regex = Regex::new(&pattern).map_err(|err| {
+            LuaError::RuntimeError(format!("Invalid problem matcher '{pattern}': {err}"))
+        })?;
+        Ok(Self { regex })
+    }
+}
+
+/// A named task (such as building, testing or linting a project)
+#[derive(Debug, Clone)]
+pub struct TaskDefinition {
+    /// The command to run (through the shell)
+    pub command: String,
+    /// The folder to run it in (the project's folder if not given)
+    pub cwd: Option<String>,
+    /// Patterns that pick out problems from what the command prints
+    pub matchers: Vec<ProblemMatcher>,
+}
+
+/// Patterns that find problems in the usual formats
+pub fn default_matchers() -> Vec<ProblemMatcher> {
+    DEFAULT_MATCHERS
+        .iter()
+        .map(|pattern| ProblemMatcher {
+            regex: Regex::new(pattern).unwrap(),
+        })
+        .collect()
+}
+
+impl TaskDefinition {
+    /// A task that runs a command, looking for problems in the usual formats
+    pub fn from_command(command: String) -> Self {
+        Self {
+            command,
+            cwd: None,
+            matchers: default_matchers(),
+        }
+    }
+}
+
+impl FromLua for TaskDefinition {
+    fn from_lua(val: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match val {
+            LuaValue::String(command) => Ok(Self::from_command(command.to_str()?.to_string())),
+            LuaValue::Table(table) => {
+                let mut task = Self::from_command(table.get("command")?);
+                task.cwd = table.get("cwd")?;
+                if let Some(matchers) = table.get::<Option<Vec<ProblemMatcher>>>("matchers")? {
+                    task.matchers = matchers;
+                }
+                Ok(task)
+            }
+            _ => Err(LuaError::FromLuaConversionError {
+                from: val.type_name(),
+                to: "TaskDefinition".to_string(),
+                message: Some("expected a command or a table".to_string()),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Look for a problem with the first default matcher that finds one
+    fn find(text: &str) -> Option<(String, usize, usize, String)> {
+        default_matchers()
+            .iter()
+            .find_map(|matcher| matcher.find(text))
+    }
+
+    #[test]
+    fn default_matchers_find_problems() {
+        let problem = |file: &str, line, column, message: &str| {
+            Some((file.to_string(), line, column, message.to_string()))
+        };
+        // Lines with a column
+        assert_eq!(
+            find("src/main.rs:3:5: error: expected `;`"),
+            problem("src/main.rs", 3, 5, "error: expected `;`")
+        );
+        assert_eq!(
+            find("  lib/a b.c:10:2:warning: unused"),
+            problem("lib/a b.c", 10, 2, "warning: unused")
+        );
+        assert_eq!(
+            find("   --> src/editor/mod.rs:12:9"),
+            problem("src/editor/mod.rs", 12, 9, "--> src/editor/mod.rs:12:9")
+        );
+        // Lines without one
+        assert_eq!(
+            find("main.py:10: SyntaxError: invalid syntax"),
+            problem("main.py", 10, 1, "SyntaxError: invalid syntax")
+        );
+        assert_eq!(find("Makefile:4:"), problem("Makefile", 4, 1, ""));
+        // Lines that aren't problems
+        assert_eq!(find("error: could not compile `ox` (bin \"ox\")"), None);
+        assert_eq!(find("   Compiling ox v0.7.0 (/root/ox)"), None);
+        assert_eq!(find("see https://example.com:8080/docs"), None);
+        assert_eq!(find(":3:5: no file"), None);
+        assert_eq!(find(""), None);
+    }
+}
@@ -13,11 +13,17 @@ pub struct Task {
 pub struct TaskManager {
     pub tasks: Vec<Task>,
     pub to_execute: Vec<String>,
+    /// Seconds since swap files were last written
+    pub swap_timer: usize,
+    /// Seconds since open files were last checked for changes by other programs
+    pub disk_timer: usize,
 }
 
 impl TaskManager {
     /// Thread to run and keep track of which tasks to execute
     pub fn cycle(&mut self) {
+        self.swap_timer += 1;
+        self.disk_timer += 1;
         for task in &mut self.tasks {
             // Decrement remaining time
             if task.remaining > 0 {
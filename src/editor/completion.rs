@@ -0,0 +1,418 @@
+/// Functions for suggesting ways to finish off what is being typed
+use crate::error::Result;
+use crate::ui::Feedback;
+use crate::{config, ged, handle_lua_error};
+use crossterm::event::{Event as CEvent, KeyCode as KCode, KeyModifiers as KMod};
+use kaolinite::event::Event;
+use kaolinite::searching::{fuzzy_match, FuzzyMatch};
+use kaolinite::Loc;
+use mlua::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+
+use super::Editor;
+
+/// Characters that can't be part of a path being typed
+const PATH_DELIMITERS: &str = "\"'`()[]{}<>=,;";
+
+/// Something that could be inserted to finish off what is being typed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// What is shown in the popup (and matched against what has been typed)
+    pub label: String,
+    /// The text to insert
+    pub text: String,
+    /// A short note shown next to the candidate (e.g. where it came from)
+    pub detail: String,
+    /// A longer description, shown beside the popup when the candidate is selected
+    pub documentation: Option<String>,
+}
+
+impl Candidate {
+    /// Create a candidate that inserts what it shows
+    pub fn new(text: &str, detail: &str) -> Self {
+        Self {
+            label: text.to_string(),
+            text: text.to_string(),
+            detail: detail.to_string(),
+            documentation: None,
+        }
+    }
+}
+
+/// The state of the completion popup
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    /// Where the text being completed starts (by character index)
+    pub start: Loc,
+    /// Every candidate that was suggested
+    pub candidates: Vec<Candidate>,
+    /// The candidates that match what has been typed (best first)
+    pub matches: Vec<(usize, FuzzyMatch)>,
+    /// The match that is selected
+    pub selected: usize,
+    /// The first match shown in the popup (when there are too many to fit)
+    pub offset: usize,
+}
+
+impl Completion {
+    /// Filter the candidates down to those that match what has been typed
+    pub fn filter(&mut self, prefix: &str) {
+        let candidates = &self.candidates;
+        self.matches = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, c)| Some((idx, fuzzy_match(prefix, &c.label)?)))
+            .collect();
+        self.matches.sort_by(|(a, am), (b, bm)| {
+            let (a, b) = (&candidates[*a].label, &candidates[*b].label);
+            bm.score
+                .cmp(&am.score)
+                .then(a.len().cmp(&b.len()))
+                .then(a.cmp(b))
+        });
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    /// Get the selected candidate
+    pub fn current(&self) -> Option<&Candidate> {
+        let (idx, _) = self.matches.get(self.selected)?;
+        self.candidates.get(*idx)
+    }
+
+    /// Get the matches that fit in a popup of a certain height
+    pub fn visible(&self, height: usize) -> &[(usize, FuzzyMatch)] {
+        let end = (self.offset + height).min(self.matches.len());
+        &self.matches[self.offset..end]
+    }
+
+    /// Select the previous match (going round to the last after the first)
+    pub fn select_up(&mut self, height: usize) {
+        self.selected = self
+            .selected
+            .checked_sub(1)
+            .unwrap_or(self.matches.len().saturating_sub(1));
+        self.scroll(height);
+    }
+
+    /// Select the next match (going round to the first after the last)
+    pub fn select_down(&mut self, height: usize) {
+        self.selected += 1;
+        if self.selected >= self.matches.len() {
+            self.selected = 0;
+        }
+        self.scroll(height);
+    }
+
+    /// Scroll the popup so that the selected match is visible
+    fn scroll(&mut self, height: usize) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height.max(1);
+        }
+    }
+}
+
+/// What is being typed at the cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Typed {
+    /// Where it starts (by character index)
+    pub start: Loc,
+    /// What has been typed so far
+    pub prefix: String,
+    /// The directory being looked in (if a path is being typed)
+    pub dir: Option<String>,
+}
+
+/// Work out what is being typed on a line before a certain character index.
+/// Paths are completed a part at a time (after the last slash), anything else a word at a time.
+fn typed_at(line: &str, x: usize, y: usize) -> Typed {
+    let before: Vec<char> = line.chars().take(x).collect();
+    let x = before.len();
+    let word_start = before
+        .iter()
+        .rposition(|c| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |idx| idx + 1);
+    let token_start = before
+        .iter()
+        .rposition(|c| c.is_whitespace() || PATH_DELIMITERS.contains(*c))
+        .map_or(0, |idx| idx + 1);
+    let token = &before[token_start..];
+    let dir = token
+        .iter()
+        .rposition(|c| *c == '/')
+        .map(|idx| token[..=idx].iter().collect::<String>())
+        // Comments and links (e.g. https://) aren't worth looking up on the disk
+        .filter(|dir| !dir.contains("//"));
+    let start = match &dir {
+        Some(dir) => token_start + dir.chars().count(),
+        None => word_start,
+    };
+    Typed {
+        start: Loc::at(start, y),
+        prefix: before[start..x].iter().collect(),
+        dir,
+    }
+}
+
+/// Find the files and folders in a directory that could finish off a path
+fn path_candidates(dir: &str, prefix: &str) -> Vec<Candidate> {
+    let path = shellexpand::tilde(dir).to_string();
+    let Ok(entries) = fs::read_dir(path) else {
+        return vec![];
+    };
+    let mut result: Vec<Candidate> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Hidden files are only suggested when they're asked for
+            if name.starts_with('.') && !prefix.starts_with('.') {
+                return None;
+            }
+            if entry.file_type().ok()?.is_dir() {
+                Some(Candidate::new(&format!("{name}/"), "folder"))
+            } else {
+                Some(Candidate::new(&name, "file"))
+            }
+        })
+        .collect();
+    result.sort_by(|a, b| a.label.cmp(&b.label));
+    result
+}
+
+/// Ask the sources plug-ins have registered (in the `completion_sources` table) for candidates.
+/// Sources are functions that take what has been typed and return a list of candidates,
+/// either as text, or as tables with `text`, `label`, `detail` and `documentation` fields.
+pub fn lua_candidates(lua: &Lua, prefix: &str) -> LuaResult<Vec<Candidate>> {
+    let mut result = vec![];
+    let Some(sources) = lua
+        .globals()
+        .get::<Option<LuaTable>>("completion_sources")?
+    else {
+        return Ok(result);
+    };
+    for source in sources.pairs::<String, LuaFunction>() {
+        let (name, source) = source?;
+        let Some(found) = source.call::<Option<LuaTable>>(prefix.to_string())? else {
+            continue;
+        };
+        for item in found.sequence_values::<LuaValue>() {
+            let candidate = match item? {
+                LuaValue::Table(item) => {
+                    let text: String = item.get("text")?;
+                    Candidate {
+                        label: item.get::<Option<String>>("label")?.unwrap_or(text.clone()),
+                        text,
+                        detail: item
+                            .get::<Option<String>>("detail")?
+                            .unwrap_or(name.clone()),
+                        documentation: item.get("documentation")?,
+                    }
+                }
+                item => Candidate::new(&String::from_lua(item, lua)?, &name),
+            };
+            result.push(candidate);
+        }
+    }
+    Ok(result)
+}
+
+/// Keep the completion popup up to date after an event.
+/// Typing opens (or filters) the popup, anything else closes it.
+pub fn update_completion(editor: &LuaAnyUserData, event: &CEvent, lua: &Lua) {
+    if let CEvent::Resize(_, _) = event {
+        return;
+    }
+    let requested = std::mem::take(&mut ged!(mut &editor).completion_requested);
+    let typing = match event {
+        CEvent::Key(key) => matches!(
+            (key.modifiers, key.code),
+            (KMod::NONE | KMod::SHIFT, KCode::Char(_)) | (KMod::NONE, KCode::Backspace)
+        ),
+        _ => false,
+    };
+    let open = ged!(&editor).completion.is_some();
+    let (enabled, min_length) = {
+        let editor = ged!(&editor);
+        let config = config!(editor.config, completion);
+        (config.enabled, config.min_length)
+    };
+    if !(requested || (typing && (open || enabled))) {
+        ged!(mut &editor).completion = None;
+        return;
+    }
+    let Some(typed) = ged!(&editor).typed() else {
+        ged!(mut &editor).completion = None;
+        return;
+    };
+    // Words are only worth completing once enough of them has been typed
+    let is_path = typed.dir.is_some();
+    let too_short = typed.prefix.chars().count() < if open { 1 } else { min_length };
+    if !requested && !is_path && too_short {
+        ged!(mut &editor).completion = None;
+        return;
+    }
+    // Candidates are gathered when completion starts, then filtered as more is typed
+    let same_start = ged!(&editor)
+        .completion
+        .as_ref()
+        .is_some_and(|completion| completion.start == typed.start);
+    if same_start && !requested {
+        ged!(mut &editor).filter_completion(&typed.prefix, false);
+        return;
+    }
+    let mut candidates = ged!(&editor).candidates(&typed);
+    match lua_candidates(lua, &typed.prefix) {
+        Ok(found) => candidates.extend(found),
+        Err(err) => handle_lua_error("completion", Err(err), &mut ged!(mut &editor).feedback),
+    }
+    ged!(mut &editor).completion = Some(Completion {
+        start: typed.start,
+        candidates,
+        ..Completion::default()
+    });
+    ged!(mut &editor).filter_completion(&typed.prefix, requested);
+}
+
+impl Editor {
+    /// Work out what is being typed at the cursor (None if not editing a document)
+    pub fn typed(&self) -> Option<Typed> {
+        if self.try_hex().is_some() {
+            return None;
+        }
+        let doc = self.try_doc()?;
+        let loc = doc.char_loc();
+        Some(typed_at(&doc.line(loc.y)?, loc.x, loc.y))
+    }
+
+    /// Gather the candidates built into the editor (words in open documents and paths)
+    pub fn candidates(&self, typed: &Typed) -> Vec<Candidate> {
+        let config = config!(self.config, completion);
+        if let Some(dir) = &typed.dir {
+            return if config.paths {
+                path_candidates(dir, &typed.prefix)
+            } else {
+                vec![]
+            };
+        }
+        if !config.words {
+            return vec![];
+        }
+        // Words in the current document come first, then words in the others
+        let mut docs = vec![];
+        docs.extend(self.try_doc());
+        docs.extend(
+            self.files
+                .containers()
+                .into_iter()
+                .map(|fc| &fc.doc)
+                .filter(|doc| {
+                    !self
+                        .try_doc()
+                        .is_some_and(|current| std::ptr::eq(*doc, current))
+                }),
+        );
+        let mut seen = HashSet::new();
+        let mut result = vec![];
+        for doc in docs {
+            for line in &doc.lines {
+                for (start, end) in doc.word_boundaries(line) {
+                    let word = &line[start..end];
+                    let is_word = word.chars().all(|c| c.is_alphanumeric() || c == '_');
+                    let is_number = word.chars().all(|c| c.is_ascii_digit());
+                    if word.len() < 2 || !is_word || is_number || word == typed.prefix {
+                        continue;
+                    }
+                    if seen.insert(word) {
+                        result.push(Candidate::new(word, "word"));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Filter the completion popup down to what has been typed, closing it if nothing matches
+    pub fn filter_completion(&mut self, prefix: &str, requested: bool) {
+        let Some(completion) = &mut self.completion else {
+            return;
+        };
+        completion.filter(prefix);
+        // There's no point suggesting what has already been typed
+        let finished =
+            completion.matches.len() == 1 && completion.current().is_some_and(|c| c.text == prefix);
+        if completion.matches.is_empty() || (finished && !requested) {
+            self.completion = None;
+            if requested {
+                self.feedback = Feedback::Warning("No completions found".to_string());
+            }
+        }
+    }
+
+    /// Let the completion popup deal with a key press (if it is open).
+    /// Returns true if the key was used by the popup.
+    pub fn completion_key(&mut self, modifiers: KMod, code: KCode) -> Result<bool> {
+        let height = config!(self.config, completion).height;
+        let Some(completion) = &mut self.completion else {
+            return Ok(false);
+        };
+        match (modifiers, code) {
+            (KMod::NONE, KCode::Up) | (KMod::CONTROL, KCode::Char('p')) => {
+                completion.select_up(height);
+            }
+            (KMod::NONE, KCode::Down) | (KMod::CONTROL, KCode::Char('n')) => {
+                completion.select_down(height);
+            }
+            (KMod::NONE, KCode::Tab | KCode::Enter) => self.accept_completion()?,
+            (KMod::NONE, KCode::Esc) => self.completion = None,
+            _ => return Ok(false),
+        }
+        self.needs_rerender = true;
+        Ok(true)
+    }
+
+    /// Replace what has been typed with the selected candidate (as a single undoable change)
+    pub fn accept_completion(&mut self) -> Result<()> {
+        let Some(completion) = self.completion.take() else {
+            return Ok(());
+        };
+        let Some(text) = completion.current().map(|c| c.text.clone()) else {
+            return Ok(());
+        };
+        let Some(doc) = self.try_doc_mut() else {
+            return Ok(());
+        };
+        let start = completion.start;
+        let loc = doc.char_loc();
+        if loc.y != start.y || loc.x < start.x {
+            return Ok(());
+        }
+        let typed: String = doc
+            .line(loc.y)
+            .unwrap_or_default()
+            .chars()
+            .skip(start.x)
+            .take(loc.x - start.x)
+            .collect();
+        doc.commit();
+        if !typed.is_empty() {
+            doc.exe(Event::Delete(start, typed))?;
+        }
+        let at = self.insert_text(start, &text)?;
+        let Some(doc) = self.try_doc_mut() else {
+            return Ok(());
+        };
+        doc.move_to(&at);
+        doc.commit();
+        if at.y == start.y {
+            if let Some(file) = self.files.get_mut(self.ptr.clone()) {
+                file.highlighter.edit(at.y, &file.doc.lines[at.y]);
+            }
+        } else {
+            self.reload_highlight();
+        }
+        Ok(())
+    }
+}
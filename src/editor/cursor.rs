@@ -227,6 +227,8 @@ fn adjust_other_cursors(
                 // Adjust x position
                 if old_pos.y == c.y && old_pos.x < c.x && at_line_start {
                     new_loc.x += new_pos.x;
+                } else if old_pos.y == c.y && old_pos.x < c.x && new_pos.y == old_pos.y {
+                    new_loc.x -= old_pos.x - new_pos.x.min(old_pos.x);
                 }
                 // If this cursor is after the currently moved cursor, shift up
                 if (c.y > old_pos.y || (c.y == old_pos.y && c.x > old_pos.x)) && at_line_start {
@@ -236,6 +238,17 @@ fn adjust_other_cursors(
                 *c = new_loc;
             }
         }
+        CEvent::Key(KeyEvent {
+            code: KeyCode::Char(_) | KeyCode::Tab,
+            ..
+        }) => {
+            // Typing, push cursors further along this line to the right
+            for c in cursors.iter_mut() {
+                if c != old_pos && old_pos.y == c.y && old_pos.x < c.x && new_pos.y == old_pos.y {
+                    c.x += new_pos.x.saturating_sub(old_pos.x);
+                }
+            }
+        }
         _ => (),
     }
     cursors.pop().unwrap()
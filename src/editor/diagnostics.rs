@@ -0,0 +1,165 @@
+/// Functions for keeping track of problems with files (from plug-ins and language servers)
+use crate::config::Colors;
+use crate::error::Result;
+use crate::ui::Feedback;
+use crossterm::style::Color;
+use kaolinite::lsp::Severity;
+use kaolinite::utils::get_absolute_path;
+use kaolinite::Loc;
+
+use super::{Editor, FileContainer};
+
+/// A problem with a range of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Where the problem starts (by character index)
+    pub start: Loc,
+    /// Where the problem ends (by character index, exclusive)
+    pub end: Loc,
+    /// How serious the problem is
+    pub severity: Severity,
+    /// A description of the problem
+    pub message: String,
+    /// What found the problem (e.g. a compiler or linter)
+    pub source: Option<String>,
+}
+
+impl Diagnostic {
+    /// Determine if a location (by character index) is part of the problem.
+    /// Problems that don't span any characters are treated as covering the character they start at.
+    pub fn contains(&self, loc: Loc) -> bool {
+        let at = (loc.y, loc.x);
+        let start = (self.start.y, self.start.x);
+        let end = (self.end.y, self.end.x);
+        if end <= start {
+            at == start
+        } else {
+            start <= at && at < end
+        }
+    }
+
+    /// How serious the problem is, for putting problems in order (0 is the most serious)
+    pub fn rank(&self) -> usize {
+        match self.severity {
+            Severity::Error => 0,
+            Severity::Warning => 1,
+            Severity::Information => 2,
+            Severity::Hint => 3,
+        }
+    }
+
+    /// Describe the problem (for the feedback line)
+    pub fn describe(&self) -> String {
+        match &self.source {
+            Some(source) => format!("{source}: {}", self.message),
+            None => self.message.clone(),
+        }
+    }
+
+    /// Describe the problem on the feedback line, coloured by how serious it is
+    pub fn feedback(&self) -> Feedback {
+        match self.severity {
+            Severity::Error => Feedback::Error(self.describe()),
+            Severity::Warning => Feedback::Warning(self.describe()),
+            Severity::Information | Severity::Hint => Feedback::Info(self.describe()),
+        }
+    }
+
+    /// Find the colour that represents how serious the problem is
+    pub fn color(&self, colors: &Colors) -> Result<Color> {
+        match self.severity {
+            Severity::Error => colors.error_fg.to_color(),
+            Severity::Warning => colors.warning_fg.to_color(),
+            Severity::Information => colors.info_fg.to_color(),
+            Severity::Hint => colors.line_number_fg.to_color(),
+        }
+    }
+}
+
+/// Find the most serious diagnostic out of a selection
+pub fn most_serious<'a, I: IntoIterator<Item = &'a Diagnostic>>(
+    diagnostics: I,
+) -> Option<&'a Diagnostic> {
+    diagnostics.into_iter().min_by_key(|d| d.rank())
+}
+
+impl Editor {
+    /// Set the diagnostics for a file (replacing any that were set before)
+    pub fn set_diagnostics(&mut self, file_name: &str, diagnostics: Vec<Diagnostic>) {
+        let file_name = get_absolute_path(file_name).unwrap_or(file_name.to_string());
+        if diagnostics.is_empty() {
+            self.diagnostics.remove(&file_name);
+        } else {
+            self.diagnostics.insert(file_name, diagnostics);
+        }
+    }
+
+    /// Get every diagnostic for a file (from plug-ins and language servers), in order
+    pub fn diagnostics_for(&self, file_name: &str) -> Vec<Diagnostic> {
+        let mut result = self.lsp_diagnostics(file_name);
+        if let Some(diagnostics) = self.diagnostics.get(file_name) {
+            result.extend(diagnostics.iter().cloned());
+        }
+        result.sort_by_key(|d| (d.start.y, d.start.x));
+        result
+    }
+
+    /// Determine if a file has any diagnostics (without working out where they are)
+    pub fn has_diagnostics(&self, fc: &FileContainer) -> bool {
+        let Some(file_name) = &fc.doc.file_name else {
+            return false;
+        };
+        self.diagnostics.contains_key(file_name) || self.has_lsp_diagnostics(fc)
+    }
+
+    /// Get the diagnostics for the current document
+    pub fn current_diagnostics(&self) -> Vec<Diagnostic> {
+        self.try_doc()
+            .and_then(|doc| doc.file_name.as_ref())
+            .map_or(vec![], |file_name| self.diagnostics_for(file_name))
+    }
+
+    /// Get the most serious diagnostic under the cursor (if any)
+    pub fn diagnostic_at_cursor(&self) -> Option<Diagnostic> {
+        let loc = self.try_doc()?.char_loc();
+        let diagnostics = self.current_diagnostics();
+        most_serious(diagnostics.iter().filter(|d| d.contains(loc))).cloned()
+    }
+
+    /// Move the cursor to the next diagnostic in the current document (going back to the first
+    /// one after the last). Returns false if there are no diagnostics.
+    pub fn next_diagnostic(&mut self) -> bool {
+        let diagnostics = self.current_diagnostics();
+        let Some(doc) = self.try_doc_mut() else {
+            return false;
+        };
+        let loc = doc.char_loc();
+        let next = diagnostics
+            .iter()
+            .find(|d| (d.start.y, d.start.x) > (loc.y, loc.x))
+            .or(diagnostics.first());
+        if let Some(next) = next {
+            doc.move_to(&next.start);
+        }
+        next.is_some()
+    }
+
+    /// Move the cursor to the previous diagnostic in the current document (going round to the
+    /// last one before the first). Returns false if there are no diagnostics.
+    pub fn prev_diagnostic(&mut self) -> bool {
+        let diagnostics = self.current_diagnostics();
+        let Some(doc) = self.try_doc_mut() else {
+            return false;
+        };
+        let loc = doc.char_loc();
+        let prev = diagnostics
+            .iter()
+            .rev()
+            .find(|d| (d.start.y, d.start.x) < (loc.y, loc.x))
+            .or(diagnostics.last());
+        if let Some(prev) = prev {
+            doc.move_to(&prev.start);
+        }
+        prev.is_some()
+    }
+}
@@ -1,13 +1,15 @@
 /// Tools for placing all information about open files into one place
-use crate::editor::{get_absolute_path, Editor, FileType};
+use crate::editor::{get_absolute_path, Editor, FileType, ProjectSearch, SnippetSession, TaskRun};
 #[cfg(not(target_os = "windows"))]
 use crate::pty::Pty;
 use crate::Loc;
-use kaolinite::Document;
+use kaolinite::document::history::fnv_hash;
 use kaolinite::Size;
+use kaolinite::{Document, HexDocument};
 use std::ops::Range;
 #[cfg(not(target_os = "windows"))]
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use synoptic::Highlighter;
 
 pub type Span = Vec<(Vec<usize>, Range<usize>, Range<usize>)>;
@@ -25,6 +27,8 @@ pub enum FileLayout {
     None,
     /// Representing a file tree
     FileTree,
+    /// Representing the quickfix list (places in files to go through)
+    Quickfix,
     /// Representing a terminal
     #[cfg(not(target_os = "windows"))]
     Terminal(Arc<Mutex<Pty>>),
@@ -50,8 +54,8 @@ impl FileLayout {
     pub fn span(&self, idx: Vec<usize>, size: Size, at: Loc) -> Span {
         match self {
             Self::None => vec![],
-            // Atom file trees and terminals: stretch from starting position through to end of their containers
-            Self::Atom(_, _) | Self::FileTree | Self::Terminal(_) => {
+            // Atoms, file trees, quickfix lists and terminals: stretch from starting position through to end of their containers
+            Self::Atom(_, _) | Self::FileTree | Self::Quickfix | Self::Terminal(_) => {
                 vec![(idx, at.y..at.y + size.h, at.x..at.x + size.w)]
             }
             // SideBySide: distributes available container space to each sub-layout
@@ -167,17 +171,41 @@ impl FileLayout {
     /// Work out how many files are currently open
     pub fn len(&self) -> usize {
         match self {
-            Self::None | Self::FileTree | Self::Terminal(_) => 0,
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => 0,
             Self::Atom(containers, _) => containers.len(),
             Self::SideBySide(layouts) => layouts.iter().map(|(layout, _)| layout.len()).sum(),
             Self::TopToBottom(layouts) => layouts.iter().map(|(layout, _)| layout.len()).sum(),
         }
     }
 
+    /// Collect every file container that is currently open
+    pub fn containers(&self) -> Vec<&FileContainer> {
+        match self {
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => vec![],
+            Self::Atom(containers, _) => containers.iter().collect(),
+            Self::SideBySide(layouts) | Self::TopToBottom(layouts) => layouts
+                .iter()
+                .flat_map(|(layout, _)| layout.containers())
+                .collect(),
+        }
+    }
+
+    /// Collect every file container that is currently open (mutably)
+    pub fn containers_mut(&mut self) -> Vec<&mut FileContainer> {
+        match self {
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => vec![],
+            Self::Atom(containers, _) => containers.iter_mut().collect(),
+            Self::SideBySide(layouts) | Self::TopToBottom(layouts) => layouts
+                .iter_mut()
+                .flat_map(|(layout, _)| layout.containers_mut())
+                .collect(),
+        }
+    }
+
     /// Work out how many atoms are currently open
     pub fn n_atoms(&self) -> usize {
         match self {
-            Self::None | Self::FileTree | Self::Terminal(_) => 0,
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => 0,
             Self::Atom(_, _) => 1,
             Self::SideBySide(layouts) => layouts.iter().map(|(layout, _)| layout.n_atoms()).sum(),
             Self::TopToBottom(layouts) => layouts.iter().map(|(layout, _)| layout.n_atoms()).sum(),
@@ -187,7 +215,7 @@ impl FileLayout {
     /// Find a file container location from it's path
     pub fn find(&self, idx: Vec<usize>, path: &str) -> Option<(Vec<usize>, usize)> {
         match self {
-            Self::None | Self::FileTree | Self::Terminal(_) => None,
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => None,
             Self::Atom(containers, _) => {
                 // Scan this atom for any documents
                 for (ptr, container) in containers.iter().enumerate() {
@@ -214,10 +242,27 @@ impl FileLayout {
         }
     }
 
+    /// Find where the quickfix list is (if it is open)
+    pub fn find_quickfix(&self, idx: Vec<usize>) -> Option<Vec<usize>> {
+        match self {
+            Self::Quickfix => Some(idx),
+            Self::None | Self::FileTree | Self::Atom(_, _) | Self::Terminal(_) => None,
+            Self::SideBySide(layouts) | Self::TopToBottom(layouts) => {
+                layouts.iter().enumerate().find_map(|(nth, (layout, _))| {
+                    let mut this_idx = idx.clone();
+                    this_idx.push(nth);
+                    layout.find_quickfix(this_idx)
+                })
+            }
+        }
+    }
+
     /// Get the `FileLayout` at a certain index
     pub fn get_raw(&self, mut idx: Vec<usize>) -> Option<&FileLayout> {
         match self {
-            Self::None | Self::Atom(_, _) | Self::FileTree | Self::Terminal(_) => Some(self),
+            Self::None | Self::Atom(_, _) | Self::FileTree | Self::Quickfix | Self::Terminal(_) => {
+                Some(self)
+            }
             Self::SideBySide(layouts) => {
                 if idx.is_empty() {
                     Some(self)
@@ -243,7 +288,11 @@ impl FileLayout {
             Some(self)
         } else {
             match self {
-                Self::None | Self::Atom(_, _) | Self::FileTree | Self::Terminal(_) => Some(self),
+                Self::None
+                | Self::Atom(_, _)
+                | Self::FileTree
+                | Self::Quickfix
+                | Self::Terminal(_) => Some(self),
                 Self::SideBySide(layouts) => {
                     let subidx = idx.remove(0);
                     layouts.get_mut(subidx)?.0.get_raw_mut(idx)
@@ -259,7 +308,9 @@ impl FileLayout {
     /// Get the `FileLayout` at a certain index
     pub fn set(&mut self, mut idx: Vec<usize>, fl: FileLayout) {
         match self {
-            Self::None | Self::Atom(_, _) | Self::FileTree | Self::Terminal(_) => *self = fl,
+            Self::None | Self::Atom(_, _) | Self::FileTree | Self::Quickfix | Self::Terminal(_) => {
+                *self = fl;
+            }
             Self::SideBySide(layouts) | Self::TopToBottom(layouts) => {
                 if idx.is_empty() {
                     *self = fl;
@@ -274,7 +325,7 @@ impl FileLayout {
     /// Given an index, find the file containers in the tree
     pub fn get_atom(&self, mut idx: Vec<usize>) -> Option<(&[FileContainer], usize)> {
         match self {
-            Self::None | Self::FileTree | Self::Terminal(_) => None,
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => None,
             Self::Atom(containers, ptr) => Some((containers, *ptr)),
             Self::SideBySide(layouts) => {
                 let subidx = idx.remove(0);
@@ -293,7 +344,7 @@ impl FileLayout {
         mut idx: Vec<usize>,
     ) -> Option<(&mut Vec<FileContainer>, &mut usize)> {
         match self {
-            Self::None | Self::FileTree | Self::Terminal(_) => None,
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => None,
             Self::Atom(ref mut containers, ref mut ptr) => Some((containers, ptr)),
             Self::SideBySide(layouts) => {
                 let subidx = idx.remove(0);
@@ -326,7 +377,7 @@ impl FileLayout {
     /// In the currently active atom, move to a different document
     pub fn move_to(&mut self, mut idx: Vec<usize>, ptr: usize) {
         match self {
-            Self::None | Self::FileTree | Self::Terminal(_) => (),
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => (),
             Self::Atom(_, ref mut old_ptr) => *old_ptr = ptr,
             Self::SideBySide(layouts) | Self::TopToBottom(layouts) => {
                 let subidx = idx.remove(0);
@@ -372,7 +423,11 @@ impl FileLayout {
             // Determine behaviour based on parent
             if let Some(parent) = self.get_raw_mut(at_parent) {
                 match parent {
-                    Self::None | Self::Atom(_, _) | Self::FileTree | Self::Terminal(_) => {
+                    Self::None
+                    | Self::Atom(_, _)
+                    | Self::FileTree
+                    | Self::Quickfix
+                    | Self::Terminal(_) => {
                         unreachable!()
                     }
                     Self::SideBySide(layouts) | Self::TopToBottom(layouts) => {
@@ -398,7 +453,7 @@ impl FileLayout {
     /// Traverse the tree and return a list of indices to empty atoms
     pub fn empty_atoms(&self, at: Vec<usize>) -> Option<Vec<usize>> {
         match self {
-            Self::None | Self::FileTree | Self::Terminal(_) => None,
+            Self::None | Self::FileTree | Self::Quickfix | Self::Terminal(_) => None,
             Self::Atom(fcs, _) => {
                 if fcs.is_empty() {
                     Some(at)
@@ -426,7 +481,9 @@ impl FileLayout {
     /// Traverse the tree and return a list of indices to redundant sidebyside/toptobottom
     pub fn redundant_multis(&self, at: Vec<usize>) -> Option<Vec<usize>> {
         match self {
-            Self::None | Self::FileTree | Self::Atom(_, _) | Self::Terminal(_) => None,
+            Self::None | Self::FileTree | Self::Quickfix | Self::Atom(_, _) | Self::Terminal(_) => {
+                None
+            }
             Self::SideBySide(layouts) | Self::TopToBottom(layouts) => {
                 if layouts.len() == 1 {
                     Some(at)
@@ -444,31 +501,6 @@ impl FileLayout {
         }
     }
 
-    /// Traverse the tree and return a list of indices to empty atoms
-    #[cfg(not(target_os = "windows"))]
-    pub fn terminal_rerender(&mut self) -> bool {
-        match self {
-            Self::None | Self::FileTree | Self::Atom(_, _) => false,
-            Self::Terminal(term) => {
-                let mut term = term.lock().unwrap();
-                if term.force_rerender {
-                    term.force_rerender = false;
-                    true
-                } else {
-                    false
-                }
-            }
-            Self::SideBySide(layouts) | Self::TopToBottom(layouts) => {
-                for layout in layouts.iter_mut() {
-                    if layout.0.terminal_rerender() {
-                        return true;
-                    }
-                }
-                false
-            }
-        }
-    }
-
     /// Find a new pointer position when something is removed
     pub fn new_pointer_position(&self, old: &[usize]) -> Vec<usize> {
         // Zoom out until a sidebyside or toptobottom is found
@@ -497,6 +529,7 @@ impl FileLayout {
                 Self::Atom(_, _)
                 | Self::SideBySide(_)
                 | Self::TopToBottom(_)
+                | Self::Quickfix
                 | Self::Terminal(_) => {
                     new_ptr.push(0);
                     let old_fl = std::mem::replace(old_fl, FileLayout::None);
@@ -518,6 +551,7 @@ impl FileLayout {
                 Self::Atom(_, _)
                 | Self::SideBySide(_)
                 | Self::TopToBottom(_)
+                | Self::Quickfix
                 | Self::Terminal(_) => {
                     new_ptr.push(1);
                     let old_fl = std::mem::replace(old_fl, FileLayout::None);
@@ -539,6 +573,7 @@ impl FileLayout {
                 Self::Atom(_, _)
                 | Self::SideBySide(_)
                 | Self::TopToBottom(_)
+                | Self::Quickfix
                 | Self::Terminal(_) => {
                     new_ptr.push(0);
                     let old_fl = std::mem::replace(old_fl, FileLayout::None);
@@ -560,6 +595,7 @@ impl FileLayout {
                 Self::Atom(_, _)
                 | Self::SideBySide(_)
                 | Self::TopToBottom(_)
+                | Self::Quickfix
                 | Self::Terminal(_) => {
                     new_ptr.push(1);
                     let old_fl = std::mem::replace(old_fl, FileLayout::None);
@@ -852,6 +888,22 @@ pub struct FileContainer {
     pub highlighter: Highlighter,
     /// File type (stores which file type this file is)
     pub file_type: Option<FileType>,
+    /// What the file looked like on the disk when it was last opened or saved
+    pub disk_state: Option<DiskState>,
+    /// Whether the file was changed by another program while it had unsaved changes
+    pub external_change: bool,
+    /// The file as raw bytes (when being viewed in hex)
+    pub hex: Option<HexDocument>,
+    /// Whether to soft wrap this file (overriding the configuration)
+    pub soft_wrap: Option<bool>,
+    /// The snippet whose tab stops are being moved between (if any)
+    pub snippet: Option<SnippetSession>,
+    /// The search through the project whose results are in this buffer (if any)
+    pub search: Option<ProjectSearch>,
+    /// The task whose output is in this buffer (if any)
+    pub task: Option<TaskRun>,
+    /// The names of the tasks listed in this buffer to pick from (if any)
+    pub tasks: Option<Vec<String>>,
 }
 
 impl Default for FileContainer {
@@ -860,6 +912,61 @@ impl Default for FileContainer {
             doc: Document::new(Size { w: 10, h: 10 }),
             highlighter: Highlighter::new(4),
             file_type: None,
+            disk_state: None,
+            external_change: false,
+            hex: None,
+            soft_wrap: None,
+            snippet: None,
+            search: None,
+            task: None,
+            tasks: None,
         }
     }
 }
+
+/// Information about a file on the disk, to notice when other programs change it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskState {
+    /// When the file was last modified
+    pub modified: Option<SystemTime>,
+    /// The size of the file in bytes
+    pub len: u64,
+    /// A hash of the contents of the file
+    pub hash: u64,
+}
+
+impl DiskState {
+    /// Find the state of the file a document represents
+    pub fn new(doc: &Document) -> Option<Self> {
+        // Reading in huge files to hash them would defeat the point of lazy loading
+        if doc.lazy.is_some() {
+            return None;
+        }
+        let path = doc.file_name.as_ref()?;
+        let meta = std::fs::metadata(path).ok()?;
+        let data = std::fs::read(path).ok()?;
+        Some(Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            hash: fnv_hash(&data),
+        })
+    }
+
+    /// Check if a file has changed since this state was taken.
+    /// Returns the new state and contents of the file if it has been changed.
+    pub fn changed(&self, path: &str) -> Option<(Self, Vec<u8>)> {
+        let meta = std::fs::metadata(path).ok()?;
+        let modified = meta.modified().ok();
+        // Only read the file in when there is a sign it has been changed
+        if modified == self.modified && meta.len() == self.len {
+            return None;
+        }
+        let data = std::fs::read(path).ok()?;
+        let state = Self {
+            modified,
+            len: meta.len(),
+            hash: fnv_hash(&data),
+        };
+        Some((state, data))
+    }
+}
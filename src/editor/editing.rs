@@ -1,7 +1,9 @@
 /// General functions for editing a document
 use crate::error::Result;
+use crate::ui::Feedback;
 use kaolinite::event::Event;
 use kaolinite::utils::Loc;
+use std::time::Duration;
 
 use super::Editor;
 
@@ -116,9 +118,8 @@ impl Editor {
                 loc.x = file.doc.line(loc.y).unwrap().chars().count();
                 self.exe(Event::SpliceUp(loc))?;
                 let file = self.files.get_mut(self.ptr.clone()).unwrap();
-                let line = &file.doc.lines[loc.y];
                 if !file.doc.info.read_only {
-                    file.highlighter.edit(loc.y, line);
+                    file.highlighter.edit(loc.y, &file.doc.lines[loc.y]);
                 }
             } else if !(c == 0 && on_first_line) {
                 // Backspace was pressed in the middle of the line, delete the character
@@ -198,6 +199,9 @@ impl Editor {
 
     /// Perform redo action
     pub fn redo(&mut self) -> Result<()> {
+        if self.try_hex().is_some() {
+            return self.undo_hex(true);
+        }
         if let Some(doc) = self.try_doc_mut() {
             doc.redo()?;
             self.reload_highlight();
@@ -207,6 +211,9 @@ impl Editor {
 
     /// Perform undo action
     pub fn undo(&mut self) -> Result<()> {
+        if self.try_hex().is_some() {
+            return self.undo_hex(false);
+        }
         if let Some(doc) = self.try_doc_mut() {
             doc.undo()?;
             self.reload_highlight();
@@ -214,6 +221,39 @@ impl Editor {
         Ok(())
     }
 
+    /// Move to the tip of another branch in the undo tree
+    pub fn switch_undo_branch(&mut self, branch: usize) -> Result<()> {
+        if let Some(doc) = self.try_doc_mut() {
+            let idx = doc.event_mgmt.branches().get(branch).copied();
+            if let Some(idx) = idx {
+                doc.switch_branch(idx)?;
+                self.reload_highlight();
+            } else {
+                self.feedback =
+                    Feedback::Error(format!("Undo branch {} doesn't exist", branch + 1));
+            }
+        }
+        Ok(())
+    }
+
+    /// Go back to the state the document was in a certain amount of time ago
+    pub fn undo_earlier(&mut self, duration: Duration) -> Result<()> {
+        if let Some(doc) = self.try_doc_mut() {
+            doc.earlier(duration)?;
+            self.reload_highlight();
+        }
+        Ok(())
+    }
+
+    /// Go forward to the state the document was in a certain amount of time later
+    pub fn undo_later(&mut self, duration: Duration) -> Result<()> {
+        if let Some(doc) = self.try_doc_mut() {
+            doc.later(duration)?;
+            self.reload_highlight();
+        }
+        Ok(())
+    }
+
     /// Copy the selected text
     pub fn copy(&mut self) -> Result<()> {
         if let Some(doc) = self.try_doc() {
@@ -234,6 +274,25 @@ impl Editor {
         Ok(())
     }
 
+    /// Insert text (which may span several lines) into the document, returning where it ends.
+    /// This doesn't commit, so callers can choose how it is grouped in the undo history.
+    pub fn insert_text(&mut self, at: Loc, text: &str) -> Result<Loc> {
+        let mut at = at;
+        if let Some(doc) = self.try_doc_mut() {
+            for (idx, part) in text.split('\n').enumerate() {
+                if idx > 0 {
+                    doc.exe(Event::SplitDown(at))?;
+                    at = Loc::at(0, at.y + 1);
+                }
+                if !part.is_empty() {
+                    doc.exe(Event::Insert(at, part.to_string()))?;
+                    at.x += part.chars().count();
+                }
+            }
+        }
+        Ok(at)
+    }
+
     /// Shortcut to help rehighlight a line
     pub fn hl_edit(&mut self, y: usize) {
         if let Some(doc) = self.try_doc() {
@@ -242,3 +301,18 @@ impl Editor {
         }
     }
 }
+
+/// Parse a period of time such as "30s", "5m", "2h" or "1d" (a bare number is in seconds)
+pub fn parse_duration(period: &str) -> Option<Duration> {
+    let period = period.trim();
+    let (amount, unit) = period.split_at(period.trim_end_matches(char::is_alphabetic).len());
+    let amount: u64 = amount.trim().parse().ok()?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return None,
+    };
+    amount.checked_mul(multiplier).map(Duration::from_secs)
+}
@@ -0,0 +1,117 @@
+/// Functions for noticing when files are changed by other programs
+use crate::error::Result;
+use crate::ui::Feedback;
+use kaolinite::document::encoding::decode_file;
+use kaolinite::document::LineEnding;
+use kaolinite::utils::get_file_name;
+use mlua::Lua;
+
+use super::recovery::diff;
+use super::{DiskState, Editor, FileContainer};
+
+impl Editor {
+    /// Look for any open files that have been changed on the disk by other programs.
+    /// Files without unsaved changes are reloaded straight away, the rest are marked so
+    /// the user can decide what to do with them.
+    /// Returns the paths of the files that were changed.
+    pub fn check_external_changes(&mut self) -> Vec<String> {
+        let mut changed = vec![];
+        for fc in self.files.containers_mut() {
+            let (Some(file_name), Some(old)) = (fc.doc.file_name.clone(), fc.disk_state) else {
+                continue;
+            };
+            let Some((new, contents)) = old.changed(&file_name) else {
+                continue;
+            };
+            fc.disk_state = Some(new);
+            // The file was touched, but not actually changed
+            if new.hash == old.hash {
+                continue;
+            }
+            if fc.doc.event_mgmt.with_disk() {
+                if reload(fc, &contents).is_ok() {
+                    let name = get_file_name(&file_name).unwrap_or_default();
+                    self.feedback = Feedback::Info(format!("{name} was reloaded from the disk"));
+                }
+            } else {
+                fc.external_change = true;
+            }
+            changed.push(file_name);
+        }
+        changed
+    }
+
+    /// Determine if the current document has been changed on the disk by another program
+    pub fn changed_on_disk(&self) -> bool {
+        self.files.get(self.ptr.clone()).is_some_and(|fc| {
+            let file_name = fc.doc.file_name.as_deref().unwrap_or_default();
+            fc.disk_state
+                .and_then(|old| old.changed(file_name).map(|(new, _)| new.hash != old.hash))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Take note of what the current document looks like on the disk
+    pub fn update_disk_state(&mut self) {
+        if let Some(fc) = self.files.get_mut(self.ptr.clone()) {
+            fc.disk_state = DiskState::new(&fc.doc);
+            fc.external_change = false;
+        }
+    }
+
+    /// Ask the user what to do about a document with unsaved changes being changed on the disk
+    pub fn external_change_prompt(&mut self, lua: &Lua) -> Result<()> {
+        let Some(fc) = self.files.get(self.ptr.clone()) else {
+            return Ok(());
+        };
+        if !fc.external_change {
+            return Ok(());
+        }
+        let file_name = fc.doc.file_name.clone().unwrap_or_default();
+        let name = get_file_name(&file_name).unwrap_or_default();
+        let msg = format!(
+            "{name} was changed by another program: [r] Reload │ [d] Diff │ [Esc] Keep your changes"
+        );
+        match self.choose(&msg, &['r', 'd'])? {
+            // Throw away unsaved changes and load in the new version
+            Some('r') => {
+                let contents = std::fs::read(&file_name)?;
+                let fc = self.files.get_mut(self.ptr.clone()).unwrap();
+                reload(fc, &contents)?;
+                self.feedback = Feedback::Info(format!("Reloaded {name}"));
+            }
+            // Show how the new version differs (and ask again when the user returns)
+            Some(_) => {
+                let contents = std::fs::read(&file_name)?;
+                let (disk, _, _, _) = decode_file(&contents);
+                let buffer = self.try_doc().unwrap().file.to_string();
+                self.show_diff(&diff(&name, &disk, &buffer))?;
+            }
+            // Keep the unsaved changes (which will overwrite the new version on save)
+            None => {
+                let fc = self.files.get_mut(self.ptr.clone()).unwrap();
+                fc.external_change = false;
+            }
+        }
+        self.needs_rerender = true;
+        self.render(lua)
+    }
+}
+
+/// Replace the contents of a document with the version on the disk
+fn reload(fc: &mut FileContainer, contents: &[u8]) -> Result<()> {
+    let (text, encoding, bom, lossy) = decode_file(contents);
+    let doc = &mut fc.doc;
+    let loc = doc.char_loc();
+    // The line ending may have been changed too
+    doc.info.line_ending = LineEnding::detect(&text.as_str().into());
+    doc.set_contents(&text)?;
+    doc.event_mgmt.disk_write(doc.cursor);
+    doc.info.encoding = encoding;
+    doc.info.bom = bom;
+    doc.info.lossy = lossy;
+    doc.move_to(&loc);
+    fc.highlighter.run(&doc.lines);
+    fc.external_change = false;
+    Ok(())
+}
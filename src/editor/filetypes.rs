@@ -66,6 +66,8 @@ pub struct FileType {
     pub modelines: Vec<String>,
     /// The colour associated with this file type
     pub color: String,
+    /// The command that starts a language server for this file type
+    pub language_server: Option<String>,
 }
 
 impl Default for FileType {
@@ -77,6 +79,7 @@ impl Default for FileType {
             extensions: vec![],
             modelines: vec![],
             color: "grey".to_string(),
+            language_server: None,
         }
     }
 }
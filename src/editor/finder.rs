@@ -0,0 +1,421 @@
+/// Functions for finding files in a project by typing part of their path
+use crate::editor::FileLayout;
+use crate::error::{OxError, Result};
+use crate::events::get_event;
+use crate::ui::{key_event, size};
+use crate::{config, display};
+use crossterm::event::{KeyCode as KCode, KeyModifiers as KMod};
+use crossterm::style::{
+    Attribute, SetAttribute, SetBackgroundColor as Bg, SetForegroundColor as Fg,
+};
+use ignore::WalkBuilder;
+use kaolinite::searching::{fuzzy_match_path, FuzzyMatch};
+use kaolinite::utils::{get_cwd, trim, width, width_char};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::{looks_binary, Editor};
+
+/// The most files that will be indexed, so huge directories don't eat up memory
+const MAX_INDEXED_FILES: usize = 100_000;
+/// How deep to look when the directory isn't under version control (e.g. a home directory)
+const LOOSE_DEPTH: usize = 3;
+
+/// A list of the files in a project, built up in the background
+#[derive(Debug)]
+pub struct FileIndex {
+    /// The directory of the project
+    pub root: PathBuf,
+    /// The paths of the files found so far (relative to the root)
+    pub files: Arc<Mutex<Vec<String>>>,
+    /// Whether the project is still being looked through
+    pub indexing: Arc<AtomicBool>,
+    /// Whether hidden and ignored files were included
+    settings: (bool, bool),
+}
+
+impl FileIndex {
+    /// Start looking through a project for files
+    pub fn new(root: PathBuf, hidden: bool, ignored: bool) -> Self {
+        let index = Self {
+            root,
+            files: Arc::new(Mutex::new(vec![])),
+            indexing: Arc::new(AtomicBool::new(false)),
+            settings: (hidden, ignored),
+        };
+        index.refresh();
+        index
+    }
+
+    /// Look through the project again (for files that have been created or removed).
+    /// The files found last time are kept until the new list is complete.
+    pub fn refresh(&self) {
+        if self.indexing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let (root, files, indexing) = (
+            self.root.clone(),
+            Arc::clone(&self.files),
+            Arc::clone(&self.indexing),
+        );
+        let (hidden, ignored) = self.settings;
+        // Outside of a project, only look a few folders deep
+        let depth = (!root.join(".git").exists()).then_some(LOOSE_DEPTH);
+        std::thread::spawn(move || {
+            let first = files.lock().unwrap().is_empty();
+            let mut found = vec![];
+            let walker = WalkBuilder::new(&root)
+                .max_depth(depth)
+                .hidden(!hidden)
+                .ignore(!ignored)
+                .git_ignore(!ignored)
+                .git_global(!ignored)
+                .git_exclude(!ignored)
+                .require_git(false)
+                .filter_entry(|entry| entry.file_name() != ".git")
+                .build();
+            let mut count = 0;
+            for entry in walker.flatten() {
+                if !entry.file_type().is_some_and(|t| t.is_file()) {
+                    continue;
+                }
+                if count == MAX_INDEXED_FILES {
+                    break;
+                }
+                count += 1;
+                let Ok(path) = entry.path().strip_prefix(&root) else {
+                    continue;
+                };
+                let path = path.to_string_lossy().replace('\\', "/");
+                // The first time round, files can be searched through as soon as they're found
+                if first {
+                    files.lock().unwrap().push(path);
+                } else {
+                    found.push(path);
+                }
+            }
+            if !first {
+                *files.lock().unwrap() = found;
+            }
+            indexing.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Find the directory of the project a directory is in
+/// (the closest one under version control, otherwise the directory itself)
+pub fn project_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// Work out how many files can be shown in the finder at once
+fn finder_height() -> Result<usize> {
+    Ok((size()?.h * 4 / 5).saturating_sub(1).max(1))
+}
+
+/// Where to open a file that has been found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Place {
+    /// In a new tab in the current split
+    Here,
+    /// In a new split to the right
+    Right,
+    /// In a new split below
+    Below,
+}
+
+/// The state of the finder while it is open
+#[derive(Debug, Default)]
+struct Finder {
+    /// What has been typed
+    query: String,
+    /// The files that match what has been typed (best first)
+    matches: Vec<(usize, FuzzyMatch)>,
+    /// The match that is selected
+    selected: usize,
+    /// The first match shown (when there are too many to fit)
+    offset: usize,
+    /// The start of the selected file, for previewing
+    preview: Option<(String, Vec<String>)>,
+}
+
+impl Finder {
+    /// Work out which files match what has been typed
+    fn filter(&mut self, files: &[String]) {
+        self.matches = files
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, path)| Some((idx, fuzzy_match_path(&self.query, path)?)))
+            .collect();
+        self.matches.sort_by(|(a, am), (b, bm)| {
+            let (a, b) = (&files[*a], &files[*b]);
+            bm.score
+                .cmp(&am.score)
+                .then(a.len().cmp(&b.len()))
+                .then(a.cmp(b))
+        });
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Move the selection by a certain amount, keeping it in view
+    fn select(&mut self, up: bool, amount: usize, height: usize) {
+        self.selected = if up {
+            self.selected.saturating_sub(amount)
+        } else {
+            (self.selected + amount).min(self.matches.len().saturating_sub(1))
+        };
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height.max(1);
+        }
+    }
+
+    /// Read the start of the selected file to preview it
+    fn load_preview(&mut self, root: &Path, path: &str, lines: usize) {
+        if self.preview.as_ref().is_some_and(|(p, _)| p == path) {
+            return;
+        }
+        let full = root.join(path);
+        let content = if looks_binary(&full.to_string_lossy()) {
+            vec!["(binary file)".to_string()]
+        } else if let Ok(file) = File::open(&full) {
+            BufReader::new(file)
+                .lines()
+                .take(lines)
+                .map_while(std::result::Result::ok)
+                .collect()
+        } else {
+            vec![]
+        };
+        self.preview = Some((path.to_string(), content));
+    }
+}
+
+impl Editor {
+    /// Start (or refresh) the index of files in the current project
+    pub fn index_files(&mut self) {
+        let config = config!(self.config, finder);
+        let settings = (config.hidden, config.ignored);
+        let Some(cwd) = get_cwd() else {
+            return;
+        };
+        let root = project_root(Path::new(&cwd));
+        match &self.file_index {
+            Some(index) if index.root == root && index.settings == settings => index.refresh(),
+            _ => self.file_index = Some(FileIndex::new(root, settings.0, settings.1)),
+        }
+    }
+
+    /// Open the fuzzy finder, letting the user search for a file in the project to open
+    pub fn find_file(&mut self) -> Result<()> {
+        self.index_files();
+        let Some(index) = &self.file_index else {
+            return Ok(());
+        };
+        let (root, files) = (index.root.clone(), Arc::clone(&index.files));
+        let mut finder = Finder::default();
+        let mut found: Vec<String> = vec![];
+        let mut synced = false;
+        let mut changed = true;
+        let result = loop {
+            let height = finder_height()?;
+            // Keep up with files as they are found
+            if files.lock().unwrap().len() != found.len() || !synced {
+                found.clone_from(&files.lock().unwrap());
+                synced = true;
+                finder.filter(&found);
+                changed = true;
+            }
+            let files = &found;
+            if changed {
+                self.render_finder(&mut finder, files, &root)?;
+                changed = false;
+            }
+            let Some(event) = get_event(self) else {
+                continue;
+            };
+            let Some((modifiers, code)) = key_event(&event, &mut self.macro_man) else {
+                changed = true;
+                continue;
+            };
+            changed = true;
+            let place = match (modifiers, code) {
+                (KMod::NONE, KCode::Esc) => break None,
+                (KMod::NONE, KCode::Enter) => Place::Here,
+                (KMod::CONTROL, KCode::Char('v')) => Place::Right,
+                (KMod::CONTROL, KCode::Char('x')) => Place::Below,
+                (KMod::NONE, KCode::Up) | (KMod::CONTROL, KCode::Char('p' | 'k')) => {
+                    finder.select(true, 1, height);
+                    continue;
+                }
+                (KMod::NONE, KCode::Down) | (KMod::CONTROL, KCode::Char('n' | 'j')) => {
+                    finder.select(false, 1, height);
+                    continue;
+                }
+                (KMod::NONE, KCode::PageUp) => {
+                    finder.select(true, height, height);
+                    continue;
+                }
+                (KMod::NONE, KCode::PageDown) => {
+                    finder.select(false, height, height);
+                    continue;
+                }
+                (KMod::NONE, KCode::Backspace) => {
+                    finder.query.pop();
+                    finder.filter(files);
+                    continue;
+                }
+                (KMod::NONE | KMod::SHIFT, KCode::Char(c)) => {
+                    finder.query.push(c);
+                    finder.selected = 0;
+                    finder.offset = 0;
+                    finder.filter(files);
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some((idx, _)) = finder.matches.get(finder.selected) {
+                break Some((files[*idx].clone(), place));
+            }
+        };
+        self.needs_rerender = true;
+        let Some((path, place)) = result else {
+            return Ok(());
+        };
+        let path = root.join(path).to_string_lossy().to_string();
+        match place {
+            Place::Here => match self.open(&path) {
+                Ok(()) => self.next(),
+                // Files that are already open are switched to
+                Err(OxError::AlreadyOpen { .. }) => (),
+                Err(err) => return Err(err),
+            },
+            Place::Right | Place::Below => {
+                let fc = self.open_fc(&path)?;
+                let layout = FileLayout::Atom(vec![fc], 0);
+                self.ptr = if place == Place::Right {
+                    self.files.open_right(self.ptr.clone(), layout)
+                } else {
+                    self.files.open_down(self.ptr.clone(), layout)
+                };
+                self.cache_old_ptr(&self.ptr.clone());
+            }
+        }
+        self.update_cwd();
+        Ok(())
+    }
+
+    /// Render the finder over the top of the editor
+    #[allow(clippy::similar_names)]
+    fn render_finder(&mut self, finder: &mut Finder, files: &[String], root: &Path) -> Result<()> {
+        let size = size()?;
+        let colors = config!(self.config, colors);
+        let tab_width = config!(self.config, document).tab_width;
+        let show_preview = config!(self.config, finder).preview;
+        let popup_bg = Bg(colors.status_bg.to_color()?);
+        let popup_fg = Fg(colors.editor_fg.to_color()?);
+        let selection_bg = Bg(colors.selection_bg.to_color()?);
+        let selection_fg = Fg(colors.selection_fg.to_color()?);
+        let highlight = Fg(colors.highlight.to_color()?);
+        let info_fg = Fg(colors.info_fg.to_color()?);
+        let editor_bg = Bg(colors.editor_bg.to_color()?);
+        let editor_fg = Fg(colors.editor_fg.to_color()?);
+        // Work out where everything goes
+        let w = (size.w * 9 / 10).max(size.w.min(20));
+        let height = finder_height()?;
+        let (x, y) = ((size.w - w) / 2, (size.h.saturating_sub(height + 1)) / 2);
+        let list_w = if show_preview && w >= 60 {
+            w * 2 / 5
+        } else {
+            w
+        };
+        let preview_w = w - list_w;
+        // The prompt
+        let indexing = self
+            .file_index
+            .as_ref()
+            .is_some_and(|index| index.indexing.load(Ordering::SeqCst));
+        let count = format!(
+            " {}/{}{} ",
+            finder.matches.len(),
+            files.len(),
+            if indexing { " …" } else { "" }
+        );
+        let prompt = format!(" Find: {}", finder.query);
+        let prompt = trim(
+            &prompt,
+            0,
+            w.saturating_sub(width(&count, tab_width)),
+            tab_width,
+        );
+        let padding =
+            " ".repeat(w.saturating_sub(width(&prompt, tab_width) + width(&count, tab_width)));
+        self.terminal.goto(x, y);
+        display!(self, popup_bg, popup_fg, prompt, padding, info_fg, count);
+        // The matching files
+        if let Some((idx, _)) = finder.matches.get(finder.selected) {
+            if preview_w > 0 {
+                finder.load_preview(root, &files[*idx], height);
+            }
+        }
+        let preview = finder
+            .preview
+            .as_ref()
+            .map(|(_, lines)| lines.clone())
+            .unwrap_or_default();
+        for row in 0..height {
+            let mut line = String::new();
+            if let Some((idx, found)) = finder.matches.get(finder.offset + row) {
+                let (bg, fg) = if finder.offset + row == finder.selected {
+                    (selection_bg, selection_fg)
+                } else {
+                    (popup_bg, popup_fg)
+                };
+                line += &(bg.to_string() + &fg.to_string() + " ");
+                let mut used = 1;
+                for (i, c) in files[*idx].chars().enumerate() {
+                    if used + width_char(&c, tab_width) > list_w {
+                        break;
+                    }
+                    used += width_char(&c, tab_width);
+                    if found.indices.contains(&i) {
+                        line += &highlight.to_string();
+                        line.push(c);
+                        line += &fg.to_string();
+                    } else {
+                        line.push(c);
+                    }
+                }
+                line += &" ".repeat(list_w - used);
+            } else {
+                line += &(popup_bg.to_string() + &" ".repeat(list_w));
+            }
+            // The preview of the selected file
+            if preview_w > 0 {
+                let text = preview.get(row).map_or(String::new(), |text| {
+                    text.replace('\t', &" ".repeat(tab_width))
+                });
+                let text = trim(&text, 0, preview_w - 2, tab_width);
+                let padding = " ".repeat(preview_w - 2 - width(&text, tab_width));
+                line += &popup_fg.to_string();
+                line.push('│');
+                line += &(editor_bg.to_string() + &editor_fg.to_string() + " ");
+                line += &(text + &padding);
+            }
+            self.terminal.goto(x, y + 1 + row);
+            display!(self, line);
+        }
+        // Put the cursor after what has been typed
+        self.terminal.show_cursor();
+        self.terminal.goto(x + width(&prompt, tab_width), y);
+        self.terminal.flush()?;
+        Ok(())
+    }
+}
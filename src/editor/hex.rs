@@ -0,0 +1,222 @@
+/// Functions for viewing and editing files in hex
+use crate::config;
+use crate::error::Result;
+use crate::ui::Feedback;
+use crossterm::event::{KeyCode as KCode, KeyModifiers as KMod};
+use crossterm::style::{SetBackgroundColor as Bg, SetForegroundColor as Fg};
+use kaolinite::hex::ROW_WIDTH;
+use kaolinite::utils::{Loc, Size};
+use kaolinite::{Document, HexDocument};
+use std::fmt::Write;
+
+use super::{Editor, FileContainer};
+
+/// The number of columns taken up by the offset at the start of each row
+const OFFSET_WIDTH: usize = 12;
+
+impl FileContainer {
+    /// Create a file container that shows a file in hex
+    pub fn from_hex(hex: HexDocument) -> Self {
+        // The document stands in for the hex document in the rest of the editor (e.g. tab line)
+        let mut doc = Document::new(hex.size);
+        doc.file_name.clone_from(&hex.file_name);
+        doc.info.read_only = true;
+        Self {
+            doc,
+            hex: Some(hex),
+            ..Self::default()
+        }
+    }
+
+    /// Let the rest of the editor know whether the hex document has unsaved changes
+    pub fn sync_hex(&mut self) {
+        if let Some(hex) = &self.hex {
+            self.doc.event_mgmt.force_not_with_disk = hex.modified();
+        }
+    }
+}
+
+impl Editor {
+    /// Try to get the hex document currently being viewed
+    pub fn try_hex(&self) -> Option<&HexDocument> {
+        self.files.get(self.ptr.clone())?.hex.as_ref()
+    }
+
+    /// Try to get the hex document currently being viewed
+    pub fn try_hex_mut(&mut self) -> Option<&mut HexDocument> {
+        self.files.get_mut(self.ptr.clone())?.hex.as_mut()
+    }
+
+    /// Switch a document in the current atom over to being viewed in hex
+    pub fn convert_to_hex(&mut self, idx: usize) {
+        if let Some((fcs, _)) = self.files.get_atom_mut(self.ptr.clone()) {
+            if let Some(fc) = fcs.get_mut(idx) {
+                let size = fc.doc.size;
+                let file_name = fc.doc.file_name.clone();
+                let read_only = fc
+                    .hex
+                    .as_ref()
+                    .map_or(fc.doc.info.read_only, |h| h.read_only);
+                let mut hex = file_name
+                    .as_ref()
+                    .and_then(|file_name| HexDocument::open(size, file_name).ok())
+                    .unwrap_or_else(|| {
+                        let mut hex = HexDocument::new(size);
+                        hex.file_name = file_name;
+                        hex
+                    });
+                hex.read_only = read_only;
+                *fc = FileContainer::from_hex(hex);
+            }
+        }
+    }
+
+    /// Handle a key press while viewing a file in hex
+    pub fn handle_hex_key(&mut self, modifiers: KMod, code: KCode) -> Result<()> {
+        let Some(hex) = self.try_hex_mut() else {
+            return Ok(());
+        };
+        match (modifiers, code) {
+            // Moving around
+            (KMod::NONE, KCode::Up) => hex.move_up(),
+            (KMod::NONE, KCode::Down) => hex.move_down(),
+            (KMod::NONE, KCode::Left) => hex.move_left(),
+            (KMod::NONE, KCode::Right) => hex.move_right(),
+            (KMod::NONE, KCode::Home) => hex.move_home(),
+            (KMod::NONE, KCode::End) => hex.move_end(),
+            (KMod::NONE, KCode::PageUp) => hex.move_page_up(),
+            (KMod::NONE, KCode::PageDown) => hex.move_page_down(),
+            (KMod::CONTROL, KCode::Up) => hex.move_to(0),
+            (KMod::CONTROL, KCode::Down) => hex.move_to(usize::MAX),
+            // Editing
+            (KMod::SHIFT | KMod::NONE, KCode::Char(ch)) => {
+                if let Some(digit) = ch.to_digit(16) {
+                    hex.type_digit(u8::try_from(digit).unwrap_or_default())?;
+                }
+            }
+            (KMod::NONE, KCode::Backspace) => hex.backspace()?,
+            (KMod::NONE, KCode::Delete) => hex.delete()?,
+            (KMod::NONE, KCode::Insert) => {
+                hex.overwrite = !hex.overwrite;
+                let mode = if hex.overwrite { "Overwrite" } else { "Insert" };
+                self.feedback = Feedback::Info(format!("{mode} mode"));
+            }
+            _ => (),
+        }
+        if let Some(fc) = self.files.get_mut(self.ptr.clone()) {
+            fc.sync_hex();
+        }
+        Ok(())
+    }
+
+    /// Save the hex document currently being viewed
+    pub fn save_hex(&mut self) -> Result<()> {
+        let backup = config!(self.config, document).backup.clone();
+        if let Some(fc) = self.files.get_mut(self.ptr.clone()) {
+            if let Some(hex) = &mut fc.hex {
+                hex.backup = backup;
+                hex.save()?;
+                fc.sync_hex();
+                self.feedback = Feedback::Info("Document saved successfully".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo or redo an edit in the hex document currently being viewed
+    pub fn undo_hex(&mut self, redo: bool) -> Result<()> {
+        if let Some(fc) = self.files.get_mut(self.ptr.clone()) {
+            if let Some(hex) = &mut fc.hex {
+                if redo {
+                    hex.redo()?;
+                } else {
+                    hex.undo()?;
+                }
+                fc.sync_hex();
+            }
+        }
+        Ok(())
+    }
+
+    /// Search for a sequence of bytes in the hex document currently being viewed
+    pub fn search_hex(&mut self) -> Result<()> {
+        let target = self.prompt("Search (hex bytes)")?;
+        if target.is_empty() {
+            return Ok(());
+        }
+        let Some(pattern) = HexDocument::parse_pattern(&target) else {
+            self.feedback = Feedback::Error(format!("'{target}' isn't a valid sequence of bytes"));
+            return Ok(());
+        };
+        if let Some(hex) = self.try_hex_mut() {
+            if hex.find(&pattern).is_none() {
+                self.feedback = Feedback::Warning(format!("No matches found for {target}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Render a row of a hex document: offset, hex bytes and a gutter of printable characters
+    #[allow(clippy::similar_names)]
+    pub fn render_hex(&self, hex: &HexDocument, y: usize, size: Size) -> Result<String> {
+        let editor_bg = Bg(config!(self.config, colors).editor_bg.to_color()?);
+        let editor_fg = Fg(config!(self.config, colors).editor_fg.to_color()?);
+        let line_number_bg = Bg(config!(self.config, colors).line_number_bg.to_color()?);
+        let line_number_fg = Fg(config!(self.config, colors).line_number_fg.to_color()?);
+        let selection_bg = Bg(config!(self.config, colors).selection_bg.to_color()?);
+        let selection_fg = Fg(config!(self.config, colors).selection_fg.to_color()?);
+        let row = hex.offset + y;
+        let Some(bytes) = hex.row(row) else {
+            return Ok(format!("{editor_bg}{editor_fg}{}", " ".repeat(size.w)));
+        };
+        // Work out the text (and the colour of that text)
+        let start = row * ROW_WIDTH;
+        let mut parts = vec![(
+            format!("{line_number_bg}{line_number_fg}"),
+            format!(" {start:08x} │"),
+        )];
+        let mut digits = String::from(" ");
+        for i in 0..ROW_WIDTH {
+            if i == ROW_WIDTH / 2 {
+                digits.push(' ');
+            }
+            match bytes.get(i) {
+                Some(byte) => write!(digits, "{byte:02x} ").unwrap_or_default(),
+                None => digits.push_str("   "),
+            }
+        }
+        parts.push((format!("{editor_bg}{editor_fg}"), digits + "│ "));
+        for (i, byte) in bytes.iter().enumerate() {
+            let ch = if byte.is_ascii_graphic() || *byte == b' ' {
+                char::from(*byte)
+            } else {
+                '.'
+            };
+            let colour = if start + i == hex.cursor {
+                format!("{selection_bg}{selection_fg}")
+            } else {
+                format!("{editor_bg}{editor_fg}")
+            };
+            parts.push((colour, ch.to_string()));
+        }
+        // Fit into the space available
+        let mut result = String::new();
+        let mut remaining = size.w;
+        for (colour, text) in parts {
+            let text: String = text.chars().take(remaining).collect();
+            remaining -= text.chars().count();
+            result += &colour;
+            result += &text;
+        }
+        write!(result, "{editor_bg}{editor_fg}{}", " ".repeat(remaining)).unwrap_or_default();
+        Ok(result)
+    }
+}
+
+/// Work out where the cursor of a hex document should be within the viewport
+pub fn hex_cursor(hex: &HexDocument) -> Option<Loc> {
+    let Loc { x, y } = hex.loc();
+    let y = y.checked_sub(hex.offset).filter(|y| *y < hex.size.h)?;
+    let x = OFFSET_WIDTH + x * 3 + usize::from(x >= ROW_WIDTH / 2) + usize::from(hex.low_nibble);
+    Some(Loc { x, y })
+}
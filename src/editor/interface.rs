@@ -1,18 +1,29 @@
 /// Functions for rendering the UI
 use crate::config::SyntaxHighlighting as SH;
-use crate::editor::{FTParts, FileLayout};
+use crate::editor::{
+    hex_cursor, most_serious, Diagnostic, FTParts, FileContainer, FileLayout, HistoryBrowser,
+    MatchCache, QueryKind, QuickfixList,
+};
 use crate::error::{OxError, Result};
 use crate::events::wait_for_event_hog;
 use crate::ui::{key_event, size, Feedback};
-#[cfg(not(target_os = "windows"))]
-use crate::ui::{remove_ansi_codes, replace_reset, strip_escape_codes};
 use crate::{config, display, handle_lua_error};
 use crossterm::{
     event::{KeyCode as KCode, KeyModifiers as KMod},
-    style::{Attribute, Color, SetAttribute, SetBackgroundColor as Bg, SetForegroundColor as Fg},
+    style::{
+        Attribute, Color, SetAttribute, SetBackgroundColor as Bg, SetForegroundColor as Fg,
+        SetUnderlineColor,
+    },
+    Command,
+};
+use kaolinite::document::{wrap::wrap_line, Wrap};
+use kaolinite::searching::Searcher;
+use kaolinite::terminal::{Colour as TermColour, Style as TermStyle};
+use kaolinite::utils::{
+    file_or_dir, get_cwd, get_parent, list_dir, trim, width, width_char, Loc, Size,
 };
-use kaolinite::utils::{file_or_dir, get_cwd, get_parent, list_dir, width, width_char, Loc, Size};
 use mlua::Lua;
+use std::collections::HashMap;
 use std::ops::Range;
 use synoptic::{trim_fit, Highlighter, TokOpt};
 
@@ -29,6 +40,8 @@ pub struct RenderCache {
     pub file_tree: FTParts,
     pub file_tree_selection: Option<usize>,
     pub term_cursor: Option<Loc>,
+    pub diagnostics: HashMap<String, Vec<Diagnostic>>,
+    pub search_matches: MatchCache,
 }
 
 impl Editor {
@@ -70,6 +83,16 @@ impl Editor {
         }
         // Clear the terminal cursor position
         self.render_cache.term_cursor = None;
+        // Work out where the diagnostics of each open file are
+        let mut diagnostics = HashMap::new();
+        for fc in self.files.containers() {
+            if let Some(file_name) = &fc.doc.file_name {
+                if self.has_diagnostics(fc) {
+                    diagnostics.insert(file_name.clone(), self.diagnostics_for(file_name));
+                }
+            }
+        }
+        self.render_cache.diagnostics = diagnostics;
     }
 
     /// Render a specific line
@@ -84,14 +107,10 @@ impl Editor {
         let mut accounted_for = 0;
         // Render each component of this line
         for (c, (fc, rows, range)) in fcs.iter().enumerate() {
-            let in_file_tree = matches!(
-                self.files.get_raw(fc.to_owned()),
-                Some(FileLayout::FileTree)
-            );
-            let in_terminal = matches!(
-                self.files.get_raw(fc.to_owned()),
-                Some(FileLayout::Terminal(_))
-            );
+            let layout = self.files.get_raw(fc.to_owned());
+            let in_file_tree = matches!(layout, Some(FileLayout::FileTree));
+            let in_terminal = matches!(layout, Some(FileLayout::Terminal(_)));
+            let in_quickfix = matches!(layout, Some(FileLayout::Quickfix));
             // Check if we have encountered an area of discontinuity in the line
             if range.start != accounted_for {
                 // Discontinuity detected, fill with vertical bar!
@@ -126,6 +145,9 @@ impl Editor {
             } else if in_terminal {
                 // Part of terminal!
                 result += &self.render_terminal(fc, rel_y, length, height)?;
+            } else if in_quickfix {
+                // Part of the quickfix list
+                result += &self.render_quickfix(rel_y, length, height)?;
             } else if y == rows.start && tab_line_enabled {
                 // Tab line
                 result += &self.render_tab_line(fc, lua, length)?;
@@ -207,8 +229,17 @@ impl Editor {
         // Update all document's size
         let updates = self.files.update_doc_sizes(&self.render_cache.span, self);
         for (ptr, doc_idx, new_size) in updates {
-            let doc = &mut self.files.get_atom_mut(ptr.clone()).unwrap().0[doc_idx].doc;
+            let wrap = self.soft_wrap_for(
+                &self.files.get_atom(ptr.clone()).unwrap().0[doc_idx],
+                new_size,
+            );
+            let fc = &mut self.files.get_atom_mut(ptr.clone()).unwrap().0[doc_idx];
+            if let Some(hex) = &mut fc.hex {
+                hex.size = new_size;
+            }
+            let doc = &mut fc.doc;
             doc.size = new_size;
+            doc.set_wrap(wrap);
             doc.load_to(doc.offset.y + doc.size.h + 1);
             self.update_highlighter_for(&ptr, doc_idx);
         }
@@ -221,6 +252,8 @@ impl Editor {
             self.terminal.goto(0, y);
             display!(self, line);
         }
+        // Render the completion popup over the document
+        self.render_completion(size)?;
         // Render the feedback line
         self.render_feedback_line(w, h)?;
         // Move cursor to the correct location and perform render
@@ -242,14 +275,26 @@ impl Editor {
             self.files.get_raw(self.ptr.clone()),
             Some(FileLayout::Terminal(_))
         );
+        // The quickfix list shows which place is selected instead of a cursor
+        if let Some(FileLayout::Quickfix) = self.files.get_raw(self.ptr.clone()) {
+            return None;
+        }
         match (in_file_tree, in_terminal) {
             // Move cursor to location within file
             (false, false) => {
-                let Loc { x, y } = self.try_doc().unwrap().cursor_loc_in_screen()?;
+                let Loc { x, y } = if let Some(hex) = self.try_hex() {
+                    hex_cursor(hex)?
+                } else {
+                    let Loc { x, y } = self.try_doc().unwrap().cursor_loc_in_screen()?;
+                    Loc {
+                        x: x + self.dent(),
+                        y,
+                    }
+                };
                 for (ptr, rows, cols) in &self.render_cache.span {
                     if ptr == &self.ptr {
                         return Some(Loc {
-                            x: cols.start + x + self.dent(),
+                            x: cols.start + x,
                             y: rows.start + y + self.push_down,
                         });
                     }
@@ -276,6 +321,9 @@ impl Editor {
     /// Render the lines of the document
     #[allow(clippy::similar_names, clippy::too_many_lines)]
     pub fn render_file(&mut self, ptr: &[usize], y: usize, size: Size, sh: &SH) -> Result<String> {
+        if let Some(hex) = &self.files.get(ptr.to_owned()).unwrap().hex {
+            return self.render_hex(hex, y, size);
+        }
         let Size { mut w, h } = size;
         let mut result = String::new();
         // Get various information
@@ -285,6 +333,8 @@ impl Editor {
         let line_number_fg = Fg(config!(self.config, colors).line_number_fg.to_color()?);
         let selection_bg = Bg(config!(self.config, colors).selection_bg.to_color()?);
         let selection_fg = Fg(config!(self.config, colors).selection_fg.to_color()?);
+        let search_match_bg = Bg(config!(self.config, colors).search_match_bg.to_color()?);
+        let search_match_fg = Fg(config!(self.config, colors).search_match_fg.to_color()?);
         let underline = SetAttribute(Attribute::Underlined);
         let no_underline = SetAttribute(Attribute::NoUnderline);
         let tab_width = config!(self.config, document).tab_width;
@@ -293,6 +343,8 @@ impl Editor {
         let ln_pad_right = config!(self.config, line_numbers).padding_right;
         let fc = self.files.get(ptr.to_owned()).unwrap();
         let doc = &fc.doc;
+        let sign = &config!(self.config, document).diagnostic_sign;
+        let sign_column = !sign.is_empty() && self.has_diagnostics(fc);
         let selection = doc.selection_loc_bound_disp();
         let has_file = doc.file_name.is_none();
         // Refuse to render help message on splits - awkward edge case
@@ -305,31 +357,104 @@ impl Editor {
         } else {
             0
         };
+        // Work out which part of which line is shown on this row
+        let (at_line, row) = if doc.wrap.is_some() {
+            // Rows past the end of the document don't belong to any line
+            doc.visual_row(y).unwrap_or((doc.len_lines() + y, 0..0))
+        } else if doc.folds.is_empty() {
+            (y + doc.offset.y, doc.offset.x..doc.offset.x + w)
+        } else {
+            let at_line = doc.visual_row(y).map_or(doc.len_lines() + y, |(y, _)| y);
+            (at_line, doc.offset.x..doc.offset.x + w)
+        };
+        let fold = doc.fold_at(at_line);
+        // Gather the diagnostics on this line (with the colours that represent them)
+        let mut diagnostics = vec![];
+        let all_diagnostics = doc
+            .file_name
+            .as_ref()
+            .and_then(|file_name| self.render_cache.diagnostics.get(file_name));
+        for diagnostic in all_diagnostics.into_iter().flatten() {
+            if diagnostic.start.y <= at_line && at_line <= diagnostic.end.y {
+                let colour = diagnostic.color(&config!(self.config, colors))?;
+                diagnostics.push((diagnostic, colour));
+            }
+        }
+        // Render the sign column, marking the most serious diagnostic that starts on this line
+        if sign_column {
+            let starting = diagnostics
+                .iter()
+                .filter(|(d, _)| d.start.y == at_line && row.start == 0)
+                .map(|(d, _)| *d);
+            let sign_width = width(sign, tab_width);
+            if let Some(diagnostic) = most_serious(starting) {
+                let colour = Fg(diagnostic.color(&config!(self.config, colors))?);
+                result += &line_number_bg.to_string();
+                result += &colour.to_string();
+                result += sign;
+            } else {
+                result += &line_number_bg.to_string();
+                result += &" ".repeat(sign_width);
+            }
+            total_width += sign_width;
+        }
         // Render the line numbers if enabled
         if line_numbers_enabled {
-            let num = doc.line_number(y + doc.offset.y);
+            let mut num = doc.line_number(at_line);
+            if row.start > 0 && doc.wrap.is_some() {
+                // Show the wrap indicator instead on rows that continue a line
+                let indicator = &config!(self.config, document).wrap_indicator;
+                let total = width(&num, tab_width);
+                num = if width(indicator, tab_width) <= total {
+                    " ".repeat(total - width(indicator, tab_width)) + indicator
+                } else {
+                    " ".repeat(total)
+                };
+            }
             let padding_left = " ".repeat(ln_pad_left);
             let padding_right = " ".repeat(ln_pad_right);
-            result += &format!("{line_number_bg}{line_number_fg}{padding_left}{num}{padding_right}│{editor_fg}{editor_bg}");
-            total_width += ln_pad_left + ln_pad_right + width(&num, tab_width) + 1;
+            // Mark folded lines in the divider
+            let divider = match fold {
+                Some(_) => &config!(self.config, document).fold_indicator,
+                None => "│",
+            };
+            result += &format!("{line_number_bg}{line_number_fg}{padding_left}{num}{padding_right}{divider}{editor_fg}{editor_bg}");
+            total_width +=
+                ln_pad_left + ln_pad_right + width(&num, tab_width) + width(divider, tab_width);
         } else {
             result += &format!("{editor_fg}{editor_bg}");
         }
         w = w.saturating_sub(total_width);
         // Render the body of the document if available
-        let at_line = y + doc.offset.y;
         if let Some(line) = doc.line(at_line) {
             // Reset the cache
             let mut cache_bg = editor_bg;
             let mut cache_fg = editor_fg;
             // Gather the tokens
-            let tokens = fc.highlighter.line(at_line, &line);
-            let tokens = trim_fit(&tokens, doc.offset.x, w, tab_width);
-            let mut x_disp = doc.offset.x;
-            let mut x_char = doc.character_idx(&doc.offset);
+            let tokens = if doc.lazy.is_some() {
+                // Lazily loaded documents aren't syntax highlighted
+                vec![TokOpt::None(line.replace('\t', &" ".repeat(tab_width)))]
+            } else {
+                fc.highlighter.line(at_line, &line)
+            };
+            let tokens = trim_fit(&tokens, row.start, row.len().min(w), tab_width);
+            let mut x_disp = row.start;
+            let mut x_char = doc.character_idx(&Loc::at(row.start, at_line));
             // Run some more calcs
             let is_focus = self.ptr == ptr;
             let has_selection_somewhere = doc.cursor.selection_end != doc.cursor.loc;
+            let mut cache_underline = None;
+            // Find every match of what is being searched for
+            let search_matches: Vec<Range<usize>> = match &self.search_highlight {
+                Some(re) if is_focus && config!(self.config, search).highlight => {
+                    Searcher { re: re.clone() }
+                        .lfinds(&line)
+                        .into_iter()
+                        .map(|m| m.loc.x..m.loc.x + m.text.chars().count())
+                        .collect()
+                }
+                _ => vec![],
+            };
             for token in tokens {
                 // Find out the text (and colour of that text)
                 let (text, colour, feedback) = self.breakdown_token(token, sh)?;
@@ -354,6 +479,15 @@ impl Editor {
                             result += &selection_fg.to_string();
                             cache_fg = selection_fg;
                         }
+                    } else if search_matches.iter().any(|m| m.contains(&x_char)) {
+                        if cache_bg != search_match_bg {
+                            result += &search_match_bg.to_string();
+                            cache_bg = search_match_bg;
+                        }
+                        if cache_fg != search_match_fg {
+                            result += &search_match_fg.to_string();
+                            cache_fg = search_match_fg;
+                        }
                     } else {
                         if cache_bg != editor_bg {
                             result += &editor_bg.to_string();
@@ -364,6 +498,23 @@ impl Editor {
                             cache_fg = colour;
                         }
                     }
+                    // Underline problems, in the colour of the most serious one
+                    let problem = diagnostics
+                        .iter()
+                        .filter(|(d, _)| d.contains(char_loc))
+                        .min_by_key(|(d, _)| d.rank())
+                        .map(|(_, colour)| *colour);
+                    if problem != cache_underline {
+                        if let Some(colour) = problem {
+                            SetUnderlineColor(colour)
+                                .write_ansi(&mut result)
+                                .unwrap_or_default();
+                            result += &underline.to_string();
+                        } else {
+                            result += &no_underline.to_string();
+                        }
+                        cache_underline = problem;
+                    }
                     // Render multi-cursors
                     let multi_cursor_here = doc.has_cursor(char_loc).is_some();
                     if multi_cursor_here {
@@ -374,6 +525,7 @@ impl Editor {
                     // Reset any multi-cursor display
                     if multi_cursor_here {
                         result += &format!("{no_underline}{cache_bg}{cache_fg}");
+                        cache_underline = None;
                     }
                     x_char += 1;
                     let c_width = width_char(&c, tab_width);
@@ -381,7 +533,23 @@ impl Editor {
                     total_width += c_width;
                 }
             }
+            if cache_underline.is_some() {
+                result += &no_underline.to_string();
+            }
             result += &format!("{editor_fg}{editor_bg}{cache_fg}");
+            // Summarise what is hidden by a fold
+            if let Some(fold) = fold {
+                let plural = if fold.len() == 1 { "" } else { "s" };
+                let summary = format!(" ⋯ {} line{plural}", fold.len());
+                let summary: String = summary
+                    .chars()
+                    .take(w.saturating_sub(total_width))
+                    .collect();
+                total_width += width(&summary, tab_width);
+                result += &line_number_fg.to_string();
+                result += &summary;
+                result += &cache_fg.to_string();
+            }
             result += &" ".repeat(w.saturating_sub(total_width));
         } else if config!(self.config, greeting_message).enabled && self.greet && has_file {
             // Render the greeting message (if enabled)
@@ -551,10 +719,128 @@ impl Editor {
         Ok(result)
     }
 
+    /// Render the completion popup, lined up with the start of what is being typed
+    #[allow(clippy::similar_names, clippy::too_many_lines)]
+    fn render_completion(&mut self, size: Size) -> Result<()> {
+        let (Some(completion), Some(cursor)) = (&self.completion, self.cursor_position()) else {
+            return Ok(());
+        };
+        let config = config!(self.config, completion);
+        let colors = config!(self.config, colors);
+        let tab_width = config!(self.config, document).tab_width;
+        let popup_bg = Bg(colors.status_bg.to_color()?);
+        let popup_fg = Fg(colors.editor_fg.to_color()?);
+        let selection_bg = Bg(colors.selection_bg.to_color()?);
+        let selection_fg = Fg(colors.selection_fg.to_color()?);
+        let highlight = Fg(colors.highlight.to_color()?);
+        let detail_fg = Fg(colors.info_fg.to_color()?);
+        let visible = completion.visible(config.height.max(1));
+        // Work out how big the popup needs to be
+        let label_width = visible
+            .iter()
+            .map(|(idx, _)| width(&completion.candidates[*idx].label, tab_width))
+            .max()
+            .unwrap_or(0)
+            .min(size.w / 2);
+        let detail_width = visible
+            .iter()
+            .map(|(idx, _)| width(&completion.candidates[*idx].detail, tab_width))
+            .max()
+            .unwrap_or(0);
+        let w = (label_width + detail_width + 3).min(size.w);
+        let label_width = w.saturating_sub(detail_width + 3);
+        // Show the popup below the cursor, or above it if there isn't room
+        let typed = self
+            .try_doc()
+            .map_or(0, |doc| doc.char_loc().x.saturating_sub(completion.start.x));
+        let x = cursor.x.saturating_sub(typed).min(size.w.saturating_sub(w));
+        let y = if cursor.y + 1 + visible.len() < size.h {
+            cursor.y + 1
+        } else {
+            cursor.y.saturating_sub(visible.len())
+        };
+        for (row, (idx, found)) in visible.iter().enumerate() {
+            let candidate = &completion.candidates[*idx];
+            let (bg, fg) = if completion.offset + row == completion.selected {
+                (selection_bg, selection_fg)
+            } else {
+                (popup_bg, popup_fg)
+            };
+            // Highlight the characters that match what has been typed
+            let mut line = bg.to_string() + &fg.to_string() + " ";
+            let mut used = 0;
+            for (i, c) in candidate.label.chars().enumerate() {
+                used += width_char(&c, tab_width);
+                if used > label_width {
+                    used -= width_char(&c, tab_width);
+                    break;
+                }
+                if found.indices.contains(&i) {
+                    line += &highlight.to_string();
+                    line.push(c);
+                    line += &fg.to_string();
+                } else {
+                    line.push(c);
+                }
+            }
+            line += &" ".repeat(label_width - used + 1);
+            line += &detail_fg.to_string();
+            line += &trim(&candidate.detail, 0, detail_width, tab_width);
+            line += &" ".repeat(detail_width.saturating_sub(width(&candidate.detail, tab_width)));
+            line += " ";
+            self.terminal.goto(x, y + row);
+            display!(self, line);
+        }
+        // Show the documentation for the selected candidate beside the popup
+        let documentation = completion.current().and_then(|c| c.documentation.as_ref());
+        let Some(documentation) = documentation.filter(|_| config.documentation) else {
+            return Ok(());
+        };
+        let doc_width = 40.min(size.w.saturating_sub(w + 2));
+        let doc_x = if x + w + doc_width + 2 <= size.w {
+            x + w
+        } else if x >= doc_width + 2 {
+            x - doc_width - 2
+        } else {
+            return Ok(());
+        };
+        let wrap = Wrap {
+            width: doc_width,
+            words: true,
+        };
+        let mut lines = vec![];
+        for line in documentation.lines() {
+            let mut starts = wrap_line(line, tab_width, wrap);
+            starts.push(width(line, tab_width));
+            for part in starts.windows(2) {
+                lines.push(trim(line, part[0], part[1] - part[0], tab_width));
+            }
+        }
+        let height = config.height.max(visible.len()).min(lines.len());
+        let doc_y = if y + height <= size.h {
+            y
+        } else {
+            size.h.saturating_sub(height)
+        };
+        for (row, line) in lines.iter().take(height).enumerate() {
+            let line = line.trim_end();
+            let padding = " ".repeat(doc_width.saturating_sub(width(line, tab_width)));
+            self.terminal.goto(doc_x, doc_y + row);
+            display!(self, popup_bg, popup_fg, " ", line, padding, " ");
+        }
+        Ok(())
+    }
+
     /// Render the feedback line
     pub fn render_feedback_line(&mut self, w: usize, h: usize) -> Result<()> {
         self.terminal.goto(0, h + 2);
-        let content = self.feedback.render(&config!(self.config, colors), w)?;
+        // Describe the problem under the cursor when there is nothing else to say
+        let diagnostic = match self.feedback {
+            Feedback::None => self.diagnostic_at_cursor().map(|d| d.feedback()),
+            _ => None,
+        };
+        let feedback = diagnostic.as_ref().unwrap_or(&self.feedback);
+        let content = feedback.render(&config!(self.config, colors), w)?;
         display!(self, content);
         Ok(())
     }
@@ -637,41 +923,94 @@ impl Editor {
         }
     }
 
+    /// Render a line of the quickfix list (a heading, followed by the places in it)
+    fn render_quickfix(&mut self, y: usize, length: usize, height: usize) -> Result<String> {
+        let colors = config!(self.config, colors);
+        let list = &mut self.quickfix;
+        // Keep the selected place in view
+        let shown = height.saturating_sub(1).max(1);
+        if let Some(current) = list.current {
+            if current < list.offset {
+                list.offset = current;
+            } else if current >= list.offset + shown {
+                list.offset = current + 1 - shown;
+            }
+        }
+        let (bg, fg, line) = if y == 0 {
+            let line = match list.entries.len() {
+                0 if list.title.is_empty() => " The quickfix list is empty".to_string(),
+                0 => format!(" {} (no places)", list.title),
+                1 => format!(" {} (1 place)", list.title),
+                n => format!(" {} ({n} places)", list.title),
+            };
+            (&colors.status_bg, &colors.status_fg, line)
+        } else if let Some(entry) = list.entries.get(list.offset + y - 1) {
+            let line = format!(" {}", QuickfixList::describe(entry));
+            if list.current == Some(list.offset + y - 1) {
+                (&colors.selection_bg, &colors.selection_fg, line)
+            } else {
+                (&colors.editor_bg, &colors.editor_fg, line)
+            }
+        } else {
+            (&colors.editor_bg, &colors.editor_fg, String::new())
+        };
+        // Cut the line down to fit the split
+        let mut total_width = 0;
+        let mut fitted = String::new();
+        for ch in line.chars() {
+            total_width += width_char(&ch, 4);
+            if total_width > length {
+                total_width -= width_char(&ch, 4);
+                break;
+            }
+            fitted.push(ch);
+        }
+        fitted += &" ".repeat(length.saturating_sub(total_width));
+        Ok(format!(
+            "{}{}{fitted}",
+            Bg(bg.to_color()?),
+            Fg(fg.to_color()?)
+        ))
+    }
+
     /// Render the line of a terminal
     #[allow(clippy::similar_names)]
     #[cfg(not(target_os = "windows"))]
     fn render_terminal(&mut self, fc: &Vec<usize>, y: usize, l: usize, h: usize) -> Result<String> {
         if let Some(FileLayout::Terminal(term)) = self.files.get_raw(fc.to_owned()) {
-            let term = term.lock().unwrap();
-            let editor_fg = Fg(config!(self.config, colors).editor_fg.to_color()?).to_string();
-            let editor_bg = Bg(config!(self.config, colors).editor_bg.to_color()?).to_string();
-            let reset = SetAttribute(Attribute::NoBold);
-            let n_lines = term.output.matches('\n').count();
-            let shift_down = n_lines.saturating_sub(h.saturating_sub(1));
-            // Calculate the contents and amount of padding for this line of the terminal
-            let (line, pad) = if let Some(line) = term.output.split('\n').nth(shift_down + y) {
-                // Calculate line and padding
-                let line = line.replace(['\n', '\r'], "");
-                let mut visible_line = strip_escape_codes(&line);
-                // Replace resets with editor style
-                visible_line = replace_reset(&visible_line, &editor_bg, &editor_fg);
-                let mut w = width(&remove_ansi_codes(&line), 4);
-                // Work out if this is where the cursor should be
-                if n_lines.saturating_sub(shift_down) == y && self.ptr == *fc {
-                    visible_line += &format!("{editor_fg}{reset}{}", term.input);
-                    w += width(&term.input, 4);
-                    self.render_cache.term_cursor = Some(Loc { x: w, y });
+            let mut term = term.lock().unwrap();
+            let editor_fg = config!(self.config, colors).editor_fg.to_color()?;
+            let editor_bg = config!(self.config, colors).editor_bg.to_color()?;
+            // Let the program know if the split has changed size
+            if term.screen.size != (Size { w: l, h }) {
+                term.resize(l, h)?;
+            }
+            // Work out where the cursor should be
+            if self.ptr == *fc {
+                self.render_cache.term_cursor = term.screen.cursor_in_view();
+            }
+            // Render each cell, only changing style where it differs from the previous cell
+            let mut result = String::new();
+            let mut last_style = None;
+            let mut total_width = 0;
+            for cell in term.screen.row(y).unwrap_or_default() {
+                let w = width_char(&cell.ch, 4);
+                if cell.ch == '\0' || total_width + w > l {
+                    continue;
                 }
-                // Return the result
-                (visible_line, l.saturating_sub(w))
-            } else {
-                (" ".repeat(l), 0)
-            };
+                if last_style != Some(cell.style) {
+                    last_style = Some(cell.style);
+                    result += &terminal_style(cell.style, editor_fg, editor_bg)?;
+                }
+                result.push(cell.ch);
+                total_width += w;
+            }
             std::mem::drop(term);
-            // Calculate the final result
+            // Pad out lines from the scrollback that are shorter than the terminal
+            let reset = terminal_style(TermStyle::default(), editor_fg, editor_bg)?;
             Ok(format!(
-                "{reset}{editor_fg}{editor_bg}{line}{}",
-                " ".repeat(pad)
+                "{result}{reset}{}",
+                " ".repeat(l.saturating_sub(total_width))
             ))
         } else {
             unreachable!()
@@ -686,8 +1025,19 @@ impl Editor {
 
     /// Display a prompt in the document
     pub fn prompt<S: Into<String>>(&mut self, prompt: S) -> Result<String> {
+        self.history_prompt(prompt, None)
+    }
+
+    /// Display a prompt in the document, where past terms can be moved through with up and down
+    /// (what is typed is remembered too)
+    pub fn history_prompt<S: Into<String>>(
+        &mut self,
+        prompt: S,
+        kind: Option<QueryKind>,
+    ) -> Result<String> {
         let prompt = prompt.into();
         let mut input = String::new();
+        let mut browser = HistoryBrowser::default();
         let mut done = false;
         // Enter into a menu that asks for a prompt
         while !done {
@@ -722,10 +1072,21 @@ impl Editor {
                     }
                     // Add to the input string if the user presses a character
                     (KMod::NONE | KMod::SHIFT, KCode::Char(c)) => input.push(c),
+                    // Move through past terms
+                    (KMod::NONE, KCode::Up | KCode::Down) => {
+                        if let Some(kind) = kind {
+                            let past = self.query_history.list(kind);
+                            let up = code == KCode::Up;
+                            input = browser.step(past, &input, up).unwrap_or(input);
+                        }
+                    }
                     _ => (),
                 }
             }
         }
+        if let Some(kind) = kind {
+            self.remember_query(kind, &input);
+        }
         // Return input string result
         Ok(input)
     }
@@ -882,6 +1243,28 @@ impl Editor {
         Ok(result)
     }
 
+    /// Choice dialog, giving back which of the choices was picked (or None if escape was pressed)
+    pub fn choose(&mut self, msg: &str, choices: &[char]) -> Result<Option<char>> {
+        // Enter into the choice menu
+        self.terminal.hide_cursor();
+        let result = loop {
+            let Size { w, h } = size()?;
+            // Render message
+            self.feedback = Feedback::Warning(msg.to_string());
+            self.render_feedback_line(w, h)?;
+            self.terminal.flush()?;
+            // Handle events
+            match key_event(&wait_for_event_hog(self), &mut self.macro_man) {
+                Some((KMod::NONE, KCode::Esc)) => break None,
+                Some((KMod::NONE, KCode::Char(c))) if choices.contains(&c) => break Some(c),
+                _ => (),
+            }
+        };
+        self.feedback = Feedback::None;
+        self.terminal.show_cursor();
+        Ok(result)
+    }
+
     /// Append any missed lines to the syntax highlighter
     pub fn update_highlighter(&mut self) {
         if let Some((_, doc_idx)) = self.files.get_atom(self.ptr.clone()) {
@@ -928,6 +1311,38 @@ impl Editor {
         }
     }
 
+    /// Work out how a file should be soft wrapped at a certain size (if at all)
+    pub fn soft_wrap_for(&self, fc: &FileContainer, size: Size) -> Option<Wrap> {
+        let document = config!(self.config, document);
+        let file_type = fc
+            .file_type
+            .as_ref()
+            .map_or("Unknown", |ft| ft.name.as_str());
+        let enabled = fc
+            .soft_wrap
+            .unwrap_or_else(|| document.soft_wrap.applies_to(file_type));
+        let width = match document.wrap_column {
+            0 => size.w,
+            column => column.min(size.w),
+        };
+        (enabled && fc.hex.is_none()).then_some(Wrap {
+            width,
+            words: document.wrap_words,
+        })
+    }
+
+    /// Determine if regions of the current file can be folded by matching brackets
+    pub fn fold_brackets(&self) -> bool {
+        let file_type = self
+            .files
+            .get(self.ptr.clone())
+            .and_then(|fc| fc.file_type.as_ref())
+            .map_or("Unknown", |ft| ft.name.as_str());
+        config!(self.config, document)
+            .fold_brackets
+            .applies_to(file_type)
+    }
+
     /// Work out how much to push the document to the right (to make way for line numbers)
     pub fn dent(&self) -> usize {
         if let Some((_, doc)) = self.files.get_atom(self.ptr.clone()) {
@@ -939,16 +1354,51 @@ impl Editor {
 
     /// Work out how much to push the document to the right (to make way for line numbers)
     pub fn dent_for(&self, at: &[usize], doc: usize) -> usize {
+        let Some((fcs, _)) = self.files.get_atom(at.to_owned()) else {
+            return 0;
+        };
+        // Make way for the sign column (if there are diagnostics to show)
+        let sign = &config!(self.config, document).diagnostic_sign;
+        let tab_width = config!(self.config, document).tab_width;
+        let signs = if !sign.is_empty() && self.has_diagnostics(&fcs[doc]) {
+            width(sign, tab_width)
+        } else {
+            0
+        };
         if config!(self.config, line_numbers).enabled {
             let padding_left = config!(self.config, line_numbers).padding_left;
             let padding_right = config!(self.config, line_numbers).padding_right;
-            if let Some((fcs, _)) = self.files.get_atom(at.to_owned()) {
-                fcs[doc].doc.len_lines().to_string().len() + 1 + padding_left + padding_right
-            } else {
-                0
-            }
+            signs + fcs[doc].doc.len_lines().to_string().len() + 1 + padding_left + padding_right
         } else {
-            0
+            signs
+        }
+    }
+}
+
+/// Turn the style of a cell in a terminal into ANSI codes
+/// (default colours are those of the editor)
+#[allow(clippy::similar_names)]
+#[cfg(not(target_os = "windows"))]
+fn terminal_style(style: TermStyle, editor_fg: Color, editor_bg: Color) -> Result<String> {
+    let colour = |colour, default| match colour {
+        TermColour::Default => Ok(default),
+        TermColour::Indexed(n) => config::Color::Ansi(n).to_color(),
+        TermColour::Rgb(r, g, b) => config::Color::Rgb(r, g, b).to_color(),
+    };
+    let (mut fg, mut bg) = (colour(style.fg, editor_fg)?, colour(style.bg, editor_bg)?);
+    if style.reverse {
+        std::mem::swap(&mut fg, &mut bg);
+    }
+    let mut result = format!("{}{}{}", SetAttribute(Attribute::Reset), Fg(fg), Bg(bg));
+    for (on, attribute) in [
+        (style.bold, Attribute::Bold),
+        (style.dim, Attribute::Dim),
+        (style.italic, Attribute::Italic),
+        (style.underline, Attribute::Underlined),
+    ] {
+        if on {
+            result += &SetAttribute(attribute).to_string();
         }
     }
+    Ok(result)
 }
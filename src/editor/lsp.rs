@@ -0,0 +1,303 @@
+/// Functions for talking to language servers about the files that are open
+use crate::error::{OxError, Result};
+use crate::ui::Feedback;
+use kaolinite::event::Error as KError;
+use kaolinite::lsp::{
+    path_to_uri, uri_to_path, Client, Location, Position, PositionEncoding, Severity, TextEdit,
+};
+use kaolinite::{Document, Loc};
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+use super::{Diagnostic, Editor, FileContainer};
+
+/// Work out the command that starts the language server for a file (if it should have one)
+fn language_server_for(fc: &FileContainer) -> Option<String> {
+    if fc.hex.is_some() || fc.doc.lazy.is_some() || fc.doc.file_name.is_none() {
+        return None;
+    }
+    fc.file_type.as_ref()?.language_server.clone()
+}
+
+/// Work out the identifier language servers use for a file type
+fn language_id(fc: &FileContainer) -> String {
+    let name = fc.file_type.as_ref().map_or("", |t| t.name.as_str());
+    match name {
+        "C++" | "C++ Header" => "cpp".to_string(),
+        "C Header" => "c".to_string(),
+        "C#" => "csharp".to_string(),
+        "JSX" => "javascriptreact".to_string(),
+        "Shell" => "shellscript".to_string(),
+        _ => name.to_lowercase().replace(' ', ""),
+    }
+}
+
+/// Start a language server, returning None if it couldn't be started
+fn start(command: &str) -> Option<Client> {
+    let mut parts = command.split_whitespace();
+    let program = parts.next()?;
+    let args: Vec<String> = parts.map(str::to_string).collect();
+    let root = env::current_dir().ok()?;
+    Client::spawn(program, &args, &root.to_string_lossy()).ok()
+}
+
+/// Create an error to do with language servers
+fn server_error(message: &str) -> OxError {
+    OxError::Kaolinite(KError::LanguageServer {
+        message: message.to_string(),
+    })
+}
+
+impl Editor {
+    /// Start language servers for the open files that need them, and bring them up to date
+    /// with any changes made to those files
+    pub fn sync_language_servers(&mut self) {
+        let mut open: HashMap<String, HashSet<String>> = HashMap::new();
+        for fc in self.files.containers_mut() {
+            let Some(command) = language_server_for(fc) else {
+                continue;
+            };
+            let language_id = language_id(fc);
+            // Servers that couldn't be started aren't tried again
+            let client = self
+                .language_servers
+                .entry(command.clone())
+                .or_insert_with(|| start(&command));
+            let Some(client) = client.as_mut().filter(|client| client.running) else {
+                continue;
+            };
+            let _ = client.sync_document(&mut fc.doc, &language_id);
+            let uri = path_to_uri(fc.doc.file_name.as_deref().unwrap_or_default());
+            open.entry(command).or_default().insert(uri);
+        }
+        // Let servers know about the files that have been closed
+        for (command, client) in &mut self.language_servers {
+            let Some(client) = client.as_mut().filter(|client| client.running) else {
+                continue;
+            };
+            let closed: Vec<String> = client
+                .documents
+                .keys()
+                .filter(|uri| !open.get(command).is_some_and(|open| open.contains(*uri)))
+                .cloned()
+                .collect();
+            for uri in closed {
+                let _ = client.close_document(&uri);
+            }
+        }
+    }
+
+    /// Deal with anything the language servers have sent.
+    /// Returns true if the editor needs to be rerendered (e.g. diagnostics have changed).
+    pub fn poll_language_servers(&mut self) -> bool {
+        let mut changed = false;
+        for client in self.language_servers.values_mut().flatten() {
+            changed |= client.poll().unwrap_or(false);
+            for (severity, message) in client.messages.drain(..) {
+                self.feedback = match severity {
+                    Severity::Error => Feedback::Error(message),
+                    Severity::Warning => Feedback::Warning(message),
+                    Severity::Information | Severity::Hint => Feedback::Info(message),
+                };
+            }
+        }
+        changed
+    }
+
+    /// Ask every language server to shut down
+    pub fn stop_language_servers(&mut self) {
+        for client in self.language_servers.values_mut().flatten() {
+            client.shutdown();
+        }
+    }
+
+    /// Let the language server know the current document has been saved
+    pub fn language_server_saved(&mut self) {
+        let Some(fc) = self.files.get(self.ptr.clone()) else {
+            return;
+        };
+        let client = language_server_for(fc)
+            .and_then(|command| self.language_servers.get_mut(&command))
+            .and_then(Option::as_mut);
+        if let Some(client) = client.filter(|client| client.running) {
+            let _ = client.save_document(&fc.doc);
+        }
+    }
+
+    /// Get the language server for the current document (brought up to date with it)
+    fn language_server(&mut self) -> Result<(&mut Client, &mut Document)> {
+        self.sync_language_servers();
+        let fc = self
+            .files
+            .get_mut(self.ptr.clone())
+            .ok_or_else(|| server_error("There is no file open"))?;
+        let client = language_server_for(fc)
+            .and_then(|command| self.language_servers.get_mut(&command))
+            .and_then(Option::as_mut)
+            .filter(|client| client.running)
+            .ok_or_else(|| server_error("No language server is running for this file"))?;
+        Ok((client, &mut fc.doc))
+    }
+
+    /// Get the diagnostics language servers have reported for a file
+    pub fn lsp_diagnostics(&self, file_name: &str) -> Vec<Diagnostic> {
+        let mut result = vec![];
+        for client in self.language_servers.values().flatten() {
+            for diagnostic in client.diagnostics_for(file_name) {
+                let start = self.position_loc(client.encoding, file_name, diagnostic.range.start);
+                let end = self.position_loc(client.encoding, file_name, diagnostic.range.end);
+                result.push(Diagnostic {
+                    start,
+                    end,
+                    severity: diagnostic.severity,
+                    message: diagnostic.message.clone(),
+                    source: diagnostic.source.clone(),
+                });
+            }
+        }
+        result
+    }
+
+    /// Determine if language servers have reported any diagnostics for a file
+    pub fn has_lsp_diagnostics(&self, fc: &FileContainer) -> bool {
+        let Some(file_name) = &fc.doc.file_name else {
+            return false;
+        };
+        self.language_servers
+            .values()
+            .flatten()
+            .any(|client| !client.diagnostics_for(file_name).is_empty())
+    }
+
+    /// Get information about the code under the cursor
+    pub fn hover(&mut self) -> Result<Option<String>> {
+        let (client, doc) = self.language_server()?;
+        let loc = doc.char_loc();
+        Ok(client.hover(doc, loc)?)
+    }
+
+    /// Go to where the code under the cursor is defined.
+    /// Returns false if the definition couldn't be found.
+    pub fn goto_definition(&mut self) -> Result<bool> {
+        let (client, doc) = self.language_server()?;
+        let loc = doc.char_loc();
+        let definitions = client.definition(doc, loc)?;
+        let encoding = client.encoding;
+        let Some(definition) = definitions.first() else {
+            return Ok(false);
+        };
+        self.goto_location(definition, encoding)?;
+        Ok(true)
+    }
+
+    /// Find everywhere the code under the cursor is referred to, as file names and locations
+    pub fn references(&mut self) -> Result<Vec<(String, Loc)>> {
+        let (client, doc) = self.language_server()?;
+        let loc = doc.char_loc();
+        let references = client.references(doc, loc)?;
+        let encoding = client.encoding;
+        Ok(references
+            .iter()
+            .filter_map(|location| {
+                let file_name = uri_to_path(&location.uri)?;
+                let loc = self.position_loc(encoding, &file_name, location.range.start);
+                Some((file_name, loc))
+            })
+            .collect())
+    }
+
+    /// Rename the symbol under the cursor, everywhere it is used
+    pub fn rename(&mut self, new_name: &str) -> Result<()> {
+        let (client, doc) = self.language_server()?;
+        let loc = doc.char_loc();
+        let changes = client.rename(doc, loc, new_name)?;
+        if changes.is_empty() {
+            return Err(server_error("There is nothing to rename here"));
+        }
+        let ptr = self.ptr.clone();
+        for (uri, edits) in changes {
+            let Some(file_name) = uri_to_path(&uri) else {
+                continue;
+            };
+            // Files that aren't open yet are opened, so the changes can be reviewed and saved
+            self.goto_file(&file_name)?;
+            self.apply_language_server_edits(&edits)?;
+        }
+        // Go back to where the rename was started
+        if self.files.get(ptr.clone()).is_some() {
+            self.ptr = ptr;
+        }
+        Ok(())
+    }
+
+    /// Format the current document
+    pub fn format(&mut self, tab_size: usize, insert_spaces: bool) -> Result<()> {
+        let (client, doc) = self.language_server()?;
+        let edits = client.format(doc, tab_size, insert_spaces)?;
+        self.apply_language_server_edits(&edits)
+    }
+
+    /// Make edits suggested by the language server to the current document
+    fn apply_language_server_edits(&mut self, edits: &[TextEdit]) -> Result<()> {
+        let (client, doc) = self.language_server()?;
+        let loc = doc.char_loc();
+        doc.commit();
+        client.apply_edits(doc, edits)?;
+        doc.commit();
+        // Try to keep the cursor where it was
+        doc.move_to_y(loc.y.min(doc.len_lines().saturating_sub(1)));
+        doc.move_to_x(loc.x);
+        self.reload_highlight();
+        Ok(())
+    }
+
+    /// Open a file (if it isn't already open) and move to it
+    pub fn goto_file(&mut self, file_name: &str) -> Result<()> {
+        let current = self.try_doc().and_then(|doc| doc.file_name.clone());
+        if current.as_deref() == Some(file_name) {
+            return Ok(());
+        }
+        match self.open(file_name) {
+            Ok(()) => {
+                self.next();
+                self.update_cwd();
+                Ok(())
+            }
+            // The file was already open, and has been moved to
+            Err(OxError::AlreadyOpen { .. }) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Open the file a location is in, and move the cursor to it
+    fn goto_location(&mut self, location: &Location, encoding: PositionEncoding) -> Result<()> {
+        let file_name = uri_to_path(&location.uri)
+            .ok_or_else(|| server_error("The location isn't in a file"))?;
+        self.goto_file(&file_name)?;
+        let loc = self.position_loc(encoding, &file_name, location.range.start);
+        if let Some(doc) = self.try_doc_mut() {
+            doc.move_to(&loc);
+        }
+        Ok(())
+    }
+
+    /// Work out the location (by character index) of a position in a file
+    fn position_loc(&self, encoding: PositionEncoding, file_name: &str, pos: Position) -> Loc {
+        let doc = self
+            .files
+            .containers()
+            .into_iter()
+            .find(|fc| fc.doc.file_name.as_deref() == Some(file_name));
+        let line = match doc {
+            Some(fc) => fc.doc.line(pos.line),
+            // Files that aren't open are read from the disk
+            None => std::fs::read_to_string(file_name)
+                .ok()
+                .and_then(|text| text.lines().nth(pos.line).map(str::to_string)),
+        };
+        let x = line.map_or(pos.character, |line| {
+            encoding.char_index(&line, pos.character)
+        });
+        Loc::at(x, pos.line)
+    }
+}
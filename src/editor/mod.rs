@@ -4,34 +4,62 @@ use crate::config::{Config, Indentation};
 use crate::error::{OxError, Result};
 use crate::ui::{size, Feedback, Terminal};
 use crossterm::event::{
-    Event as CEvent, KeyCode as KCode, KeyModifiers as KMod, MouseEvent, MouseEventKind,
+    Event as CEvent, KeyCode as KCode, KeyEvent, KeyModifiers as KMod, MouseEvent, MouseEventKind,
 };
+use kaolinite::document::encoding::is_binary;
 use kaolinite::event::Error as KError;
+use kaolinite::lsp::Client;
 use kaolinite::utils::{file_or_dir, get_absolute_path, get_file_name};
-use kaolinite::{Document, Loc};
+use kaolinite::{Document, HexDocument, Loc};
 use mlua::{Error as LuaError, Lua};
+use regex::Regex;
+use std::collections::HashMap;
 use std::env;
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use synoptic::Highlighter;
 
+mod completion;
 mod cursor;
+mod diagnostics;
 mod documents;
 mod editing;
+mod external;
 mod filetree;
 mod filetypes;
+mod finder;
+mod hex;
 mod interface;
+mod lsp;
 mod macros;
 mod mouse;
+mod project;
+mod queries;
+mod quickfix;
+mod recovery;
+mod results;
 mod scanning;
+mod snippets;
+mod tasks;
 
+pub use completion::{update_completion, Completion};
 pub use cursor::{allowed_by_multi_cursor, handle_multiple_cursors};
-pub use documents::{FileContainer, FileLayout};
+pub use diagnostics::{most_serious, Diagnostic};
+pub use documents::{DiskState, FileContainer, FileLayout};
+pub use editing::parse_duration;
 pub use filetree::{FTParts, FileTree};
 pub use filetypes::{FileType, FileTypes};
+pub use finder::FileIndex;
+pub use hex::hex_cursor;
 pub use interface::RenderCache;
 pub use macros::MacroMan;
+pub use project::ProjectSearch;
+pub use queries::{HistoryBrowser, QueryHistory, QueryKind};
+pub use quickfix::{QuickfixEntry, QuickfixList};
+pub use scanning::MatchCache;
+pub use snippets::SnippetSession;
+pub use tasks::TaskRun;
 
 /// For managing all editing and rendering of cactus
 #[allow(clippy::struct_excessive_bools)]
@@ -78,6 +106,26 @@ pub struct Editor {
     pub file_tree_selection: Option<String>,
     /// For caching a pointer to go back to when in a file tree
     pub old_ptr: Vec<usize>,
+    /// Files with unsaved changes from a previous session, waiting to be recovered
+    pub recovery: Vec<String>,
+    /// Diagnostics set by plug-ins, by file name
+    pub diagnostics: HashMap<String, Vec<Diagnostic>>,
+    /// Language servers, by the command that starts them (None if they couldn't be started)
+    pub language_servers: HashMap<String, Option<Client>>,
+    /// The completion popup (if it is open)
+    pub completion: Option<Completion>,
+    /// Set when the completion popup has been asked for (it is opened after the key press)
+    pub completion_requested: bool,
+    /// The files in the current project, for the fuzzy finder
+    pub file_index: Option<FileIndex>,
+    /// What is being searched for in the current document (every match of it is highlighted)
+    pub search_highlight: Option<Regex>,
+    /// Past search and replace terms
+    pub query_history: QueryHistory,
+    /// The name of the task that was run last (to run it again)
+    pub last_task: Option<String>,
+    /// Places in files (like errors or search matches) to go through
+    pub quickfix: QuickfixList,
 }
 
 impl Editor {
@@ -106,6 +154,16 @@ impl Editor {
             file_tree: None,
             file_tree_selection: None,
             old_ptr: vec![],
+            recovery: vec![],
+            diagnostics: HashMap::new(),
+            language_servers: HashMap::new(),
+            completion: None,
+            completion_requested: false,
+            file_index: None,
+            search_highlight: None,
+            query_history: QueryHistory::default(),
+            last_task: None,
+            quickfix: QuickfixList::default(),
         })
     }
 
@@ -132,6 +190,14 @@ impl Editor {
             highlighter,
             file_type: Some(FileType::default()),
             doc,
+            disk_state: None,
+            external_change: false,
+            hex: None,
+            soft_wrap: None,
+            snippet: None,
+            search: None,
+            task: None,
+            tasks: None,
         };
         if let Some((files, ptr)) = self.files.get_atom_mut(self.ptr.clone()) {
             if *ptr + 1 >= files.len() {
@@ -204,7 +270,20 @@ impl Editor {
         }
         let mut size = size()?;
         size.h = size.h.saturating_sub(1 + self.push_down);
-        let mut doc = Document::open(size, file_name)?;
+        // Binary files are shown in hex, as they would be mangled if decoded as text
+        if looks_binary(file_name) {
+            return Ok(FileContainer::from_hex(HexDocument::open(size, file_name)?));
+        }
+        // Files that are too large to read into memory are read on demand instead
+        let threshold = config!(self.config, document).lazy_threshold;
+        let file_size = std::fs::metadata(file_name).map_or(0, |m| m.len());
+        let mut doc = if threshold != 0 && file_size > threshold * 1024 * 1024 {
+            Document::open_lazy(size, file_name)?
+        } else {
+            Document::open(size, file_name)?
+        };
+        doc.load_history(&history_store());
+        self.check_swap(&doc);
         // Collect various data from the document
         let tab_width = config!(self.config, document).tab_width;
         let file_type = config!(self.config, document).file_types.identify(&mut doc);
@@ -218,9 +297,17 @@ impl Editor {
         highlighter.run(&doc.lines);
         // Add in the file
         let file = FileContainer {
+            disk_state: DiskState::new(&doc),
             doc,
             highlighter,
             file_type,
+            external_change: false,
+            hex: None,
+            soft_wrap: None,
+            snippet: None,
+            search: None,
+            task: None,
+            tasks: None,
         };
         Ok(file)
     }
@@ -273,21 +360,82 @@ impl Editor {
         self.files.find(vec![], abs_path)
     }
 
-    /// save the document to the disk
-    pub fn save(&mut self) -> Result<()> {
+    /// save the document to the disk (returns false if it wasn't saved)
+    pub fn save(&mut self) -> Result<bool> {
+        if self.try_hex().is_some() {
+            self.save_hex()?;
+            return Ok(true);
+        }
+        let backup = config!(self.config, document).backup.clone();
+        // Don't silently overwrite changes made by other programs
+        if self.changed_on_disk() {
+            let file_name = self.try_doc().and_then(|doc| doc.file_name.clone());
+            let name = get_file_name(&file_name.unwrap_or_default()).unwrap_or_default();
+            let msg = format!("{name} was changed by another program, press Ctrl + Q to overwrite or Esc to cancel");
+            if !self.confirm(&msg)? {
+                return Ok(false);
+            }
+        }
+        if !self.confirm_lossy()? {
+            return Ok(false);
+        }
         if let Some(doc) = self.try_doc_mut() {
             // Perform the save
+            doc.backup = backup;
             doc.save()?;
+            let history = doc.save_history(&history_store());
+            doc.remove_swap(&swap_store());
+            self.update_disk_state();
+            self.language_server_saved();
             // All done
-            self.feedback = Feedback::Info("Document saved successfully".to_string());
+            self.feedback = match history {
+                Ok(()) => Feedback::Info("Document saved successfully".to_string()),
+                Err(err) => Feedback::Warning(format!(
+                    "Document saved, but its undo history wasn't: {err}"
+                )),
+            };
+            return Ok(true);
         }
-        Ok(())
+        Ok(false)
+    }
+
+    /// Don't silently lose bytes that couldn't be decoded when saving (returns false to cancel)
+    fn confirm_lossy(&mut self) -> Result<bool> {
+        if self.try_doc().is_some_and(|doc| doc.info.lossy) {
+            let msg = "This file couldn't be fully decoded, saving may lose data, press Ctrl + Q to save anyway or Esc to cancel";
+            if !self.confirm(msg)? {
+                return Ok(false);
+            }
+            self.try_doc_mut().unwrap().info.lossy = false;
+        }
+        Ok(true)
     }
 
     /// save the document to the disk at a specified path
     pub fn save_as(&mut self) -> Result<()> {
         if self.try_doc().is_some() {
             let file_name = self.prompt("Save as")?;
+            let backup = config!(self.config, document).backup.clone();
+            let fc = self.files.get_mut(self.ptr.clone()).unwrap();
+            if let Some(hex) = &mut fc.hex {
+                hex.backup = backup;
+                // If this file is currently unnamed, give it a name
+                if hex.file_name.is_none() {
+                    hex.file_name = Some(file_name.clone());
+                    hex.save()?;
+                    fc.doc.file_name = Some(file_name.clone());
+                    fc.sync_hex();
+                } else {
+                    hex.save_as(&file_name)?;
+                }
+                self.feedback =
+                    Feedback::Info(format!("Document saved as {file_name} successfully"));
+                return Ok(());
+            }
+            if !self.confirm_lossy()? {
+                return Ok(());
+            }
+            self.try_doc_mut().unwrap().backup = backup;
             self.try_doc_mut().unwrap().save_as(&file_name)?;
             // If this file is currently unnamed, give it a name, syntax highlighting and a type
             if self.try_doc().unwrap().file_name.is_none() {
@@ -311,7 +459,8 @@ impl Editor {
                     file.highlighter.run(&file.doc.lines);
                     // Set up to date with disk
                     file.doc.event_mgmt.force_not_with_disk = false;
-                    file.doc.event_mgmt.disk_write(&file.doc.take_snapshot());
+                    file.doc.event_mgmt.disk_write(file.doc.cursor);
+                    file.disk_state = DiskState::new(&file.doc);
                 }
             }
             // Commit events to event manager (for undo / redo)
@@ -324,14 +473,31 @@ impl Editor {
 
     /// Save all the open documents to the disk
     pub fn save_all(&mut self) -> Result<()> {
-        if let Some((files, _)) = self.files.get_atom_mut(self.ptr.clone()) {
-            for file in files {
-                file.doc.save()?;
-                // Commit events to event manager (for undo / redo)
-                file.doc.commit();
+        let Some((files, ptr)) = self.files.get_atom(self.ptr.clone()) else {
+            return Ok(());
+        };
+        let (count, focused) = (files.len(), ptr);
+        // Save each document as if it were focused (so it is checked in the same way)
+        let mut result = Ok(());
+        let mut skipped = 0;
+        for idx in 0..count {
+            *self.files.get_atom_mut(self.ptr.clone()).unwrap().1 = idx;
+            match self.save() {
+                Ok(true) => (),
+                Ok(false) => skipped += 1,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
             }
         }
-        self.feedback = Feedback::Info("Saved all documents".to_string());
+        *self.files.get_atom_mut(self.ptr.clone()).unwrap().1 = focused;
+        result?;
+        self.feedback = match skipped {
+            0 => Feedback::Info("Saved all documents".to_string()),
+            1 => Feedback::Warning("Saved all documents except 1 that was skipped".to_string()),
+            n => Feedback::Warning(format!("Saved all documents except {n} that were skipped")),
+        };
         Ok(())
     }
 
@@ -344,8 +510,26 @@ impl Editor {
                 let msg =
                     "This document isn't saved, press Ctrl + Q to force quit or Esc to cancel";
                 let doc = &fcs[*ptr].doc;
-                if doc.event_mgmt.with_disk(&doc.take_snapshot()) || self.confirm(msg)? {
+                if doc.event_mgmt.with_disk() || self.confirm(msg)? {
                     let (fcs, ptr) = self.files.get_atom_mut(self.ptr.clone()).unwrap();
+                    // Keep hold of the undo history for next time this file is opened
+                    let doc = &mut fcs[*ptr].doc;
+                    if !doc.event_mgmt.history.is_empty() {
+                        doc.commit();
+                        if let Err(err) = doc.save_history(&history_store()) {
+                            self.feedback = Feedback::Warning(format!(
+                                "Failed to keep the undo history of this document: {err}"
+                            ));
+                        }
+                    }
+                    // This was a clean exit, so unsaved changes don't need recovering
+                    if !self
+                        .recovery
+                        .iter()
+                        .any(|f| Some(f) == doc.file_name.as_ref())
+                    {
+                        doc.remove_swap(&swap_store());
+                    }
                     fcs.remove(*ptr);
                     self.prev();
                 }
@@ -359,7 +543,7 @@ impl Editor {
                     self.ptr = self.files.clean_up_multis(self.ptr.clone());
                 }
             }
-            Some(FileLayout::Terminal(_)) => {
+            Some(FileLayout::Terminal(_) | FileLayout::Quickfix) => {
                 self.files.remove(self.ptr.clone());
                 // Find a new pointer position
                 self.ptr = self.files.new_pointer_position(&self.ptr);
@@ -371,7 +555,10 @@ impl Editor {
         // If there are no longer any active atoms, quit the entire editor
         self.active = !matches!(
             self.files,
-            FileLayout::None | FileLayout::FileTree | FileLayout::Terminal(_)
+            FileLayout::None
+                | FileLayout::FileTree
+                | FileLayout::Quickfix
+                | FileLayout::Terminal(_)
         );
         Ok(())
     }
@@ -408,6 +595,12 @@ impl Editor {
         }
     }
 
+    /// Which split and which document in it are focused
+    pub fn focus(&self) -> (Vec<usize>, Option<usize>) {
+        let doc = self.files.get_atom(self.ptr.clone()).map(|(_, doc)| doc);
+        (self.ptr.clone(), doc)
+    }
+
     /// Try to get a document
     pub fn try_doc(&self) -> Option<&Document> {
         self.files.get(self.ptr.clone()).map(|file| &file.doc)
@@ -430,6 +623,14 @@ impl Editor {
         self.files.get_atom(self.ptr.clone()).unwrap().0.len()
     }
 
+    /// Determine if any lazily loaded documents are still having their lines indexed
+    pub fn indexing(&self) -> bool {
+        self.files
+            .containers()
+            .iter()
+            .any(|fc| fc.doc.lazy.as_ref().is_some_and(|lazy| !lazy.indexed()))
+    }
+
     /// Load the configuration values
     pub fn load_config(&mut self, path: &str, lua: &Lua) -> Option<LuaError> {
         self.config_path = path.to_string();
@@ -463,6 +664,12 @@ impl Editor {
         };
         // Pass event down to special handlers
         match event {
+            // Enter runs the task under the cursor when picking one
+            CEvent::Key(KeyEvent {
+                modifiers: KMod::NONE,
+                code: KCode::Enter,
+                ..
+            }) if self.pick_task(lua)? => (),
             CEvent::Key(key) => self.handle_key_event(key.modifiers, key.code)?,
             CEvent::Resize(_, _) => self.handle_resize(lua)?,
             CEvent::Mouse(mouse_event) => self.handle_mouse_event(lua, mouse_event)?,
@@ -489,17 +696,18 @@ impl Editor {
                 (KMod::NONE, KCode::Char('c')) => self.file_tree_copy()?,
                 _ => (),
             },
-            // Terminal behaviour
-            #[cfg(not(target_os = "windows"))]
-            Some(FileLayout::Terminal(term)) => match (modifiers, code) {
-                (KMod::NONE, KCode::Enter) => term.lock().unwrap().char_input('\n')?,
-                (KMod::SHIFT | KMod::NONE, KCode::Char(ch)) => {
-                    term.lock().unwrap().char_input(ch)?;
+            // Quickfix list key behaviour
+            Some(FileLayout::Quickfix) => match (modifiers, code) {
+                (KMod::NONE, KCode::Up) => self.quickfix_select(false),
+                (KMod::NONE, KCode::Down) => self.quickfix_select(true),
+                (KMod::NONE, KCode::Enter) => {
+                    self.goto_quickfix(self.quickfix.current.unwrap_or_default())?;
                 }
-                (KMod::NONE, KCode::Backspace) => term.lock().unwrap().char_pop(),
-                (KMod::CONTROL, KCode::Char('l')) => term.lock().unwrap().clear()?,
                 _ => (),
             },
+            // Terminal behaviour (keys are passed straight on to the program running in it)
+            #[cfg(not(target_os = "windows"))]
+            Some(FileLayout::Terminal(term)) => term.lock().unwrap().key(modifiers, code)?,
             // File behaviour
             _ => {
                 // Check period of inactivity
@@ -511,6 +719,10 @@ impl Editor {
                 }
                 // Register this activity
                 self.last_active = Instant::now();
+                // Hex documents have their own editing behaviour
+                if self.try_hex().is_some() {
+                    return self.handle_hex_key(modifiers, code);
+                }
                 // Editing - these key bindings can't be modified (only added to)!
                 match (modifiers, code) {
                     // Core key bindings (non-configurable behaviour)
@@ -518,6 +730,10 @@ impl Editor {
                     (KMod::NONE, KCode::Tab) => self.handle_tab()?,
                     (KMod::NONE, KCode::Backspace) => self.backspace()?,
                     (KMod::NONE, KCode::Delete) => self.delete()?,
+                    // Enter opens matches when in the results of a project search
+                    (KMod::NONE, KCode::Enter) if self.jump_to_hit()? => (),
+                    // ...and problems when in the output of a task
+                    (KMod::NONE, KCode::Enter) if self.jump_to_problem()? => (),
                     (KMod::NONE, KCode::Enter) => self.enter()?,
                     _ => (),
                 }
@@ -537,6 +753,11 @@ impl Editor {
     pub fn handle_paste(&mut self, text: &str) -> Result<()> {
         // Take the text and ensure there are no nasty surprises when it comes to newlines
         let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        // Terminals pass pasted text straight on to the program running in them
+        #[cfg(not(target_os = "windows"))]
+        if let Some(FileLayout::Terminal(term)) = self.files.get_raw(self.ptr.clone()) {
+            return Ok(term.lock().unwrap().paste(&text)?);
+        }
         if self.try_doc().is_some() {
             // If we're playing back a macro, use the last text the user copied
             // (to prevent hard-coded pasting)
@@ -572,3 +793,27 @@ impl Editor {
         Ok(())
     }
 }
+
+/// Location of the directory where undo history is kept between sessions
+pub fn history_store() -> PathBuf {
+    let config_dir = if cfg!(target_os = "windows") {
+        "~/ox"
+    } else {
+        "~/.config/ox"
+    };
+    PathBuf::from(shellexpand::tilde(config_dir).to_string()).join("undo")
+}
+
+/// Location of the directory where swap files for unsaved changes are kept
+pub fn swap_store() -> PathBuf {
+    history_store().with_file_name("swap")
+}
+
+/// Determine if a file looks like it contains binary data (by checking the start of it)
+fn looks_binary(file_name: &str) -> bool {
+    use std::io::Read;
+    let mut start = vec![];
+    std::fs::File::open(file_name)
+        .and_then(|file| file.take(8192).read_to_end(&mut start))
+        .is_ok_and(|_| is_binary(&start))
+}
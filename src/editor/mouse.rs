@@ -18,6 +18,8 @@ enum MouseLocation {
     FileTree(usize),
     /// Where the mouse has clicked in the terminal
     Terminal(Vec<usize>),
+    /// Where the mouse has clicked in the quickfix list (and on which line of it)
+    Quickfix(Vec<usize>, usize),
     /// Mouse has clicked nothing of importance
     Out,
 }
@@ -71,15 +73,12 @@ impl Editor {
                         // Clicked on line numbers
                         MouseLocation::Out
                     } else if let Some((fcs, ptr)) = self.files.get_atom(idx.clone()) {
-                        // Clicked on document
-                        let offset = fcs[ptr].doc.offset;
-                        MouseLocation::File(
-                            idx.clone(),
-                            Loc {
-                                x: clicked.x.saturating_sub(dent) + offset.x,
-                                y: clicked.y.saturating_sub(tab) + offset.y,
-                            },
-                        )
+                        // Clicked on document (accounting for scrolling and wrapped lines)
+                        let loc = fcs[ptr].doc.disp_loc_from_screen(Loc {
+                            x: clicked.x.saturating_sub(dent),
+                            y: clicked.y.saturating_sub(tab),
+                        });
+                        MouseLocation::File(idx.clone(), loc)
                     } else {
                         // We can't seem to get the atom for some reason, just default to Out
                         MouseLocation::Out
@@ -87,6 +86,9 @@ impl Editor {
                 }
                 Some(FileLayout::FileTree) => MouseLocation::FileTree(row),
                 Some(FileLayout::Terminal(_)) => MouseLocation::Terminal(idx),
+                Some(FileLayout::Quickfix) => {
+                    MouseLocation::Quickfix(idx, row.saturating_sub(rows.start))
+                }
                 _ => MouseLocation::Out,
             }
         } else {
@@ -102,7 +104,10 @@ impl Editor {
                 // Single click
                 MouseEventKind::Down(MouseButton::Left) => {
                     let location = self.find_mouse_location(lua, event);
-                    let clicked_in_ft = matches!(location, MouseLocation::FileTree(_));
+                    let clicked_in_ft = matches!(
+                        location,
+                        MouseLocation::FileTree(_) | MouseLocation::Quickfix(_, _)
+                    );
                     // Determine if there has been a click within 500ms
                     if let Some((time, last_event)) = self.last_click {
                         let now = Instant::now();
@@ -149,6 +154,13 @@ impl Editor {
                             self.cache_old_ptr(&idx);
                             self.ptr.clone_from(&idx);
                         }
+                        MouseLocation::Quickfix(idx, y) => {
+                            // Go to the place that was clicked on (below the heading)
+                            self.ptr.clone_from(&idx);
+                            if let Some(at) = y.checked_sub(1) {
+                                self.goto_quickfix(self.quickfix.offset + at)?;
+                            }
+                        }
                         MouseLocation::Out => (),
                     }
                 }
@@ -218,6 +230,7 @@ impl Editor {
                         MouseLocation::Tabs(_, _)
                         | MouseLocation::Out
                         | MouseLocation::FileTree(_)
+                        | MouseLocation::Quickfix(_, _)
                         | MouseLocation::Terminal(_) => (),
                     }
                 }
@@ -253,13 +266,29 @@ impl Editor {
                         MouseLocation::Tabs(_, _)
                         | MouseLocation::Out
                         | MouseLocation::FileTree(_)
+                        | MouseLocation::Quickfix(_, _)
                         | MouseLocation::Terminal(_) => (),
                     }
                 }
                 // Mouse scroll behaviour
                 MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
                     let scroll_amount = config!(self.config, terminal).scroll_amount;
-                    if let MouseLocation::File(idx, _) = self.find_mouse_location(lua, event) {
+                    let location = self.find_mouse_location(lua, event);
+                    // Terminals scroll back through what has been output
+                    #[cfg(not(target_os = "windows"))]
+                    if let MouseLocation::Terminal(idx) = &location {
+                        if let Some(FileLayout::Terminal(term)) = self.files.get_raw(idx.clone()) {
+                            let up = event.kind == MouseEventKind::ScrollUp;
+                            term.lock().unwrap().screen.scroll_view(up, scroll_amount);
+                        }
+                    }
+                    // The quickfix list moves through its places
+                    if let MouseLocation::Quickfix(_, _) = location {
+                        for _ in 0..scroll_amount {
+                            self.quickfix_select(event.kind == MouseEventKind::ScrollDown);
+                        }
+                    }
+                    if let MouseLocation::File(idx, _) = location {
                         self.cache_old_ptr(&idx);
                         self.ptr.clone_from(&idx);
                         self.update_cwd();
@@ -312,6 +341,25 @@ impl Editor {
             self.cache_old_ptr(&idx);
             self.ptr.clone_from(&idx);
             self.update_cwd();
+            // Double clicking on a problem in the output of a task opens it
+            // (the first click will have already moved the cursor onto it)
+            if self
+                .files
+                .get(idx.clone())
+                .is_some_and(|fc| fc.task.is_some())
+            {
+                let _ = self.jump_to_problem();
+                return;
+            }
+            // ...and double clicking on a task in a list of them runs it
+            if self
+                .files
+                .get(idx.clone())
+                .is_some_and(|fc| fc.tasks.is_some())
+            {
+                let _ = self.pick_task(lua);
+                return;
+            }
             if let Some(doc) = self.try_doc_mut() {
                 doc.select_word_at(&loc);
                 let mut selection = doc.cursor.selection_end;
@@ -0,0 +1,391 @@
+/// Functions for searching and replacing across every file in a project
+use crate::error::Result;
+use crate::events::wait_for_event_hog;
+use crate::ui::{key_event, size, Feedback};
+use crate::{config, display};
+use crossterm::{
+    event::{KeyCode as KCode, KeyModifiers as KMod},
+    style::{Attribute, Print, SetAttribute, SetBackgroundColor as Bg},
+};
+use ignore::WalkBuilder;
+use kaolinite::utils::{get_cwd, Loc, Size};
+use mlua::Lua;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use super::finder::project_root;
+use super::results::{reset_results, show_results};
+use super::{looks_binary, Editor, QueryKind, QuickfixEntry, QuickfixList};
+
+/// The most matches a search will find (to keep huge projects manageable)
+const MAX_HITS: usize = 10_000;
+
+/// A match found while searching through a project
+#[derive(Debug, Clone)]
+pub struct Hit {
+    /// The path of the file the match is in (relative to the project)
+    pub path: String,
+    /// Where the match starts (by character index)
+    pub loc: Loc,
+    /// The number of characters in the match
+    pub len: usize,
+    /// The line the match is on
+    pub context: String,
+}
+
+/// A search through a project, with its results shown in a buffer
+#[derive(Debug, Clone)]
+pub struct ProjectSearch {
+    /// What is being searched for
+    pub pattern: String,
+    /// The directory of the project
+    pub root: PathBuf,
+    /// Matches that have been found but not shown yet
+    pending: Arc<Mutex<Vec<Hit>>>,
+    /// Whether the project is still being searched through
+    searching: Arc<AtomicBool>,
+    /// Matches that are shown in the buffer (the first is on the second line)
+    pub hits: Vec<Hit>,
+    /// Whether every match has been shown
+    done: bool,
+}
+
+impl ProjectSearch {
+    /// Start searching through a project in the background
+    pub fn new(root: PathBuf, pattern: String, re: Regex) -> Self {
+        let search = Self {
+            pattern,
+            root: root.clone(),
+            pending: Arc::new(Mutex::new(vec![])),
+            searching: Arc::new(AtomicBool::new(true)),
+            hits: vec![],
+            done: false,
+        };
+        // The search stops early if its results buffer is closed
+        let pending = Arc::downgrade(&search.pending);
+        let searching = Arc::clone(&search.searching);
+        std::thread::spawn(move || {
+            search_files(&root, &re, |hit| send_hit(&pending, hit));
+            searching.store(false, Ordering::SeqCst);
+        });
+        search
+    }
+
+    /// The first line of the results buffer
+    fn summary(&self) -> String {
+        let files = self
+            .hits
+            .iter()
+            .enumerate()
+            .filter(|(idx, hit)| *idx == 0 || self.hits[idx - 1].path != hit.path)
+            .count();
+        let status = if self.searching.load(Ordering::SeqCst) {
+            "searching…"
+        } else if self.hits.len() >= MAX_HITS {
+            "stopped early"
+        } else {
+            "done"
+        };
+        format!(
+            "{} matches for /{}/ in {} files ({status})",
+            self.hits.len(),
+            self.pattern,
+            files,
+        )
+    }
+
+    /// The matches found, as a quickfix list
+    fn quickfix(&self) -> QuickfixList {
+        let entries = self
+            .hits
+            .iter()
+            .map(|hit| QuickfixEntry {
+                file: self.root.join(&hit.path).to_string_lossy().to_string(),
+                loc: hit.loc,
+                text: hit.context.clone(),
+            })
+            .collect();
+        QuickfixList::new(format!("Matches for /{}/", self.pattern), entries)
+    }
+}
+
+/// Pass a match back from the search thread, returning false if it is no longer wanted
+fn send_hit(pending: &Weak<Mutex<Vec<Hit>>>, hit: Hit) -> bool {
+    let Some(pending) = pending.upgrade() else {
+        return false;
+    };
+    pending.lock().unwrap().push(hit);
+    true
+}
+
+/// Search through every file in a project (that isn't ignored, hidden or binary), line by line.
+/// Each match is handed to a function, which returns false to stop searching.
+fn search_files(root: &Path, re: &Regex, mut found: impl FnMut(Hit) -> bool) {
+    let walker = WalkBuilder::new(root)
+        .require_git(false)
+        .sort_by_file_name(std::cmp::Ord::cmp)
+        .build();
+    let mut count = 0;
+    for entry in walker.flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let path = entry.path();
+        if looks_binary(&path.to_string_lossy()) {
+            continue;
+        }
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            continue;
+        };
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        for (y, line) in contents.lines().enumerate() {
+            for mtch in re.find_iter(line) {
+                // Empty matches can't be seen or replaced
+                if mtch.is_empty() {
+                    continue;
+                }
+                let hit = Hit {
+                    path: relative.clone(),
+                    loc: Loc::at(line[..mtch.start()].chars().count(), y),
+                    len: mtch.as_str().chars().count(),
+                    context: line.to_string(),
+                };
+                count += 1;
+                if !found(hit) || count >= MAX_HITS {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// How a match is shown in the results buffer
+fn describe_hit(hit: &Hit) -> String {
+    format!(
+        "{}:{}:{}: {}",
+        hit.path,
+        hit.loc.y + 1,
+        hit.loc.x + 1,
+        hit.context.trim()
+    )
+}
+
+impl Editor {
+    /// Search through the project for a pattern, showing the matches in a new buffer
+    pub fn search_project(&mut self, pattern: Option<String>) -> Result<()> {
+        let pattern = match pattern {
+            Some(pattern) => {
+                self.remember_query(QueryKind::Search, &pattern);
+                pattern
+            }
+            None => self.history_prompt("Search project", Some(QueryKind::Search))?,
+        };
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        let re = match Regex::new(&pattern) {
+            Ok(re) => re,
+            Err(err) => {
+                self.feedback = Feedback::Error(format!("Invalid pattern: {err}"));
+                return Ok(());
+            }
+        };
+        let root = project_root(Path::new(&get_cwd().unwrap_or_default()));
+        let search = ProjectSearch::new(root, pattern, re);
+        // Set up the results buffer
+        self.blank()?;
+        self.next();
+        let fc = self.files.get_mut(self.ptr.clone()).unwrap();
+        reset_results(fc, "Search Results".to_string(), search.summary());
+        fc.search = Some(search);
+        self.poll_project_search();
+        Ok(())
+    }
+
+    /// Show matches that have been found since last time, returning true if there were any
+    pub fn poll_project_search(&mut self) -> bool {
+        let mut changed = false;
+        let mut finished = vec![];
+        for fc in self.files.containers_mut() {
+            let Some(search) = &mut fc.search else {
+                continue;
+            };
+            let was_searching = search.searching.load(Ordering::SeqCst);
+            let found: Vec<Hit> = search.pending.lock().unwrap().drain(..).collect();
+            if found.is_empty() && (was_searching || search.done) {
+                continue;
+            }
+            let start = search.hits.len() + 1;
+            let lines = found.iter().map(describe_hit).collect();
+            search.hits.extend(found);
+            show_results(
+                &mut fc.doc,
+                &mut fc.highlighter,
+                start,
+                lines,
+                search.summary(),
+            );
+            // Let the user know when the search has finished
+            if !was_searching {
+                finished.push((search.summary(), search.quickfix()));
+                search.done = true;
+            }
+            changed = true;
+        }
+        if let Some((summary, matches)) = finished.pop() {
+            self.set_quickfix(matches);
+            self.feedback = Feedback::Info(summary);
+        }
+        changed
+    }
+
+    /// Open the match under the cursor in the search results (returns false if not in them)
+    pub fn jump_to_hit(&mut self) -> Result<bool> {
+        let Some(fc) = self.files.get(self.ptr.clone()) else {
+            return Ok(false);
+        };
+        let Some(search) = &fc.search else {
+            return Ok(false);
+        };
+        let y = fc.doc.loc().y;
+        let Some(hit) = y.checked_sub(1).and_then(|y| search.hits.get(y)).cloned() else {
+            return Ok(true);
+        };
+        self.open_result(&search.root.join(&hit.path), hit.loc, hit.len)?;
+        Ok(true)
+    }
+
+    /// Replace matches of a pattern across the project, asking about each one
+    #[allow(clippy::too_many_lines)]
+    pub fn replace_project(&mut self, lua: &Lua) -> Result<()> {
+        let editor_bg = Bg(config!(self.config, colors).editor_bg.to_color()?);
+        // Request replace information
+        let pattern = self.history_prompt("Replace in project", Some(QueryKind::Search))?;
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        let re = match Regex::new(&pattern) {
+            Ok(re) => re,
+            Err(err) => {
+                self.feedback = Feedback::Error(format!("Invalid pattern: {err}"));
+                return Ok(());
+            }
+        };
+        let into = self.history_prompt("With", Some(QueryKind::Replace))?;
+        let root = project_root(Path::new(&get_cwd().unwrap_or_default()));
+        let mut hits = vec![];
+        search_files(&root, &re, |hit| {
+            hits.push(hit);
+            true
+        });
+        // Go through each match, opening the files they're in
+        let (mut replaced, mut files) = (0, vec![]);
+        let mut all = false;
+        // Where the last replacement ended, before and after (as it moves later matches along)
+        let mut shift: Option<(String, usize, usize, usize)> = None;
+        'hits: for hit in &hits {
+            let path = root.join(&hit.path).to_string_lossy().to_string();
+            if let Err(err) = self.goto_file(&path) {
+                self.feedback = Feedback::Error(err.to_string());
+                continue;
+            }
+            if self.try_hex().is_some() {
+                continue;
+            }
+            let x = match &shift {
+                Some((p, y, old, new)) if *p == hit.path && *y == hit.loc.y => {
+                    new + hit.loc.x.saturating_sub(*old)
+                }
+                _ => hit.loc.x,
+            };
+            let doc = self.try_doc_mut().unwrap();
+            if doc.info.read_only {
+                continue;
+            }
+            doc.load_to(hit.loc.y + doc.size.h);
+            let Some(line) = doc.lines.get(hit.loc.y).cloned() else {
+                continue;
+            };
+            // The file may have changed since it was searched, so check the match is still there
+            let Some(start) = line.char_indices().nth(x).map(|(idx, _)| idx) else {
+                continue;
+            };
+            let Some(caps) = re.captures_at(&line, start).filter(|c| !c[0].is_empty()) else {
+                continue;
+            };
+            if caps.get(0).unwrap().start() != start {
+                continue;
+            }
+            let target = caps[0].to_string();
+            let mut expanded = String::new();
+            caps.expand(&into, &mut expanded);
+            let loc = Loc::at(x, hit.loc.y);
+            // Select the match
+            doc.cancel_selection();
+            doc.move_to(&Loc::at(x + target.chars().count(), loc.y));
+            doc.select_to(&loc);
+            self.update_highlighter();
+            // Ask whether to replace it
+            let mut accept = all;
+            while !all {
+                let Size { w, h } = size()?;
+                self.needs_rerender = true;
+                self.render(lua)?;
+                self.terminal.prepare_line(h);
+                display!(
+                    self,
+                    editor_bg,
+                    Print("[y] Replace | [n] Skip | [a] Replace all | [Esc] Stop"),
+                    Print(" ".repeat(w.saturating_sub(54)))
+                );
+                if let Some(Loc { x, y }) = self.cursor_position() {
+                    self.terminal.goto(x, y);
+                    self.terminal.show_cursor();
+                } else {
+                    self.terminal.hide_cursor();
+                }
+                self.terminal.flush()?;
+                if let Some((modifiers, code)) =
+                    key_event(&wait_for_event_hog(self), &mut self.macro_man)
+                {
+                    match (modifiers, code) {
+                        (KMod::NONE, KCode::Char('y')) => accept = true,
+                        (KMod::NONE, KCode::Char('n')) => (),
+                        (KMod::NONE, KCode::Char('a')) => {
+                            accept = true;
+                            all = true;
+                        }
+                        (KMod::NONE, KCode::Esc) => {
+                            self.try_doc_mut().unwrap().cancel_selection();
+                            break 'hits;
+                        }
+                        _ => continue,
+                    }
+                    break;
+                }
+            }
+            self.try_doc_mut().unwrap().cancel_selection();
+            self.try_doc_mut().unwrap().move_to(&loc);
+            if accept {
+                self.do_replace(&expanded, &target)?;
+                let (old, new) = (hit.loc.x + hit.len, x + expanded.chars().count());
+                shift = Some((hit.path.clone(), hit.loc.y, old, new));
+                replaced += 1;
+                if !files.contains(&hit.path) {
+                    files.push(hit.path.clone());
+                }
+            }
+        }
+        self.feedback = Feedback::Info(format!(
+            "Replaced {replaced} of {} matches in {} files",
+            hits.len(),
+            files.len()
+        ));
+        Ok(())
+    }
+}
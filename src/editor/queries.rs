@@ -0,0 +1,137 @@
+/// Functions for remembering what has been searched for and replaced with
+use crate::config;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::{history_store, Editor};
+
+/// The kinds of things that are remembered from prompts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryKind {
+    /// Terms that have been searched for
+    Search,
+    /// Text that matches have been replaced with
+    Replace,
+}
+
+impl QueryKind {
+    /// The name of this kind, as written in the history file
+    fn name(self) -> &'static str {
+        match self {
+            Self::Search => "search",
+            Self::Replace => "replace",
+        }
+    }
+}
+
+/// Past search and replace terms (the most recent last)
+#[derive(Debug, Default, Clone)]
+pub struct QueryHistory {
+    pub search: Vec<String>,
+    pub replace: Vec<String>,
+}
+
+impl QueryHistory {
+    /// Get the past terms of a certain kind
+    pub fn list(&self, kind: QueryKind) -> &[String] {
+        match kind {
+            QueryKind::Search => &self.search,
+            QueryKind::Replace => &self.replace,
+        }
+    }
+
+    /// Remember a term (moving it to the end if it was already there), keeping at most `limit`
+    pub fn add(&mut self, kind: QueryKind, term: &str, limit: usize) {
+        let list = match kind {
+            QueryKind::Search => &mut self.search,
+            QueryKind::Replace => &mut self.replace,
+        };
+        list.retain(|past| past != term);
+        list.push(term.to_string());
+        let excess = list.len().saturating_sub(limit);
+        list.drain(..excess);
+    }
+
+    /// Read the history from a file (one term per line, after its kind and a tab)
+    pub fn load(path: &Path) -> Self {
+        let mut history = Self::default();
+        let contents = std::fs::read_to_string(path).unwrap_or_default();
+        for line in contents.lines() {
+            match line.split_once('\t') {
+                Some(("search", term)) => history.search.push(term.to_string()),
+                Some(("replace", term)) => history.replace.push(term.to_string()),
+                _ => (),
+            }
+        }
+        history
+    }
+
+    /// Write the history to a file
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::File::create(path)?;
+        for kind in [QueryKind::Search, QueryKind::Replace] {
+            // Terms on several lines can't be written one per line
+            for term in self.list(kind).iter().filter(|term| !term.contains('\n')) {
+                writeln!(file, "{}\t{term}", kind.name())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Moves through past terms while in a prompt, keeping hold of what was typed beforehand
+#[derive(Debug, Default)]
+pub struct HistoryBrowser {
+    /// The past term being shown (None when showing what was typed)
+    at: Option<usize>,
+    /// What was typed before moving through past terms
+    draft: String,
+}
+
+impl HistoryBrowser {
+    /// Move to an older (up) or more recent term, returning what the prompt should now contain
+    pub fn step(&mut self, past: &[String], input: &str, up: bool) -> Option<String> {
+        if past.is_empty() {
+            return None;
+        }
+        if self.at.is_none() {
+            self.draft = input.to_string();
+        }
+        self.at = match (self.at, up) {
+            (None, true) => Some(past.len() - 1),
+            (Some(at), true) => Some(at.saturating_sub(1)),
+            (Some(at), false) if at + 1 < past.len() => Some(at + 1),
+            (_, false) => None,
+        };
+        Some(self.at.map_or(self.draft.clone(), |at| past[at].clone()))
+    }
+}
+
+/// Location of the file where search and replace history is kept between sessions
+pub fn query_history_store() -> PathBuf {
+    history_store().with_file_name("queries")
+}
+
+impl Editor {
+    /// Read in search and replace history from previous sessions (if it is being saved)
+    pub fn load_query_history(&mut self) {
+        if config!(self.config, search).save_history {
+            self.query_history = QueryHistory::load(&query_history_store());
+        }
+    }
+
+    /// Remember a term typed into a search or replace prompt
+    pub fn remember_query(&mut self, kind: QueryKind, term: &str) {
+        if term.is_empty() {
+            return;
+        }
+        let search = config!(self.config, search);
+        self.query_history.add(kind, term, search.history_length);
+        if search.save_history {
+            let _ = self.query_history.save(&query_history_store());
+        }
+    }
+}
@@ -0,0 +1,265 @@
+/// Functions for the quickfix list (places in files, like errors or search matches, to go through)
+use crate::config::default_matchers;
+use crate::error::{OxError, Result};
+use crate::ui::Feedback;
+use kaolinite::utils::{get_cwd, Loc};
+use mlua::prelude::*;
+use std::path::Path;
+
+use super::{Editor, FileLayout};
+
+/// A place in a file
+#[derive(Debug, Clone)]
+pub struct QuickfixEntry {
+    /// The path of the file the place is in
+    pub file: String,
+    /// Where in the file the place is (by character index)
+    pub loc: Loc,
+    /// What is there (like an error message or the line that matched)
+    pub text: String,
+}
+
+impl FromLua for QuickfixEntry {
+    fn from_lua(val: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Table(table) = val {
+            let line: usize = table.get("line")?;
+            let column: Option<usize> = table.get("column")?;
+            Ok(Self {
+                file: table.get("file")?,
+                loc: Loc::at(
+                    column.unwrap_or(1).saturating_sub(1),
+                    line.saturating_sub(1),
+                ),
+                text: table.get::<Option<String>>("text")?.unwrap_or_default(),
+            })
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: val.type_name(),
+                to: "QuickfixEntry".to_string(),
+                message: Some("expected a table with a file, line, column and text".to_string()),
+            })
+        }
+    }
+}
+
+impl IntoLua for QuickfixEntry {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        table.set("file", self.file)?;
+        table.set("line", self.loc.y + 1)?;
+        table.set("column", self.loc.x + 1)?;
+        table.set("text", self.text)?;
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// A list of places in files that can be gone through one after another
+#[derive(Debug, Default)]
+pub struct QuickfixList {
+    /// What the places are (like the name of the task that found them)
+    pub title: String,
+    /// The places themselves
+    pub entries: Vec<QuickfixEntry>,
+    /// The place that is selected (and was gone to last)
+    pub current: Option<usize>,
+    /// The first place shown in the quickfix panel
+    pub offset: usize,
+}
+
+impl QuickfixList {
+    /// Create a list of places
+    pub fn new(title: String, entries: Vec<QuickfixEntry>) -> Self {
+        Self {
+            title,
+            entries,
+            current: None,
+            offset: 0,
+        }
+    }
+
+    /// Find places in what a command (such as a compiler or grep) has output,
+    /// looking for lines like `src/main.rs:3:5: message` that point to files that exist
+    pub fn from_output(title: String, output: &str, root: &Path) -> Self {
+        let matchers = default_matchers();
+        let entries = output
+            .lines()
+            .filter_map(|text| {
+                matchers.iter().find_map(|matcher| {
+                    let (file, y, x, message) = matcher.find(text)?;
+                    let path = root.join(&file);
+                    path.is_file().then(|| QuickfixEntry {
+                        file: path.to_string_lossy().to_string(),
+                        loc: Loc::at(x.saturating_sub(1), y.saturating_sub(1)),
+                        text: message,
+                    })
+                })
+            })
+            .collect();
+        Self::new(title, entries)
+    }
+
+    /// How a place is shown in the quickfix panel
+    pub fn describe(entry: &QuickfixEntry) -> String {
+        let cwd = get_cwd().unwrap_or_default();
+        let file = Path::new(&entry.file)
+            .strip_prefix(&cwd)
+            .map_or(entry.file.clone(), |file| {
+                file.to_string_lossy().to_string()
+            });
+        format!(
+            "{file}:{}:{}  {}",
+            entry.loc.y + 1,
+            entry.loc.x + 1,
+            entry.text.trim().replace('\t', " ")
+        )
+    }
+}
+
+impl Editor {
+    /// Replace the places in the quickfix list
+    pub fn set_quickfix(&mut self, list: QuickfixList) {
+        self.quickfix = list;
+        self.needs_rerender = true;
+    }
+
+    /// Open the quickfix panel below the current split (or move to it if it is already open)
+    pub fn open_quickfix(&mut self) {
+        if let Some(at) = self.files.find_quickfix(vec![]) {
+            self.ptr = at;
+            return;
+        }
+        // The panel can't go below the file tree, so put it below the last file instead
+        let at = if matches!(
+            self.files.get_raw(self.ptr.clone()),
+            Some(FileLayout::FileTree)
+        ) {
+            let mut at = self.old_ptr.clone();
+            at.insert(0, 1);
+            at
+        } else {
+            self.ptr.clone()
+        };
+        self.cache_old_ptr(&at);
+        self.ptr = self.files.open_down(at, FileLayout::Quickfix);
+        self.files.set_proportion(self.ptr.clone(), 0.3);
+        self.needs_rerender = true;
+    }
+
+    /// Close the quickfix panel (if it is open)
+    pub fn close_quickfix(&mut self) {
+        if let Some(at) = self.files.find_quickfix(vec![]) {
+            self.files.remove(at.clone());
+            // Find a new pointer position
+            self.ptr = self.files.new_pointer_position(&at);
+            // Clean up the redundant sidebyside/toptobottom
+            self.ptr = self.files.clean_up_multis(self.ptr.clone());
+            self.needs_rerender = true;
+        }
+    }
+
+    /// Open or close the quickfix panel
+    pub fn toggle_quickfix(&mut self) {
+        if self.files.find_quickfix(vec![]).is_some() {
+            self.close_quickfix();
+        } else {
+            self.open_quickfix();
+        }
+    }
+
+    /// Select a place in the quickfix panel (without going to it)
+    pub fn quickfix_select(&mut self, down: bool) {
+        let last = self.quickfix.entries.len().saturating_sub(1);
+        self.quickfix.current = match (self.quickfix.current, down) {
+            (None, _) => Some(0),
+            (Some(at), true) => Some((at + 1).min(last)),
+            (Some(at), false) => Some(at.saturating_sub(1)),
+        };
+    }
+
+    /// Go to the next place in the quickfix list
+    pub fn quickfix_next(&mut self) -> Result<()> {
+        let len = self.quickfix.entries.len();
+        let next = self.quickfix.current.map_or(0, |at| at + 1);
+        if len == 0 {
+            self.feedback = Feedback::Warning("The quickfix list is empty".to_string());
+        } else if next < len {
+            self.goto_quickfix(next)?;
+        } else {
+            self.feedback = Feedback::Warning("No more places in the quickfix list".to_string());
+        }
+        Ok(())
+    }
+
+    /// Go to the previous place in the quickfix list
+    pub fn quickfix_prev(&mut self) -> Result<()> {
+        let len = self.quickfix.entries.len();
+        let prev = self
+            .quickfix
+            .current
+            .map_or(len.checked_sub(1), |at| at.checked_sub(1));
+        if len == 0 {
+            self.feedback = Feedback::Warning("The quickfix list is empty".to_string());
+        } else if let Some(prev) = prev {
+            self.goto_quickfix(prev)?;
+        } else {
+            self.feedback = Feedback::Warning("No more places in the quickfix list".to_string());
+        }
+        Ok(())
+    }
+
+    /// Go to a place in the quickfix list
+    pub fn goto_quickfix(&mut self, at: usize) -> Result<()> {
+        let Some(entry) = self.quickfix.entries.get(at).cloned() else {
+            return Ok(());
+        };
+        self.quickfix.current = Some(at);
+        // Files are opened in a split with documents in it (rather than in the quickfix panel)
+        if !matches!(
+            self.files.get_raw(self.ptr.clone()),
+            Some(FileLayout::Atom(_, _))
+        ) {
+            self.ptr = self.quickfix_target();
+        }
+        match self.open_or_new(entry.file.clone()) {
+            Ok(()) => self.next(),
+            // The file was already open, and has been moved to
+            Err(OxError::AlreadyOpen { .. }) => (),
+            Err(err) => return Err(err),
+        }
+        self.update_cwd();
+        if let Some(doc) = self.try_doc_mut() {
+            doc.load_to(entry.loc.y + doc.size.h);
+            doc.cancel_selection();
+            doc.move_to(&entry.loc);
+        }
+        self.update_highlighter();
+        self.feedback = Feedback::Info(format!(
+            "({} of {}) {}",
+            at + 1,
+            self.quickfix.entries.len(),
+            entry.text.trim()
+        ));
+        Ok(())
+    }
+
+    /// Work out which split to open places in (the one above the quickfix panel, if possible)
+    fn quickfix_target(&self) -> Vec<usize> {
+        let is_atom = |idx: &Vec<usize>| {
+            matches!(
+                self.files.get_raw(idx.clone()),
+                Some(FileLayout::Atom(_, _))
+            )
+        };
+        let above = FileLayout::move_up(self.ptr.clone(), &self.render_cache.span);
+        if is_atom(&above) {
+            return above;
+        }
+        self.render_cache
+            .span
+            .iter()
+            .map(|(idx, _, _)| idx)
+            .find(|idx| is_atom(idx))
+            .cloned()
+            .unwrap_or(above)
+    }
+}
@@ -0,0 +1,198 @@
+/// Functions for keeping swap files and recovering unsaved changes
+use crate::error::Result;
+use crate::ui::Feedback;
+use kaolinite::utils::get_file_name;
+use kaolinite::Document;
+use mlua::Lua;
+use std::fmt::Write;
+
+use super::{swap_store, Editor};
+
+/// Number of unchanged lines to show around each change in a diff
+const DIFF_CONTEXT: usize = 3;
+
+impl Editor {
+    /// Write out swap files for any documents with unsaved changes
+    pub fn write_swaps(&mut self) {
+        let store = swap_store();
+        for fc in self.files.containers() {
+            let Some(file_name) = &fc.doc.file_name else {
+                continue;
+            };
+            // Leave swap files from previous sessions alone until the user has dealt with them
+            // (and the document behind a hex view has no contents to recover)
+            if self.recovery.contains(file_name) || fc.hex.is_some() {
+                continue;
+            }
+            if fc.doc.event_mgmt.with_disk() {
+                fc.doc.remove_swap(&store);
+            } else {
+                let _ = fc.doc.write_swap(&store);
+            }
+        }
+    }
+
+    /// Check a newly opened document for unsaved changes left behind by a previous session
+    pub fn check_swap(&mut self, doc: &Document) {
+        let store = swap_store();
+        let Some((pid, contents)) = doc.read_swap(&store) else {
+            return;
+        };
+        let file_name = doc.file_name.clone().unwrap_or_default();
+        if doc.file == contents.as_str() {
+            // Nothing would be recovered
+            doc.remove_swap(&store);
+        } else if process_alive(pid) {
+            let name = get_file_name(&file_name).unwrap_or_default();
+            self.feedback = Feedback::Warning(format!(
+                "{name} has unsaved changes in another instance of ox"
+            ));
+        } else if !self.recovery.contains(&file_name) {
+            self.recovery.push(file_name);
+        }
+    }
+
+    /// Ask the user what to do with the unsaved changes of the current document
+    pub fn recovery_prompt(&mut self, lua: &Lua) -> Result<()> {
+        let Some(file_name) = self.try_doc().and_then(|doc| doc.file_name.clone()) else {
+            return Ok(());
+        };
+        let Some(idx) = self.recovery.iter().position(|f| *f == file_name) else {
+            return Ok(());
+        };
+        let Some((_, contents)) = self.try_doc().unwrap().read_swap(&swap_store()) else {
+            self.recovery.remove(idx);
+            return Ok(());
+        };
+        let name = get_file_name(&file_name).unwrap_or_default();
+        let msg = format!(
+            "{name} has unsaved changes from a previous session: [r] Recover │ [d] Diff │ [Esc] Discard"
+        );
+        match self.choose(&msg, &['r', 'd'])? {
+            // Apply the unsaved changes to the document
+            Some('r') => {
+                self.recovery.remove(idx);
+                self.try_doc_mut().unwrap().set_contents(&contents)?;
+                self.reload_highlight();
+                self.feedback = Feedback::Info(format!("Recovered changes to {name}"));
+            }
+            // Show what the unsaved changes are (and ask again when the user returns)
+            Some(_) => {
+                let disk = self.try_doc().unwrap().file.to_string();
+                self.show_diff(&diff(&name, &disk, &contents))?;
+            }
+            // Throw away the unsaved changes
+            None => {
+                self.recovery.remove(idx);
+                self.try_doc().unwrap().remove_swap(&swap_store());
+                self.feedback = Feedback::Info(format!("Discarded changes to {name}"));
+            }
+        }
+        self.needs_rerender = true;
+        self.render(lua)
+    }
+
+    /// Open up a read only document containing a diff
+    pub fn show_diff(&mut self, diff: &str) -> Result<()> {
+        self.new_document()?;
+        let doc = self.try_doc_mut().unwrap();
+        doc.set_contents(diff)?;
+        doc.event_mgmt.force_not_with_disk = false;
+        doc.event_mgmt.disk_write(doc.cursor);
+        doc.info.read_only = true;
+        self.reload_highlight();
+        Ok(())
+    }
+}
+
+/// Determine if another process is still running
+#[cfg(not(target_os = "windows"))]
+#[allow(clippy::cast_possible_wrap)]
+fn process_alive(pid: u32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+    kill(Pid::from_raw(pid as i32), None) != Err(Errno::ESRCH)
+}
+
+/// Determine if another process is still running (assume not)
+#[cfg(target_os = "windows")]
+fn process_alive(_: u32) -> bool {
+    false
+}
+
+/// Create a line by line diff between two versions of a file
+pub fn diff(name: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_ops(&old, &new);
+    // Only show the lines surrounding changes
+    let show: Vec<bool> = (0..ops.len())
+        .map(|i| {
+            let start = i.saturating_sub(DIFF_CONTEXT);
+            let end = (i + DIFF_CONTEXT + 1).min(ops.len());
+            ops[start..end].iter().any(|(op, _)| *op != ' ')
+        })
+        .collect();
+    let mut result = format!("--- {name} (on disk)\n+++ {name} (unsaved changes)\n");
+    let mut skipping = false;
+    for (i, (op, line)) in ops.iter().enumerate() {
+        if show[i] {
+            let _ = writeln!(result, "{op} {line}");
+            skipping = false;
+        } else if !skipping {
+            result.push_str("...\n");
+            skipping = true;
+        }
+    }
+    result
+}
+
+/// Work out which lines were kept (' '), removed ('-') and added ('+')
+fn diff_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    // Lines that are the same at the start and end of both don't need comparing
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let mut result: Vec<(char, &str)> = old[..prefix].iter().map(|l| (' ', *l)).collect();
+    if old_mid.len() * new_mid.len() > 10_000_000 {
+        // Too large to compare line by line
+        result.extend(old_mid.iter().map(|l| ('-', *l)));
+        result.extend(new_mid.iter().map(|l| ('+', *l)));
+    } else {
+        // Find the longest common subsequence of the differing lines
+        let (n, m) = (old_mid.len(), new_mid.len());
+        let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old_mid[i] == new_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_mid[i] == new_mid[j] {
+                result.push((' ', old_mid[i]));
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                result.push(('-', old_mid[i]));
+                i += 1;
+            } else {
+                result.push(('+', new_mid[j]));
+                j += 1;
+            }
+        }
+    }
+    result.extend(old[old.len() - suffix..].iter().map(|l| (' ', *l)));
+    result
+}
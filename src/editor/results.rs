@@ -0,0 +1,67 @@
+/// Functions for buffers that show the results of work done in the background
+/// (such as searching through a project or running a task)
+use crate::error::Result;
+use kaolinite::event::EventMgmt;
+use kaolinite::utils::Loc;
+use kaolinite::Document;
+use std::path::Path;
+use synoptic::Highlighter;
+
+use super::{Editor, FileContainer};
+
+/// Empty a buffer so that results can be shown in it (it can't be edited by the user)
+pub fn reset_results(fc: &mut FileContainer, title: String, summary: String) {
+    let mut doc = Document::new(fc.doc.size);
+    doc.set_tab_width(fc.doc.tab_width);
+    doc.file_name = Some(title);
+    doc.info.read_only = true;
+    show_summary(&mut doc, summary);
+    doc.event_mgmt = EventMgmt::default();
+    fc.doc = doc;
+    fc.highlighter.run(&fc.doc.lines);
+}
+
+/// Add lines to a results buffer (the first going on line `start`)
+/// and bring its first line up to date (without any of it counting as an edit)
+pub fn show_results(
+    doc: &mut Document,
+    highlighter: &mut Highlighter,
+    start: usize,
+    lines: Vec<String>,
+    summary: String,
+) {
+    let cursor = doc.char_loc();
+    for (y, line) in (start..).zip(lines) {
+        let _ = doc.insert_line(y, line);
+        highlighter.insert_line(y, &doc.lines[y]);
+    }
+    doc.move_to(&cursor);
+    show_summary(doc, summary);
+    highlighter.edit(0, &doc.lines[0]);
+    doc.event_mgmt = EventMgmt::default();
+}
+
+/// Replace the first line of a results buffer
+fn show_summary(doc: &mut Document, summary: String) {
+    let cursor = doc.char_loc();
+    let _ = doc.delete_line(0);
+    let _ = doc.insert_line(0, summary);
+    doc.move_to(&cursor);
+}
+
+impl Editor {
+    /// Open a file that a result points to, selecting the characters it is about
+    pub fn open_result(&mut self, path: &Path, loc: Loc, len: usize) -> Result<()> {
+        self.goto_file(&path.to_string_lossy())?;
+        if let Some(doc) = self.try_doc_mut() {
+            doc.load_to(loc.y + doc.size.h);
+            doc.cancel_selection();
+            doc.move_to(&Loc::at(loc.x + len, loc.y));
+            if len > 0 {
+                doc.select_to(&loc);
+            }
+        }
+        self.update_highlighter();
+        Ok(())
+    }
+}
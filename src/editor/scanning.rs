@@ -5,41 +5,61 @@ use crate::ui::{key_event, size};
 use crate::{config, display};
 use crossterm::{
     event::{KeyCode as KCode, KeyModifiers as KMod},
-    style::{Attribute, Print, SetAttribute, SetBackgroundColor as Bg},
+    style::{Attribute, Print, SetAttribute, SetBackgroundColor as Bg, SetForegroundColor as Fg},
 };
-use kaolinite::utils::{Loc, Size};
+use kaolinite::searching::Searcher;
+use kaolinite::utils::{width, Loc, Size};
 use mlua::Lua;
+use regex::Regex;
 
-use super::Editor;
+use super::{Editor, HistoryBrowser, QueryKind};
+
+/// The pattern, which document it was in, and that document's version and loaded length
+type MatchKey = (String, Vec<usize>, Option<usize>, usize, usize);
+
+/// The matches of a search in a document, kept so they aren't looked for on every redraw
+#[derive(Debug, Default)]
+pub struct MatchCache {
+    /// What the matches were found for
+    key: Option<MatchKey>,
+    /// Where each match starts (in order)
+    matches: Vec<Loc>,
+}
 
 impl Editor {
     /// Use search feature
+    #[allow(clippy::too_many_lines)]
     pub fn search(&mut self, lua: &Lua) -> Result<()> {
         // Block any non-documents from activating search
         if self.try_doc().is_none() {
             return Ok(());
         }
+        // Hex documents are searched by bytes instead
+        if self.try_hex().is_some() {
+            return self.search_hex();
+        }
         // Gather data
         let editor_bg = Bg(config!(self.config, colors).editor_bg.to_color()?);
         let cache = self.try_doc().unwrap().char_loc();
         // Prompt for a search term
         let mut target = String::new();
+        let mut browser = HistoryBrowser::default();
         let mut done = false;
         while !done {
             let Size { w, h } = size()?;
             // Rerender the editor
             self.needs_rerender = true;
             self.render(lua)?;
-            // Render prompt message
+            // Render prompt message (with how many matches there are and the search settings)
+            let prompt = format!("Search: {target}│");
+            let (status, status_width) = self.search_status(true)?;
+            let padding = w.saturating_sub(width(&prompt, 4) + status_width);
             self.terminal.prepare_line(h);
-            display!(
-                self,
-                editor_bg,
-                "Search: ",
-                target.clone(),
-                "│",
-                " ".to_string().repeat(w)
-            );
+            if padding > 0 {
+                display!(self, editor_bg, prompt, " ".repeat(padding), status);
+            } else {
+                display!(self, editor_bg, prompt, " ".repeat(w));
+            }
             // Move back to correct cursor position
             if let Some(Loc { x, y }) = self.cursor_position() {
                 self.terminal.goto(x, y);
@@ -58,29 +78,44 @@ impl Editor {
                     (KMod::NONE, KCode::Esc) => {
                         self.try_doc_mut().unwrap().move_to(&cache);
                         self.try_doc_mut().unwrap().cancel_selection();
+                        self.search_highlight = None;
                         return Err(OxError::Cancelled);
                     }
                     // Remove from the input string if the user presses backspace
                     (KMod::NONE, KCode::Backspace) => {
                         target.pop();
-                        self.try_doc_mut().unwrap().move_to(&cache);
-                        self.next_match(&target);
+                        self.research(&target, &cache);
                     }
                     // Add to the input string if the user presses a character
                     (KMod::NONE | KMod::SHIFT, KCode::Char(c)) => {
                         target.push(c);
-                        self.try_doc_mut().unwrap().move_to(&cache);
-                        self.next_match(&target);
+                        self.research(&target, &cache);
+                    }
+                    // Move through past search terms
+                    (KMod::NONE, KCode::Up | KCode::Down) => {
+                        let past = self.query_history.list(QueryKind::Search);
+                        if let Some(term) = browser.step(past, &target, code == KCode::Up) {
+                            target = term;
+                            self.research(&target, &cache);
+                        }
+                    }
+                    // Switch search settings on and off
+                    (modifiers, code) => {
+                        if self.toggle_search_option(modifiers, code) {
+                            self.research(&target, &cache);
+                        }
                     }
-                    _ => (),
                 }
             }
         }
 
         // If no target is given, do nothing
         if target.is_empty() {
+            self.search_highlight = None;
             return Ok(());
         }
+        self.remember_query(QueryKind::Search, &target);
+        let pattern = self.search_pattern(&target);
 
         // Main body of the search feature
         let mut done = false;
@@ -91,12 +126,14 @@ impl Editor {
             self.needs_rerender = true;
             self.render(lua)?;
             // Render custom status line with mode information
+            let (status, status_width) = self.search_status(false)?;
             self.terminal.prepare_line(h);
             display!(
                 self,
                 editor_bg,
                 Print("[<-]: Search previous | [->]: Search next | [Enter] Finish | [Esc] Cancel"),
-                Print(" ".repeat(w.saturating_sub(73)))
+                Print(" ".repeat(w.saturating_sub(73 + status_width))),
+                status
             );
             // Move back to correct cursor position
             if let Some(Loc { x, y }) = self.cursor_position() {
@@ -118,18 +155,127 @@ impl Editor {
                         done = true;
                     }
                     // On left key, move to the previous match in the document
-                    (KMod::NONE, KCode::Left) => std::mem::drop(self.prev_match(&target)),
+                    (KMod::NONE, KCode::Left) => std::mem::drop(self.prev_match(&pattern)),
                     // On right key, move to the next match in the document
-                    (KMod::NONE, KCode::Right) => std::mem::drop(self.next_match(&target)),
+                    (KMod::NONE, KCode::Right) => std::mem::drop(self.next_match(&pattern)),
                     _ => (),
                 }
             }
             self.update_highlighter();
         }
         self.try_doc_mut().unwrap().cancel_selection();
+        self.search_highlight = None;
         Ok(())
     }
 
+    /// Turn a search term into the regex to search with (according to the search settings)
+    pub fn search_pattern(&self, term: &str) -> String {
+        config!(self.config, search).options().pattern(term)
+    }
+
+    /// Move to the first match of a search term after a location, highlighting the others
+    fn research(&mut self, term: &str, from: &Loc) {
+        let pattern = self.search_pattern(term);
+        self.search_highlight = if term.is_empty() {
+            None
+        } else {
+            Regex::new(&pattern).ok()
+        };
+        self.try_doc_mut().unwrap().move_to(from);
+        self.try_doc_mut().unwrap().cancel_selection();
+        if !term.is_empty() {
+            self.next_match(&pattern);
+        }
+    }
+
+    /// Switch a search setting on or off (returns false if the key isn't for one)
+    fn toggle_search_option(&mut self, modifiers: KMod, code: KCode) -> bool {
+        let mut search = self.config.search.borrow_mut::<config::Search>().unwrap();
+        match (modifiers, code) {
+            (KMod::ALT, KCode::Char('c')) => search.case_sensitive = !search.case_sensitive,
+            (KMod::ALT, KCode::Char('w')) => search.whole_word = !search.whole_word,
+            (KMod::ALT, KCode::Char('r')) => search.regex = !search.regex,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Work out which match is selected and how many there are in the current document
+    pub fn count_matches(&mut self) -> (Option<usize>, usize) {
+        let (Some(re), Some(doc)) = (&self.search_highlight, self.try_doc()) else {
+            return (None, 0);
+        };
+        let (start, _) = doc.selection_loc_bound();
+        let selected = !doc.is_selection_empty();
+        // Only look through the document again when the search or the document has changed
+        let (ptr, idx) = self.focus();
+        let key = (
+            re.as_str().to_string(),
+            ptr,
+            idx,
+            doc.version,
+            doc.file.len_lines(),
+        );
+        if self.render_cache.search_matches.key.as_ref() != Some(&key) {
+            let mut searcher = Searcher { re: re.clone() };
+            let mut matches = vec![];
+            for (y, line) in doc.file.lines().enumerate() {
+                let line = line.to_string();
+                for mtch in searcher.lfinds(line.trim_end_matches(['\n', '\r'])) {
+                    matches.push(Loc::at(mtch.loc.x, y));
+                }
+            }
+            self.render_cache.search_matches = MatchCache {
+                key: Some(key),
+                matches,
+            };
+        }
+        let matches = &self.render_cache.search_matches.matches;
+        let current = matches
+            .binary_search_by_key(&(start.y, start.x), |loc| (loc.y, loc.x))
+            .ok()
+            .filter(|_| selected)
+            .map(|idx| idx + 1);
+        (current, matches.len())
+    }
+
+    /// Describe the matches and search settings, for showing while searching
+    /// (returns the description and its width)
+    fn search_status(&mut self, settings: bool) -> Result<(String, usize)> {
+        let colors = config!(self.config, colors);
+        let (on, off) = (
+            Fg(colors.highlight.to_color()?),
+            Fg(colors.line_number_fg.to_color()?),
+        );
+        let editor_fg = Fg(colors.editor_fg.to_color()?);
+        let counter = match self.count_matches() {
+            (_, 0) => "No matches".to_string(),
+            (Some(current), total) => format!("Match {current} of {total}"),
+            (None, 1) => "1 match".to_string(),
+            (None, total) => format!("{total} matches"),
+        };
+        let mut status = counter.clone();
+        let mut used = counter.len();
+        if settings {
+            let search = config!(self.config, search);
+            let flags = [
+                ("Aa", search.case_sensitive),
+                ("\\b", search.whole_word),
+                (".*", search.regex),
+            ];
+            for (flag, enabled) in flags {
+                let colour = if enabled { on } else { off };
+                status += "  ";
+                status += &colour.to_string();
+                status += flag;
+                status += &editor_fg.to_string();
+                used += flag.len() + 2;
+            }
+        }
+        status.push(' ');
+        Ok((status, used + 1))
+    }
+
     /// Move to the next match
     pub fn next_match(&mut self, target: &str) -> Option<String> {
         if target.is_empty() {
@@ -184,12 +330,13 @@ impl Editor {
         // Gather data
         let editor_bg = Bg(config!(self.config, colors).editor_bg.to_color()?);
         // Request replace information
-        let target = self.prompt("Replace")?;
+        let target = self.history_prompt("Replace", Some(QueryKind::Search))?;
         // If no target is given, do nothing
         if target.is_empty() {
             return Ok(());
         }
-        let into = self.prompt("With")?;
+        let into = self.history_prompt("With", Some(QueryKind::Replace))?;
+        let target = self.search_pattern(&target);
         let mut done = false;
         let Size { w, h } = size()?;
         // Jump to match
@@ -205,6 +352,7 @@ impl Editor {
             // Exit if there are no matches in the document
             return Ok(());
         }
+        self.search_highlight = Regex::new(&target).ok();
         self.update_highlighter();
         // Enter into the replace menu
         while !done {
@@ -251,11 +399,12 @@ impl Editor {
             self.update_highlighter();
         }
         self.try_doc_mut().unwrap().cancel_selection();
+        self.search_highlight = None;
         Ok(())
     }
 
     /// Replace an instance in a document
-    fn do_replace(&mut self, into: &str, text: &str) -> Result<()> {
+    pub fn do_replace(&mut self, into: &str, text: &str) -> Result<()> {
         if let Some(doc) = self.try_doc_mut() {
             // Commit events to event manager (for undo / redo)
             doc.commit();
@@ -266,7 +415,9 @@ impl Editor {
             // Update syntax highlighter
             self.update_highlighter();
             if let Some(file) = self.files.get_mut(self.ptr.clone()) {
-                file.highlighter.edit(loc.y, &file.doc.lines[loc.y]);
+                if !file.doc.info.read_only {
+                    file.highlighter.edit(loc.y, &file.doc.lines[loc.y]);
+                }
             }
         }
         Ok(())
@@ -274,7 +425,8 @@ impl Editor {
 
     /// Replace all instances in a document
     fn do_replace_all(&mut self, target: &str, into: &str) {
-        if self.try_doc().is_some() {
+        // Nothing would be replaced (and the search would never move on)
+        if self.try_doc().is_some_and(|doc| !doc.info.read_only) {
             // Commit events to event manager (for undo / redo)
             self.try_doc_mut().unwrap().commit();
             // Replace everything top to bottom
@@ -287,8 +439,10 @@ impl Editor {
                 );
                 self.update_highlighter();
                 if let Some(file) = self.files.get_mut(self.ptr.clone()) {
-                    file.highlighter
-                        .edit(mtch.loc.y, &file.doc.lines[mtch.loc.y]);
+                    if !file.doc.info.read_only {
+                        file.highlighter
+                            .edit(mtch.loc.y, &file.doc.lines[mtch.loc.y]);
+                    }
                 }
             }
         }
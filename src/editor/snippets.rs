@@ -0,0 +1,452 @@
+/// Functions for inserting snippets and moving between their tab stops
+use crate::config::Indentation;
+use crate::error::Result;
+use crate::ui::Feedback;
+use crate::{config, Loc};
+use crossterm::event::{KeyCode as KCode, KeyModifiers as KMod};
+use kaolinite::event::Event;
+use kaolinite::snippet::Snippet;
+use kaolinite::utils::{get_absolute_path, get_file_name, get_parent};
+use kaolinite::Document;
+use mlua::prelude::*;
+use std::collections::HashMap;
+
+use super::completion::Candidate;
+use super::{Completion, Editor};
+
+/// A tab stop of a snippet that has been inserted
+#[derive(Debug, Clone)]
+struct Stop {
+    /// The marks at the start and end of each of its ranges (the first is where the cursor goes)
+    ranges: Vec<(usize, usize)>,
+    /// Choices for what to put at the tab stop
+    choices: Vec<String>,
+}
+
+/// A snippet that has been inserted, whose tab stops are being moved between.
+/// The tab stops are kept track of using marks in the document, so they move along with edits.
+#[derive(Debug, Clone)]
+pub struct SnippetSession {
+    /// The tab stops, in the order they are visited
+    stops: Vec<Stop>,
+    /// The tab stop the cursor is at
+    current: usize,
+    /// The marks at the start and end of the whole snippet
+    bounds: (usize, usize),
+    /// The first mark in the document that belongs to the snippet
+    first_mark: usize,
+    /// Whether secondary cursors have been put on the mirrors of the current tab stop
+    mirroring: bool,
+}
+
+impl SnippetSession {
+    /// Work out where the ranges of a tab stop are (None if a mark has gone out of the document)
+    fn ranges(&self, doc: &Document, stop: usize) -> Option<Vec<(Loc, Loc)>> {
+        let valid = |loc: Loc| {
+            doc.line(loc.y)
+                .is_some_and(|line| loc.x <= line.chars().count())
+                .then_some(loc)
+        };
+        self.stops[stop]
+            .ranges
+            .iter()
+            .map(|(start, end)| {
+                let start = valid(doc.marks.get(*start)?.loc)?;
+                let end = valid(doc.marks.get(*end)?.loc)?;
+                Some((start, end.max(start)))
+            })
+            .collect()
+    }
+
+    /// Set which marks are pushed along by text typed right at them.
+    /// Text typed at the current tab stop goes inside it, and before anything that follows it.
+    fn set_pushes(&self, doc: &mut Document) {
+        let current = &self.stops[self.current];
+        let ranges: Vec<(Loc, Loc)> = current
+            .ranges
+            .iter()
+            .map(|(start, end)| (doc.marks[*start].loc, doc.marks[*end].loc))
+            .collect();
+        for (idx, stop) in self.stops.iter().enumerate() {
+            for (start, end) in &stop.ranges {
+                for (mark, is_end) in [(*start, false), (*end, true)] {
+                    let loc = doc.marks[mark].loc;
+                    doc.marks[mark].push = if idx == self.current {
+                        is_end
+                    } else {
+                        ranges
+                            .iter()
+                            .any(|(s, e)| loc > *e || (loc == *e && (is_end || s != e)))
+                    };
+                }
+            }
+        }
+        doc.marks[self.bounds.0].push = false;
+        doc.marks[self.bounds.1].push = true;
+    }
+}
+
+/// Find the snippets defined for a file type (in the `snippets` table), by what triggers them.
+/// Snippets are defined as `snippets["File Type"]["trigger"] = "body"`.
+pub fn snippets_for(lua: &Lua, file_type: &str) -> LuaResult<HashMap<String, String>> {
+    let Some(snippets) = lua.globals().get::<Option<LuaTable>>("snippets")? else {
+        return Ok(HashMap::new());
+    };
+    let Some(snippets) = snippets.get::<Option<LuaTable>>(file_type)? else {
+        return Ok(HashMap::new());
+    };
+    snippets.pairs::<String, String>().collect()
+}
+
+impl Editor {
+    /// Get the name of the current file type (for looking up snippets)
+    fn file_type_name(&self) -> String {
+        self.files
+            .get(self.ptr.clone())
+            .and_then(|file| file.file_type.as_ref())
+            .map_or("Unknown".to_string(), |ft| ft.name.clone())
+    }
+
+    /// Find a snippet whose trigger has just been typed, giving where the trigger starts
+    fn snippet_trigger(&self, lua: &Lua) -> LuaResult<Option<(Loc, String)>> {
+        let Some(doc) = self.try_doc() else {
+            return Ok(None);
+        };
+        let loc = doc.char_loc();
+        let before: Vec<char> = doc
+            .line(loc.y)
+            .unwrap_or_default()
+            .chars()
+            .take(loc.x)
+            .collect();
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let mut best: Option<(usize, String)> = None;
+        for (trigger, body) in snippets_for(lua, &self.file_type_name())? {
+            let trigger: Vec<char> = trigger.chars().collect();
+            let Some(start) = before.len().checked_sub(trigger.len()) else {
+                continue;
+            };
+            // Triggers have to be typed on their own, not at the end of a longer word
+            let on_own = start == 0 || !(is_word(&before[start - 1]) && is_word(&trigger[0]));
+            let longer = best.as_ref().is_none_or(|(s, _)| start < *s);
+            if !trigger.is_empty() && before[start..] == trigger[..] && on_own && longer {
+                best = Some((start, body));
+            }
+        }
+        Ok(best.map(|(start, body)| (Loc::at(start, loc.y), body)))
+    }
+
+    /// Work out the values of the variables snippets can use
+    fn snippet_variables(&self) -> HashMap<&'static str, String> {
+        let mut result = HashMap::new();
+        let Some(doc) = self.try_doc() else {
+            return result;
+        };
+        let file_name = doc.file_name.clone().unwrap_or_default();
+        let path = get_absolute_path(&file_name).unwrap_or(file_name.clone());
+        result.insert("FILENAME", get_file_name(&file_name).unwrap_or_default());
+        result.insert("DIRECTORY", get_parent(&path).unwrap_or_default());
+        result.insert("FILEPATH", path);
+        result.insert("SELECTION", doc.selection_text());
+        result.insert("LINE_NUMBER", (doc.loc().y + 1).to_string());
+        result.insert("CLIPBOARD", self.terminal.last_copy.clone());
+        result
+    }
+
+    /// Insert a snippet at the cursor (replacing any selection, or from `start` if given),
+    /// then move to its first tab stop
+    pub fn expand_snippet(&mut self, body: &str, start: Option<Loc>) -> Result<()> {
+        if self.try_hex().is_some() || self.try_doc().is_none() {
+            return Ok(());
+        }
+        self.end_snippet();
+        let variables = self.snippet_variables();
+        // Lines of the snippet line up with the line it is inserted on
+        let document = config!(self.config, document);
+        let unit = match document.indentation {
+            Indentation::Tabs => "\t".to_string(),
+            Indentation::Spaces => " ".repeat(document.tab_width),
+        };
+        let doc = self.try_doc_mut().unwrap();
+        doc.commit();
+        if !doc.is_selection_empty() {
+            doc.remove_selection();
+        }
+        let loc = doc.char_loc();
+        let start = start
+            .filter(|s| s.y == loc.y && s.x <= loc.x)
+            .unwrap_or(loc);
+        let line = doc.line(loc.y).unwrap_or_default();
+        let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+        let body = body
+            .replace('\t', &unit)
+            .replace('\n', &format!("\n{indent}"));
+        let snippet = Snippet::expand(&body, |name| variables.get(name).cloned());
+        // Insert the snippet in place of its trigger
+        let typed: String = line.chars().skip(start.x).take(loc.x - start.x).collect();
+        if !typed.is_empty() {
+            doc.exe(Event::Delete(start, typed))?;
+        }
+        let end = self.insert_text(start, &snippet.text)?;
+        self.reload_highlight();
+        // Keep track of the tab stops
+        let doc = self.try_doc_mut().unwrap();
+        let place = |loc: Loc| {
+            if loc.y == 0 {
+                Loc::at(start.x + loc.x, start.y)
+            } else {
+                Loc::at(loc.x, start.y + loc.y)
+            }
+        };
+        let first_mark = doc.marks.len();
+        let bounds = (doc.add_mark(start, false), doc.add_mark(end, true));
+        let stops = snippet
+            .stops
+            .iter()
+            .map(|stop| Stop {
+                ranges: stop
+                    .ranges
+                    .iter()
+                    .map(|(s, e)| {
+                        (
+                            doc.add_mark(place(*s), false),
+                            doc.add_mark(place(*e), true),
+                        )
+                    })
+                    .collect(),
+                choices: stop.choices.clone(),
+            })
+            .collect();
+        if let Some(file) = self.files.get_mut(self.ptr.clone()) {
+            file.snippet = Some(SnippetSession {
+                stops,
+                current: 0,
+                bounds,
+                first_mark,
+                mirroring: false,
+            });
+        }
+        self.goto_stop(0);
+        Ok(())
+    }
+
+    /// Insert a snippet defined for the current file type by its trigger
+    pub fn insert_snippet(&mut self, lua: &Lua, name: &str) -> Result<()> {
+        let snippets = snippets_for(lua, &self.file_type_name())?;
+        if let Some(body) = snippets.get(name) {
+            self.expand_snippet(body, None)?;
+        } else {
+            self.feedback = Feedback::Error(format!("There is no snippet called '{name}'"));
+        }
+        Ok(())
+    }
+
+    /// Move to a tab stop of the snippet being edited (finishing at the last one)
+    fn goto_stop(&mut self, idx: usize) {
+        self.clear_mirrors();
+        let Some(file) = self.files.get_mut(self.ptr.clone()) else {
+            return;
+        };
+        let Some(session) = &mut file.snippet else {
+            return;
+        };
+        session.current = idx;
+        let Some(ranges) = session.ranges(&file.doc, idx) else {
+            self.end_snippet();
+            return;
+        };
+        session.set_pushes(&mut file.doc);
+        let (start, end) = ranges[0];
+        let choices = session.stops[idx].choices.clone();
+        let last = idx + 1 == session.stops.len();
+        file.doc.move_to(&start);
+        file.doc.select_to(&end);
+        if last {
+            // The snippet is finished once the cursor reaches the final tab stop
+            self.end_snippet();
+        } else if !choices.is_empty() {
+            let candidates = choices.iter().map(|c| Candidate::new(c, "choice"));
+            let mut completion = Completion {
+                start,
+                candidates: candidates.collect(),
+                ..Completion::default()
+            };
+            // Choices are shown in the order they are written
+            completion.filter("");
+            completion.matches.sort_by_key(|(idx, _)| *idx);
+            self.completion = Some(completion);
+        }
+        self.needs_rerender = true;
+    }
+
+    /// Take secondary cursors away from the mirrors of a tab stop
+    fn clear_mirrors(&mut self) {
+        if let Some(file) = self.files.get_mut(self.ptr.clone()) {
+            if let Some(session) = &mut file.snippet {
+                if std::mem::take(&mut session.mirroring) {
+                    file.doc.clear_cursors();
+                }
+            }
+        }
+    }
+
+    /// Stop moving between the tab stops of a snippet
+    pub fn end_snippet(&mut self) {
+        self.clear_mirrors();
+        if let Some(file) = self.files.get_mut(self.ptr.clone()) {
+            if let Some(session) = file.snippet.take() {
+                file.doc.marks.truncate(session.first_mark);
+            }
+        }
+    }
+
+    /// Let snippets deal with a key press.
+    /// Tab expands a snippet or moves to the next tab stop (shift + tab moves back), and
+    /// typing over a placeholder replaces it (and its mirrors, using secondary cursors).
+    /// Returns true if the key was used up.
+    pub fn snippet_key(&mut self, lua: &Lua, modifiers: KMod, code: KCode) -> Result<bool> {
+        if self.try_hex().is_some() {
+            return Ok(false);
+        }
+        let Some(file) = self.files.get_mut(self.ptr.clone()) else {
+            return Ok(false);
+        };
+        let Some(session) = &file.snippet else {
+            // Tab after a trigger expands its snippet
+            let selecting = !file.doc.is_selection_empty();
+            if (modifiers, code) != (KMod::NONE, KCode::Tab) || selecting {
+                return Ok(false);
+            }
+            let Some((start, body)) = self.snippet_trigger(lua)? else {
+                return Ok(false);
+            };
+            self.expand_snippet(&body, Some(start))?;
+            return Ok(true);
+        };
+        let current = session.current;
+        match (modifiers, code) {
+            (KMod::NONE, KCode::Tab) => {
+                self.goto_stop(current + 1);
+                return Ok(true);
+            }
+            (KMod::SHIFT, KCode::BackTab) => {
+                self.goto_stop(current.saturating_sub(1));
+                return Ok(true);
+            }
+            (KMod::NONE | KMod::SHIFT, KCode::Char(_))
+            | (KMod::NONE, KCode::Backspace | KCode::Delete) => (),
+            _ => return Ok(false),
+        }
+        // Typing at a tab stop
+        let Some(ranges) = session.ranges(&file.doc, current) else {
+            self.end_snippet();
+            return Ok(false);
+        };
+        let (start, end) = ranges[0];
+        let loc = file.doc.char_loc();
+        if loc < start || loc > end {
+            return Ok(false);
+        }
+        // Typing over a selected placeholder replaces it (in the mirrors too)
+        let selected = start != end && file.doc.selection_loc_bound() == (start, end);
+        if selected {
+            file.doc.commit();
+            for (start, end) in ranges.iter().rev() {
+                let text = text_between(&file.doc, *start, *end);
+                delete_between(&mut file.doc, *start, &text)?;
+            }
+        }
+        let Some(ranges) = session.ranges(&file.doc, current) else {
+            return Ok(false);
+        };
+        let primary = ranges[0].0;
+        if selected {
+            file.doc.move_to(&primary);
+        }
+        let loc = file.doc.char_loc();
+        // Put cursors in the same place in each of the mirrors
+        if ranges.len() > 1 && !session.mirroring {
+            let offset = loc.x.saturating_sub(primary.x);
+            file.doc.clear_cursors();
+            for (start, end) in &ranges[1..] {
+                let mirror = if loc.y == primary.y && start.y == end.y {
+                    Loc::at((start.x + offset).min(end.x), start.y)
+                } else {
+                    *start
+                };
+                file.doc.new_cursor(mirror);
+            }
+            if let Some(session) = &mut file.snippet {
+                session.mirroring = true;
+            }
+        }
+        if selected {
+            self.reload_highlight();
+        }
+        Ok(selected && !matches!(code, KCode::Char(_)))
+    }
+
+    /// Check up on the snippet being edited after an event,
+    /// finishing it once the cursor has left it
+    pub fn update_snippet(&mut self) {
+        let Some(file) = self.files.get_mut(self.ptr.clone()) else {
+            return;
+        };
+        let Some(session) = &file.snippet else {
+            return;
+        };
+        let marks = &file.doc.marks;
+        let bounds = (marks.get(session.bounds.0), marks.get(session.bounds.1));
+        let loc = file.doc.char_loc();
+        let inside = match bounds {
+            (Some(start), Some(end)) => start.loc <= loc && loc <= end.loc,
+            _ => false,
+        };
+        if !inside {
+            self.end_snippet();
+            return;
+        }
+        // Mirrors are only typed into while the cursor stays at their tab stop
+        let at_stop = session
+            .ranges(&file.doc, session.current)
+            .is_some_and(|ranges| ranges[0].0 <= loc && loc <= ranges[0].1);
+        if !at_stop {
+            self.clear_mirrors();
+        }
+    }
+}
+
+/// Get the text between two locations in a document
+fn text_between(doc: &Document, start: Loc, end: Loc) -> String {
+    let mut result = String::new();
+    for y in start.y..=end.y {
+        let line = doc.line(y).unwrap_or_default();
+        let from = if y == start.y { start.x } else { 0 };
+        let to = if y == end.y {
+            end.x
+        } else {
+            line.chars().count()
+        };
+        if y != start.y {
+            result.push('\n');
+        }
+        result.extend(line.chars().skip(from).take(to.saturating_sub(from)));
+    }
+    result
+}
+
+/// Delete text (which may span several lines) from a location in a document
+fn delete_between(doc: &mut Document, start: Loc, text: &str) -> Result<()> {
+    let mut lines = text.split('\n');
+    let first = lines.next().unwrap_or_default();
+    if !first.is_empty() {
+        doc.exe(Event::Delete(start, first.to_string()))?;
+    }
+    for line in lines {
+        doc.exe(Event::SpliceUp(start))?;
+        if !line.is_empty() {
+            doc.exe(Event::Delete(start, line.to_string()))?;
+        }
+    }
+    Ok(())
+}
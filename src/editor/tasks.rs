@@ -0,0 +1,519 @@
+/// Functions for running tasks (like building or testing a project) and finding the problems they report
+use crate::config::{ProblemMatcher, TaskDefinition};
+use crate::error::Result;
+use crate::ui::Feedback;
+use kaolinite::utils::{get_cwd, Loc};
+use mlua::{HookTriggers, Lua};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use super::finder::project_root;
+use super::results::{reset_results, show_results};
+use super::{Editor, QuickfixEntry, QuickfixList};
+
+/// The file in the folder of a project where tasks for it can be defined
+/// (a lua table of tasks, in the same form as the `tasks` table, which is read without access
+/// to anything else so that projects can't run code in the editor)
+const PROJECT_TASKS: &str = ".oxtasks";
+/// How many lua instructions a project's tasks file can take (so it can't hang the editor)
+const PROJECT_TASKS_LIMIT: u32 = 1_000_000;
+/// Where tasks defined in the configuration file come from
+const CONFIG_TASKS: &str = "config";
+/// How long a task has to finish up after being asked to stop, before it is killed outright
+const STOP_GRACE: Duration = Duration::from_secs(2);
+
+/// A problem that a task has pointed out in a file
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// The path of the file (relative to the folder the task ran in, unless it is absolute)
+    pub path: String,
+    /// Where the problem is in the file (by character index)
+    pub loc: Loc,
+    /// What the problem is
+    pub message: String,
+    /// The line of the output buffer the problem was found on
+    pub line: usize,
+}
+
+/// Where a task has got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskStatus {
+    Running,
+    /// It has been asked to stop, but hasn't finished yet
+    Stopping,
+    /// It finished, with an exit code
+    Exited(i32),
+    /// It was killed before it could finish
+    Stopped,
+}
+
+impl TaskStatus {
+    /// Whether the task may still output something
+    fn running(self) -> bool {
+        matches!(self, Self::Running | Self::Stopping)
+    }
+}
+
+/// A task that has been run, with its output shown in a buffer
+#[derive(Debug, Clone)]
+pub struct TaskRun {
+    /// The name of the task
+    pub name: String,
+    /// The folder the task is running in
+    pub root: PathBuf,
+    /// Patterns that pick out problems from the output
+    matchers: Vec<ProblemMatcher>,
+    /// Lines of output that haven't been shown yet
+    pending: Arc<Mutex<Vec<String>>>,
+    /// Whether the task is still running, and how it finished if not
+    status: Arc<Mutex<TaskStatus>>,
+    /// The program carrying out the task (until it has finished)
+    child: Arc<Mutex<Option<Child>>>,
+    /// The number of lines of output in the buffer (the first is on the second line)
+    pub lines: usize,
+    /// Problems found in the output so far
+    pub problems: Vec<Problem>,
+    /// Whether the task has finished and all its output has been shown
+    done: bool,
+}
+
+impl TaskRun {
+    /// Start running a task in the background
+    pub fn new(name: String, task: &TaskDefinition, root: &Path) -> std::io::Result<Self> {
+        let root = task.cwd.as_ref().map_or(root.to_path_buf(), |cwd| {
+            root.join(shellexpand::tilde(cwd).to_string())
+        });
+        let mut child = shell_command(&task.command)
+            .current_dir(&root)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let run = Self {
+            name,
+            root,
+            matchers: task.matchers.clone(),
+            pending: Arc::new(Mutex::new(vec![])),
+            status: Arc::new(Mutex::new(TaskStatus::Running)),
+            child: Arc::new(Mutex::new(Some(child))),
+            lines: 0,
+            problems: vec![],
+            done: false,
+        };
+        // Output stops being collected if the buffer it is shown in is closed
+        let pending = Arc::downgrade(&run.pending);
+        let status = Arc::clone(&run.status);
+        let child = Arc::clone(&run.child);
+        std::thread::spawn(move || {
+            let errors = stderr.map(|stderr| {
+                let pending = pending.clone();
+                std::thread::spawn(move || collect_output(stderr, &pending))
+            });
+            if let Some(stdout) = stdout {
+                collect_output(stdout, &pending);
+            }
+            if let Some(errors) = errors {
+                let _ = errors.join();
+            }
+            // The output has ended, so the task is finishing (unless it is being stopped)
+            let child = child.lock().unwrap().take();
+            let code = child.and_then(|mut child| child.wait().ok()?.code());
+            let mut status = status.lock().unwrap();
+            if *status == TaskStatus::Running {
+                *status = code.map_or(TaskStatus::Stopped, TaskStatus::Exited);
+            }
+        });
+        Ok(run)
+    }
+
+    /// Stop the task if it is still running (along with anything it started)
+    pub fn stop(&self) {
+        let child = self.child.lock().unwrap();
+        let Some(id) = child.as_ref().map(Child::id) else {
+            return;
+        };
+        *self.status.lock().unwrap() = TaskStatus::Stopping;
+        drop(child);
+        // It is only stopped once everything has exited, which can take a while
+        let status = Arc::clone(&self.status);
+        let child = Arc::clone(&self.child);
+        std::thread::spawn(move || {
+            terminate(id, &child);
+            *status.lock().unwrap() = TaskStatus::Stopped;
+        });
+    }
+
+    /// The first line of the output buffer
+    fn summary(&self) -> String {
+        let status = match *self.status.lock().unwrap() {
+            TaskStatus::Running => "running…".to_string(),
+            TaskStatus::Stopping => "stopping…".to_string(),
+            TaskStatus::Exited(0) => "succeeded".to_string(),
+            TaskStatus::Exited(code) => format!("failed (exit code {code})"),
+            TaskStatus::Stopped => "was stopped".to_string(),
+        };
+        let problems = match self.problems.len() {
+            0 => String::new(),
+            1 => ", 1 problem".to_string(),
+            n => format!(", {n} problems"),
+        };
+        format!("Task '{}' {status}{problems}", self.name)
+    }
+
+    /// The problems found, as a quickfix list
+    fn quickfix(&self) -> QuickfixList {
+        let entries = self
+            .problems
+            .iter()
+            .map(|problem| QuickfixEntry {
+                file: self.root.join(&problem.path).to_string_lossy().to_string(),
+                loc: problem.loc,
+                text: problem.message.clone(),
+            })
+            .collect();
+        QuickfixList::new(format!("Task '{}'", self.name), entries)
+    }
+
+    /// Look for a problem in a line of output (only counting it if the file it is in exists)
+    fn find_problem(&self, text: &str, line: usize) -> Option<Problem> {
+        self.matchers.iter().find_map(|matcher| {
+            let (path, y, x, message) = matcher.find(text)?;
+            if !self.root.join(&path).is_file() {
+                return None;
+            }
+            Some(Problem {
+                path,
+                loc: Loc::at(x.saturating_sub(1), y.saturating_sub(1)),
+                message,
+                line,
+            })
+        })
+    }
+}
+
+/// Run a command line through the shell
+fn shell_command(command: &str) -> Command {
+    let (shell, flag) = if cfg!(target_os = "windows") {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell = Command::new(shell);
+    shell.args([flag, command]);
+    // Give the task a process group of its own, so anything it starts can be stopped with it
+    #[cfg(not(target_os = "windows"))]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    shell
+}
+
+/// Stop a task's process group, killing it if it doesn't exit when asked to
+#[cfg(not(target_os = "windows"))]
+#[allow(clippy::cast_possible_wrap)]
+fn terminate(id: u32, child: &Mutex<Option<Child>>) {
+    use nix::errno::Errno;
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+    let group = Pid::from_raw(id as i32);
+    let exited = || {
+        let start = Instant::now();
+        while start.elapsed() < STOP_GRACE {
+            // Tidy up the shell if it is done (it would otherwise keep the group around)
+            if let Some(child) = child.lock().unwrap().as_mut() {
+                let _ = child.try_wait();
+            }
+            if killpg(group, None) == Err(Errno::ESRCH) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    };
+    let _ = killpg(group, Signal::SIGTERM);
+    if !exited() {
+        let _ = killpg(group, Signal::SIGKILL);
+        exited();
+    }
+}
+
+/// Stop a task, killing it outright
+#[cfg(target_os = "windows")]
+fn terminate(_: u32, child: &Mutex<Option<Child>>) {
+    if let Some(child) = child.lock().unwrap().as_mut() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Read what a task prints, line by line, passing it back to be shown
+fn collect_output(source: impl Read, pending: &Weak<Mutex<Vec<String>>>) {
+    let mut source = BufReader::new(source);
+    let mut buffer = vec![];
+    while matches!(source.read_until(b'\n', &mut buffer), Ok(1..)) {
+        let text = String::from_utf8_lossy(&buffer);
+        let text = text.trim_end_matches(['\r', '\n']);
+        // Progress bars redraw themselves over the same line, so only keep the last of it
+        let text = text.rsplit('\r').next().unwrap_or_default();
+        // Keep reading (even when the output isn't wanted) so the task doesn't get stuck
+        if let Some(pending) = pending.upgrade() {
+            pending.lock().unwrap().push(text.to_string());
+        }
+        buffer.clear();
+    }
+}
+
+/// Gather the tasks defined in the configuration and in the project's own tasks file,
+/// along with where each of them came from
+fn task_definitions(
+    lua: &Lua,
+    root: &Path,
+) -> mlua::Result<HashMap<String, (TaskDefinition, &'static str)>> {
+    let config: HashMap<String, TaskDefinition> =
+        lua.globals().get::<Option<_>>("tasks")?.unwrap_or_default();
+    let mut tasks: HashMap<String, (TaskDefinition, &'static str)> = config
+        .into_iter()
+        .map(|(name, task)| (name, (task, CONFIG_TASKS)))
+        .collect();
+    let path = root.join(PROJECT_TASKS);
+    if let Ok(code) = std::fs::read_to_string(&path) {
+        // Give up on the file if it runs for too long (e.g. it has an endless loop in it)
+        let triggers = HookTriggers::new().every_nth_instruction(PROJECT_TASKS_LIMIT);
+        lua.set_hook(triggers, |_, _| {
+            Err(mlua::Error::runtime("took too long to run"))
+        });
+        let project: mlua::Result<HashMap<String, TaskDefinition>> = lua
+            .load(code)
+            .set_name(path.to_string_lossy().to_string())
+            .set_environment(lua.create_table()?)
+            .eval();
+        lua.remove_hook();
+        // A project can't replace the user's own tasks
+        for (name, task) in project? {
+            tasks.entry(name).or_insert((task, PROJECT_TASKS));
+        }
+    }
+    Ok(tasks)
+}
+
+impl Editor {
+    /// Run a task, showing its output in a buffer (the one from the last time it ran, if open)
+    pub fn run_task(&mut self, lua: &Lua, name: &str) -> Result<()> {
+        let root = project_root(Path::new(&get_cwd().unwrap_or_default()));
+        let tasks = match task_definitions(lua, &root) {
+            Ok(tasks) => tasks,
+            Err(err) => {
+                self.feedback = Feedback::Error(format!("Failed to load tasks: {err}"));
+                return Ok(());
+            }
+        };
+        let Some((task, _)) = tasks.get(name) else {
+            self.feedback = Feedback::Error(format!("Task '{name}' is not defined"));
+            return Ok(());
+        };
+        let run = match TaskRun::new(name.to_string(), task, &root) {
+            Ok(run) => run,
+            Err(err) => {
+                self.feedback = Feedback::Error(format!("Task '{name}' failed to start: {err}"));
+                return Ok(());
+            }
+        };
+        self.last_task = Some(name.to_string());
+        // Set up the output buffer
+        let reuse = self
+            .files
+            .containers()
+            .iter()
+            .any(|fc| fc.task.as_ref().is_some_and(|task| task.name == name));
+        if !reuse {
+            self.blank()?;
+            self.next();
+        }
+        let fc = if reuse {
+            self.files
+                .containers_mut()
+                .into_iter()
+                .find(|fc| fc.task.as_ref().is_some_and(|task| task.name == name))
+                .unwrap()
+        } else {
+            self.files.get_mut(self.ptr.clone()).unwrap()
+        };
+        // Running a task again replaces the run before it
+        if let Some(old) = &fc.task {
+            old.stop();
+        }
+        reset_results(fc, format!("Task: {name}"), run.summary());
+        fc.task = Some(run);
+        self.poll_tasks();
+        Ok(())
+    }
+
+    /// List the tasks that can be run (and where they are defined) in a new buffer,
+    /// where one can be picked to run
+    pub fn list_tasks(&mut self, lua: &Lua) -> Result<()> {
+        let root = project_root(Path::new(&get_cwd().unwrap_or_default()));
+        let tasks = match task_definitions(lua, &root) {
+            Ok(tasks) => tasks,
+            Err(err) => {
+                self.feedback = Feedback::Error(format!("Failed to load tasks: {err}"));
+                return Ok(());
+            }
+        };
+        let mut names: Vec<String> = tasks.keys().cloned().collect();
+        names.sort();
+        let lines = names
+            .iter()
+            .map(|name| {
+                let (task, source) = &tasks[name];
+                format!("{name} ({source}): {}", task.command)
+            })
+            .collect();
+        let summary = match names.len() {
+            0 => "No tasks are defined".to_string(),
+            1 => "1 task, press enter on it to run it".to_string(),
+            n => format!("{n} tasks, press enter on one to run it"),
+        };
+        self.blank()?;
+        self.next();
+        let fc = self.files.get_mut(self.ptr.clone()).unwrap();
+        reset_results(fc, "Tasks".to_string(), summary.clone());
+        show_results(&mut fc.doc, &mut fc.highlighter, 1, lines, summary);
+        fc.tasks = Some(names);
+        Ok(())
+    }
+
+    /// Run the task under the cursor in a list of tasks (returns false if not in one)
+    pub fn pick_task(&mut self, lua: &Lua) -> Result<bool> {
+        let Some(fc) = self.files.get(self.ptr.clone()) else {
+            return Ok(false);
+        };
+        let Some(tasks) = &fc.tasks else {
+            return Ok(false);
+        };
+        let y = fc.doc.loc().y;
+        let Some(name) = y.checked_sub(1).and_then(|y| tasks.get(y)).cloned() else {
+            return Ok(true);
+        };
+        self.run_task(lua, &name)?;
+        Ok(true)
+    }
+
+    /// Run the task that was run last again
+    pub fn rerun_task(&mut self, lua: &Lua) -> Result<()> {
+        if let Some(name) = self.last_task.clone() {
+            self.run_task(lua, &name)
+        } else {
+            self.feedback = Feedback::Warning("No task has been run yet".to_string());
+            Ok(())
+        }
+    }
+
+    /// Show output from tasks since last time, returning true if there was any
+    pub fn poll_tasks(&mut self) -> bool {
+        let mut changed = false;
+        let mut finished = vec![];
+        for fc in self.files.containers_mut() {
+            let Some(run) = &mut fc.task else {
+                continue;
+            };
+            let status = *run.status.lock().unwrap();
+            let output: Vec<String> = run.pending.lock().unwrap().drain(..).collect();
+            if output.is_empty() && (status.running() || run.done) {
+                continue;
+            }
+            let start = run.lines + 1;
+            for (y, text) in (start..).zip(&output) {
+                if let Some(problem) = run.find_problem(text, y) {
+                    run.problems.push(problem);
+                }
+            }
+            run.lines += output.len();
+            show_results(
+                &mut fc.doc,
+                &mut fc.highlighter,
+                start,
+                output,
+                run.summary(),
+            );
+            // Let the user know how the task went once it has finished
+            if !status.running() {
+                finished.push((
+                    run.summary(),
+                    status == TaskStatus::Exited(0),
+                    run.quickfix(),
+                ));
+                run.done = true;
+            }
+            changed = true;
+        }
+        if let Some((summary, succeeded, problems)) = finished.pop() {
+            self.set_quickfix(problems);
+            self.feedback = if succeeded {
+                Feedback::Info(summary)
+            } else {
+                Feedback::Error(summary)
+            };
+        }
+        changed
+    }
+
+    /// Open the problem under the cursor in a task's output (returns false if not in one)
+    pub fn jump_to_problem(&mut self) -> Result<bool> {
+        let Some(fc) = self.files.get(self.ptr.clone()) else {
+            return Ok(false);
+        };
+        let Some(run) = &fc.task else {
+            return Ok(false);
+        };
+        let y = fc.doc.loc().y;
+        let Some(problem) = run.problems.iter().find(|p| p.line == y).cloned() else {
+            return Ok(true);
+        };
+        self.open_result(&run.root.join(&problem.path), problem.loc, 0)?;
+        self.feedback = Feedback::Info(problem.message);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(target_os = "windows"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopping_kills_everything_the_task_started() {
+        let task = TaskDefinition {
+            command: "sleep 30; true".to_string(),
+            cwd: None,
+            matchers: vec![],
+        };
+        let run = TaskRun::new("sleep".to_string(), &task, Path::new(".")).unwrap();
+        let group = run.child.lock().unwrap().as_ref().unwrap().id().to_string();
+        // Wait for the shell to start the sleep
+        let find_sleep = || {
+            let found = Command::new("pgrep")
+                .args(["-g", &group, "sleep"])
+                .output()
+                .unwrap();
+            String::from_utf8_lossy(&found.stdout).trim().to_string()
+        };
+        let start = Instant::now();
+        while find_sleep().is_empty() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "sleep never started"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        run.stop();
+        let start = Instant::now();
+        while *run.status.lock().unwrap() != TaskStatus::Stopped {
+            assert!(start.elapsed() < STOP_GRACE * 3, "task never stopped");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(find_sleep(), "");
+    }
+}
@@ -11,6 +11,11 @@ error_set! {
                 KError::NoFileName => "This document has no file name, please use 'save as' instead".to_string(),
                 KError::OutOfRange => "Requested operation is out of range".to_string(),
                 KError::ReadOnlyFile => "This file is read only and can't be saved or edited".to_string(),
+                KError::UnknownLineEnding => "Unknown line ending, expected lf, crlf or cr".to_string(),
+                KError::UnknownEncoding => "Unknown encoding, expected utf-8, utf-16le, utf-16be, latin-1 or windows-1252".to_string(),
+                KError::LossyFile => "This file couldn't be fully decoded, saving it would lose data".to_string(),
+                KError::CannotEncode => "This document contains characters that can't be saved in its encoding".to_string(),
+                KError::LanguageServer { message } => format!("Language server error: {message}"),
                 KError::Rope(rerr) => format!("Backend had an issue processing text: {rerr}"),
                 KError::Io(ioerr) => format!("I/O Error: {ioerr}"),
             }
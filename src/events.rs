@@ -1,30 +1,149 @@
-use crate::{ged, handle_lua_error, CEvent, Editor, Feedback, KeyEvent, KeyEventKind, Result};
+use crate::config::get_listeners;
+#[cfg(not(target_os = "windows"))]
+use crate::pty::terminal_output;
+use crate::{
+    config, ged, handle_lua_error, CEvent, Editor, Feedback, KeyEvent, KeyEventKind, Result,
+};
 use crossterm::event::{poll, read};
-use mlua::{AnyUserData, Lua};
+use mlua::{AnyUserData, Lua, MultiValue};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
 use std::time::Duration;
 
-#[allow(unused_variables)]
-pub fn term_force(editor: &AnyUserData) -> bool {
-    #[cfg(not(target_os = "windows"))]
-    return ged!(mut &editor).files.terminal_rerender();
-    #[cfg(target_os = "windows")]
-    return false;
+/// How often (in seconds) to check for files being changed by other programs
+const EXTERNAL_CHECK_PERIOD: usize = 3;
+/// How long to wait for something to happen before seeing to background work anyway
+const TICK: Duration = Duration::from_millis(50);
+
+/// Something that wakes the editor up while it is waiting
+enum Wakeup {
+    /// An event from the terminal the editor is running in (a key press, a resize, etc)
+    Input(CEvent),
+    /// Something happened in the background that should be shown (e.g. terminal output)
+    Background,
+}
+
+/// Everything that can wake the editor up comes through here
+struct Wakeups {
+    sender: Sender<Wakeup>,
+    receiver: Mutex<Receiver<Wakeup>>,
+    /// An input event that arrived while waiting for background work
+    held: Mutex<Option<CEvent>>,
+    /// Lets the terminal event reader be paused
+    reader: Arc<(Mutex<ReaderState>, Condvar)>,
+}
+
+/// What the terminal event reader is up to
+#[derive(Default)]
+struct ReaderState {
+    /// Another program is using the terminal, so events shouldn't be taken from it
+    paused: bool,
+    /// The reader is in the middle of waiting for an event
+    reading: bool,
+}
+
+/// Get hold of the wakeups (terminal events are read on their own thread from the first use,
+/// so that other threads are able to wake the editor up too)
+fn wakeups() -> &'static Wakeups {
+    static WAKEUPS: OnceLock<Wakeups> = OnceLock::new();
+    WAKEUPS.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        let input = sender.clone();
+        let reader = Arc::new((Mutex::new(ReaderState::default()), Condvar::new()));
+        let gate = Arc::clone(&reader);
+        std::thread::spawn(move || loop {
+            // Leave the terminal alone while another program is using it
+            let (state, changed) = &*gate;
+            let mut lock = changed
+                .wait_while(state.lock().unwrap(), |s| s.paused)
+                .unwrap();
+            lock.reading = true;
+            drop(lock);
+            // Only wait for a short while at a time, so pausing doesn't take long
+            let event = match poll(TICK) {
+                Ok(true) => read().map(Some),
+                Ok(false) => Ok(None),
+                Err(err) => Err(err),
+            };
+            state.lock().unwrap().reading = false;
+            changed.notify_all();
+            match event {
+                Ok(Some(event)) => {
+                    if input.send(Wakeup::Input(event)).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => (),
+                Err(_) => break,
+            }
+        });
+        Wakeups {
+            sender,
+            receiver: Mutex::new(receiver),
+            held: Mutex::new(None),
+            reader,
+        }
+    })
+}
+
+/// Wake the editor up from another thread, so it can show something new straight away
+#[cfg(not(target_os = "windows"))]
+pub fn wake() {
+    let _ = wakeups().sender.send(Wakeup::Background);
+}
+
+/// Stop reading terminal events (until `resume_input` is called),
+/// so that a program running in the foreground gets the key presses meant for it
+pub fn pause_input() {
+    let (state, changed) = &*wakeups().reader;
+    let mut lock = state.lock().unwrap();
+    lock.paused = true;
+    drop(changed.wait_while(lock, |s| s.reading).unwrap());
+}
+
+/// Carry on reading terminal events after `pause_input`
+pub fn resume_input() {
+    let (state, changed) = &*wakeups().reader;
+    state.lock().unwrap().paused = false;
+    changed.notify_all();
+}
+
+/// Pause reading terminal events while plug-ins run commands through `os.execute`
+/// (such as `shell:run`), as the commands may want to read from the terminal themselves
+pub fn pause_input_for_commands(lua: &Lua) -> mlua::Result<()> {
+    let os: mlua::Table = lua.globals().get("os")?;
+    let execute: mlua::Function = os.get("execute")?;
+    let wrapped = lua.create_function(move |_, args: MultiValue| {
+        pause_input();
+        let result = execute.call::<MultiValue>(args);
+        resume_input();
+        result
+    })?;
+    os.set("execute", wrapped)
 }
 
 pub fn mm_active(editor: &AnyUserData) -> bool {
     ged!(mut &editor).macro_man.playing
 }
 
-/// (should hold event, triggered by term force?)
-pub fn hold_event(editor: &AnyUserData) -> (bool, bool) {
-    let tf = term_force(editor);
-    (
-        matches!(
-            (mm_active(editor), tf, poll(Duration::from_millis(50))),
-            (false, false, Ok(false))
-        ),
-        !tf,
-    )
+/// Should hold event (no event has arrived and a macro isn't playing).
+/// This waits until an event arrives, something is woken up for, or a tick has passed.
+pub fn hold_event(editor: &AnyUserData) -> bool {
+    if mm_active(editor) {
+        return false;
+    }
+    let wakeups = wakeups();
+    let mut held = wakeups.held.lock().unwrap();
+    if held.is_some() {
+        return false;
+    }
+    match wakeups.receiver.lock().unwrap().recv_timeout(TICK) {
+        Ok(Wakeup::Input(event)) => {
+            *held = Some(event);
+            false
+        }
+        _ => true,
+    }
 }
 
 #[allow(unused_variables)]
@@ -32,7 +151,7 @@ pub fn wait_for_event(editor: &AnyUserData, lua: &Lua) -> Result<CEvent> {
     loop {
         // While waiting for an event to come along, service the task manager
         if !mm_active(editor) {
-            while let (true, was_term) = hold_event(editor) {
+            while hold_event(editor) {
                 let exec = ged!(mut &editor)
                     .config
                     .task_manager
@@ -48,9 +167,35 @@ pub fn wait_for_event(editor: &AnyUserData, lua: &Lua) -> Result<CEvent> {
                             Feedback::Warning(format!("Function '{task}' was not found"));
                     }
                 }
-                // If a terminal dictates, force a rerender
+                // Periodically write out swap files for unsaved changes
+                let swap_period = config!(ged!(&editor).config, document).swap_period;
+                let swap_timer = ged!(&editor).config.task_manager.lock().unwrap().swap_timer;
+                if swap_period != 0 && swap_timer >= swap_period {
+                    ged!(&editor).config.task_manager.lock().unwrap().swap_timer = 0;
+                    ged!(mut &editor).write_swaps();
+                }
+                // Every so often, check for files that have been changed by other programs
+                let disk_timer = ged!(&editor).config.task_manager.lock().unwrap().disk_timer;
+                if disk_timer >= EXTERNAL_CHECK_PERIOD {
+                    ged!(&editor).config.task_manager.lock().unwrap().disk_timer = 0;
+                    check_external_changes(editor, lua);
+                    // Keep the line counts of huge files that are still being indexed up to date
+                    if ged!(&editor).indexing() {
+                        ged!(mut &editor).needs_rerender = true;
+                        ged!(mut &editor).render(lua)?;
+                    }
+                }
+                // Keep language servers up to date, and show anything new they have to say
+                ged!(mut &editor).sync_language_servers();
+                let found = ged!(mut &editor).poll_project_search();
+                let ran = ged!(mut &editor).poll_tasks();
+                if ged!(mut &editor).poll_language_servers() || found || ran {
+                    ged!(mut &editor).needs_rerender = true;
+                    ged!(mut &editor).render(lua)?;
+                }
+                // Show anything new that programs running in terminals have output
                 #[cfg(not(target_os = "windows"))]
-                if was_term {
+                if terminal_output() {
                     ged!(mut &editor).needs_rerender = true;
                     ged!(mut &editor).render(lua)?;
                 }
@@ -76,6 +221,25 @@ pub fn wait_for_event(editor: &AnyUserData, lua: &Lua) -> Result<CEvent> {
     }
 }
 
+/// Reload files that have been changed by other programs, and notify plug-ins
+pub fn check_external_changes(editor: &AnyUserData, lua: &Lua) {
+    let changed = ged!(mut &editor).check_external_changes();
+    if changed.is_empty() {
+        return;
+    }
+    ged!(mut &editor).needs_rerender = true;
+    let listeners = get_listeners("on_external_change", lua).unwrap_or_default();
+    for file in changed {
+        for listener in &listeners {
+            handle_lua_error(
+                "on_external_change",
+                listener.call(file.clone()),
+                &mut ged!(mut &editor).feedback,
+            );
+        }
+    }
+}
+
 /// Wait for event, but without the task manager (and it hogs editor)
 pub fn wait_for_event_hog(editor: &mut Editor) -> CEvent {
     loop {
@@ -103,14 +267,15 @@ pub fn get_event(editor: &mut Editor) -> Option<CEvent> {
     if let Some(ev) = editor.macro_man.next() {
         // Take from macro man
         Some(ev)
-    } else if let Ok(true) = poll(Duration::from_millis(50)) {
-        if let Ok(ev) = read() {
-            // Use standard crossterm event
-            Some(ev)
-        } else {
-            None
-        }
     } else {
-        None
+        // Use standard crossterm event (one that was already received first)
+        let wakeups = wakeups();
+        if let Some(ev) = wakeups.held.lock().unwrap().take() {
+            return Some(ev);
+        }
+        match wakeups.receiver.lock().unwrap().recv_timeout(TICK) {
+            Ok(Wakeup::Input(ev)) => Some(ev),
+            _ => None,
+        }
     }
 }
@@ -15,9 +15,11 @@ use config::{
     PLUGIN_MANAGER, PLUGIN_NETWORKING, PLUGIN_RUN,
 };
 use crossterm::event::{Event as CEvent, KeyEvent, KeyEventKind};
-use editor::{allowed_by_multi_cursor, handle_multiple_cursors, Editor, FileTypes};
+use editor::{
+    allowed_by_multi_cursor, handle_multiple_cursors, update_completion, Editor, FileTypes,
+};
 use error::{OxError, Result};
-use events::wait_for_event;
+use events::{check_external_changes, pause_input_for_commands, wait_for_event};
 use kaolinite::event::{Error as KError, Event};
 use kaolinite::searching::Searcher;
 use kaolinite::utils::{file_or_dir, get_cwd};
@@ -76,6 +78,7 @@ fn run(cli: &CommandLineInterface) -> Result<()> {
     // Push editor into lua
     let editor = lua.create_userdata(editor)?;
     lua.globals().set("editor", editor.clone())?;
+    pause_input_for_commands(&lua)?;
 
     // Inject the networking library for plug-ins to use
     handle_lua_error(
@@ -125,12 +128,14 @@ fn run(cli: &CommandLineInterface) -> Result<()> {
         // Open the file
         let result = ged!(mut &editor).open_or_new(file.to_string());
         handle_file_opening(&editor, result, file);
-        // Set read only if applicable
-        if cli.flags.read_only {
-            ged!(mut &editor).get_doc(c).info.read_only = true;
-        }
-        // Set highlighter if applicable
-        if let Some(ref file_type) = cli.file_type {
+        // Set highlighter if applicable (or view in hex)
+        if cli
+            .file_type
+            .as_deref()
+            .is_some_and(|ft| ft.eq_ignore_ascii_case("hex"))
+        {
+            ged!(mut &editor).convert_to_hex(c);
+        } else if let Some(ref file_type) = cli.file_type {
             let tab_width = config!(ged!(&editor).config, document).tab_width;
             let file_type = config!(ged!(mut &editor).config, document)
                 .file_types
@@ -144,6 +149,16 @@ fn run(cli: &CommandLineInterface) -> Result<()> {
             file.highlighter = highlighter;
             file.file_type = Some(file_type);
         }
+        // Set read only if applicable (including when viewed in hex)
+        if cli.flags.read_only {
+            let mut editor = ged!(mut &editor);
+            let current_ptr = editor.ptr.clone();
+            let file = &mut editor.files.get_atom_mut(current_ptr).unwrap().0[c];
+            file.doc.info.read_only = true;
+            if let Some(hex) = &mut file.hex {
+                hex.read_only = true;
+            }
+        }
         // Move the pointer to the file we just created
         ged!(mut &editor).next();
     }
@@ -181,6 +196,7 @@ fn run(cli: &CommandLineInterface) -> Result<()> {
 
     // Run the editor and handle errors if applicable
     ged!(&editor).update_cwd();
+    ged!(mut &editor).load_query_history();
     ged!(mut &editor).init()?;
     while ged!(&editor).active {
         // Render (unless a macro is being played, in which case, don't bother)
@@ -188,24 +204,46 @@ fn run(cli: &CommandLineInterface) -> Result<()> {
             ged!(mut &editor).render(&lua)?;
         }
 
+        // Offer to recover unsaved changes from a previous session
+        ged!(mut &editor).recovery_prompt(&lua)?;
+        // Ask what to do with files changed by other programs
+        ged!(mut &editor).external_change_prompt(&lua)?;
+
         // Wait for an event
         let event = wait_for_event(&editor, &lua)?;
 
-        // Handle the event
-        let original_loc = ged!(&editor)
-            .try_doc()
-            .map(Document::char_loc)
-            .unwrap_or_default();
-        handle_event(&editor, &event, &lua)?;
+        // Let the completion popup and snippets use the keys meant for them
+        let used = match event {
+            CEvent::Key(key) => {
+                ged!(mut &editor).completion_key(key.modifiers, key.code)?
+                    || ged!(mut &editor).snippet_key(&lua, key.modifiers, key.code)?
+            }
+            _ => false,
+        };
 
-        // Handle multi cursors
-        if let CEvent::Key(_) = event {
-            let has_multicursors = !ged!(&editor)
+        if !used {
+            // Handle the event
+            let original_loc = ged!(&editor)
                 .try_doc()
-                .map_or(true, |doc| doc.secondary_cursors.is_empty());
-            if ged!(&editor).active && allowed_by_multi_cursor(&event) && has_multicursors {
-                handle_multiple_cursors(&editor, &event, &lua, &original_loc)?;
+                .map(Document::char_loc)
+                .unwrap_or_default();
+            handle_event(&editor, &event, &lua)?;
+
+            // Handle multi cursors
+            if let CEvent::Key(_) = event {
+                let has_multicursors = !ged!(&editor)
+                    .try_doc()
+                    .map_or(true, |doc| doc.secondary_cursors.is_empty());
+                if ged!(&editor).active && allowed_by_multi_cursor(&event) && has_multicursors {
+                    handle_multiple_cursors(&editor, &event, &lua, &original_loc)?;
+                }
             }
+
+            // Finish off snippets the cursor has left
+            ged!(mut &editor).update_snippet();
+
+            // Open, filter or close the completion popup
+            update_completion(&editor, &event, &lua);
         }
 
         ged!(mut &editor).update_highlighter();
@@ -221,6 +259,7 @@ fn run(cli: &CommandLineInterface) -> Result<()> {
     // Run any plugin cleanup operations
     let result = lua.load(run_key("exit")).exec();
     handle_lua_error("exit", result, &mut ged!(mut &editor).feedback);
+    ged!(mut &editor).stop_language_servers();
 
     ged!(mut &editor).terminal.end()?;
     Ok(())
@@ -241,6 +280,11 @@ fn handle_event(editor: &AnyUserData, event: &CEvent, lua: &Lua) -> Result<()> {
         handle_lua_error(&key_str, result, &mut ged!(mut &editor).feedback);
     }
 
+    // Check for files being changed by other programs when ox is switched back to
+    if let CEvent::FocusGained = event {
+        check_external_changes(editor, lua);
+    }
+
     // Handle paste event (before event)
     if let CEvent::Paste(ref paste_text) = event {
         let listeners = get_listeners("before:paste", lua)?;
@@ -254,6 +298,7 @@ fn handle_event(editor: &AnyUserData, event: &CEvent, lua: &Lua) -> Result<()> {
     }
 
     // Actually handle editor event (errors included)
+    let focus = ged!(&editor).focus();
     let event_result = ged!(mut &editor).handle_event(lua, event.clone());
     if let Err(err) = event_result {
         // Nicely display error to user
@@ -282,7 +327,9 @@ fn handle_event(editor: &AnyUserData, event: &CEvent, lua: &Lua) -> Result<()> {
     }
 
     // Handle plug-in after key press mappings (if no errors occured)
-    if let CEvent::Key(key) = event {
+    // Key presses that moved to another document (like opening a search match) are dealt with
+    let moved = ged!(&editor).focus() != focus;
+    if let (CEvent::Key(key), false) = (event, moved) {
         let key_str = key_to_string(key.modifiers, key.code);
         let code = run_key(&key_str);
         let result = lua.load(&code).exec();
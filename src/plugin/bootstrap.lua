@@ -36,6 +36,13 @@ plugins = {}
 builtins = {}
 plugin_issues = false
 
+-- Sources of completions, functions that take what is being typed and return a list of
+-- suggestions (either text, or tables with text, label, detail and documentation fields)
+completion_sources = {}
+
+-- Snippets, by file type and then by the text that triggers them (expanded with tab)
+snippets = {}
+
 function load_plugin(base)
     path_cross = base
     path_unix = home .. "/.config/ox/" .. base
@@ -158,6 +165,9 @@ function shell:kill(pid)
     end
 end
 
+-- Tasks (like building, testing or linting), by name
+tasks = {}
+
 -- Behaviour for compiling / running projects
 runner = {
     ["Rust"] = {
@@ -176,6 +186,7 @@ runner = {
 
 -- Add types for built-in file type detection
 -- Colours are in the format of a string of:
+-- language_server is the command that starts a language server for the file type (if any)
 file_types = {
     ["ABAP"] = {
         icon = "󰅩 ",
@@ -267,6 +278,7 @@ file_types = {
         extensions = {"c"},
         modelines = {},
         color = "lightblue",
+        language_server = "clangd",
     },
     ["CMake"] = {
         icon = " ",
@@ -323,6 +335,7 @@ file_types = {
         extensions = {"cpp", "cxx"},
         modelines = {},
         color = "darkblue",
+        language_server = "clangd",
     },
     ["C#"] = {
         icon = " ",
@@ -358,6 +371,7 @@ file_types = {
         extensions = {"dart"},
         modelines = {},
         color = "lightblue",
+        language_server = "dart language-server",
     },
     ["Diff"] = {
         icon = " ",
@@ -379,6 +393,7 @@ file_types = {
         extensions = {"ex", "exs"},
         modelines = {},
         color = "purple",
+        language_server = "elixir-ls",
     },
     ["Elm"] = {
         icon = " ",
@@ -470,6 +485,7 @@ file_types = {
         extensions = {"go"},
         modelines = {},
         color = "lightblue",
+        language_server = "gopls",
     },
     ["Groovy"] = {
         icon = " ",
@@ -491,6 +507,7 @@ file_types = {
         extensions = {"h"},
         modelines = {},
         color = "lightblue",
+        language_server = "clangd",
     },
     ["Haml"] = {
         icon = "",
@@ -512,6 +529,7 @@ file_types = {
         extensions = {"hs"},
         modelines = {},
         color = "purple",
+        language_server = "haskell-language-server-wrapper --lsp",
     },
     ["C++ Header"] = {
         icon = " ",
@@ -519,6 +537,7 @@ file_types = {
         extensions = {"hpp"},
         modelines = {},
         color = "darkblue",
+        language_server = "clangd",
     },
     ["HTML"] = {
         icon = " ",
@@ -561,6 +580,7 @@ file_types = {
         extensions = {"jsx"},
         modelines = {},
         color = "lightblue",
+        language_server = "typescript-language-server --stdio",
     },
     ["JavaScript"] = {
         icon = " ",
@@ -568,6 +588,7 @@ file_types = {
         extensions = {"js"},
         modelines = {"#!\\s*/usr/bin/(env )?node"},
         color = "yellow",
+        language_server = "typescript-language-server --stdio",
     },
     ["Julia"] = {
         icon = " ",
@@ -603,6 +624,7 @@ file_types = {
         extensions = {"lua"},
         modelines = {"#!\\s*/usr/bin/(env )?lua"},
         color = "darkblue",
+        language_server = "lua-language-server",
     },
     ["LiveScript"] = {
         icon = " ",
@@ -673,6 +695,7 @@ file_types = {
         extensions = {"ml"},
         modelines = {},
         color = "orange",
+        language_server = "ocamllsp",
     },
     ["Makefile"] = {
         icon = " ",
@@ -694,6 +717,7 @@ file_types = {
         extensions = {"nix"},
         modelines = {},
         color = "lightblue",
+        language_server = "nil",
     },
     ["NumPy"] = {
         icon = "󰘨 ",
@@ -750,6 +774,7 @@ file_types = {
         extensions = {"py", "pyw"},
         modelines = {"#!\\s*/usr/bin/(env )?python3?"},
         color = "lightblue",
+        language_server = "pylsp",
     },
     ["Cython"] = {
         icon = " ",
@@ -785,6 +810,7 @@ file_types = {
         extensions = {"rb", "ruby"},
         modelines = {"#!\\s*/usr/bin/(env )?ruby"},
         color = "red",
+        language_server = "solargraph stdio",
     },
     ["Rust"] = {
         icon = " ",
@@ -792,6 +818,7 @@ file_types = {
         extensions = {"rs"},
         modelines = {"#!\\s*/usr/bin/(env )?rust"},
         color = "orange",
+        language_server = "rust-analyzer",
     },
     ["Shell"] = {
         icon = " ",
@@ -879,6 +906,7 @@ file_types = {
         extensions = {"ts", "tsx"},
         modelines = {},
         color = "darkblue",
+        language_server = "typescript-language-server --stdio",
     },
     ["Plain Text"] = {
         icon = " ",
@@ -1,189 +1,395 @@
 //! User friendly interface for dealing with pseudo terminals
 
+use crate::events::wake;
+use crossterm::event::{KeyCode as KCode, KeyModifiers as KMod};
+use kaolinite::terminal::Screen;
 use mio::unix::SourceFd;
-use mio::{Events, Interest, Poll, Token};
+use mio::{Events, Interest, Poll, Registry, Token};
 use mlua::prelude::*;
 use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use ptyprocess::PtyProcess;
-use std::io::{BufReader, Read, Result, Write};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
 use std::os::unix::io::AsRawFd;
 use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
 
 #[derive(Debug)]
 pub struct Pty {
     pub process: PtyProcess,
-    pub output: String,
-    pub input: String,
-    pub shell: Shell,
-    pub force_rerender: bool,
+    pub screen: Screen,
+    /// Handle for talking to the program
+    stream: File,
+    /// Input that the program wasn't ready to receive yet
+    pending_input: Vec<u8>,
+    /// What is sent to the program when enter is pressed
+    newline: String,
+    /// Identifies this terminal to the reactor
+    token: Token,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum Shell {
-    Bash,
-    Dash,
-    Zsh,
-    Fish,
+/// A program to run in a terminal, and how to talk to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// The program to run
+    pub command: String,
+    /// Arguments given to the program
+    pub args: Vec<String>,
+    /// Extra environment variables for the program
+    pub env: BTreeMap<String, String>,
+    /// The folder to run the program in (the editor's own folder if not given)
+    pub cwd: Option<String>,
+    /// Whether what is typed is echoed back by the terminal
+    pub echo: bool,
+    /// What is sent to the program when enter is pressed
+    pub newline: String,
 }
 
-impl Shell {
-    pub fn manual_input_echo(self) -> bool {
-        matches!(self, Self::Bash | Self::Dash)
+impl Default for Program {
+    fn default() -> Self {
+        Self::from_command("bash")
     }
+}
 
-    pub fn inserts_extra_newline(self) -> bool {
-        !matches!(self, Self::Zsh)
+impl Program {
+    /// Describe a program from a command line (the program followed by its arguments)
+    pub fn from_command(command: &str) -> Self {
+        let mut words = command.split_whitespace().map(str::to_string);
+        Self {
+            command: words.next().unwrap_or_default(),
+            args: words.collect(),
+            env: BTreeMap::new(),
+            cwd: None,
+            echo: true,
+            newline: "\r".to_string(),
+        }
     }
 
-    pub fn command(&self) -> &str {
-        match self {
-            Self::Bash => "bash",
-            Self::Dash => "dash",
-            Self::Zsh => "zsh",
-            Self::Fish => "fish",
-        }
+    /// Whether this program can be described by just a command line
+    fn is_plain(&self) -> bool {
+        let plain = Self::from_command(&self.command);
+        self.env.is_empty()
+            && self.cwd.is_none()
+            && self.echo == plain.echo
+            && self.newline == plain.newline
+            && !self.command.contains(char::is_whitespace)
+            && !self
+                .args
+                .iter()
+                .any(|arg| arg.contains(char::is_whitespace))
     }
 }
 
-impl IntoLua for Shell {
+impl IntoLua for Program {
     fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
-        let string = lua.create_string(self.command())?;
-        Ok(LuaValue::String(string))
+        // Plain programs are given back as they are usually set, as a command line
+        if self.is_plain() {
+            let line = std::iter::once(self.command)
+                .chain(self.args)
+                .collect::<Vec<_>>();
+            return Ok(LuaValue::String(lua.create_string(line.join(" "))?));
+        }
+        let table = lua.create_table()?;
+        table.set("command", self.command)?;
+        table.set("args", self.args)?;
+        table.set("env", self.env)?;
+        table.set("cwd", self.cwd)?;
+        table.set("echo", self.echo)?;
+        table.set("newline", self.newline)?;
+        Ok(LuaValue::Table(table))
     }
 }
 
-impl FromLua for Shell {
+impl FromLua for Program {
     fn from_lua(val: LuaValue, _: &Lua) -> LuaResult<Self> {
-        Ok(if let LuaValue::String(inner) = val {
-            if let Ok(s) = inner.to_str() {
-                match s.to_owned().as_str() {
-                    "dash" => Self::Dash,
-                    "zsh" => Self::Zsh,
-                    "fish" => Self::Fish,
-                    _ => Self::Bash,
-                }
-            } else {
-                Self::Bash
+        match val {
+            LuaValue::String(command) => Ok(Self::from_command(&command.to_str()?)),
+            LuaValue::Table(table) => {
+                let command: String = table.get("command")?;
+                let default = Self::from_command(&command);
+                Ok(Self {
+                    args: table.get::<Option<_>>("args")?.unwrap_or(default.args),
+                    env: table.get::<Option<_>>("env")?.unwrap_or_default(),
+                    cwd: table.get("cwd")?,
+                    echo: table.get::<Option<_>>("echo")?.unwrap_or(default.echo),
+                    newline: table
+                        .get::<Option<_>>("newline")?
+                        .unwrap_or(default.newline),
+                    command: default.command,
+                })
             }
-        } else {
-            Self::Bash
+            _ => Err(LuaError::FromLuaConversionError {
+                from: val.type_name(),
+                to: "Program".to_string(),
+                message: Some("expected a command or a table describing a program".to_string()),
+            }),
+        }
+    }
+}
+
+/// Terminals that are being watched for output, by their token
+static TERMINALS: Mutex<BTreeMap<Token, Weak<Mutex<Pty>>>> = Mutex::new(BTreeMap::new());
+/// The token to give to the next terminal that is opened
+static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(0);
+/// Set when any terminal has new output that needs to be shown
+static OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Where terminals are registered so their output is read as soon as it arrives
+/// (a single thread waits on every terminal at once, started when the first one is opened)
+fn registry() -> Result<&'static Registry> {
+    static REGISTRY: OnceLock<Option<Registry>> = OnceLock::new();
+    REGISTRY
+        .get_or_init(|| {
+            let poll = Poll::new().ok()?;
+            let registry = poll.registry().try_clone().ok()?;
+            std::thread::spawn(move || react(poll));
+            Some(registry)
         })
+        .as_ref()
+        .ok_or_else(|| Error::other("Unable to watch terminals for output"))
+}
+
+/// Wait for terminals to output something (or be ready for more input) and deal with it
+fn react(mut poll: Poll) {
+    let mut events = Events::with_capacity(128);
+    loop {
+        if let Err(e) = poll.poll(&mut events, None) {
+            if e.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+        for event in &events {
+            let term = TERMINALS
+                .lock()
+                .unwrap()
+                .get(&event.token())
+                .and_then(Weak::upgrade);
+            let Some(term) = term else {
+                continue;
+            };
+            let mut term = term.lock().unwrap();
+            if event.is_writable() {
+                let _ = term.flush_input();
+            }
+            // Only wake the editor if it doesn't already know there's something to show
+            if (event.is_readable() || event.is_read_closed())
+                && term.read_output()
+                && !OUTPUT.swap(true, Ordering::AcqRel)
+            {
+                wake();
+            }
+        }
     }
 }
 
+/// Find out if any terminal has output something new since this was last asked
+pub fn terminal_output() -> bool {
+    OUTPUT.swap(false, Ordering::AcqRel)
+}
+
 impl Pty {
-    pub fn new(shell: Shell) -> Result<Arc<Mutex<Self>>> {
+    pub fn new(program: &Program) -> Result<Arc<Mutex<Self>>> {
+        let screen = Screen::new(80, 24);
+        let mut command = Command::new(&program.command);
+        command
+            .args(&program.args)
+            .env("TERM", "xterm-256color")
+            .envs(&program.env);
+        if let Some(cwd) = &program.cwd {
+            command.current_dir(shellexpand::tilde(cwd).to_string());
+        }
+        let mut process = PtyProcess::spawn(command)?;
+        process.set_echo(program.echo, None)?;
+        process.set_window_size(80, 24)?;
+        // Reading and writing never waits, the reactor says when the terminal is ready
+        let stream = process.get_raw_handle()?;
+        let raw_fd = stream.as_raw_fd();
+        let flags = fcntl(raw_fd, FcntlArg::F_GETFL)?;
+        fcntl(
+            raw_fd,
+            FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK),
+        )?;
+        let token = Token(NEXT_TOKEN.fetch_add(1, Ordering::Relaxed));
         let pty = Arc::new(Mutex::new(Self {
-            process: PtyProcess::spawn(Command::new(shell.command()))?,
-            output: String::new(),
-            input: String::new(),
-            shell,
-            force_rerender: false,
+            process,
+            screen,
+            stream,
+            pending_input: vec![],
+            newline: program.newline.clone(),
+            token,
         }));
-        pty.lock().unwrap().process.set_echo(false, None)?;
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        pty.lock().unwrap().run_command("")?;
-        // Spawn thread to constantly read from the terminal
-        let pty_clone = Arc::clone(&pty);
-        std::thread::spawn(move || loop {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            let mut pty = pty_clone.lock().unwrap();
-            pty.force_rerender = matches!(pty.catch_up(), Ok(true));
-            std::mem::drop(pty);
-        });
-        // Return the pty
+        // Start watching for output
+        TERMINALS
+            .lock()
+            .unwrap()
+            .insert(token, Arc::downgrade(&pty));
+        registry()?.register(
+            &mut SourceFd(&raw_fd),
+            token,
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
         Ok(pty)
     }
 
-    pub fn run_command(&mut self, cmd: &str) -> Result<()> {
-        let mut stream = self.process.get_raw_handle()?;
-        // Write the command
-        write!(stream, "{cmd}")?;
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        if self.shell.manual_input_echo() {
-            // println!("Adding (pre-cmd) {:?}", cmd);
-            self.output += cmd;
-        }
-        // Read the output
-        let mut reader = BufReader::new(stream);
-        let mut buf = [0u8; 10240];
-        let bytes_read = reader.read(&mut buf)?;
-        let mut output = String::from_utf8_lossy(&buf[..bytes_read]).to_string();
-        // Add on the output
-        if self.shell.inserts_extra_newline() {
-            output = output.replace("\u{1b}[?2004l\r\r\n", "");
-        }
-        // println!("Adding (aftercmd) \"{:?}\"", output);
-        self.output += &output;
-        Ok(())
+    /// Send some input to the program, as if it were typed
+    /// (whatever the program isn't ready for yet is sent when it is)
+    pub fn write(&mut self, input: &[u8]) -> Result<()> {
+        self.pending_input.extend_from_slice(input);
+        self.flush_input()
     }
 
-    pub fn silent_run_command(&mut self, cmd: &str) -> Result<()> {
-        self.output.clear();
-        self.run_command(cmd)?;
-        if self.output.starts_with(cmd) {
-            self.output = self.output.chars().skip(cmd.chars().count()).collect();
+    /// Send as much of the waiting input as the program is ready for
+    fn flush_input(&mut self) -> Result<()> {
+        while !self.pending_input.is_empty() {
+            match self.stream.write(&self.pending_input) {
+                Ok(0) => break,
+                Ok(written) => {
+                    self.pending_input.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
         }
         Ok(())
     }
 
-    pub fn char_input(&mut self, c: char) -> Result<()> {
-        self.input.push(c);
-        if c == '\n' {
-            // Return key pressed, send the input
-            self.run_command(&self.input.to_string())?;
-            self.input.clear();
+    /// Type a command into the program and press enter (using the program's newline)
+    pub fn run_command(&mut self, cmd: &str) -> Result<()> {
+        let line = format!("{cmd}{}", self.newline);
+        self.write(line.as_bytes())
+    }
+
+    /// Send a key press to the program
+    pub fn key(&mut self, modifiers: KMod, code: KCode) -> Result<()> {
+        // Typing brings the view back down from the scrollback
+        self.screen.view_offset = 0;
+        if (modifiers, code) == (KMod::NONE, KCode::Enter) {
+            let newline = self.newline.clone();
+            return self.write(newline.as_bytes());
+        }
+        if let Some(bytes) = key_bytes(modifiers, code, self.screen.application_cursor) {
+            self.write(&bytes)?;
         }
         Ok(())
     }
 
-    pub fn char_pop(&mut self) {
-        self.input.pop();
+    /// Send pasted text to the program (marking it as pasted if the program wants that)
+    pub fn paste(&mut self, text: &str) -> Result<()> {
+        self.screen.view_offset = 0;
+        let text = text.replace('\n', "\r");
+        if self.screen.bracketed_paste {
+            self.write(format!("\x1b[200~{text}\x1b[201~").as_bytes())
+        } else {
+            self.write(text.as_bytes())
+        }
     }
 
-    pub fn clear(&mut self) -> Result<()> {
-        self.output.clear();
-        self.run_command("\n")?;
-        self.output = self.output.trim_start_matches('\n').to_string();
+    /// Change the size of the terminal (letting the program know)
+    pub fn resize(&mut self, w: usize, h: usize) -> Result<()> {
+        self.screen.resize(w, h);
+        let cols = u16::try_from(w).unwrap_or(u16::MAX);
+        let rows = u16::try_from(h).unwrap_or(u16::MAX);
+        self.process.set_window_size(cols, rows)?;
         Ok(())
     }
 
-    pub fn catch_up(&mut self) -> Result<bool> {
-        let stream = self.process.get_raw_handle()?;
-        let raw_fd = stream.as_raw_fd();
-        let flags = fcntl(raw_fd, FcntlArg::F_GETFL).unwrap();
-        fcntl(
-            raw_fd,
-            FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK),
-        )
-        .unwrap();
-        let mut source = SourceFd(&raw_fd);
-        // Set up mio Poll and register the raw_fd
-        let mut poll = Poll::new()?;
-        let mut events = Events::with_capacity(128);
-        poll.registry()
-            .register(&mut source, Token(0), Interest::READABLE)?;
-        match poll.poll(&mut events, Some(Duration::from_millis(100))) {
-            Ok(()) => {
-                // Data is available to read
-                let mut reader = BufReader::new(stream);
-                let mut buf = [0u8; 10240];
-                let bytes_read = reader.read(&mut buf)?;
-
-                // Process the read data
-                let mut output = String::from_utf8_lossy(&buf[..bytes_read]).to_string();
-                if self.shell.inserts_extra_newline() {
-                    output = output.replace("\u{1b}[?2004l\r\r\n", "");
-                }
+    /// Read everything the program has output and pass it through the emulator,
+    /// returning whether there was anything new
+    fn read_output(&mut self) -> bool {
+        let mut buf = [0u8; 10240];
+        let mut read_any = false;
+        // Errors here mean there is nothing more to read (e.g. the program has exited)
+        while let Ok(bytes_read @ 1..) = self.stream.read(&mut buf) {
+            self.screen.feed(&buf[..bytes_read]);
+            read_any = true;
+        }
+        // Answer any questions the program asked (e.g. where the cursor is)
+        let responses = self.screen.take_responses();
+        if !responses.is_empty() {
+            let _ = self.write(&responses);
+        }
+        read_any
+    }
+}
 
-                // Append the output to self.output
-                // println!("Adding (aftercmd) \"{:?}\"", output);
-                self.output += &output;
-                Ok(!output.is_empty())
-            }
-            Err(e) => Err(e),
+impl Drop for Pty {
+    fn drop(&mut self) {
+        // Stop watching for output
+        TERMINALS.lock().unwrap().remove(&self.token);
+        if let Ok(registry) = registry() {
+            let _ = registry.deregister(&mut SourceFd(&self.stream.as_raw_fd()));
         }
     }
 }
+
+/// Work out what a terminal would send to a program for a key press
+pub fn key_bytes(modifiers: KMod, code: KCode, application_cursor: bool) -> Option<Vec<u8>> {
+    let alt = modifiers.contains(KMod::ALT);
+    let ctrl = modifiers.contains(KMod::CONTROL);
+    // Modified special keys include a number describing the modifiers
+    let modifier =
+        1 + u8::from(modifiers.contains(KMod::SHIFT)) + u8::from(alt) * 2 + u8::from(ctrl) * 4;
+    let cursor_key = |letter: char| {
+        if modifier > 1 {
+            format!("\x1b[1;{modifier}{letter}")
+        } else if application_cursor {
+            format!("\x1bO{letter}")
+        } else {
+            format!("\x1b[{letter}")
+        }
+    };
+    let tilde_key = |number: u8| {
+        if modifier > 1 {
+            format!("\x1b[{number};{modifier}~")
+        } else {
+            format!("\x1b[{number}~")
+        }
+    };
+    let sequence = match code {
+        KCode::Char(ch) if ctrl => {
+            let control = match ch.to_ascii_lowercase() {
+                ch @ 'a'..='z' => ch as u8 - b'a' + 1,
+                '@' | ' ' | '2' => 0,
+                '[' | '3' => 0x1b,
+                '\\' | '4' => 0x1c,
+                ']' | '5' => 0x1d,
+                '^' | '6' => 0x1e,
+                '_' | '/' | '7' => 0x1f,
+                '?' | '8' => 0x7f,
+                _ => return None,
+            };
+            char::from(control).to_string()
+        }
+        KCode::Char(ch) => ch.to_string(),
+        KCode::Enter => "\r".to_string(),
+        KCode::Tab => "\t".to_string(),
+        KCode::BackTab => return Some(b"\x1b[Z".to_vec()),
+        KCode::Backspace => "\x7f".to_string(),
+        KCode::Esc => "\x1b".to_string(),
+        KCode::Up => return Some(cursor_key('A').into_bytes()),
+        KCode::Down => return Some(cursor_key('B').into_bytes()),
+        KCode::Right => return Some(cursor_key('C').into_bytes()),
+        KCode::Left => return Some(cursor_key('D').into_bytes()),
+        KCode::Home => return Some(cursor_key('H').into_bytes()),
+        KCode::End => return Some(cursor_key('F').into_bytes()),
+        KCode::Insert => return Some(tilde_key(2).into_bytes()),
+        KCode::Delete => return Some(tilde_key(3).into_bytes()),
+        KCode::PageUp => return Some(tilde_key(5).into_bytes()),
+        KCode::PageDown => return Some(tilde_key(6).into_bytes()),
+        KCode::F(n @ 1..=4) => return Some(format!("\x1bO{}", char::from(b'O' + n)).into_bytes()),
+        KCode::F(n @ 5..=12) => {
+            let number = [15, 17, 18, 19, 20, 21, 23, 24][usize::from(n - 5)];
+            return Some(tilde_key(number).into_bytes());
+        }
+        _ => return None,
+    };
+    // The alt key sends an escape before the key
+    Some(if alt {
+        format!("\x1b{sequence}").into_bytes()
+    } else {
+        sequence.into_bytes()
+    })
+}
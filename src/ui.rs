@@ -6,9 +6,9 @@ use base64::prelude::*;
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
     event::{
-        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
-        Event as CEvent, KeyCode as KCode, KeyEvent, KeyEventKind, KeyModifiers as KMod,
-        KeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+        DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+        EnableFocusChange, EnableMouseCapture, Event as CEvent, KeyCode as KCode, KeyEvent,
+        KeyEventKind, KeyModifiers as KMod, KeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
     },
     execute, queue,
     style::{Attribute, Color, SetAttribute, SetBackgroundColor as Bg, SetForegroundColor as Fg},
@@ -22,8 +22,6 @@ use mlua::AnyUserData;
 use std::collections::HashMap;
 use std::env;
 use std::io::{stdout, Stdout, Write};
-#[cfg(not(target_os = "windows"))]
-use synoptic::Regex;
 
 /// Printing macro
 #[macro_export]
@@ -185,6 +183,7 @@ impl Terminal {
                 Show,
                 DisableMouseCapture,
                 DisableBracketedPaste,
+                DisableFocusChange,
             )
             .unwrap();
             eprintln!("{e}");
@@ -205,6 +204,7 @@ impl Terminal {
                 self.stdout,
                 PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES),
                 EnableBracketedPaste,
+                EnableFocusChange,
             )?;
         }
         self.flush()?;
@@ -217,7 +217,7 @@ impl Terminal {
         terminal::disable_raw_mode()?;
         execute!(self.stdout, LeaveAlternateScreen, EnableLineWrap,)?;
         if cfg!(not(target_os = "windows")) {
-            execute!(self.stdout, DisableBracketedPaste,)?;
+            execute!(self.stdout, DisableBracketedPaste, DisableFocusChange)?;
         }
         let cfg = self.config.borrow::<TerminalConfig>().unwrap();
         if cfg.mouse_enabled {
@@ -339,87 +339,3 @@ pub fn get_xterm_lookup() -> HashMap<u8, (u8, u8, u8)> {
     }
     result
 }
-
-/// Define various ANSI code regex patterns for matching
-#[cfg(not(target_os = "windows"))]
-pub const CURSORS: &str = r"\x1b(?:\[(?:\d+(?:;\d+)?(?:A|B|C|D|F|f|G|H)|(?:#[A-G]|6n|s|u|H)|[0-6] q|(?:[0-9]{1,3};)*[0-9]{1,3}t)|[7-8])";
-#[cfg(not(target_os = "windows"))]
-pub const ERASING: &str = r"\x1b\[[0-3]?(?:J|K)";
-#[cfg(not(target_os = "windows"))]
-pub const DISPLAY: &str =
-    r"\x1b\[(?:(?:[0-9]|22|23|24|25|27|28|29)(?:t|m)|(?:[0-9]{1,3};)*[0-9]{1,3}m)";
-#[cfg(not(target_os = "windows"))]
-pub const SC_MODE: &str = r"\x1b\[(?:=|\?)[0-9]{1,4}(?:h|l)";
-#[cfg(not(target_os = "windows"))]
-pub const AC_JUNK: &str = r"(\a|\b|\n|\v|\f|\r)";
-
-/// Global ANSI removal
-#[cfg(not(target_os = "windows"))]
-fn global() -> String {
-    format!("({CURSORS}|{ERASING}|{DISPLAY}|{SC_MODE}|{AC_JUNK})")
-}
-
-#[cfg(not(target_os = "windows"))]
-/// Remove ANSI codes from a string
-pub fn remove_ansi_codes(input: &str) -> String {
-    // Define a regular expression to match ANSI escape codes and other control sequences
-    let re = Regex::new(&global()).unwrap();
-    let weird_newline = Regex::new(r"⏎\s*⏎\s?").unwrap();
-    let long_spaces = Regex::new(r"%(?:\x1b\[1m)?\s{5,}").unwrap();
-    // Replace all matches with an empty string
-    let result = re.replace_all(input, "").to_string();
-    // Replace weird new line stuff
-    let result = weird_newline.replace_all(&result, "").to_string();
-    // Replace long spaces
-    let result = long_spaces.replace_all(&result, "").to_string();
-    // Return result
-    result
-}
-
-#[cfg(not(target_os = "windows"))]
-/// Remove all ANSI codes outside of color and attribute codes
-pub fn strip_escape_codes(input: &str) -> String {
-    let re = Regex::new(&global()).unwrap();
-    let display = Regex::new(DISPLAY).unwrap();
-    let weird_newline = Regex::new(r"⏎\s*⏎\s?").unwrap();
-    let long_spaces = Regex::new(r"%(?:\x1b\[1m)?\s{5,}").unwrap();
-    // Replace escape sequences, keeping those for attributes and colors
-    let result = re
-        .replace_all(input, |caps: &regex::Captures| {
-            let code = caps.get(0).unwrap().as_str();
-            if display.is_match(code) {
-                // Return the escape code unchanged
-                code.to_string()
-            } else {
-                // Return an empty string for non-allowed escape codes (including cursor styling)
-                String::new()
-            }
-        })
-        .to_string();
-    // Replace weird new line stuff
-    let result = weird_newline.replace_all(&result, "").to_string();
-    // Replace long spaces
-    let result = long_spaces.replace_all(&result, "").to_string();
-    // Return result
-    result
-}
-
-#[cfg(not(target_os = "windows"))]
-#[allow(clippy::similar_names)]
-/// Replace reset colour ANSI codes with a custom background color.
-pub fn replace_reset(input: &str, custom_bg: &str, custom_fg: &str) -> String {
-    // Replace total reset with appropriate codes
-    let total_reset_regex = Regex::new(r"\x1b\[0m").unwrap();
-    // Define the regex to match reset background ANSI codes
-    let reset_bg_regex = Regex::new(r"\x1b\[49m").unwrap();
-    // Define the regex to match reset foreground ANSI codes
-    let reset_fg_regex = Regex::new(r"\x1b\[39m").unwrap();
-    // Replace reset background with the custom background color
-    let attr_full_reset =
-        "\u{1b}[22m\u{1b}[23m\u{1b}[24m\u{1b}[25m\u{1b}[27m\u{1b}[28m\u{1b}[29m".to_string();
-    let full_reset = format!("{custom_bg}{custom_fg}{attr_full_reset}");
-    let input = total_reset_regex.replace_all(input, full_reset).to_string();
-    let input = reset_bg_regex.replace_all(&input, custom_bg).to_string();
-    let input = reset_fg_regex.replace_all(&input, custom_fg).to_string();
-    input
-}